@@ -12,9 +12,14 @@ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 See the License for the specific language governing permissions and
 limitations under the License.*/
 
+use std::time::Duration;
+
 use structopt::StructOpt;
 
+use mbei_central::options::CentralOptions;
 use mbei_central::start_central;
+use mbei_grpc::tls::TlsOptions;
+use mbei_grpc::tracer::TraceExport;
 
 #[derive(StructOpt)]
 pub struct Cli {
@@ -23,11 +28,85 @@ pub struct Cli {
 
     #[structopt(short = "-p", long = "--port")]
     pub port: u16,
+
+    #[structopt(long = "--metrics-port")]
+    pub metrics_port: Option<u16>,
+
+    #[structopt(long = "--trace-file", parse(from_os_str), conflicts_with = "trace-collector")]
+    pub trace_file: Option<std::path::PathBuf>,
+
+    #[structopt(long = "--trace-collector")]
+    pub trace_collector: Option<String>,
+
+    #[structopt(long = "--max-queued-deltas")]
+    pub max_queued_deltas: Option<usize>,
+
+    #[structopt(long = "--max-queued-retractions")]
+    pub max_queued_retractions: Option<usize>,
+
+    #[structopt(long = "--full-queue-wait-ms")]
+    pub full_queue_wait_ms: Option<u64>,
+
+    #[structopt(long = "--max-changes")]
+    pub max_changes: Option<u64>,
+
+    #[structopt(long = "--inbox-path", parse(from_os_str))]
+    pub inbox_path: Option<std::path::PathBuf>,
+
+    #[structopt(long = "--tls-ca", parse(from_os_str), requires_all = &["tls-certificate", "tls-key"])]
+    pub tls_ca: Option<std::path::PathBuf>,
+
+    #[structopt(long = "--tls-certificate", parse(from_os_str), requires = "tls-ca")]
+    pub tls_certificate: Option<std::path::PathBuf>,
+
+    #[structopt(long = "--tls-key", parse(from_os_str), requires = "tls-ca")]
+    pub tls_key: Option<std::path::PathBuf>,
+
+    //Hosts that components must present certificates for, any component with a certificate from the ca if none
+    #[structopt(long = "--tls-allowed-client-host")]
+    pub tls_allowed_client_hosts: Vec<String>,
+
+    //Hosts of the components, which alone may forward the manual assertions of the operators they authenticated
+    #[structopt(long = "--tls-assertion-sender-host")]
+    pub tls_assertion_sender_hosts: Vec<String>,
 }
 
 fn main() {
     env_logger::init();
     let cli: Cli = Cli::from_args();
-    start_central(cli.sqlite_path, cli.port);
+    let trace_export = match (cli.trace_file, cli.trace_collector) {
+        (Some(path), _) => Some(TraceExport::File(path)),
+        (None, Some(url)) => Some(TraceExport::Collector(url)),
+        (None, None) => None,
+    };
+    let mut options = CentralOptions {
+        metrics_port: cli.metrics_port,
+        trace_export,
+        inbox_path: cli.inbox_path,
+        assertion_sender_hosts: cli.tls_assertion_sender_hosts.into_iter().collect(),
+        max_changes: cli.max_changes,
+        ..CentralOptions::default()
+    };
+    if let Some(max_queued_deltas) = cli.max_queued_deltas {
+        options.queue_limits.max_deltas = max_queued_deltas;
+    }
+    if let Some(max_queued_retractions) = cli.max_queued_retractions {
+        options.queue_limits.max_retractions = max_queued_retractions;
+    }
+    if let Some(full_queue_wait_ms) = cli.full_queue_wait_ms {
+        options.queue_limits.full_queue_wait = Duration::from_millis(full_queue_wait_ms);
+    }
+    if let (Some(ca_certificate_path), Some(certificate_path), Some(key_path)) = (cli.tls_ca, cli.tls_certificate, cli.tls_key) {
+        options.tls = Some(TlsOptions {
+            ca_certificate_path,
+            certificate_path,
+            key_path,
+            allowed_client_hosts: cli.tls_allowed_client_hosts.into_iter().collect(),
+        });
+    }
+    if let Err(e) = start_central(cli.sqlite_path, cli.port, options) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 
 }
@@ -0,0 +1,47 @@
+/*Copyright 2022 Prediktor AS
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.*/
+
+use structopt::StructOpt;
+
+use mbei_central::Central;
+
+/// Prints why central has an edge between two nodes at a timestamp, as a YAML derivation tree.
+/// Each delta has its source, and deltas derived from matches the derivations of the deltas behind the matched edges.
+#[derive(StructOpt)]
+pub struct Cli {
+    #[structopt(short = "-s", long = "--sqlite-path", parse(from_os_str))]
+    pub sqlite_path: std::path::PathBuf,
+
+    /// Instance node id of the source of the edge, as the barrel
+    #[structopt(long = "--src")]
+    pub src: String,
+
+    /// Instance node id of the target of the edge, as where the barrel is
+    #[structopt(long = "--trg")]
+    pub trg: String,
+
+    #[structopt(short = "-e", long = "--edge-type")]
+    pub edge_type: String,
+
+    #[structopt(short = "-t", long = "--timestamp")]
+    pub timestamp: u64,
+}
+
+fn main() {
+    env_logger::init();
+    let cli: Cli = Cli::from_args();
+    let central = Central::new(cli.sqlite_path);
+    let derivations = central.explain_edge(&cli.src, &cli.trg, &cli.edge_type, cli.timestamp);
+    print!("{}", serde_yaml::to_string(&derivations).expect("Could not serialize"));
+}
@@ -12,42 +12,136 @@ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 See the License for the specific language governing permissions and
 limitations under the License.*/
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
 
 use log::{debug, error};
-use rusqlite::{params, Connection, Result, Row};
+use rusqlite::{params, Connection, OptionalExtension, Result, Row, Transaction};
+use serde::Serialize;
 
-use mbei_core::event::{Deltas, Retractions, Update};
-use mbei_core::graph::{Delta, Node};
+use mbei_core::event::{deterministic_assertion_deltas_id, Deltas, InputEdge, ManualAssertion, Provenance, Retractions, Update};
+use mbei_core::graph::{Delta, DeltaType, Edge, Node};
+use mbei_grpc::subscription_server::{ChangeLog, ChangeType, StateChange};
+
+use crate::metrics::{FINALIZED_BEFORE, STORED_DELTAS, UPDATES};
+
+//Derivations of longer chains of queries leave out the inputs beyond this depth
+const MAX_DERIVATION_DEPTH: usize = 64;
+/// Changes kept for subscribers to resume from, unless configured otherwise.
+pub const DEFAULT_MAX_CHANGES: u64 = 1_000_000;
+
+/// Who asserted deltas by hand and why, and who revoked them.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ManualAssertionRecord {
+    pub assertion_id: String,
+    //Empty until the assertion itself is stored, as the revocation may arrive first
+    pub operator: String,
+    pub reason: String,
+    pub timestamp: Option<u64>,
+    pub revoked_by: Option<String>,
+    pub revoke_reason: Option<String>,
+    pub revoked_timestamp: Option<u64>,
+}
+
+/// Where a stored delta came from.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub enum DerivationSource {
+    //A query interpreting an event and one of its matches
+    Match {
+        query_name: String,
+        application: String,
+        origin_id: String,
+        origin_timestamp: u64,
+        match_hash: u64,
+    },
+    ManualAssertion(ManualAssertionRecord),
+    //Sent by a producer, or by a component which did not record how it derived the delta
+    External,
+}
+
+/// Why a delta is stored: its source and, for matches, the derivations of the deltas behind each matched edge.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Derivation {
+    pub deltas_id: String,
+    pub delta: Delta,
+    pub source: DerivationSource,
+    pub inputs: Vec<DerivedInput>,
+    //Set when the inputs were left out, as the derivation went deeper than MAX_DERIVATION_DEPTH
+    pub truncated: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DerivedInput {
+    pub edge: Edge,
+    //Empty for edges of events, and for deltas never sent to central
+    pub derivations: Vec<Derivation>,
+}
 
 pub struct Central {
     pub(crate) conn: Connection,
+    //The oldest changes are pruned beyond it
+    max_changes: u64,
 }
 
 impl Central {
     pub fn new(db_path: PathBuf) -> Central {
         let central = Central {
             conn: Connection::open(db_path.as_path()).expect("Could not connect"),
+            max_changes: DEFAULT_MAX_CHANGES,
         };
+        //Subscriptions read the changes through a second connection
+        central.conn.busy_timeout(Duration::from_secs(5)).expect("Could not set busy timeout");
         central.create_deltas_table();
         central.create_retracted_updates_table();
+        central.create_finality_table();
+        central.create_manual_assertions_table();
+        central.create_provenance_tables();
+        central.create_changes_table();
+        STORED_DELTAS.set(central.count_stored_deltas());
         central
     }
 
+    pub fn with_max_changes(mut self, max_changes: u64) -> Central {
+        self.max_changes = max_changes;
+        self
+    }
+
     pub(crate) fn process_update(&self, update:Update) {
         match update {
                 Update::Deltas(deltas) => {
                     debug!("Received delta update with deltas id {}, event_id {}", &deltas.deltas_id, &deltas.origin_id);
-                    if !self.is_update_retracted(&deltas.deltas_id) {
-                        self.insert_deltas(&deltas);
-                    } else {
+                    if self.is_update_retracted(&deltas.deltas_id) {
                         debug!("Update was retracted, will do nothing");
+                        UPDATES.with_label_values(&["deltas", "retracted"]).inc();
+                    } else if self.is_update_stored(&deltas.deltas_id) {
+                        debug!("Update was already stored, will do nothing");
+                        UPDATES.with_label_values(&["deltas", "duplicate"]).inc();
+                    } else {
+                        self.insert_deltas(&deltas);
+                        UPDATES.with_label_values(&["deltas", "stored"]).inc();
                     }
                 }
                 Update::Retractions(retractions) => {
                     debug!("Received retractions");
                     self.retract_deltas(&retractions);
+                    UPDATES.with_label_values(&["retractions", "stored"]).inc();
+                }
+                Update::ManualAssertion(assertion) => {
+                    debug!("Received manual assertion {} by {}", &assertion.assertion_id, &assertion.operator);
+                    self.record_manual_assertion(&assertion);
+                    let outcome = if assertion.revoke { "revoked" } else { "stored" };
+                    UPDATES.with_label_values(&["manual_assertion", outcome]).inc();
+                }
+                Update::Provenance(provenance) => {
+                    debug!("Received provenance of deltas id {}", &provenance.deltas_id);
+                    if self.is_update_retracted(&provenance.deltas_id) {
+                        UPDATES.with_label_values(&["provenance", "retracted"]).inc();
+                    } else {
+                        self.insert_provenance(&provenance);
+                        UPDATES.with_label_values(&["provenance", "stored"]).inc();
+                    }
                 }
                 _ => {error!("Should never happen"); }
             };
@@ -75,6 +169,26 @@ impl Central {
             .expect("Could not execute");
     }
 
+    //Every addition, removal and retraction of a delta, in the order they were stored
+    fn create_changes_table(&self) {
+        let query = "CREATE TABLE IF NOT EXISTS changes
+                        (cursor INTEGER PRIMARY KEY AUTOINCREMENT,
+                        change_type STRING,
+                        deltas_id STRING,
+                        ts INT,
+                        delta_type STRING,
+                        src_name STRING,
+                        src_nodetype STRING,
+                        src_nodeclass STRING,
+                        src_value BYTES,
+                        trg_name STRING,
+                        trg_nodetype STRING,
+                        trg_nodeclass STRING,
+                        trg_value BYTES,
+                        edge_type STRING);";
+        self.conn.execute(query, []).expect("Could not execute");
+    }
+
     fn create_retracted_updates_table(&self) {
         let query = "CREATE TABLE IF NOT EXISTS retracted_updates
                 (deltas_id STRING PRIMARY KEY);";
@@ -82,19 +196,149 @@ impl Central {
     }
 
 
+    fn create_finality_table(&self) {
+        let query = "CREATE TABLE IF NOT EXISTS finality
+                (source STRING PRIMARY KEY,
+                finalized_before INT);";
+        self.conn.execute(query, []).expect("Could not execute");
+    }
+
+    //The asserted deltas are stored in the deltas table under deltas_id, and are retracted from there when revoked
+    fn create_manual_assertions_table(&self) {
+        let query = "CREATE TABLE IF NOT EXISTS manual_assertions
+                (assertion_id STRING PRIMARY KEY,
+                deltas_id STRING,
+                operator STRING,
+                reason STRING,
+                ts INT,
+                revoked_by STRING,
+                revoke_reason STRING,
+                revoked_ts INT);";
+        self.conn.execute(query, []).expect("Could not execute");
+    }
+
+    //Components send the assertion and its revocation concurrently, so either may be recorded first
+    fn record_manual_assertion(&self, assertion: &ManualAssertion) {
+        let query = if assertion.revoke {
+            "INSERT INTO manual_assertions (assertion_id, deltas_id, revoked_by, revoke_reason, revoked_ts) VALUES (?1, ?2, ?3, ?4, ?5)
+                ON CONFLICT(assertion_id) DO UPDATE SET revoked_by = excluded.revoked_by,
+                revoke_reason = excluded.revoke_reason, revoked_ts = excluded.revoked_ts"
+        } else {
+            "INSERT INTO manual_assertions (assertion_id, deltas_id, operator, reason, ts) VALUES (?1, ?2, ?3, ?4, ?5)
+                ON CONFLICT(assertion_id) DO UPDATE SET operator = excluded.operator,
+                reason = excluded.reason, ts = excluded.ts"
+        };
+        let deltas_id = deterministic_assertion_deltas_id(&assertion.assertion_id, bincode::config::standard());
+        self.conn
+            .execute(
+                query,
+                params![
+                    &assertion.assertion_id,
+                    &deltas_id,
+                    &assertion.operator,
+                    &assertion.reason,
+                    assertion.timestamp as i64
+                ],
+            )
+            .expect("Could not execute query");
+    }
+
+    //Receivers get deltas under routed ids, which the provenance of what they derive refers to
+    fn create_provenance_tables(&self) {
+        let query = "CREATE TABLE IF NOT EXISTS provenance
+                (deltas_id STRING PRIMARY KEY,
+                query_name STRING,
+                application STRING,
+                origin_id STRING,
+                origin_ts INT,
+                match_hash INT,
+                input_edges BLOB);";
+        let routed_query = "CREATE TABLE IF NOT EXISTS routed_deltas
+                (routed_deltas_id STRING PRIMARY KEY,
+                deltas_id STRING);";
+        let routed_index_query = "CREATE INDEX IF NOT EXISTS routed_deltas_deltas_id_index ON routed_deltas (deltas_id);";
+        self.conn.execute(query, []).expect("Could not execute");
+        self.conn.execute(routed_query, []).expect("Could not execute");
+        self.conn.execute(routed_index_query, []).expect("Could not execute");
+    }
+
+    //Replaces earlier provenance, as when the deltas are relinked to an equivalent match.
+    //Components send it again whenever they reprocess, so unchanged provenance is not written again
+    fn insert_provenance(&self, provenance: &Provenance) {
+        let query = "INSERT INTO provenance
+                (deltas_id, query_name, application, origin_id, origin_ts, match_hash, input_edges)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                ON CONFLICT(deltas_id) DO UPDATE SET
+                query_name = excluded.query_name, application = excluded.application, origin_id = excluded.origin_id,
+                origin_ts = excluded.origin_ts, match_hash = excluded.match_hash, input_edges = excluded.input_edges
+                WHERE (query_name, application, origin_id, origin_ts, match_hash, input_edges)
+                IS NOT (excluded.query_name, excluded.application, excluded.origin_id, excluded.origin_ts, excluded.match_hash, excluded.input_edges)";
+        //Routed ids are derived from the deltas id, so they never change
+        let routed_query = "INSERT OR IGNORE INTO routed_deltas (routed_deltas_id, deltas_id) VALUES (?1, ?2)";
+        let input_edges = bincode::encode_to_vec(&provenance.input_edges, bincode::config::standard()).expect("Encodable");
+        let transaction = self.conn.unchecked_transaction().expect("Could not begin transaction");
+        transaction
+            .execute(
+                query,
+                params![
+                    &provenance.deltas_id,
+                    &provenance.query_name,
+                    &provenance.application,
+                    &provenance.origin_id,
+                    provenance.origin_timestamp as i64,
+                    provenance.match_hash as i64,
+                    &input_edges
+                ],
+            )
+            .expect("Could not execute query");
+        for routed_deltas_id in &provenance.routed_deltas_ids {
+            transaction
+                .execute(routed_query, params![routed_deltas_id, &provenance.deltas_id])
+                .expect("Could not execute query");
+        }
+        transaction.commit().expect("Could not commit transaction");
+    }
+
+    /// Records that the deltas of the source before the timestamp are final, when that is later than recorded.
+    pub(crate) fn record_finality(&self, source: &str, finalized_before: u64) {
+        let query = "INSERT INTO finality (source, finalized_before) VALUES (?1, ?2)
+                ON CONFLICT(source) DO UPDATE SET finalized_before = MAX(finalized_before, excluded.finalized_before)";
+        self.conn
+            .execute(query, params![source, finalized_before as i64])
+            .expect("Could not execute query");
+        FINALIZED_BEFORE.with_label_values(&[source]).set(finalized_before as i64);
+    }
+
+    /// Before which timestamp the stored deltas of each source are final, and are neither added to nor retracted.
+    /// History is stable before the earliest of them, given that every source consumers depend on is present.
+    pub fn get_finality(&self) -> BTreeMap<String, u64> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT source, finalized_before FROM finality")
+            .expect("Could not prepare");
+        let rows = stmt
+            .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)? as u64)))
+            .expect("Could not map result");
+        rows.map(|r| r.expect("Error mapping row")).collect()
+    }
+
     fn is_update_retracted(&self, deltas_id:&str) -> bool {
         let query = "SELECT 1 FROM retracted_updates WHERE deltas_id=:updateid";
         let mut stmt = self.conn.prepare(query).expect("Could not prepare");
 
-        let rows = stmt
+        let mut rows = stmt
             .query_map(&[(":updateid", &deltas_id)], |row| -> Result<u8> {
                 Ok(row.get(0).expect("Could not find index 0"))
             })
             .expect("Could not map result");
-        for _ in rows {
-            return true;
-        }
-        return false;
+        rows.next().is_some()
+    }
+
+    fn is_update_stored(&self, deltas_id:&str) -> bool {
+        let query = "SELECT 1 FROM deltas WHERE deltas_id=:updateid LIMIT 1";
+        let mut stmt = self.conn.prepare(query).expect("Could not prepare");
+        stmt.exists(&[(":updateid", &deltas_id)])
+            .expect("Could not execute query")
     }
 
     fn insert_deltas(&self, deltas: &Deltas) {
@@ -102,9 +346,11 @@ impl Central {
                 (deltas_id, src_name, src_nodetype, src_nodeclass, src_value,
                 trg_name, trg_nodetype, trg_nodeclass, trg_value, edge_type, delta_type, ts)
                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)";
+        //Subscribers read the changes through another connection, so they see the deltas of an update all at once or not at all
+        let transaction = self.conn.unchecked_transaction().expect("Could not begin transaction");
         for delta in &deltas.deltas {
             debug!("Inserting delta {:?}", delta);
-            self.conn
+            transaction
                 .execute(
                     query,
                     params![
@@ -124,21 +370,262 @@ impl Central {
                 )
                 .expect("Could not execute query");
         }
+        let change_query = "INSERT INTO changes
+                (change_type, deltas_id, src_name, src_nodetype, src_nodeclass, src_value,
+                trg_name, trg_nodetype, trg_nodeclass, trg_value, edge_type, delta_type, ts)
+                SELECT delta_type, deltas_id, src_name, src_nodetype, src_nodeclass, src_value,
+                trg_name, trg_nodetype, trg_nodeclass, trg_value, edge_type, delta_type, ts
+                FROM deltas WHERE deltas_id = ?1 ORDER BY rowid";
+        transaction
+            .execute(change_query, params![&deltas.deltas_id])
+            .expect("Could not execute query");
+        self.prune_changes(&transaction);
+        transaction.commit().expect("Could not commit transaction");
+        STORED_DELTAS.add(deltas.deltas.len() as i64);
+    }
+
+    //Subscribers resuming from a pruned change are told that they missed changes
+    fn prune_changes(&self, transaction: &Transaction) {
+        let query = "DELETE FROM changes WHERE cursor <= (SELECT MAX(cursor) FROM changes) - ?1";
+        transaction
+            .execute(query, params![self.max_changes as i64])
+            .expect("Could not execute query");
+    }
+
+    fn count_stored_deltas(&self) -> i64 {
+        self.conn
+            .query_row("SELECT COUNT(*) FROM deltas", [], |row| row.get(0))
+            .expect("Could not execute query")
     }
 
     fn retract_deltas(&self, retractions: &Retractions) {
         debug!("Retracting: {:?}", &retractions.deltas_ids);
+        let change_query = "INSERT INTO changes
+                (change_type, deltas_id, src_name, src_nodetype, src_nodeclass, src_value,
+                trg_name, trg_nodetype, trg_nodeclass, trg_value, edge_type, delta_type, ts)
+                SELECT 'Retraction', deltas_id, src_name, src_nodetype, src_nodeclass, src_value,
+                trg_name, trg_nodetype, trg_nodeclass, trg_value, edge_type, delta_type, ts
+                FROM deltas WHERE deltas_id = ?1 ORDER BY rowid";
         let delete_query = "DELETE FROM deltas WHERE deltas_id = (?1);";
+        let delete_provenance_query = "DELETE FROM provenance WHERE deltas_id = (?1);";
+        let delete_routed_query = "DELETE FROM routed_deltas WHERE deltas_id = (?1);";
         let insert_query = "INSERT OR IGNORE INTO retracted_updates (deltas_id) VALUES (?1)";
         let deltas_ids_set = BTreeSet::from_iter(retractions.deltas_ids.iter());
+        let transaction = self.conn.unchecked_transaction().expect("Could not begin transaction");
+        let mut n_deleted = 0;
         for deltas_id in deltas_ids_set {
-            self.conn
+            transaction
+                .execute(change_query, params![deltas_id])
+                .expect("Could not execute query");
+            n_deleted += transaction
                 .execute(delete_query, params![deltas_id])
                 .expect("Could not execute query");
-            self.conn
+            transaction
+                .execute(delete_provenance_query, params![deltas_id])
+                .expect("Could not execute query");
+            transaction
+                .execute(delete_routed_query, params![deltas_id])
+                .expect("Could not execute query");
+            //Kept also for deltas already stored, so that duplicates arriving later are dropped as well.
+            //Output produced again after a retraction has a new deltas id, as it is a new interpretation of the match
+            transaction
                 .execute(insert_query, params![deltas_id])
                 .expect("Could not execute query");
         }
+        self.prune_changes(&transaction);
+        transaction.commit().expect("Could not commit transaction");
+        STORED_DELTAS.sub(n_deleted as i64);
+    }
+
+    /// Every manual assertion, also those revoked, by assertion id.
+    pub fn get_manual_assertions(&self) -> Vec<ManualAssertionRecord> {
+        let query = "SELECT assertion_id, operator, reason, ts, revoked_by, revoke_reason, revoked_ts
+                FROM manual_assertions ORDER BY assertion_id";
+        let mut stmt = self.conn.prepare(query).expect("Could not prepare");
+        let rows = stmt
+            .query_map([], manual_assertion_from_row)
+            .expect("Could not map result");
+        rows.map(|r| r.expect("Error mapping row")).collect()
+    }
+
+    /// The stored deltas of a manual assertion, which are none once it is revoked.
+    pub fn get_asserted_deltas(&self, assertion_id: &str) -> Vec<Delta> {
+        let query = "SELECT src_name, src_nodetype, src_nodeclass, src_value,
+                trg_name, trg_nodetype, trg_nodeclass, trg_value, edge_type, ts, delta_type FROM deltas
+                WHERE deltas_id IN (SELECT deltas_id FROM manual_assertions WHERE assertion_id = ?1)";
+        let mut stmt = self.conn.prepare(query).expect("Could not prepare");
+        let rows = stmt
+            .query_map(params![assertion_id], delta_from_tuple)
+            .expect("Could not map result");
+        rows.map(|r| r.expect("Error mapping row")).collect()
+    }
+
+    /// Why the edge is, or is not, between the nodes at the timestamp: the derivations of the latest deltas of the edge
+    /// at or before it, which are additions while the edge is there. Empty if central has no such deltas.
+    pub fn explain_edge(&self, src_name: &str, trg_name: &str, edge_type: &str, timestamp: u64) -> Vec<Derivation> {
+        let query = "SELECT src_name, src_nodetype, src_nodeclass, src_value,
+                trg_name, trg_nodetype, trg_nodeclass, trg_value, edge_type, ts, delta_type, deltas_id FROM deltas
+                WHERE src_name = ?1 AND trg_name = ?2 AND edge_type = ?3 AND ts <= ?4
+                AND ts = (SELECT MAX(ts) FROM deltas WHERE src_name = ?1 AND trg_name = ?2 AND edge_type = ?3 AND ts <= ?4)";
+        let mut stmt = self.conn.prepare(query).expect("Could not prepare");
+        let rows = stmt
+            .query_map(params![src_name, trg_name, edge_type, timestamp as i64], |row| {
+                Ok((row.get::<_, String>(11)?, delta_from_tuple(row)?))
+            })
+            .expect("Could not map result");
+        let latest: Vec<(String, Delta)> = rows.map(|r| r.expect("Error mapping row")).collect();
+        let mut derived = BTreeMap::new();
+        latest
+            .into_iter()
+            .map(|(deltas_id, delta)| self.derive(deltas_id, delta, &mut vec![], &mut derived))
+            .collect()
+    }
+
+    //Ancestors are skipped, so that queries routing to each other in a cycle do not derive forever.
+    //Deltas reached along several paths are derived once, and derivations stop at MAX_DERIVATION_DEPTH
+    fn derive(
+        &self,
+        deltas_id: String,
+        delta: Delta,
+        ancestors: &mut Vec<String>,
+        derived: &mut BTreeMap<(String, Delta), Derivation>,
+    ) -> Derivation {
+        let key = (deltas_id, delta);
+        if let Some(derivation) = derived.get(&key) {
+            return derivation.clone();
+        }
+        let (deltas_id, delta) = key;
+        let provenance = self.get_provenance(&deltas_id);
+        let (source, input_edges) = match provenance {
+            Some((source, input_edges)) => (source, input_edges),
+            None => match self.get_manual_assertion_by_deltas_id(&deltas_id) {
+                Some(record) => (DerivationSource::ManualAssertion(record), vec![]),
+                None => (DerivationSource::External, vec![]),
+            },
+        };
+        if ancestors.len() >= MAX_DERIVATION_DEPTH && !input_edges.is_empty() {
+            //Not memoized, since the deltas may be derived in full along a shorter path
+            return Derivation {
+                deltas_id,
+                delta,
+                source,
+                inputs: vec![],
+                truncated: true,
+            };
+        }
+        ancestors.push(deltas_id.clone());
+        let mut inputs = vec![];
+        for input_edge in input_edges {
+            let mut derivations = vec![];
+            for upstream_deltas_id in input_edge.deltas_ids {
+                let upstream_deltas_id = self.resolve_routed_deltas_id(&upstream_deltas_id);
+                if ancestors.contains(&upstream_deltas_id) {
+                    continue;
+                }
+                for upstream_delta in self.get_deltas_of_edge(&upstream_deltas_id, &input_edge.edge) {
+                    derivations.push(self.derive(upstream_deltas_id.clone(), upstream_delta, ancestors, derived));
+                }
+            }
+            inputs.push(DerivedInput {
+                edge: input_edge.edge,
+                derivations,
+            });
+        }
+        ancestors.pop();
+        let derivation = Derivation {
+            deltas_id,
+            delta,
+            source,
+            inputs,
+            truncated: false,
+        };
+        derived.insert((derivation.deltas_id.clone(), derivation.delta.clone()), derivation.clone());
+        derivation
+    }
+
+    fn get_provenance(&self, deltas_id: &str) -> Option<(DerivationSource, Vec<InputEdge>)> {
+        let query = "SELECT query_name, application, origin_id, origin_ts, match_hash, input_edges
+                FROM provenance WHERE deltas_id = ?1";
+        self.conn
+            .query_row(query, params![deltas_id], |row| {
+                let source = DerivationSource::Match {
+                    query_name: row.get(0)?,
+                    application: row.get(1)?,
+                    origin_id: row.get(2)?,
+                    origin_timestamp: row.get::<_, i64>(3)? as u64,
+                    match_hash: row.get::<_, i64>(4)? as u64,
+                };
+                let input_edges_bytes: Vec<u8> = row.get(5)?;
+                let (input_edges, _) = bincode::decode_from_slice(&input_edges_bytes, bincode::config::standard())
+                    .expect("Could not decode input edges");
+                Ok((source, input_edges))
+            })
+            .optional()
+            .expect("Could not execute query")
+    }
+
+    fn get_manual_assertion_by_deltas_id(&self, deltas_id: &str) -> Option<ManualAssertionRecord> {
+        let query = "SELECT assertion_id, operator, reason, ts, revoked_by, revoke_reason, revoked_ts
+                FROM manual_assertions WHERE deltas_id = ?1";
+        self.conn
+            .query_row(query, params![deltas_id], manual_assertion_from_row)
+            .optional()
+            .expect("Could not execute query")
+    }
+
+    //The id central stored the deltas under, for ids the deltas were routed to other queries under
+    fn resolve_routed_deltas_id(&self, deltas_id: &str) -> String {
+        let query = "SELECT deltas_id FROM routed_deltas WHERE routed_deltas_id = ?1";
+        self.conn
+            .query_row(query, params![deltas_id], |row| row.get(0))
+            .optional()
+            .expect("Could not execute query")
+            .unwrap_or_else(|| deltas_id.to_string())
+    }
+
+    //The deltas which opened or closed the edge
+    fn get_deltas_of_edge(&self, deltas_id: &str, edge: &Edge) -> Vec<Delta> {
+        let query = "SELECT src_name, src_nodetype, src_nodeclass, src_value,
+                trg_name, trg_nodetype, trg_nodeclass, trg_value, edge_type, ts, delta_type FROM deltas
+                WHERE deltas_id = ?1 AND src_name = ?2 AND trg_name = ?3 AND edge_type = ?4";
+        let mut stmt = self.conn.prepare(query).expect("Could not prepare");
+        let rows = stmt
+            .query_map(
+                params![deltas_id, &edge.src.instance_node_name, &edge.trg.instance_node_name, &edge.edge_type],
+                delta_from_tuple,
+            )
+            .expect("Could not map result");
+        rows.map(|r| r.expect("Error mapping row"))
+            .filter(|d| match d.delta_type {
+                DeltaType::Addition => edge.from_timestamp == Some(d.timestamp),
+                DeltaType::Removal => edge.to_timestamp == Some(d.timestamp),
+            })
+            .collect()
+    }
+
+    /// At most limit changes after the cursor, in the order they were stored.
+    pub fn get_changes_after(&self, cursor: u64, limit: usize) -> Result<Vec<StateChange>, String> {
+        let query = "SELECT src_name, src_nodetype, src_nodeclass, src_value,
+                trg_name, trg_nodetype, trg_nodeclass, trg_value, edge_type, ts, delta_type,
+                cursor, change_type, deltas_id FROM changes
+                WHERE cursor > ?1 ORDER BY cursor LIMIT ?2";
+        let mut stmt = self.conn.prepare(query).map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params![cursor as i64, limit as i64], |row| {
+                let change_type = match row.get::<_, String>(12)?.as_str() {
+                    "Addition" => ChangeType::Addition,
+                    "Removal" => ChangeType::Removal,
+                    _ => ChangeType::Retraction,
+                };
+                Ok(StateChange {
+                    cursor: row.get::<_, i64>(11)? as u64,
+                    change_type,
+                    deltas_id: row.get(13)?,
+                    delta: delta_from_tuple(row)?,
+                })
+            })
+            .map_err(|e| e.to_string())?;
+        rows.map(|r| r.map_err(|e| e.to_string())).collect()
     }
 
     pub fn get_all_deltas(&self) -> Vec<Delta> {
@@ -146,7 +633,7 @@ impl Central {
                 trg_name, trg_nodetype, trg_nodeclass, trg_value, edge_type, ts, delta_type FROM deltas";
         let mut stmt = self.conn.prepare(query).expect("Could not prepare");
         let rows = stmt
-            .query_map([], |row| delta_from_tuple(row))
+            .query_map([], delta_from_tuple)
             .expect("Could not map result");
         let mut deltas = vec![];
         for r in rows {
@@ -156,6 +643,35 @@ impl Central {
     }
 }
 
+/// Reads the changes for subscriptions through its own connection, as the connection of central is not shared between threads.
+pub(crate) struct CentralChangeLog {
+    central: Mutex<Central>,
+}
+
+impl CentralChangeLog {
+    pub(crate) fn new(db_path: PathBuf) -> CentralChangeLog {
+        CentralChangeLog { central: Mutex::new(Central::new(db_path)) }
+    }
+}
+
+impl ChangeLog for CentralChangeLog {
+    fn get_changes_after(&self, cursor: u64, limit: usize) -> Result<Vec<StateChange>, String> {
+        self.central.lock().expect("Change log lock poisoned").get_changes_after(cursor, limit)
+    }
+}
+
+fn manual_assertion_from_row(row: &Row) -> Result<ManualAssertionRecord> {
+    Ok(ManualAssertionRecord {
+        assertion_id: row.get(0)?,
+        operator: row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+        reason: row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+        timestamp: row.get::<_, Option<i64>>(3)?.map(|t| t as u64),
+        revoked_by: row.get(4)?,
+        revoke_reason: row.get(5)?,
+        revoked_timestamp: row.get::<_, Option<i64>>(6)?.map(|t| t as u64),
+    })
+}
+
 fn delta_from_tuple(row: &Row) -> Result<Delta> {
     let src = Node {
         query_node_name: None,
@@ -179,3 +695,290 @@ fn delta_from_tuple(row: &Row) -> Result<Delta> {
         delta_type: row.get(10).unwrap(),
     })
 }
+
+#[test]
+fn test_duplicate_deltas_are_stored_once() {
+    let central = Central::new(PathBuf::from(":memory:"));
+    let delta = Delta {
+        src: Node::material_instance_node("MyBarrel0", "Barrel"),
+        trg: Node::object_instance_node("MyPlatform0", "Platform"),
+        edge_type: "At".to_string(),
+        timestamp: 1,
+        delta_type: mbei_core::graph::DeltaType::Addition,
+    };
+    let deltas = Deltas {
+        deltas_id: "mydeltas".to_string(),
+        origin_id: "myevent".to_string(),
+        origin_timestamp: 1,
+        deltas: BTreeSet::from([delta]),
+    };
+    central.process_update(Update::Deltas(deltas.clone()));
+    central.process_update(Update::Deltas(deltas));
+    assert_eq!(central.get_all_deltas().len(), 1);
+}
+
+#[test]
+fn test_deltas_produced_again_after_retraction_are_stored_in_any_arrival_order() {
+    let delta_set = BTreeSet::from([Delta {
+        src: Node::material_instance_node("MyBarrel0", "Barrel"),
+        trg: Node::object_instance_node("MyCrane0", "Crane"),
+        edge_type: "At".to_string(),
+        timestamp: 57,
+        delta_type: DeltaType::Removal,
+    }]);
+    let deltas = |generation: u32| {
+        Update::Deltas(Deltas {
+            deltas_id: mbei_core::event::deterministic_deltas_id("myevent", 7, generation, "q", &delta_set, bincode::config::standard()),
+            origin_id: "myevent".to_string(),
+            origin_timestamp: 57,
+            deltas: delta_set.clone(),
+        })
+    };
+    let first_id = match deltas(0) {
+        Update::Deltas(d) => d.deltas_id,
+        _ => unreachable!(),
+    };
+    let retraction = Update::Retractions(Retractions {
+        retraction_id: "r".to_string(),
+        timestamp: 57,
+        deltas_ids: vec![first_id],
+    });
+    //The component sent the deltas, retracted them and produced them again, and the retraction may overtake either,
+    //also when the first deltas are delivered twice
+    let arrival_orders = vec![
+        vec![deltas(0), retraction.clone(), deltas(1)],
+        vec![retraction.clone(), deltas(0), deltas(1)],
+        vec![retraction.clone(), deltas(1), deltas(0)],
+        vec![deltas(0), deltas(1), retraction.clone()],
+        vec![deltas(1), deltas(0), retraction.clone()],
+        vec![deltas(0), deltas(0), retraction.clone(), deltas(1)],
+        vec![deltas(0), retraction.clone(), deltas(0), deltas(1)],
+    ];
+    for updates in arrival_orders {
+        let central = Central::new(PathBuf::from(":memory:"));
+        for update in updates {
+            central.process_update(update);
+        }
+        assert_eq!(central.get_all_deltas(), delta_set.iter().cloned().collect::<Vec<Delta>>());
+    }
+}
+
+#[test]
+fn test_finality_only_moves_forward() {
+    let central = Central::new(PathBuf::from(":memory:"));
+    central.record_finality("q1", 10);
+    central.record_finality("q2", 4);
+    central.record_finality("q1", 7);
+    assert_eq!(central.get_finality(), BTreeMap::from([("q1".to_string(), 10), ("q2".to_string(), 4)]));
+}
+
+#[test]
+fn test_manual_assertions_are_audited_in_any_order() {
+    use mbei_core::graph::DeltaType;
+    let central = Central::new(PathBuf::from(":memory:"));
+    let delta = Delta {
+        src: Node::material_instance_node("MyBarrel0", "Barrel"),
+        trg: Node::object_instance_node("MyCrane0", "Crane"),
+        edge_type: "At".to_string(),
+        timestamp: 3,
+        delta_type: DeltaType::Addition,
+    };
+    let assertion = |assertion_id: &str, operator: &str, revoke: bool| ManualAssertion {
+        assertion_id: assertion_id.to_string(),
+        operator: operator.to_string(),
+        reason: if revoke { "sensor fixed" } else { "sensor down" }.to_string(),
+        timestamp: if revoke { 5 } else { 3 },
+        deltas: if revoke { BTreeSet::new() } else { BTreeSet::from([delta.clone()]) },
+        revoke,
+    };
+    let deltas_id = deterministic_assertion_deltas_id("a1", bincode::config::standard());
+    central.process_update(Update::ManualAssertion(assertion("a1", "alice", false)));
+    central.process_update(Update::Deltas(Deltas {
+        deltas_id: deltas_id.clone(),
+        origin_id: "a1".to_string(),
+        origin_timestamp: 3,
+        deltas: BTreeSet::from([delta.clone()]),
+    }));
+    assert_eq!(central.get_asserted_deltas("a1"), vec![delta.clone()]);
+    central.process_update(Update::Retractions(Retractions {
+        retraction_id: "r1".to_string(),
+        timestamp: 5,
+        deltas_ids: vec![deltas_id],
+    }));
+    central.process_update(Update::ManualAssertion(assertion("a1", "bob", true)));
+    assert!(central.get_asserted_deltas("a1").is_empty());
+    //The revocation of a2 is recorded before the assertion
+    central.process_update(Update::ManualAssertion(assertion("a2", "bob", true)));
+    central.process_update(Update::ManualAssertion(assertion("a2", "alice", false)));
+    let records = central.get_manual_assertions();
+    assert_eq!(records.len(), 2);
+    for record in records {
+        assert_eq!(record.operator, "alice");
+        assert_eq!(record.reason, "sensor down");
+        assert_eq!(record.timestamp, Some(3));
+        assert_eq!(record.revoked_by.as_deref(), Some("bob"));
+        assert_eq!(record.revoke_reason.as_deref(), Some("sensor fixed"));
+        assert_eq!(record.revoked_timestamp, Some(5));
+    }
+}
+
+#[test]
+fn test_deltas_are_explained_through_routed_provenance() {
+    let central = Central::new(PathBuf::from(":memory:"));
+    let barrel = Node::material_instance_node("MyBarrel0", "Barrel");
+    let at = |trg: &Node, timestamp| Delta {
+        src: barrel.clone(),
+        trg: trg.clone(),
+        edge_type: "At".to_string(),
+        timestamp,
+        delta_type: DeltaType::Addition,
+    };
+    let opened_at = |delta: &Delta| Edge {
+        from_timestamp: Some(delta.timestamp),
+        ..delta.to_edge()
+    };
+    let store = |deltas_id: &str, delta: &Delta| {
+        central.process_update(Update::Deltas(Deltas {
+            deltas_id: deltas_id.to_string(),
+            origin_id: deltas_id.to_string(),
+            origin_timestamp: delta.timestamp,
+            deltas: BTreeSet::from([delta.clone()]),
+        }))
+    };
+    let provenance = |deltas_id: &str, query_name: &str, input: &Delta, upstream_deltas_id: &str, routed_deltas_id: &str| {
+        central.process_update(Update::Provenance(Provenance {
+            deltas_id: deltas_id.to_string(),
+            query_name: query_name.to_string(),
+            application: "crane".to_string(),
+            origin_id: "e".to_string() + deltas_id,
+            origin_timestamp: input.timestamp + 2,
+            match_hash: u64::MAX,
+            input_edges: vec![InputEdge { edge: opened_at(input), deltas_ids: vec![upstream_deltas_id.to_string()] }],
+            routed_deltas_ids: vec![routed_deltas_id.to_string()],
+        }))
+    };
+    let platform = Node::object_instance_node("MyPlatform0", "Platform");
+    let crane = Node::object_instance_node("MyCrane0", "Crane");
+    let ramp = Node::object_instance_node("MyRamp0", "Ramp");
+    //A producer puts the barrel at the platform, a crane picks it up, and the next query drops it at the ramp
+    store("d0", &at(&platform, 1));
+    store("a1", &at(&crane, 3));
+    provenance("a1", "pickup", &at(&platform, 1), "d0", "a1_routed");
+    store("b1", &at(&ramp, 5));
+    provenance("b1", "drop", &at(&crane, 3), "a1_routed", "b1_routed");
+
+    assert!(central.explain_edge("MyBarrel0", "MyRamp0", "At", 4).is_empty());
+    let derivations = central.explain_edge("MyBarrel0", "MyRamp0", "At", 6);
+    assert_eq!(derivations.len(), 1);
+    let drop = &derivations[0];
+    assert_eq!(drop.delta, at(&ramp, 5));
+    assert!(matches!(&drop.source, DerivationSource::Match { query_name, match_hash, .. } if query_name == "drop" && *match_hash == u64::MAX));
+    let pickup = &drop.inputs[0].derivations[0];
+    assert_eq!(pickup.deltas_id, "a1");
+    assert_eq!(pickup.inputs[0].edge, opened_at(&at(&platform, 1)));
+    let put = &pickup.inputs[0].derivations[0];
+    assert_eq!((put.deltas_id.as_str(), &put.source), ("d0", &DerivationSource::External));
+    assert!(put.inputs.is_empty());
+    assert!(!drop.truncated);
+
+    //Provenance sent again as the match is reprocessed leaves the explanation as it was
+    provenance("b1", "drop", &at(&crane, 3), "a1_routed", "b1_routed");
+    assert_eq!(central.explain_edge("MyBarrel0", "MyRamp0", "At", 6), derivations);
+
+    //Retracted deltas are no longer explained, and neither is what they caused
+    central.process_update(Update::Retractions(Retractions {
+        retraction_id: "r1".to_string(),
+        timestamp: 3,
+        deltas_ids: vec!["a1".to_string()],
+    }));
+    assert!(central.explain_edge("MyBarrel0", "MyCrane0", "At", 6).is_empty());
+    assert!(central.explain_edge("MyBarrel0", "MyRamp0", "At", 6)[0].inputs[0].derivations.is_empty());
+    assert_eq!(central.resolve_routed_deltas_id("a1_routed"), "a1_routed");
+}
+
+#[test]
+fn test_derivations_of_long_chains_are_truncated() {
+    let central = Central::new(PathBuf::from(":memory:"));
+    let at = |position: usize| Delta {
+        src: Node::material_instance_node("MyBarrel0", "Barrel"),
+        trg: Node::object_instance_node(&format!("MyPlatform{}", position), "Platform"),
+        edge_type: "At".to_string(),
+        timestamp: position as u64 + 1,
+        delta_type: DeltaType::Addition,
+    };
+    //Each query moves the barrel to the next platform, matching where the previous one put it
+    let n_moves = MAX_DERIVATION_DEPTH + 2;
+    for position in 0..=n_moves {
+        central.process_update(Update::Deltas(Deltas {
+            deltas_id: format!("d{}", position),
+            origin_id: format!("e{}", position),
+            origin_timestamp: at(position).timestamp,
+            deltas: BTreeSet::from([at(position)]),
+        }));
+        if position > 0 {
+            let input = at(position - 1);
+            central.process_update(Update::Provenance(Provenance {
+                deltas_id: format!("d{}", position),
+                query_name: "move".to_string(),
+                application: "crane".to_string(),
+                origin_id: format!("e{}", position),
+                origin_timestamp: at(position).timestamp,
+                match_hash: position as u64,
+                input_edges: vec![InputEdge {
+                    edge: Edge { from_timestamp: Some(input.timestamp), ..input.to_edge() },
+                    deltas_ids: vec![format!("d{}", position - 1)],
+                }],
+                routed_deltas_ids: vec![],
+            }));
+        }
+    }
+    let mut derivation = central.explain_edge("MyBarrel0", &format!("MyPlatform{}", n_moves), "At", n_moves as u64 + 1).remove(0);
+    let mut depth = 0;
+    while !derivation.inputs.is_empty() {
+        derivation = derivation.inputs.remove(0).derivations.remove(0);
+        depth += 1;
+    }
+    assert_eq!(depth, MAX_DERIVATION_DEPTH);
+    assert!(derivation.truncated);
+    assert_eq!(derivation.deltas_id, "d2");
+}
+
+#[test]
+fn test_changes_are_logged_in_the_order_they_are_stored() {
+    let central = Central::new(PathBuf::from(":memory:"));
+    let delta = |timestamp, delta_type| Delta {
+        src: Node::material_instance_node("MyBarrel0", "Barrel"),
+        trg: Node::object_instance_node("MyPlatform0", "Platform"),
+        edge_type: "At".to_string(),
+        timestamp,
+        delta_type,
+    };
+    let deltas = |deltas_id: &str, delta: Delta| Deltas {
+        deltas_id: deltas_id.to_string(),
+        origin_id: "myevent".to_string(),
+        origin_timestamp: delta.timestamp,
+        deltas: BTreeSet::from([delta]),
+    };
+    central.process_update(Update::Deltas(deltas("added", delta(1, DeltaType::Addition))));
+    central.process_update(Update::Deltas(deltas("removed", delta(2, DeltaType::Removal))));
+    //Duplicates are not changes
+    central.process_update(Update::Deltas(deltas("added", delta(1, DeltaType::Addition))));
+    central.process_update(Update::Retractions(Retractions {
+        retraction_id: "myretraction".to_string(),
+        timestamp: 2,
+        deltas_ids: vec!["removed".to_string()],
+    }));
+    let changes = central.get_changes_after(0, 10).unwrap();
+    let summary: Vec<(u64, ChangeType, &str)> = changes.iter().map(|c| (c.cursor, c.change_type, c.deltas_id.as_str())).collect();
+    assert_eq!(summary, vec![(1, ChangeType::Addition, "added"), (2, ChangeType::Removal, "removed"), (3, ChangeType::Retraction, "removed")]);
+    assert_eq!(changes[2].delta, delta(2, DeltaType::Removal));
+    assert_eq!(central.get_changes_after(1, 1).unwrap(), vec![changes[1].clone()]);
+
+    //Only the latest changes are kept
+    let central = Central::new(PathBuf::from(":memory:")).with_max_changes(2);
+    central.process_update(Update::Deltas(deltas("added", delta(1, DeltaType::Addition))));
+    central.process_update(Update::Deltas(deltas("removed", delta(2, DeltaType::Removal))));
+    central.process_update(Update::Deltas(deltas("readded", delta(3, DeltaType::Addition))));
+    let cursors: Vec<u64> = central.get_changes_after(0, 10).unwrap().iter().map(|c| c.cursor).collect();
+    assert_eq!(cursors, vec![2, 3]);
+}
@@ -17,19 +17,56 @@ use log::debug;
 
 use tokio::runtime::Runtime;
 
-pub use crate::central::Central;
+use mbei_grpc::metrics_server::spawn_metrics_server;
+use mbei_grpc::process_update_server::Queue;
+use mbei_grpc::tracer::{spawn_span_exporter, Tracer};
+
+pub use crate::central::{Central, Derivation, DerivationSource, DerivedInput, ManualAssertionRecord, DEFAULT_MAX_CHANGES};
+use crate::central::CentralChangeLog;
+use crate::options::CentralOptions;
 use crate::server::CentralServer;
 
 mod central;
+mod metrics;
+pub mod options;
 mod server;
 
-pub fn start_central(sqlite_path: PathBuf, grpc_port: u16) {
-    let central = Central::new(sqlite_path.clone());
-    let central_server = CentralServer::new(grpc_port, central);
+/// Runs central until it stops, or returns why it could not be started.
+pub fn start_central(sqlite_path: PathBuf, grpc_port: u16, options: CentralOptions) -> Result<(), String> {
+    //Each connection to an in-memory database opens a database of its own
+    if sqlite_path.as_os_str().is_empty() || sqlite_path.as_os_str() == ":memory:" {
+        return Err("Central needs a database file, as subscriptions read the changes through a second connection".to_string());
+    }
+    let queue = match &options.inbox_path {
+        Some(inbox_path) => Queue::with_inbox(options.queue_limits.clone(), inbox_path)
+            .map_err(|e| format!("Could not open inbox {:?}: {}", inbox_path, e))?,
+        None => Queue::with_limits(options.queue_limits.clone()),
+    };
+    let central = Central::new(sqlite_path.clone()).with_max_changes(options.max_changes.unwrap_or(DEFAULT_MAX_CHANGES));
+    let change_log = CentralChangeLog::new(sqlite_path.clone());
+    let mut central_server = CentralServer::new(grpc_port, central, change_log, queue, options.tls.clone(), options.assertion_sender_hosts.clone());
     let rt = Runtime::new().expect("Could not create runtime");
-    rt.block_on(central_server.run());
+    rt.block_on(async {
+        let metrics_handle = options.metrics_port.map(spawn_metrics_server);
+        let (span_sender, span_exporter_handle) = match options.trace_export {
+            Some(trace_export) => {
+                let (span_sender, handle) = spawn_span_exporter(trace_export);
+                (Some(span_sender), Some(handle))
+            }
+            None => (None, None),
+        };
+        //The tracer is dropped after running, so that the exporter finishes
+        central_server.run(&Tracer::new("central", span_sender)).await;
+        if let Some(span_exporter_handle) = span_exporter_handle {
+            span_exporter_handle.await.expect("Problem exporting spans");
+        }
+        if let Some(metrics_handle) = metrics_handle {
+            metrics_handle.abort();
+        }
+    });
     debug!("Closing database");
     central_server.close();
     debug!("Database closed");
+    Ok(())
 }
 
@@ -0,0 +1,60 @@
+/*Copyright 2022 Prediktor AS
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.*/
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    HistogramVec, IntCounterVec, IntGauge, IntGaugeVec,
+};
+
+pub(crate) static UPDATES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "mbei_central_updates_total",
+        "Updates taken from the queue, by type and outcome",
+        &["update_type", "outcome"]
+    )
+    .expect("Could not register metric")
+});
+
+pub(crate) static UPDATE_PROCESSING_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "mbei_central_update_processing_seconds",
+        "Time taken to store an update, by type",
+        &["update_type"]
+    )
+    .expect("Could not register metric")
+});
+
+pub(crate) static QUEUE_LENGTH: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "mbei_central_queue_length",
+        "Updates waiting in the queue, by type",
+        &["update_type"]
+    )
+    .expect("Could not register metric")
+});
+
+pub(crate) static STORED_DELTAS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!("mbei_central_stored_deltas", "Deltas rows in the database")
+        .expect("Could not register metric")
+});
+
+pub(crate) static FINALIZED_BEFORE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "mbei_central_finalized_before",
+        "Timestamp before which the stored deltas of a source are final, by source",
+        &["source"]
+    )
+    .expect("Could not register metric")
+});
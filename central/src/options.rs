@@ -0,0 +1,38 @@
+/*Copyright 2022 Prediktor AS
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.*/
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+use mbei_grpc::process_update_server::QueueLimits;
+use mbei_grpc::tls::TlsOptions;
+use mbei_grpc::tracer::TraceExport;
+
+#[derive(Clone, Debug, Default)]
+pub struct CentralOptions {
+    //Port of the HTTP endpoint serving Prometheus metrics, if any
+    pub metrics_port: Option<u16>,
+    //Where spans are exported, if anywhere
+    pub trace_export: Option<TraceExport>,
+    pub queue_limits: QueueLimits,
+    //Inbox log that keeps acknowledged updates across restarts, if any
+    pub inbox_path: Option<PathBuf>,
+    //Certificates for mutual TLS, plaintext if none
+    pub tls: Option<TlsOptions>,
+    //Hosts of the components, whose certificates manual assertions must come with when TLS is used,
+    //since they authenticate the operators. Without TLS, central can not tell who sent a manual assertion
+    pub assertion_sender_hosts: BTreeSet<String>,
+    //Changes kept for subscribers to resume from, DEFAULT_MAX_CHANGES if none
+    pub max_changes: Option<u64>,
+}
@@ -1,47 +1,134 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::{Arc};
 use std::time::Duration;
-use log::{debug, info};
+use log::{debug, info, warn};
 use tokio::sync::Mutex;
 use mbei_core::event::Update;
-use mbei_grpc::process_update_server::{await_server_handle_with_timeout, create_and_run_server, Queue};
+use mbei_core::trace::TraceContext;
+use mbei_grpc::health_server::{Health, HEARTBEAT_INTERVAL};
+use mbei_grpc::process_update_server::{await_server_handle_with_timeout, create_and_run_server_with_subscriptions, Queue, ServedQueue};
+use mbei_grpc::subscription_server::{ChangeNotifier, SubscriptionService};
+use mbei_grpc::tls::{PeerIdentityCheck, TlsOptions};
+use mbei_grpc::tracer::Tracer;
+use crate::central::CentralChangeLog;
 use crate::Central;
+use crate::metrics::{QUEUE_LENGTH, UPDATE_PROCESSING_SECONDS};
 
 pub(crate) struct CentralServer {
     grpc_port: u16,
     pub(crate) central: Central,
+    arc_queue_mutex: Arc<Mutex<Queue>>,
+    tls: Option<TlsOptions>,
+    assertion_sender_hosts: BTreeSet<String>,
+    change_log: Option<CentralChangeLog>,
 }
 
 impl CentralServer {
-    pub(crate) fn new(grpc_port:u16, central:Central) -> CentralServer {
-        CentralServer { grpc_port, central }
+    pub(crate) fn new(grpc_port:u16, central:Central, change_log: CentralChangeLog, queue: Queue, tls: Option<TlsOptions>, assertion_sender_hosts: BTreeSet<String>) -> CentralServer {
+        CentralServer { grpc_port, central, arc_queue_mutex: Arc::new(Mutex::new(queue)), tls, assertion_sender_hosts, change_log: Some(change_log) }
     }
 
-    pub(crate) async fn run(&self) {
+    pub(crate) async fn run(&mut self, tracer: &Tracer) {
         let (new_update_sender, mut new_update_receiver) = tokio::sync::mpsc::unbounded_channel();
-        let queue_mutex = Mutex::new(Queue::new());
-        let arc_queue_mutex = Arc::new(queue_mutex);
+        let arc_queue_mutex = self.arc_queue_mutex.clone();
         let (shutdown_server_sender, shutdown_server_receiver) = tokio::sync::oneshot::channel();
-        let server_handle = create_and_run_server(self.grpc_port, arc_queue_mutex.clone(), new_update_sender, shutdown_server_receiver).await;
+        let assertion_senders = match &self.tls {
+            Some(_) if self.assertion_sender_hosts.is_empty() => {
+                warn!("No hosts are allowed to send manual assertions, so all are rejected");
+                Some(PeerIdentityCheck::for_hosts(BTreeSet::new()))
+            }
+            Some(_) => Some(PeerIdentityCheck::for_hosts(self.assertion_sender_hosts.clone())),
+            None => {
+                warn!("Without TLS, manual assertions are accepted from any client, with the operator it names");
+                None
+            }
+        };
+        let served_queues = BTreeMap::from([(
+            "central".to_string(),
+            ServedQueue { queue: arc_queue_mutex.clone(), new_update_sender, outbox: None, operators: None, assertion_senders, replicator: None },
+        )]);
+        let health = Health::default();
+        let health_reporter = health.reporter("central");
+        let notifier = ChangeNotifier::default();
+        let change_log = self.change_log.take().expect("Central runs once");
+        let subscription_service = SubscriptionService::new(change_log, notifier.clone());
+        let server_handle = create_and_run_server_with_subscriptions(self.grpc_port, served_queues, shutdown_server_receiver, subscription_service, health, self.tls.as_ref()).await;
+        //Central sends nothing, so it is ready as soon as it serves
+        health_reporter.set_live(true);
+        health_reporter.set_ready(true);
 
+        let mut recorded_finality = BTreeMap::new();
         loop {
+            health_reporter.beat();
             let update_opt;
+            let finality;
             {
                 let mut queue = arc_queue_mutex.lock().await;
-                update_opt = queue.pop_earliest_update();
+                //Deltas still queued are not stored yet, so they hold back the finality of every source
+                let min_queued_timestamp = queue.get_min_queued_timestamp();
+                finality = queue
+                    .get_watermarks()
+                    .iter()
+                    .map(|(source, timestamp)| (source.clone(), min_queued_timestamp.map_or(*timestamp, |t| t.min(*timestamp))))
+                    .collect::<Vec<(String, u64)>>();
+                update_opt = queue.pop_earliest_queued_update();
+                for (update_type, length) in [
+                    ("retractions", queue.open_retractions.len()),
+                    ("deltas", queue.open_deltas.len()),
+                    ("operator", queue.open_operator_updates.len()),
+                    ("provenance", queue.open_provenance.len()),
+                ] {
+                    QUEUE_LENGTH.with_label_values(&[update_type]).set(length as i64);
+                }
             }
-            if let Some(update) = update_opt {
+            for (source, finalized_before) in finality {
+                if recorded_finality.get(&source) < Some(&finalized_before) {
+                    debug!("Deltas of {} before {} are final", &source, finalized_before);
+                    self.central.record_finality(&source, finalized_before);
+                    recorded_finality.insert(source, finalized_before);
+                }
+            }
+            if let Some((update, queue_entry)) = update_opt {
+                let trace = match &queue_entry {
+                    Some(queue_entry) => queue_entry.context.clone().unwrap_or_else(|| TraceContext::for_update(&update)),
+                    None => TraceContext::for_update(&update),
+                };
+                if let Some(queue_entry) = &queue_entry {
+                    tracer.finish_span(tracer.start_span_at("queue", &trace, queue_entry.enqueued_at));
+                }
                 match update {
                     Update::Stop => {
+                        health_reporter.set_live(false);
+                        //Open subscriptions would keep the server from shutting down
+                        notifier.close();
                         shutdown_server_sender.send(()).expect("Shutdown error");
                         info!("Received stop update, stopping.");
                         break;
                     }
                     nonstop_update => {
+                        let update_type = match &nonstop_update {
+                            Update::Deltas(_) => "deltas",
+                            Update::Retractions(_) => "retractions",
+                            Update::ManualAssertion(_) => "manual_assertion",
+                            Update::Provenance(_) => "provenance",
+                            _ => "other",
+                        };
+                        let timer = UPDATE_PROCESSING_SECONDS
+                            .with_label_values(&[update_type])
+                            .start_timer();
+                        let span = tracer.start_span("store", &trace).with_attribute("update_type", update_type);
                         self.central.process_update(nonstop_update);
+                        notifier.notify();
+                        tracer.finish_span(span);
+                        timer.observe_duration();
                     }
                 }
+                if let Some(queue_entry) = queue_entry {
+                    arc_queue_mutex.lock().await.mark_processed(&queue_entry);
+                }
             } else {
-                new_update_receiver.recv().await;
+                //Woken while idle to beat for liveness
+                let _ = tokio::time::timeout(HEARTBEAT_INTERVAL, new_update_receiver.recv()).await;
             }
         }
         debug!("Almost shut down, wait for server handle");
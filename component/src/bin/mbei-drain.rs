@@ -0,0 +1,69 @@
+/*Copyright 2022 Prediktor AS
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.*/
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use structopt::StructOpt;
+
+use mbei_component::config::ComponentConfig;
+use mbei_component::coordinator::drain_cluster;
+
+/// Drains and stops all components and central in dependency order, without discarding queued or in-flight updates.
+/// Prints the stages the cluster was stopped in as YAML once it has reached a consistent stop.
+#[derive(StructOpt)]
+pub struct Cli {
+    /// Config file of any of the components, whose peers have the urls of all queries and of central
+    #[structopt(short = "-c", long = "--config", parse(from_os_str))]
+    pub config_path: std::path::PathBuf,
+
+    #[structopt(long = "--poll-interval-ms", default_value = "500")]
+    pub poll_interval_ms: u64,
+
+    /// How long the components of one stage may take to drain before giving up, leaving later stages running
+    #[structopt(long = "--stage-timeout-s", default_value = "600")]
+    pub stage_timeout_s: u64,
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+    let cli: Cli = Cli::from_args();
+
+    let setup = match ComponentConfig::load(&cli.config_path, std::env::vars()).and_then(|config| config.validate()) {
+        Ok(setup) => setup,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let all_queries_by_name: BTreeMap<_, _> = setup.queries.into_iter().map(|q| (q.name.clone(), q)).collect();
+    let result = drain_cluster(
+        &all_queries_by_name,
+        &setup.query_url_map,
+        setup.use_central,
+        setup.options.tls.as_ref(),
+        Duration::from_millis(cli.poll_interval_ms),
+        Duration::from_secs(cli.stage_timeout_s),
+    )
+    .await;
+    let report = match result {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Could not drain the cluster: {}", e);
+            std::process::exit(1);
+        }
+    };
+    println!("{}", serde_yaml::to_string(&report).expect("Could not serialize as YAML"));
+}
@@ -0,0 +1,273 @@
+/*Copyright 2022 Prediktor AS
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.*/
+
+use serde::Serialize;
+use structopt::StructOpt;
+
+use mbei_core::event::Update;
+use mbei_core::graph::Edge;
+use mbei_grpc::health::health_check_response::ServingStatus;
+use mbei_grpc::health::health_client::HealthClient;
+use mbei_grpc::health::HealthCheckRequest;
+use mbei_grpc::inspection::inspection_client::InspectionClient;
+use mbei_grpc::inspection::{
+    GetMatchesRequest, GetQueueRequest, ListEdgesRequest, ListEventsRequest, ListQuarantinedUpdatesRequest,
+    ListRejectedUpdatesRequest,
+};
+use mbei_grpc::process_update::process_update_client::ProcessUpdateClient;
+use mbei_grpc::process_update::GetStatusRequest;
+use mbei_grpc::inspection_mapping::{from_proto_edge, from_proto_events, to_optional_timestamp};
+use mbei_grpc::process_update_mapping::update_from_request;
+use mbei_grpc::tls::{create_endpoint, TlsOptions};
+
+/// Prints the state of a running component as YAML.
+#[derive(StructOpt)]
+pub struct Cli {
+    #[structopt(short = "-u", long = "--url")]
+    pub url: String,
+
+    /// Query of the component to inspect, required when the process serves several components
+    #[structopt(short = "-q", long = "--query", default_value = "")]
+    pub query: String,
+
+    /// Certificates for components that require mutual TLS
+    #[structopt(long = "--tls-ca", parse(from_os_str), requires_all = &["tls-certificate", "tls-key"])]
+    pub tls_ca: Option<std::path::PathBuf>,
+
+    #[structopt(long = "--tls-certificate", parse(from_os_str), requires = "tls-ca")]
+    pub tls_certificate: Option<std::path::PathBuf>,
+
+    #[structopt(long = "--tls-key", parse(from_os_str), requires = "tls-ca")]
+    pub tls_key: Option<std::path::PathBuf>,
+
+    #[structopt(subcommand)]
+    pub command: Command,
+}
+
+#[derive(StructOpt)]
+pub enum Command {
+    /// Edges valid at a timestamp
+    Edges {
+        #[structopt(short = "-t", long = "--timestamp")]
+        timestamp: u64,
+    },
+    /// Stored events with timestamps in an interval
+    Events {
+        #[structopt(short = "-f", long = "--from", default_value = "0")]
+        from: u64,
+        #[structopt(short = "-t", long = "--to")]
+        to: Option<u64>,
+    },
+    /// Matches and output hashes recorded for an event, with the deltas ids of each match
+    Matches {
+        #[structopt(short = "-e", long = "--event-id")]
+        event_id: String,
+    },
+    /// Updates waiting in the queue, in processing order
+    Queue,
+    /// Updates rejected for arriving later than allowed, with the reason for each
+    Rejected,
+    /// Updates in quarantine, by the id to retry or discard them with
+    Quarantined,
+    /// Draining, queue and delivery state, and the finality point before which nothing changes any more
+    Status,
+    /// Status by the gRPC health protocol: liveness, readiness, a query name, or the empty name for the whole process
+    Health {
+        #[structopt(short = "-s", long = "--service", default_value = "")]
+        service: String,
+    },
+}
+
+#[derive(Serialize)]
+struct HealthRecord {
+    service: String,
+    status: String,
+}
+
+#[derive(Serialize)]
+struct StatusRecord {
+    draining: bool,
+    drained: bool,
+    n_accepted: u64,
+    queue_size: u32,
+    n_unacknowledged: u32,
+    finalized_before: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct RejectedRecord {
+    update: String,
+    reason: String,
+}
+
+#[derive(Serialize)]
+struct QuarantinedRecord {
+    quarantine_id: String,
+    update: String,
+    update_chain: Vec<String>,
+    pending_updates: Vec<String>,
+    loops: u32,
+}
+
+#[derive(Serialize)]
+struct MatchRecord {
+    match_hash: u64,
+    output_hash: Option<u64>,
+    deltas_ids_by_topic: Vec<(String, String)>,
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+    let cli: Cli = Cli::from_args();
+    let tls = match (cli.tls_ca, cli.tls_certificate, cli.tls_key) {
+        (Some(ca_certificate_path), Some(certificate_path), Some(key_path)) => Some(TlsOptions {
+            ca_certificate_path,
+            certificate_path,
+            key_path,
+            allowed_client_hosts: Default::default(),
+        }),
+        _ => None,
+    };
+    let endpoint = create_endpoint(&cli.url, tls.as_ref()).expect("Invalid url");
+    let mut client = InspectionClient::connect(endpoint.clone())
+        .await
+        .expect("Could not connect to component");
+    let yaml = match cli.command {
+        Command::Edges { timestamp } => {
+            let response = client
+                .list_edges(ListEdgesRequest { timestamp, query_name: cli.query })
+                .await
+                .expect("Request failed")
+                .into_inner();
+            let edges: Vec<Edge> = response
+                .edges
+                .into_iter()
+                .map(from_proto_edge)
+                .collect::<Result<_, _>>()
+                .expect("Edges are valid");
+            serde_yaml::to_string(&edges)
+        }
+        Command::Events { from, to } => {
+            let response = client
+                .list_events(ListEventsRequest {
+                    from_timestamp: from,
+                    to_timestamp: to_optional_timestamp(to),
+                    query_name: cli.query,
+                })
+                .await
+                .expect("Request failed")
+                .into_inner();
+            serde_yaml::to_string(&from_proto_events(response.events))
+        }
+        Command::Matches { event_id } => {
+            let response = client
+                .get_matches(GetMatchesRequest { event_id, query_name: cli.query })
+                .await
+                .expect("Request failed")
+                .into_inner();
+            let matches: Vec<MatchRecord> = response
+                .matches
+                .into_iter()
+                .map(|m| MatchRecord {
+                    match_hash: m.match_hash,
+                    output_hash: m.output_hash.map(|h| h.hash),
+                    deltas_ids_by_topic: m
+                        .bindings
+                        .into_iter()
+                        .map(|b| (b.topic_name, b.deltas_id))
+                        .collect(),
+                })
+                .collect();
+            serde_yaml::to_string(&matches)
+        }
+        Command::Queue => {
+            let response = client
+                .get_queue(GetQueueRequest { query_name: cli.query })
+                .await
+                .expect("Request failed")
+                .into_inner();
+            let updates: Vec<Update> = response
+                .updates
+                .iter()
+                .map(update_from_request)
+                .collect::<Result<_, _>>()
+                .expect("Queued updates are valid");
+            serde_yaml::to_string(&updates)
+        }
+        Command::Rejected => {
+            let response = client
+                .list_rejected_updates(ListRejectedUpdatesRequest { query_name: cli.query })
+                .await
+                .expect("Request failed")
+                .into_inner();
+            let rejected: Vec<RejectedRecord> = response
+                .rejected_updates
+                .into_iter()
+                .map(|r| RejectedRecord { update: r.update, reason: r.reason })
+                .collect();
+            serde_yaml::to_string(&rejected)
+        }
+        Command::Quarantined => {
+            let response = client
+                .list_quarantined_updates(ListQuarantinedUpdatesRequest { query_name: cli.query })
+                .await
+                .expect("Request failed")
+                .into_inner();
+            let quarantined: Vec<QuarantinedRecord> = response
+                .quarantined_updates
+                .into_iter()
+                .map(|q| QuarantinedRecord {
+                    quarantine_id: q.quarantine_id,
+                    update: q.update,
+                    update_chain: q.update_chain,
+                    pending_updates: q.pending_updates,
+                    loops: q.loops,
+                })
+                .collect();
+            serde_yaml::to_string(&quarantined)
+        }
+        Command::Status => {
+            let mut process_update_client = ProcessUpdateClient::connect(endpoint)
+                .await
+                .expect("Could not connect to component");
+            let response = process_update_client
+                .get_status(GetStatusRequest { target_query: cli.query })
+                .await
+                .expect("Request failed")
+                .into_inner();
+            serde_yaml::to_string(&StatusRecord {
+                draining: response.draining,
+                drained: response.drained,
+                n_accepted: response.n_accepted,
+                queue_size: response.queue_size,
+                n_unacknowledged: response.n_unacknowledged,
+                finalized_before: Some(response.finalized_before).filter(|t| *t > 0),
+            })
+        }
+        Command::Health { service } => {
+            let mut health_client = HealthClient::connect(endpoint)
+                .await
+                .expect("Could not connect to component");
+            let response = health_client
+                .check(HealthCheckRequest { service: service.clone() })
+                .await
+                .expect("Request failed")
+                .into_inner();
+            let status = ServingStatus::from_i32(response.status).unwrap_or(ServingStatus::Unknown);
+            serde_yaml::to_string(&HealthRecord { service, status: format!("{:?}", status) })
+        }
+    };
+    println!("{}", yaml.expect("Could not serialize as YAML"));
+}
@@ -12,36 +12,204 @@ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 See the License for the specific language governing permissions and
 limitations under the License.*/
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::fmt::{Display, Formatter};
 use std::str::FromStr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use backoff::{ExponentialBackoffBuilder};
 
-use log::{debug};
+use log::{debug, warn};
+use tonic::Status;
 use tonic::transport::Endpoint;
-use uuid::Uuid;
 
-use mbei_core::event::{Deltas, Event};
+use mbei_core::event::{deterministic_deltas_id, Deltas, Event};
+use mbei_core::graph::Delta;
 use mbei_core::query::{GroupedQueryMatch, Query};
 
 use mbei_grpc::application_component::call_application_client::CallApplicationClient;
+use mbei_grpc::application_component::{ApplicationRequest, ApplicationResponse};
 use mbei_grpc::application_component_mapping::{create_application_request, delta_vec_from_response};
+use mbei_grpc::health_server::HealthReporter;
+use mbei_grpc::tls::{create_endpoint, TlsOptions};
+
+use crate::interpreter::InterpreterRegistry;
+use crate::metrics::{APPLICATION_CALL_FAILURES, APPLICATION_CALL_SECONDS};
+
+/// What to do with an event when the application stays unavailable after all retries.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UnavailablePolicy {
+    //Keep the event and wait until the application is available again
+    Hold,
+    //Process the event as if the application had no output, and keep a record of the skipped call
+    Skip,
+    //Stop the component
+    Fail,
+}
+
+impl FromStr for UnavailablePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hold" => Ok(UnavailablePolicy::Hold),
+            "skip" => Ok(UnavailablePolicy::Skip),
+            "fail" => Ok(UnavailablePolicy::Fail),
+            other => Err(format!("Unknown unavailable policy {}, expected hold, skip or fail", other)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CallerOptions {
+    pub call_timeout: Duration,
+    pub max_retries: u32,
+    pub initial_retry_backoff: Duration,
+    pub max_retry_backoff: Duration,
+    //Number of consecutive failures before the circuit for an application is opened
+    pub circuit_breaker_threshold: u32,
+    //How long the circuit stays open before a trial call is let through
+    pub circuit_breaker_reset: Duration,
+    pub unavailable_policy: UnavailablePolicy,
+}
+
+impl Default for CallerOptions {
+    fn default() -> Self {
+        CallerOptions {
+            call_timeout: Duration::from_secs(10),
+            max_retries: 3,
+            initial_retry_backoff: Duration::from_millis(50),
+            max_retry_backoff: Duration::from_secs(2),
+            circuit_breaker_threshold: 5,
+            circuit_breaker_reset: Duration::from_secs(5),
+            unavailable_policy: UnavailablePolicy::Hold,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct CallerMetrics {
+    pub n_calls: u64,
+    pub n_timeouts: u64,
+    pub n_errors: u64,
+    pub n_retries: u64,
+    pub n_rejected_by_circuit_breaker: u64,
+    pub n_held: u64,
+    pub n_skipped: u64,
+    pub n_native_calls: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct SkippedCall {
+    pub query_name: String,
+    pub application: String,
+    pub event_id: String,
+    pub reason: String,
+}
+
+#[derive(Debug)]
+enum CallError {
+    Timeout,
+    Failed(Status),
+    CircuitOpen,
+}
+
+impl Display for CallError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CallError::Timeout => write!(f, "call timed out"),
+            CallError::Failed(status) => write!(f, "call failed with status {}: {}", status.code(), status.message()),
+            CallError::CircuitOpen => write!(f, "circuit breaker is open"),
+        }
+    }
+}
+
+struct CircuitBreaker {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+    //Set while the trial call of a half open circuit is in flight
+    probing: bool,
+}
+
+impl CircuitBreaker {
+    fn new() -> CircuitBreaker {
+        CircuitBreaker {
+            consecutive_failures: 0,
+            opened_at: None,
+            probing: false,
+        }
+    }
+
+    //When the reset duration has passed, the circuit is half open, and we let a single trial call through until its outcome is recorded
+    fn allows_call(&mut self, reset: Duration) -> bool {
+        match &self.opened_at {
+            None => true,
+            Some(opened_at) if opened_at.elapsed() >= reset && !self.probing => {
+                self.probing = true;
+                true
+            }
+            Some(_) => false,
+        }
+    }
+
+    fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.opened_at = None;
+        self.probing = false;
+    }
+
+    fn record_failure(&mut self, threshold: u32) {
+        self.consecutive_failures += 1;
+        //A failed trial call opens the circuit again for another reset duration
+        if self.consecutive_failures >= threshold || self.probing {
+            self.opened_at = Some(Instant::now());
+        }
+        self.probing = false;
+    }
+
+    fn is_open(&self) -> bool {
+        self.opened_at.is_some()
+    }
+}
+
+//The most recent skipped calls are kept for inspection, and the count of all of them is in the metrics
+const MAX_SKIPPED_CALLS: usize = 1000;
 
 pub struct Caller {
     endpoint: Endpoint,
     client: Option<CallApplicationClient<tonic::transport::Channel>>,
+    options: CallerOptions,
+    circuit_breakers: BTreeMap<String, CircuitBreaker>,
+    metrics: CallerMetrics,
+    skipped_calls: VecDeque<SkippedCall>,
+    interpreters: InterpreterRegistry,
+    //Applications are reported unreachable while their circuit is open
+    health: HealthReporter,
+    //Set when the fail policy gave up on the application, after which the component stops
+    failure: Option<String>,
 }
 
 impl Caller {
-    pub(crate) fn new(grpc_url: String) -> Caller {
-        let endpoint = Endpoint::from_str(&grpc_url).expect("Invalid url");
-        Caller {
+    pub(crate) fn new(grpc_url: String, options: CallerOptions, interpreters: InterpreterRegistry, tls: Option<&TlsOptions>, health: HealthReporter) -> Result<Caller, String> {
+        let endpoint = create_endpoint(&grpc_url, tls)?;
+        Ok(Caller {
             endpoint,
             client: None,
-        }
+            options,
+            circuit_breakers: BTreeMap::new(),
+            metrics: Default::default(),
+            skipped_calls: VecDeque::new(),
+            interpreters,
+            health,
+            failure: None,
+        })
     }
 
-    pub(crate) async fn start(&mut self, max_elapsed_time: Option<Duration>) {
+    pub(crate) async fn start(&mut self, application: &str, max_elapsed_time: Option<Duration>) -> Result<(), String> {
+        if self.interpreters.contains(application) {
+            debug!("Application {} is interpreted natively, not connecting to grpc service", application);
+            return Ok(());
+        }
         debug!("Starting caller");
         let op = || async {
             let client = CallApplicationClient::connect(self.endpoint.clone()).await?;
@@ -52,20 +220,53 @@ impl Caller {
             .with_max_elapsed_time(max_elapsed_time)
             .with_max_interval(Duration::from_secs(10))
             .build();
-        self.client = Some(
-            backoff::future::retry(backoff, op)
-                .await
-                .expect("Could not connect to backend grpc service in maximum allotted time"),
-        );
+        let client = backoff::future::retry(backoff, op)
+            .await
+            .map_err(|e: tonic::transport::Error| format!("Could not connect to application {} in maximum allotted time: {}", application, e))?;
+        self.client = Some(client);
         debug!("Caller started");
+        Ok(())
+    }
+
+    pub fn get_metrics(&self) -> &CallerMetrics {
+        &self.metrics
+    }
+
+    pub fn get_skipped_calls(&self) -> &VecDeque<SkippedCall> {
+        &self.skipped_calls
+    }
+
+    pub fn get_failure(&self) -> Option<&String> {
+        self.failure.as_ref()
     }
 
     pub(crate) async fn call_function(
         &mut self,
         query: &Query,
         grouped_match: &GroupedQueryMatch,
+        match_hash: u64,
+        generation: u32,
         event: &Event,
     ) -> Option<Deltas> {
+        if self.failure.is_some() {
+            //The component stops after the update, so we do not call the application again
+            return None;
+        }
+        if let Some(interpreter) = self.interpreters.get(&query.application) {
+            self.metrics.n_native_calls += 1;
+            let timer = APPLICATION_CALL_SECONDS
+                .with_label_values(&[&query.name, &query.application])
+                .start_timer();
+            let result = interpreter.interpret(query, grouped_match, event);
+            timer.observe_duration();
+            return match result {
+                Ok(delta_vec) => create_deltas(delta_vec, query, match_hash, generation, event),
+                Err(reason) => {
+                    self.skip_invalid_output(query, event, "interpreter", reason);
+                    None
+                }
+            };
+        }
         assert!(self.client.is_some());
         let request = create_application_request(
             query.name.clone(),
@@ -74,17 +275,363 @@ impl Caller {
             &query.graph,
             event,
         );
-        let response = self.client.as_mut().unwrap().send(request).await.expect("Error sending").into_inner();
-        let delta_vec = delta_vec_from_response(response);
-        if !delta_vec.is_empty() {
-            let deltas = mbei_core::event::Deltas{
-                deltas_id: Uuid::new_v4().to_hyphenated().to_string(),
-                origin_id: event.event_id.clone(),
-                origin_timestamp: event.timestamp,
-                deltas: BTreeSet::from_iter(delta_vec.into_iter()),
-            };
-            return Some(deltas);
+        let response;
+        loop {
+            match self.call_with_retries(&query.name, &query.application, &request).await {
+                Ok(r) => {
+                    response = r;
+                    break;
+                }
+                Err(call_error) => match self.options.unavailable_policy {
+                    UnavailablePolicy::Hold => {
+                        warn!(
+                            "{} application {} unavailable ({}), holding event {}",
+                            &query.name, &query.application, call_error, &event.event_id
+                        );
+                        self.metrics.n_held += 1;
+                        tokio::time::sleep(self.options.circuit_breaker_reset).await;
+                    }
+                    UnavailablePolicy::Skip => {
+                        warn!(
+                            "{} application {} unavailable ({}), skipping event {}",
+                            &query.name, &query.application, call_error, &event.event_id
+                        );
+                        self.metrics.n_skipped += 1;
+                        self.record_skipped(SkippedCall {
+                            query_name: query.name.clone(),
+                            application: query.application.clone(),
+                            event_id: event.event_id.clone(),
+                            reason: call_error.to_string(),
+                        });
+                        return None;
+                    }
+                    UnavailablePolicy::Fail => {
+                        self.failure = Some(format!(
+                            "{} application {} unavailable when processing event {}: {}",
+                            &query.name, &query.application, &event.event_id, call_error
+                        ));
+                        return None;
+                    }
+                },
+            }
+        }
+        match delta_vec_from_response(response) {
+            Ok(delta_vec) => create_deltas(delta_vec, query, match_hash, generation, event),
+            Err(reason) => {
+                self.skip_invalid_output(query, event, "invalid_response", reason);
+                None
+            }
+        }
+    }
+
+    //Calling again gives the same output, so the event is skipped regardless of policy
+    fn skip_invalid_output(&mut self, query: &Query, event: &Event, failure: &str, reason: String) {
+        warn!(
+            "{} application {} failed ({}), skipping event {}",
+            &query.name, &query.application, &reason, &event.event_id
+        );
+        self.metrics.n_errors += 1;
+        self.metrics.n_skipped += 1;
+        APPLICATION_CALL_FAILURES
+            .with_label_values(&[&query.name, &query.application, failure])
+            .inc();
+        self.record_skipped(SkippedCall {
+            query_name: query.name.clone(),
+            application: query.application.clone(),
+            event_id: event.event_id.clone(),
+            reason,
+        });
+    }
+
+    fn record_skipped(&mut self, skipped_call: SkippedCall) {
+        if self.skipped_calls.len() == MAX_SKIPPED_CALLS {
+            self.skipped_calls.pop_front();
+        }
+        self.skipped_calls.push_back(skipped_call);
+    }
+
+    async fn call_with_retries(
+        &mut self,
+        query_name: &str,
+        application: &str,
+        request: &ApplicationRequest,
+    ) -> Result<ApplicationResponse, CallError> {
+        let mut last_error = CallError::CircuitOpen;
+        let mut backoff = self.options.initial_retry_backoff;
+        for attempt in 0..=self.options.max_retries {
+            if attempt > 0 {
+                tokio::time::sleep(backoff).await;
+                backoff = std::cmp::min(backoff * 2, self.options.max_retry_backoff);
+                self.metrics.n_retries += 1;
+            }
+            let circuit_breaker = self
+                .circuit_breakers
+                .entry(application.to_string())
+                .or_insert(CircuitBreaker::new());
+            if !circuit_breaker.allows_call(self.options.circuit_breaker_reset) {
+                self.metrics.n_rejected_by_circuit_breaker += 1;
+                APPLICATION_CALL_FAILURES
+                    .with_label_values(&[query_name, application, "circuit_open"])
+                    .inc();
+                return Err(CallError::CircuitOpen);
+            }
+            let mut client = self.client.as_ref().unwrap().clone();
+            self.metrics.n_calls += 1;
+            let timer = APPLICATION_CALL_SECONDS
+                .with_label_values(&[query_name, application])
+                .start_timer();
+            let result = tokio::time::timeout(self.options.call_timeout, client.send(request.clone())).await;
+            timer.observe_duration();
+            let circuit_breaker = self.circuit_breakers.get_mut(application).unwrap();
+            match result {
+                Ok(Ok(response)) => {
+                    circuit_breaker.record_success();
+                    self.health.set_peer_reachable(&application_peer_name(application), true);
+                    return Ok(response.into_inner());
+                }
+                Ok(Err(status)) => {
+                    debug!("Call to application {} failed: {:?}", application, &status);
+                    circuit_breaker.record_failure(self.options.circuit_breaker_threshold);
+                    self.metrics.n_errors += 1;
+                    APPLICATION_CALL_FAILURES
+                        .with_label_values(&[query_name, application, "error"])
+                        .inc();
+                    last_error = CallError::Failed(status);
+                }
+                Err(_) => {
+                    debug!("Call to application {} timed out", application);
+                    circuit_breaker.record_failure(self.options.circuit_breaker_threshold);
+                    self.metrics.n_timeouts += 1;
+                    APPLICATION_CALL_FAILURES
+                        .with_label_values(&[query_name, application, "timeout"])
+                        .inc();
+                    last_error = CallError::Timeout;
+                }
+            }
+            if circuit_breaker.is_open() {
+                self.health.set_peer_reachable(&application_peer_name(application), false);
+            }
         }
-        None
+        Err(last_error)
+    }
+}
+
+fn application_peer_name(application: &str) -> String {
+    format!("application {}", application)
+}
+
+fn create_deltas(
+    delta_vec: Vec<Delta>,
+    query: &Query,
+    match_hash: u64,
+    generation: u32,
+    event: &Event,
+) -> Option<Deltas> {
+    if !delta_vec.is_empty() {
+        let deltas = BTreeSet::from_iter(delta_vec);
+        let deltas = mbei_core::event::Deltas{
+            deltas_id: deterministic_deltas_id(
+                &event.event_id,
+                match_hash,
+                generation,
+                &query.name,
+                &deltas,
+                bincode::config::standard(),
+            ),
+            origin_id: event.event_id.clone(),
+            origin_timestamp: event.timestamp,
+            deltas,
+        };
+        return Some(deltas);
     }
+    None
+}
+
+#[test]
+fn test_circuit_breaker_opens_after_threshold_and_resets_on_success() {
+    let mut circuit_breaker = CircuitBreaker::new();
+    let reset = Duration::from_secs(60);
+    circuit_breaker.record_failure(2);
+    assert!(circuit_breaker.allows_call(reset));
+    circuit_breaker.record_failure(2);
+    assert!(!circuit_breaker.allows_call(reset));
+    assert!(circuit_breaker.allows_call(Duration::from_secs(0)));
+    circuit_breaker.record_success();
+    assert!(circuit_breaker.allows_call(reset));
+}
+
+#[test]
+fn test_circuit_breaker_lets_a_single_trial_call_through_when_half_open() {
+    let mut circuit_breaker = CircuitBreaker::new();
+    circuit_breaker.record_failure(1);
+    assert!(circuit_breaker.allows_call(Duration::from_secs(0)));
+    assert!(!circuit_breaker.allows_call(Duration::from_secs(0)));
+    //The failed trial call opens the circuit again
+    circuit_breaker.record_failure(10);
+    assert!(!circuit_breaker.allows_call(Duration::from_secs(60)));
+    assert!(circuit_breaker.allows_call(Duration::from_secs(0)));
+    circuit_breaker.record_success();
+    assert!(circuit_breaker.allows_call(Duration::from_secs(60)));
+    assert!(circuit_breaker.allows_call(Duration::from_secs(60)));
+}
+
+#[cfg(test)]
+enum ScriptedResponse {
+    Respond,
+    Fail,
+    Delay(Duration),
+}
+
+//Answers calls as scripted, and fails once the script is used up
+#[cfg(test)]
+struct ScriptedApplication {
+    script: std::sync::Mutex<std::collections::VecDeque<ScriptedResponse>>,
+}
+
+#[cfg(test)]
+#[tonic::async_trait]
+impl mbei_grpc::application_component::call_application_server::CallApplication for ScriptedApplication {
+    async fn send(&self, _request: tonic::Request<ApplicationRequest>) -> Result<tonic::Response<ApplicationResponse>, Status> {
+        let scripted = self.script.lock().unwrap().pop_front();
+        match scripted {
+            Some(ScriptedResponse::Respond) => Ok(tonic::Response::new(ApplicationResponse { deltas: vec![] })),
+            Some(ScriptedResponse::Delay(delay)) => {
+                tokio::time::sleep(delay).await;
+                Ok(tonic::Response::new(ApplicationResponse { deltas: vec![] }))
+            }
+            Some(ScriptedResponse::Fail) | None => Err(Status::unavailable("Scripted failure")),
+        }
+    }
+}
+
+//Starts a caller against an application answering as scripted, and calls it for an event
+#[cfg(test)]
+fn call_scripted_application(port: u16, options: CallerOptions, script: Vec<ScriptedResponse>, n_calls: usize) -> (Caller, Duration) {
+    use mbei_grpc::application_component::call_application_server::CallApplicationServer;
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let application = ScriptedApplication {
+            script: std::sync::Mutex::new(script.into_iter().collect()),
+        };
+        let address = format!("[::1]:{}", port).parse().unwrap();
+        tokio::spawn(tonic::transport::Server::builder().add_service(CallApplicationServer::new(application)).serve(address));
+        let mut caller = Caller::new(
+            format!("http://[::1]:{}", port),
+            options,
+            InterpreterRegistry::new(),
+            None,
+            HealthReporter::detached("q1"),
+        )
+        .unwrap();
+        caller.start("app", Some(Duration::from_secs(10))).await.unwrap();
+        let query = Query {
+            name: "q1".to_string(),
+            application: "app".to_string(),
+            graph: mbei_core::graph::Graph::from_edges(vec![]),
+            optional_edges: BTreeSet::new(),
+            group: BTreeSet::new(),
+            output_edges: BTreeSet::new(),
+            input_nodes: BTreeSet::new(),
+        };
+        let grouped_match = GroupedQueryMatch { grouped_matches: vec![] };
+        let event = Event {
+            event_id: "e1".to_string(),
+            timestamp: 1,
+            node_id: "n1".to_string(),
+            payload: vec![],
+        };
+        let now = Instant::now();
+        for _ in 0..n_calls {
+            assert!(caller.call_function(&query, &grouped_match, 0, 0, &event).await.is_none());
+        }
+        (caller, now.elapsed())
+    })
+}
+
+#[test]
+fn test_caller_retries_timed_out_and_failed_calls_with_backoff() {
+    let options = CallerOptions {
+        call_timeout: Duration::from_millis(100),
+        max_retries: 3,
+        initial_retry_backoff: Duration::from_millis(50),
+        max_retry_backoff: Duration::from_millis(80),
+        unavailable_policy: UnavailablePolicy::Skip,
+        ..Default::default()
+    };
+    let script = vec![ScriptedResponse::Delay(Duration::from_millis(500)), ScriptedResponse::Fail, ScriptedResponse::Respond];
+    let (caller, elapsed) = call_scripted_application(50961, options, script, 1);
+    let metrics = caller.get_metrics();
+    assert_eq!((metrics.n_calls, metrics.n_timeouts, metrics.n_errors, metrics.n_retries), (3, 1, 1, 2));
+    assert_eq!(metrics.n_skipped, 0);
+    //The deadline of the first call, then a backoff that doubles up to its maximum
+    assert!(elapsed >= Duration::from_millis(100 + 50 + 80));
+}
+
+#[test]
+fn test_caller_holds_events_until_the_application_is_available() {
+    let options = CallerOptions {
+        max_retries: 1,
+        initial_retry_backoff: Duration::from_millis(10),
+        circuit_breaker_reset: Duration::from_millis(50),
+        unavailable_policy: UnavailablePolicy::Hold,
+        ..Default::default()
+    };
+    let script = vec![ScriptedResponse::Fail, ScriptedResponse::Fail, ScriptedResponse::Respond];
+    let (caller, _) = call_scripted_application(50962, options, script, 1);
+    let metrics = caller.get_metrics();
+    assert_eq!((metrics.n_calls, metrics.n_held, metrics.n_skipped), (3, 1, 0));
+    assert!(caller.get_failure().is_none());
+}
+
+#[test]
+fn test_caller_skips_events_when_the_application_is_unavailable() {
+    let options = CallerOptions {
+        max_retries: 1,
+        initial_retry_backoff: Duration::from_millis(10),
+        unavailable_policy: UnavailablePolicy::Skip,
+        ..Default::default()
+    };
+    let (caller, _) = call_scripted_application(50963, options, vec![], 2);
+    let metrics = caller.get_metrics();
+    assert_eq!((metrics.n_calls, metrics.n_skipped), (4, 2));
+    assert_eq!(caller.get_skipped_calls().len(), 2);
+    assert_eq!(caller.get_skipped_calls()[0].event_id, "e1");
+    assert!(caller.get_failure().is_none());
+}
+
+#[test]
+fn test_caller_fails_without_calling_again_when_the_application_is_unavailable() {
+    let options = CallerOptions {
+        max_retries: 1,
+        initial_retry_backoff: Duration::from_millis(10),
+        unavailable_policy: UnavailablePolicy::Fail,
+        ..Default::default()
+    };
+    let (caller, _) = call_scripted_application(50964, options, vec![], 2);
+    assert_eq!(caller.get_metrics().n_calls, 2);
+    assert!(caller.get_failure().unwrap().contains("processing event e1"));
+    assert!(caller.get_skipped_calls().is_empty());
+}
+
+#[test]
+fn test_skipped_calls_are_capped() {
+    let mut caller = Caller::new("http://[::1]:50965".to_string(), Default::default(), InterpreterRegistry::new(), None, HealthReporter::detached("q1")).unwrap();
+    for i in 0..MAX_SKIPPED_CALLS + 10 {
+        caller.record_skipped(SkippedCall {
+            query_name: "q1".to_string(),
+            application: "app".to_string(),
+            event_id: format!("e{}", i),
+            reason: "unavailable".to_string(),
+        });
+    }
+    assert_eq!(caller.get_skipped_calls().len(), MAX_SKIPPED_CALLS);
+    //The oldest are dropped first
+    assert_eq!(caller.get_skipped_calls().front().unwrap().event_id, "e10");
+}
+
+#[test]
+fn test_caller_start_fails_when_the_application_is_unreachable() {
+    let mut caller = Caller::new("http://[::1]:50966".to_string(), Default::default(), InterpreterRegistry::new(), None, HealthReporter::detached("q1")).unwrap();
+    let result = tokio::runtime::Runtime::new().unwrap().block_on(caller.start("app", Some(Duration::from_millis(100))));
+    assert!(result.unwrap_err().starts_with("Could not connect to application app"));
 }
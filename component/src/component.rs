@@ -13,73 +13,444 @@ See the License for the specific language governing permissions and
 limitations under the License.*/
 
 use bincode::config::{standard, Configuration};
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
-use log::{debug, info};
+use log::{debug, info, warn};
 use tokio::task::JoinHandle;
 use tonic::{Response, Status};
+use uuid::Uuid;
 
-use mbei_core::event::{Deltas, Event, Retractions, Update};
+use mbei_core::event::{
+    deterministic_assertion_deltas_id, AdminCommand, Deltas, Event, EventCorrection, InputEdge, ManualAssertion, Provenance,
+    Retractions, Update,
+};
 use mbei_core::graph::{edges_from_deltas, Delta, Edge, Graph};
 use mbei_core::query::{GroupedQueryMatch, Query};
+use mbei_core::trace::TraceContext;
+use mbei_grpc::health_server::HealthReporter;
+use mbei_grpc::outbox::Outbox;
 use mbei_grpc::process_update::ProcessUpdateResponse;
+use mbei_grpc::tracer::Tracer;
 
-use crate::caller::Caller;
+use crate::caller::{Caller, CallerMetrics};
 use crate::intervals::{
     find_intervals_to_reprocess, find_non_redundant_intervals, ReprocessInterval,
 };
+use crate::lateness::{LateArrivals, LatePolicy, RejectedUpdate};
+use crate::memory::{HardLimitPolicy, MemoryLimits};
+use crate::metrics::{LATE_ARRIVALS, MEMORY_LIMIT_ACTIONS};
+use crate::options::ComponentOptions;
+use crate::quarantine::{describe_update, Quarantine, QuarantinedUpdate};
 use crate::router::Router;
+use crate::spill::EventSpill;
 use crate::store::{DeltaAndDeltasId, Store};
 
+type ProcessingResult = (Vec<JoinHandle<Result<Response<ProcessUpdateResponse>, Status>>>, i32, i32, i32, i32, usize);
+
 pub struct Component {
     store: Store,
     caller: Caller,
     router: Router,
     query: Query,
     config: Configuration,
+    quarantine: Quarantine,
+    loop_budget: u32,
+    tracer: Tracer,
+    health: HealthReporter,
+    finality_heartbeat: Duration,
+    //Sources outside the dataflow that finality waits for
+    watermark_sources: BTreeSet<String>,
+    //When the finality point was last sent to the receivers
+    finality_sent_at: Option<Instant>,
+    late_arrivals: LateArrivals,
+    memory_limits: MemoryLimits,
+    //Whether the store was above its hard limit when the limits were last enforced
+    refusing_input: bool,
+    //Inbox sequence numbers of quarantined updates which were retried or discarded since they were last taken
+    released_inbox_seqs: Vec<u64>,
 }
 
 impl Component {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         query_name: String,
         all_queries_by_name: BTreeMap<String, Query>,
         application_grpc_url: String,
         query_url_map: BTreeMap<String, String>,
         use_central: bool,
-    ) -> Component {
-        Component {
-            store: Store::new(),
-            caller: Caller::new(application_grpc_url),
+        options: &ComponentOptions,
+        tracer: Tracer,
+        health: HealthReporter,
+    ) -> Result<Component, String> {
+        Ok(Component {
+            store: create_store(&query_name, options)?,
+            caller: Caller::new(application_grpc_url, options.caller.clone(), options.interpreters.clone(), options.tls.as_ref(), health.clone())?,
             query: all_queries_by_name.get(&query_name).unwrap().clone(),
-            router: Router::new(query_name, all_queries_by_name, query_url_map, use_central),
+            router: Router::new(query_name.clone(), all_queries_by_name, query_url_map, use_central, options.get_log_path(&query_name, "outbox"), options.tls.clone(), health.clone(), options.standby.is_some())?,
             config: standard(),
+            quarantine: Quarantine::new(),
+            loop_budget: options.loop_budget,
+            tracer,
+            health,
+            finality_heartbeat: options.finality_heartbeat,
+            watermark_sources: options.watermark_sources.clone(),
+            finality_sent_at: None,
+            late_arrivals: LateArrivals::new(options.get_lateness(&query_name)),
+            memory_limits: options.memory_limits.clone(),
+            refusing_input: false,
+            released_inbox_seqs: vec![],
+        })
+    }
+
+    //Live from the start, since connecting may take long, and ready once connected
+    pub(crate) async fn start(&mut self, max_elapsed_time: Option<Duration>) -> Result<Vec<JoinHandle<Result<Response<ProcessUpdateResponse>, Status>>>, String> {
+        self.health.set_live(true);
+        self.caller.start(&self.query.application, max_elapsed_time).await?;
+        let handles = self.router.start(max_elapsed_time).await?;
+        self.health.set_ready(true);
+        Ok(handles)
+    }
+
+    pub(crate) fn stop(&mut self) {
+        self.health.set_live(false);
+    }
+
+    /// Called every time round the processing loop, which is taken to be stuck once it stops beating
+    pub(crate) fn beat(&self) {
+        self.health.beat();
+    }
+
+    pub(crate) fn get_store(&self) -> &Store {
+        &self.store
+    }
+
+    pub(crate) fn get_rejected_updates(&self) -> &VecDeque<RejectedUpdate> {
+        self.late_arrivals.get_rejected_updates()
+    }
+
+    pub(crate) fn get_quarantined_updates(&self) -> Vec<&QuarantinedUpdate> {
+        self.quarantine.get_all()
+    }
+
+    pub(crate) fn get_n_quarantined(&self) -> usize {
+        self.quarantine.len()
+    }
+
+    /// Makes the updates routed so far durable, before the update that caused them is marked as processed
+    pub(crate) async fn sync_outbox(&self) {
+        self.router.sync_outbox().await;
+    }
+
+    /// Whether the update under the inbox sequence number was quarantined or parked, so that it stays in the inbox
+    pub(crate) fn holds_inbox_seq(&self, inbox_seq: u64) -> bool {
+        self.quarantine.holds_inbox_seq(inbox_seq)
+    }
+
+    /// The inbox sequence numbers of quarantined updates which were retried or discarded, which are processed now
+    pub(crate) fn take_released_inbox_seqs(&mut self) -> Vec<u64> {
+        std::mem::take(&mut self.released_inbox_seqs)
+    }
+
+    pub(crate) fn get_caller_metrics(&self) -> &CallerMetrics {
+        self.caller.get_metrics()
+    }
+
+    /// Why the application could not be called under the fail policy, if it could not.
+    pub(crate) fn get_application_failure(&self) -> Option<&String> {
+        self.caller.get_failure()
+    }
+
+    pub(crate) fn get_tracer(&self) -> &Tracer {
+        &self.tracer
+    }
+
+    pub(crate) fn get_outbox(&self) -> Arc<Mutex<Outbox>> {
+        self.router.get_outbox()
+    }
+
+    pub(crate) fn get_finalized_before(&self) -> Option<u64> {
+        self.store.get_finalized_before()
+    }
+
+    pub(crate) fn get_finality_heartbeat(&self) -> Duration {
+        self.finality_heartbeat
+    }
+
+    /// Moves the finality point forward from the watermarks of the sources and what is still to be processed,
+    /// and compacts the store. Returns whether it moved.
+    pub(crate) fn advance_finality(&mut self, watermarks: &BTreeMap<String, u64>, min_queued_timestamp: Option<u64>) -> bool {
+        let pending = [min_queued_timestamp, self.quarantine.get_min_timestamp()];
+        match compute_finality_point(self.router.get_upstream_set(), &self.watermark_sources, watermarks, pending) {
+            Some(finalized_before) if Some(finalized_before) > self.store.get_finalized_before() => {
+                let n_dropped = self.store.finalize(finalized_before);
+                debug!(
+                    "{} finalized everything before {}, dropped {} events",
+                    &self.query.name, finalized_before, n_dropped
+                );
+                true
+            }
+            _ => false,
         }
     }
 
-    pub(crate) async fn start(&mut self, max_elapsed_time: Option<Duration>) {
-        self.caller.start(max_elapsed_time).await;
-        self.router.start(max_elapsed_time).await;
+    /// Compacts the store above the soft limit, and above the hard limit also spills events if that is the policy.
+    /// Returns whether the store is still above the hard limit, in which case new events and deltas are refused.
+    pub(crate) fn enforce_memory_limits(&mut self) -> bool {
+        let limits = &self.memory_limits;
+        let usage = self.store.get_memory_usage();
+        if limits.is_above_soft_limit(&usage) || limits.is_above_hard_limit(&usage) {
+            let n_compacted = self.store.compact();
+            if n_compacted > 0 {
+                info!("{} compacted {} edges closed before the finality point", &self.query.name, n_compacted);
+                MEMORY_LIMIT_ACTIONS.with_label_values(&[&self.query.name, "compaction"]).inc();
+            }
+        }
+        if limits.hard_limit_policy == HardLimitPolicy::Spill && limits.is_above_hard_limit(&self.store.get_memory_usage()) {
+            let n_spilled = self.store.spill_events(limits.get_spill_target());
+            if n_spilled > 0 {
+                info!("{} spilled {} events to disk", &self.query.name, n_spilled);
+                MEMORY_LIMIT_ACTIONS.with_label_values(&[&self.query.name, "spilled_event"]).inc_by(n_spilled as u64);
+            }
+        }
+        let usage = self.store.get_memory_usage();
+        let refusing_input = limits.is_above_hard_limit(&usage);
+        if refusing_input && !self.refusing_input {
+            //Compaction frees nothing more until the finality point moves, so without watermarks this does not end
+            warn!(
+                "{} store holds about {} bytes, above its hard limit, refusing new events and deltas until the finality point moves on from {:?}",
+                &self.query.name,
+                usage.total(),
+                self.store.get_finalized_before()
+            );
+            MEMORY_LIMIT_ACTIONS.with_label_values(&[&self.query.name, "refusal"]).inc();
+            self.health.set_memory_exhausted(true);
+        } else if !refusing_input && self.refusing_input {
+            info!("{} store holds about {} bytes, accepting new events and deltas again", &self.query.name, usage.total());
+            self.health.set_memory_exhausted(false);
+        }
+        self.refusing_input = refusing_input;
+        refusing_input
     }
 
-    pub(crate) fn stop(&mut self) {}
+    /// Moves the finality point of a standby to where its primary moved it. Returns whether it moved.
+    pub(crate) fn apply_finality(&mut self, finalized_before: u64) -> bool {
+        if Some(finalized_before) > self.store.get_finalized_before() {
+            self.store.finalize(finalized_before);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn discard_held_output(&self) {
+        self.router.discard_held();
+    }
+
+    /// Makes a standby the component of its query, sending what it held back.
+    pub(crate) fn take_over(&mut self) -> Vec<JoinHandle<Result<Response<ProcessUpdateResponse>, Status>>> {
+        self.router.take_over()
+    }
+
+    /// Sends the finality point as a watermark when it has moved, and as a heartbeat otherwise.
+    pub(crate) fn send_finality(&mut self, moved: bool) {
+        if let Some(finalized_before) = self.store.get_finalized_before() {
+            let is_heartbeat_due = match self.finality_sent_at {
+                Some(sent_at) => sent_at.elapsed() >= self.finality_heartbeat,
+                None => true,
+            };
+            if moved || is_heartbeat_due {
+                self.router.send_watermarks(finalized_before);
+                self.finality_sent_at = Some(Instant::now());
+            }
+        }
+    }
 
     pub(crate) async fn process_update_until_consistency(
         &mut self,
         update: Update,
-    ) -> (Vec<JoinHandle<Result<Response<ProcessUpdateResponse>, Status>>>, i32, i32, i32, i32, usize) {
+        inbox_seq: Option<u64>,
+        trace: &TraceContext,
+    ) -> ProcessingResult {
+        let span = self
+            .tracer
+            .start_span("process_update", trace)
+            .with_attribute("update", describe_update(&update));
         if let Update::Deltas(deltas) = &update {
-            let is_retracted = self.store.is_update_rectracted(&deltas.deltas_id);
-            if is_retracted {
+            if self.store.is_update_rectracted(&deltas.deltas_id) {
+                self.tracer.finish_span(span.with_attribute("skipped", "retracted"));
+                return (vec![], 0, 0, 0, 0, self.store.open_edges.len());
+            }
+            //Deltas ids are derived from their content, so a known id is a duplicate delivery or a replay
+            if self.store.contains_deltas_id(&deltas.deltas_id) {
+                debug!("{} ignoring duplicate deltas {}", &self.query.name, &deltas.deltas_id);
+                self.tracer.finish_span(span.with_attribute("skipped", "duplicate"));
+                return (vec![], 0, 0, 0, 0, self.store.open_edges.len());
+            }
+        }
+        if let Update::Event(event) = &update {
+            //A redelivery of an event which an operator has retracted since
+            if self.store.is_update_rectracted(&event.event_id) {
+                debug!("{} ignoring retracted event {}", &self.query.name, &event.event_id);
+                self.tracer.finish_span(span.with_attribute("skipped", "retracted"));
+                return (vec![], 0, 0, 0, 0, self.store.open_edges.len());
+            }
+            if self.store.get_event_by_event_id(&event.event_id).as_ref() == Some(event) {
+                debug!("{} ignoring duplicate event {}", &self.query.name, &event.event_id);
+                self.tracer.finish_span(span.with_attribute("skipped", "duplicate"));
                 return (vec![], 0, 0, 0, 0, self.store.open_edges.len());
             }
         }
+        if let Some((policy, reason)) = self.late_arrivals.check(&update, self.store.get_finalized_before()) {
+            let update_type = if let Update::Event(_) = &update { "event" } else { "deltas" };
+            LATE_ARRIVALS
+                .with_label_values(&[&self.query.name, update_type, policy.as_str()])
+                .inc();
+            match policy {
+                LatePolicy::Process => {
+                    warn!("{} processing late {}: {}", &self.query.name, describe_update(&update), &reason);
+                }
+                LatePolicy::Park => {
+                    let quarantine_id = Uuid::new_v4().to_hyphenated().to_string();
+                    warn!(
+                        "{} parked late {} as {}: {}",
+                        &self.query.name,
+                        describe_update(&update),
+                        &quarantine_id,
+                        &reason
+                    );
+                    self.quarantine.insert(QuarantinedUpdate {
+                        quarantine_id,
+                        update: update.clone(),
+                        update_chain: vec![describe_update(&update)],
+                        pending_updates: vec![update],
+                        reprocess_intervals: vec![],
+                        retracted_ids: BTreeSet::new(),
+                        loops: 0,
+                        inbox_seq,
+                    });
+                    self.tracer.finish_span(span.with_attribute("skipped", "late"));
+                    return (vec![], 0, 0, 0, 0, self.store.open_edges.len());
+                }
+                LatePolicy::Reject => {
+                    warn!("{} rejected late {}: {}", &self.query.name, describe_update(&update), &reason);
+                    self.late_arrivals.record_rejected(&update, reason);
+                    self.tracer.finish_span(span.with_attribute("skipped", "late"));
+                    return (vec![], 0, 0, 0, 0, self.store.open_edges.len());
+                }
+            }
+        }
+        let loop_budget = self.loop_budget;
+        let result = self
+            .process_until_consistency(update.clone(), inbox_seq, vec![update], vec![], BTreeSet::new(), loop_budget, &TraceContext::child_of(&span))
+            .await;
+        self.tracer.finish_span(span);
+        result
+    }
 
-        let mut updates_to_process = vec![update];
+    pub(crate) async fn process_admin_command(&mut self, command: AdminCommand) -> ProcessingResult {
+        match command {
+            AdminCommand::RetryQuarantined(retry) => {
+                if let Some(quarantined) = self.quarantine.pop(&retry.quarantine_id) {
+                    info!(
+                        "{} retrying quarantined update {}",
+                        &self.query.name, &retry.quarantine_id
+                    );
+                    let loop_budget = if retry.loop_budget > 0 {
+                        retry.loop_budget
+                    } else {
+                        self.loop_budget
+                    };
+                    let span = self
+                        .tracer
+                        .start_span("retry_quarantined", &TraceContext::for_update(&quarantined.update))
+                        .with_attribute("quarantine_id", &retry.quarantine_id);
+                    //We continue the cascade from where it was stopped, as the store already reflects the updates processed before quarantining
+                    let result = self
+                        .process_until_consistency(
+                            quarantined.update,
+                            quarantined.inbox_seq,
+                            quarantined.pending_updates,
+                            quarantined.reprocess_intervals,
+                            quarantined.retracted_ids,
+                            loop_budget,
+                            &TraceContext::child_of(&span),
+                        )
+                        .await;
+                    self.tracer.finish_span(span);
+                    //Quarantined again, if the retry exceeded the loop budget as well
+                    if let Some(inbox_seq) = quarantined.inbox_seq {
+                        if !self.quarantine.holds_inbox_seq(inbox_seq) {
+                            self.released_inbox_seqs.push(inbox_seq);
+                        }
+                    }
+                    return result;
+                } else {
+                    warn!(
+                        "{} could not retry unknown quarantined update {}",
+                        &self.query.name, &retry.quarantine_id
+                    );
+                }
+            }
+            AdminCommand::Reconfigure(reconfigure) => {
+                let all_queries_by_name: Option<BTreeMap<String, Query>> = reconfigure
+                    .queries
+                    .map(|queries| queries.into_iter().map(|q| (q.name.clone(), q)).collect());
+                if let Some(all_queries_by_name) = &all_queries_by_name {
+                    //Stored matches were found by the current query, so it can not be replaced while running
+                    if all_queries_by_name.get(&self.query.name) != Some(&self.query) {
+                        warn!(
+                            "{} ignored reconfiguration which does not contain its current query",
+                            &self.query.name
+                        );
+                        return (vec![], 0, 0, 0, 0, self.store.open_edges.len());
+                    }
+                }
+                match self
+                    .router
+                    .reconfigure(all_queries_by_name.as_ref(), reconfigure.query_url_map)
+                    .await
+                {
+                    Ok(()) => info!("{} reconfigured routing", &self.query.name),
+                    Err(e) => warn!("{} ignored reconfiguration: {}", &self.query.name, e),
+                }
+            }
+            AdminCommand::DiscardQuarantined(discard) => {
+                if let Some(discarded) = self.quarantine.pop(&discard.quarantine_id) {
+                    info!(
+                        "{} discarded quarantined update {}",
+                        &self.query.name, &discard.quarantine_id
+                    );
+                    self.released_inbox_seqs.extend(discarded.inbox_seq);
+                } else {
+                    warn!(
+                        "{} could not discard unknown quarantined update {}",
+                        &self.query.name, &discard.quarantine_id
+                    );
+                }
+            }
+            AdminCommand::Drain => {
+                warn!("{} ignored drain command, which is handled by the server", &self.query.name);
+            }
+        }
+        (vec![], 0, 0, 0, 0, self.store.open_edges.len())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn process_until_consistency(
+        &mut self,
+        original_update: Update,
+        inbox_seq: Option<u64>,
+        mut updates_to_process: Vec<Update>,
+        mut reprocess_intervals: Vec<ReprocessInterval>,
+        mut retracted_ids: BTreeSet<String>,
+        loop_budget: u32,
+        trace: &TraceContext,
+    ) -> ProcessingResult {
         let mut handles = vec![];
-        let mut retracted_ids = BTreeSet::new();
         let mut seq = 0;
-        let mut reprocess_intervals = vec![];
+        let mut update_chain = vec![];
 
         let mut n_deltas = 0;
         let mut n_events = 0;
@@ -87,69 +458,123 @@ impl Component {
         let mut n_reprocessing = 0;
 
         while !updates_to_process.is_empty() || !reprocess_intervals.is_empty() {
-            if seq > 1000 {
-                debug!("Reached over 100 iterations, {:?}", &updates_to_process);
-                panic!("Over 100 loops");
+            if seq > loop_budget {
+                let quarantine_id = Uuid::new_v4().to_hyphenated().to_string();
+                warn!(
+                    "{} exceeded loop budget of {} while processing {}, quarantined as {}",
+                    &self.query.name,
+                    loop_budget,
+                    describe_update(&original_update),
+                    &quarantine_id
+                );
+                debug!(
+                    "{} quarantined {} with pending updates {:?} and reprocess intervals {:?}",
+                    &self.query.name, &quarantine_id, &updates_to_process, &reprocess_intervals
+                );
+                self.quarantine.insert(QuarantinedUpdate {
+                    quarantine_id,
+                    update: original_update,
+                    update_chain,
+                    pending_updates: updates_to_process,
+                    reprocess_intervals,
+                    retracted_ids,
+                    loops: seq,
+                    inbox_seq,
+                });
+                break;
             }
-            let update = updates_to_process.pop().unwrap();
-
-            match update {
-                Update::Event(event) => {
-                    info!(
-                        "{} processing event with id {}",
-                        &self.query.name, &event.event_id
-                    );
-                    if !retracted_ids.contains(&event.event_id) {
-                        let (mut new_updates, mut new_handles) =
-                            self.process_new_event(&event).await;
-                        updates_to_process.append(&mut new_updates);
-                        handles.append(&mut new_handles);
-                        n_events += 1;
-                    } else {
+            if let Some(update) = updates_to_process.pop() {
+                update_chain.push(describe_update(&update));
+                match update {
+                    Update::Event(event) => {
                         info!(
-                            "{} event with id {} was retracted, doing nothing",
+                            "{} processing event with id {}",
                             &self.query.name, &event.event_id
                         );
+                        if !retracted_ids.contains(&event.event_id) {
+                            let (mut new_updates, mut new_handles) =
+                                self.process_new_event(&event, trace).await;
+                            updates_to_process.append(&mut new_updates);
+                            handles.append(&mut new_handles);
+                            n_events += 1;
+                        } else {
+                            info!(
+                                "{} event with id {} was retracted, doing nothing",
+                                &self.query.name, &event.event_id
+                            );
+                        }
                     }
-                }
-                Update::Deltas(deltas) => {
-                    info!(
-                        "{} processing deltas with id {} and event id {}",
-                        &self.query.name, &deltas.deltas_id, &deltas.origin_id
-                    );
-                    if !retracted_ids.contains(&deltas.deltas_id)
-                        && !retracted_ids.contains(&deltas.origin_id)
-                    {
-                        let mut new_reprocess_intervals = self.process_new_deltas(&deltas);
-                        reprocess_intervals.append(&mut new_reprocess_intervals);
-                        n_deltas += 1;
-                    } else {
+                    Update::Deltas(deltas) => {
                         info!(
-                            "{} deltas with id {} and event id {} was retracted, doing nothing",
+                            "{} processing deltas with id {} and event id {}",
                             &self.query.name, &deltas.deltas_id, &deltas.origin_id
                         );
+                        if !retracted_ids.contains(&deltas.deltas_id)
+                            && !retracted_ids.contains(&deltas.origin_id)
+                        {
+                            let mut new_reprocess_intervals = self.process_new_deltas(&deltas);
+                            reprocess_intervals.append(&mut new_reprocess_intervals);
+                            n_deltas += 1;
+                        } else {
+                            info!(
+                                "{} deltas with id {} and event id {} was retracted, doing nothing",
+                                &self.query.name, &deltas.deltas_id, &deltas.origin_id
+                            );
+                        }
                     }
-                }
-                Update::Retractions(retractions) => {
-                    info!(
-                        "{} processing retractions with id {} ",
-                        &self.query.name, &retractions.retraction_id
-                    );
-                    for id in &retractions.deltas_ids {
-                        retracted_ids.insert(id.clone());
+                    Update::EventCorrection(correction) => {
+                        info!(
+                            "{} processing correction {} of event {}",
+                            &self.query.name, &correction.correction_id, &correction.event_id
+                        );
+                        let (mut new_updates, mut new_handles, replaced) =
+                            self.process_event_correction(&correction, trace).await;
+                        updates_to_process.append(&mut new_updates);
+                        handles.append(&mut new_handles);
+                        if replaced {
+                            n_events += 1;
+                        }
                     }
-                    let mut new_reprocess_intervals = self.process_retractions(retractions);
-                    reprocess_intervals.append(&mut new_reprocess_intervals);
-                    n_retractions += 1;
-                }
-                _ => {
-                    panic!("Should never happen")
-                }
-            };
+                    Update::ManualAssertion(assertion) => {
+                        info!(
+                            "{} processing manual assertion {} by {}",
+                            &self.query.name, &assertion.assertion_id, &assertion.operator
+                        );
+                        let (mut new_updates, mut new_handles) = self.process_manual_assertion(assertion, trace).await;
+                        updates_to_process.append(&mut new_updates);
+                        handles.append(&mut new_handles);
+                    }
+                    Update::Retractions(retractions) => {
+                        info!(
+                            "{} processing retractions with id {} ",
+                            &self.query.name, &retractions.retraction_id
+                        );
+                        for id in &retractions.deltas_ids {
+                            retracted_ids.insert(id.clone());
+                        }
+                        let mut new_reprocess_intervals = self.process_retractions(retractions);
+                        reprocess_intervals.append(&mut new_reprocess_intervals);
+                        n_retractions += 1;
+                    }
+                    Update::Provenance(provenance) => {
+                        warn!(
+                            "{} ignoring provenance of deltas {}, which only central records",
+                            &self.query.name, &provenance.deltas_id
+                        );
+                    }
+                    //Stops and admin updates are handled by the server, and watermarks by the queue, before they reach the component
+                    other => {
+                        warn!(
+                            "{} ignoring {}, which is not processed as an update",
+                            &self.query.name, describe_update(&other)
+                        );
+                    }
+                };
+            }
 
             if updates_to_process.is_empty() && !reprocess_intervals.is_empty() {
                 let (mut new_updates, mut new_handles, new_reprocess_intervals, n_events) = self
-                    .reprocess_events_in_intervals_until_internal_update(reprocess_intervals)
+                    .reprocess_events_in_intervals_until_internal_update(reprocess_intervals, trace)
                     .await;
                 reprocess_intervals = new_reprocess_intervals;
                 updates_to_process.append(&mut new_updates);
@@ -165,6 +590,8 @@ impl Component {
 
     fn process_retractions(&mut self, retractions: Retractions) -> Vec<ReprocessInterval> {
         //First we retract deltas contained in the updates and the new updates based on matches which are now lost
+        //Retractions are kept, so that deltas they overtook and duplicates of retracted deltas are dropped when they arrive.
+        //Output produced again after a retraction has a new deltas id, as the match generation differs
         self.store.add_retractions(&retractions.deltas_ids);
         let deltas_to_retract = self.store.pop_deltas_by_deltas_ids(&retractions.deltas_ids);
         debug!(
@@ -188,7 +615,7 @@ impl Component {
         let existing_deltas_by_edge = self.store.get_deltas_by_edge_vec(new_deltas_edges_vec);
         let updated_deltas_by_edge = self
             .store
-            .add_deltas_and_get_updated_deltas_by_edge(&deltas);
+            .add_deltas_and_get_updated_deltas_by_edge(deltas);
         info!(
             "{} new deltas processing took {} μs",
             &self.query.name,
@@ -204,6 +631,7 @@ impl Component {
     async fn process_event(
         &mut self,
         event: &Event,
+        trace: &TraceContext,
     ) -> (
         Vec<Update>,
         Vec<JoinHandle<Result<Response<ProcessUpdateResponse>, Status>>>,
@@ -238,6 +666,12 @@ impl Component {
         }
 
         debug!("{} found {} matches", &self.query.name, matches.len());
+        let span = self
+            .tracer
+            .start_span("match", trace)
+            .with_attribute("event_id", &event.event_id)
+            .with_attribute("n_matches", matches.len());
+        let trace = &TraceContext::child_of(&span);
         let matches_by_hash =
             BTreeMap::from_iter(matches.into_iter().map(|m| (m.stable_hash(self.config), m)));
         let existing_output_hashes_by_match_hashes = self
@@ -247,13 +681,13 @@ impl Component {
             existing_output_hashes_by_match_hashes
                 .iter()
                 .filter(|(_m, o)| o.is_some())
-                .map(|(m, o)| (o.unwrap(), m.clone())),
+                .map(|(m, o)| (o.unwrap(), *m)),
         );
 
         let mut matches_to_possibly_retract = BTreeSet::new();
         for h in existing_output_hashes_by_match_hashes.keys() {
             if !matches_by_hash.contains_key(h) {
-                matches_to_possibly_retract.insert(h.clone());
+                matches_to_possibly_retract.insert(*h);
             }
         }
 
@@ -264,11 +698,11 @@ impl Component {
                     "{} found new match {} for event {}, computing output",
                     &self.query.name, match_hash, &event.event_id
                 );
-                let new_deltas_opt = self.process_new_match(grouped_match, event).await;
+                let new_deltas_opt = self.process_new_match(grouped_match, match_hash, event, trace).await;
                 if let Some(new_deltas) = new_deltas_opt {
                     let new_output_hash = new_deltas.stable_hash(self.config);
                     new_output_hashes_by_match_hashes
-                        .insert(match_hash.clone(), Some(new_output_hash));
+                        .insert(*match_hash, Some(new_output_hash));
                     if let Some(old_match_hash) =
                         existing_match_hashes_by_output_hashes.get(&new_output_hash)
                     {
@@ -282,17 +716,20 @@ impl Component {
                             old_match_hash,
                             match_hash,
                         );
-                        let removed = matches_to_possibly_retract.remove(&old_match_hash);
+                        let removed = matches_to_possibly_retract.remove(old_match_hash);
                         //Else we have a duplicate match
                         assert!(removed);
+                        if let Some(handle) = self.send_provenance(grouped_match, match_hash, event, trace).await {
+                            all_handles.push(handle);
+                        }
                     } else {
                         debug!(
                             "{} output did not exist for new match {} event {}, creating update",
                             &self.query.name, match_hash, &event.event_id
                         );
-                        new_output_hashes_by_match_hashes.insert(match_hash.clone(), None);
+                        new_output_hashes_by_match_hashes.insert(*match_hash, None);
                         let (new_update_opt, mut handles) = self
-                            .create_and_send_deltas_update(new_deltas, &event.event_id, match_hash)
+                            .create_and_send_deltas_update(new_deltas, event, grouped_match, match_hash, trace)
                             .await;
                         all_handles.append(&mut handles);
                         if let Some(new_update) = new_update_opt {
@@ -326,12 +763,14 @@ impl Component {
                     matches_to_possibly_retract.iter().collect(),
                     &event.event_id,
                     &event.timestamp,
+                    trace,
                 )
                 .await;
 
             all_updates.append(&mut retraction_updates);
             all_handles.append(&mut handles)
         }
+        self.tracer.finish_span(span);
         info!(
             "{} event processing took {} μs",
             &self.query.name,
@@ -405,6 +844,7 @@ impl Component {
     async fn reprocess_events_in_intervals_until_internal_update(
         &mut self,
         reprocess_intervals: Vec<ReprocessInterval>,
+        trace: &TraceContext,
     ) -> (Vec<Update>, Vec<JoinHandle<Result<Response<ProcessUpdateResponse>, Status>>>, Vec<ReprocessInterval>, i32) {
         let now = Instant::now();
         let non_redundant_intervals_to_reprocess =
@@ -423,21 +863,20 @@ impl Component {
                 .get_event_ids_in_interval(interval.from, interval.to);
             for j in 0..event_ids_in_interval.len() {
                 let event_id = event_ids_in_interval.get(j).unwrap();
-                let event_opt = self.store.get_event_by_event_id(&event_id).clone();
+                let event_opt = self.store.get_event_by_event_id(event_id);
                 if let Some(event) = event_opt {
-                    let event = event.clone();
                     let (mut new_internal_updates, mut new_handles) =
-                        self.process_event(&event).await;
+                        self.process_event(&event, trace).await;
                     n_events += 1;
                     all_handles.append(&mut new_handles);
                     //If we find the first event with an internal update, we are done
-                    if new_internal_updates.len() > 0 {
+                    if !new_internal_updates.is_empty() {
                         internal_updates.append(&mut new_internal_updates);
                         if !(interval.to.is_some() && interval.to.unwrap() < (event.timestamp + 1))
                         {
                             remaining_intervals.push(ReprocessInterval {
                                 from: event.timestamp,
-                                to: interval.to.clone(),
+                                to: interval.to,
                             })
                         }
                         if i + 1 < non_redundant_intervals_to_reprocess.len() {
@@ -463,16 +902,26 @@ impl Component {
     async fn process_new_match(
         &mut self,
         grouped_match: &GroupedQueryMatch,
+        match_hash: &u64,
         event: &Event,
+        trace: &TraceContext,
     ) -> Option<Deltas> {
         debug!(
             "{} processing new match for event {}",
             &self.query.name, &event.event_id
         );
+        let generation = self.store.next_match_generation(&event.event_id, match_hash);
+        let span = self
+            .tracer
+            .start_span("application_call", trace)
+            .with_attribute("application", &self.query.application)
+            .with_attribute("match_hash", match_hash);
         let deltas_opt = self
             .caller
-            .call_function(&self.query, grouped_match, event)
+            .call_function(&self.query, grouped_match, *match_hash, generation, event)
             .await;
+        self.tracer
+            .finish_span(span.with_attribute("has_result", deltas_opt.is_some()));
         if deltas_opt.is_none() {
             debug!("{} function call had no result", &self.query.name);
         } else {
@@ -484,27 +933,79 @@ impl Component {
     async fn create_and_send_deltas_update(
         &mut self,
         deltas: Deltas,
-        event_id: &str,
+        event: &Event,
+        grouped_match: &GroupedQueryMatch,
         match_hash: &u64,
+        trace: &TraceContext,
     ) -> (
         Option<Update>,
         Vec<JoinHandle<Result<Response<ProcessUpdateResponse>, Status>>>,
     ) {
-        let (my_internal_update, cascaded_query_and_update_id, handles) =
-            self.router.route_deltas_update(deltas).await;
+        let span = self
+            .tracer
+            .start_span("route_deltas", trace)
+            .with_attribute("deltas_id", &deltas.deltas_id);
+        let route_trace = TraceContext::child_of(&span);
+        let (my_internal_update, cascaded_query_and_update_id, mut handles) =
+            self.router.route_deltas_update(deltas, &route_trace).await;
+        self.tracer.finish_span(span.with_attribute("n_targets", cascaded_query_and_update_id.len()));
         self.store.add_new_match_updates_binding(
-            event_id,
+            &event.event_id,
             match_hash,
             cascaded_query_and_update_id,
         );
+        if let Some(handle) = self.send_provenance(grouped_match, match_hash, event, &route_trace).await {
+            handles.push(handle);
+        }
         (my_internal_update, handles)
     }
 
+    //Central records how the deltas of the match were derived, under the id it received them with
+    async fn send_provenance(
+        &self,
+        grouped_match: &GroupedQueryMatch,
+        match_hash: &u64,
+        event: &Event,
+        trace: &TraceContext,
+    ) -> Option<JoinHandle<Result<Response<ProcessUpdateResponse>, Status>>> {
+        let bindings = self
+            .store
+            .get_deltas_ids_for_event_id_and_match_hash(&event.event_id, match_hash)?;
+        let deltas_id = &bindings.iter().find(|b| b.topic_name == "central")?.deltas_id;
+        let matched_edges: BTreeSet<&Edge> = grouped_match
+            .grouped_matches
+            .iter()
+            .flat_map(|m| m.homomorphism.values().flatten())
+            .collect();
+        let provenance = Provenance {
+            deltas_id: deltas_id.clone(),
+            query_name: self.query.name.clone(),
+            application: self.query.application.clone(),
+            origin_id: event.event_id.clone(),
+            origin_timestamp: event.timestamp,
+            match_hash: *match_hash,
+            input_edges: matched_edges
+                .into_iter()
+                .map(|edge| InputEdge {
+                    edge: edge.clone(),
+                    deltas_ids: self.store.get_deltas_ids_of_edge(edge),
+                })
+                .collect(),
+            routed_deltas_ids: bindings
+                .iter()
+                .filter(|b| &b.deltas_id != deltas_id)
+                .map(|b| b.deltas_id.clone())
+                .collect(),
+        };
+        self.router.send_to_central(&Update::Provenance(provenance), trace).await
+    }
+
     async fn retract_matches(
         &mut self,
         matches_hashes: Vec<&u64>,
         event_id: &str,
         event_timestamp: &u64,
+        trace: &TraceContext,
     ) -> (
         Vec<Update>,
         Vec<JoinHandle<Result<Response<ProcessUpdateResponse>, Status>>>,
@@ -524,50 +1025,248 @@ impl Component {
                 all_topic_names_and_deltas_ids.append(&mut topic_names_and_deltas_ids)
             }
         }
+        let span = self
+            .tracer
+            .start_span("route_retractions", trace)
+            .with_attribute("n_retractions", all_topic_names_and_deltas_ids.len());
         let (internal_updates, handles) = self
             .router
-            .route_retractions(all_topic_names_and_deltas_ids, event_timestamp)
+            .route_retractions(all_topic_names_and_deltas_ids, event_timestamp, &TraceContext::child_of(&span))
             .await;
+        self.tracer.finish_span(span);
         (internal_updates, handles)
     }
 
+    /// Retracts the deltas of every match of the corrected event and removes it from the store, so that
+    /// the components receiving the retractions reprocess the intervals they affect. The replacement, if any,
+    /// is then processed as a new event. Returns whether there was a replacement to process.
+    async fn process_event_correction(
+        &mut self,
+        correction: &EventCorrection,
+        trace: &TraceContext,
+    ) -> (
+        Vec<Update>,
+        Vec<JoinHandle<Result<Response<ProcessUpdateResponse>, Status>>>,
+        bool,
+    ) {
+        let event = match self.store.get_event_by_event_id(&correction.event_id) {
+            Some(event) => event,
+            None => {
+                if self.store.is_update_rectracted(&correction.event_id) {
+                    warn!(
+                        "{} ignored correction {}, event {} was already retracted",
+                        &self.query.name, &correction.correction_id, &correction.event_id
+                    );
+                } else if Some(correction.timestamp) < self.store.get_finalized_before() {
+                    warn!(
+                        "{} could not apply correction {}, event {} was final",
+                        &self.query.name, &correction.correction_id, &correction.event_id
+                    );
+                } else {
+                    warn!(
+                        "{} could not apply correction {} to unknown event {}",
+                        &self.query.name, &correction.correction_id, &correction.event_id
+                    );
+                }
+                return (vec![], vec![], false);
+            }
+        };
+        let match_hashes: Vec<u64> = self
+            .store
+            .get_event_output_hash_by_match_hash(&event.event_id)
+            .into_keys()
+            .collect();
+        let (mut all_updates, mut all_handles) = self
+            .retract_matches(match_hashes.iter().collect(), &event.event_id, &event.timestamp, trace)
+            .await;
+        self.store.remove_event(&event.event_id);
+        //So that a redelivery of the event is ignored, unless the replacement keeps its id
+        if correction.replacement.as_ref().map(|r| &r.event_id) != Some(&event.event_id) {
+            self.store.add_retractions(&vec![event.event_id.clone()]);
+        }
+        match &correction.replacement {
+            Some(replacement) => {
+                info!(
+                    "{} replacing event {} at {} with event {} at {}",
+                    &self.query.name, &event.event_id, event.timestamp, &replacement.event_id, replacement.timestamp
+                );
+                let (mut new_updates, mut new_handles) = self.process_new_event(replacement, trace).await;
+                all_updates.append(&mut new_updates);
+                all_handles.append(&mut new_handles);
+                (all_updates, all_handles, true)
+            }
+            None => {
+                info!("{} retracted event {} at {}", &self.query.name, &event.event_id, event.timestamp);
+                (all_updates, all_handles, false)
+            }
+        }
+    }
+
+    /// Routes the deltas of a manual assertion like those of an interpretation, or retracts them when it is revoked.
+    /// Central is also sent the assertion, to record who asserted or revoked it and why.
+    async fn process_manual_assertion(
+        &mut self,
+        assertion: ManualAssertion,
+        trace: &TraceContext,
+    ) -> (
+        Vec<Update>,
+        Vec<JoinHandle<Result<Response<ProcessUpdateResponse>, Status>>>,
+    ) {
+        let assertion_id = assertion.assertion_id.clone();
+        let (mut all_updates, mut all_handles) = if assertion.revoke {
+            let topic_names_and_deltas_ids = match self.store.pop_assertion_binding(&assertion_id) {
+                Some(topic_names_and_deltas_ids) => topic_names_and_deltas_ids,
+                None => {
+                    warn!("{} could not revoke unknown assertion {}", &self.query.name, &assertion_id);
+                    return (vec![], vec![]);
+                }
+            };
+            info!(
+                "{} revoking assertion {} by {}: {}",
+                &self.query.name, &assertion_id, &assertion.operator, &assertion.reason
+            );
+            //So that a redelivery of the assertion is ignored
+            self.store.add_retractions(&vec![assertion_id.clone()]);
+            self.router
+                .route_retractions(topic_names_and_deltas_ids, &assertion.timestamp, trace)
+                .await
+        } else {
+            if self.store.contains_assertion(&assertion_id) || self.store.is_update_rectracted(&assertion_id) {
+                debug!("{} ignoring duplicate assertion {}", &self.query.name, &assertion_id);
+                return (vec![], vec![]);
+            }
+            //Operators override what the query concluded, anything else has no route
+            if let Some(delta) = assertion.deltas.iter().find(|d| !self.router.is_output_delta(d)) {
+                warn!(
+                    "{} rejected assertion {} by {}, since it does not output edges such as {:?}",
+                    &self.query.name, &assertion_id, &assertion.operator, delta
+                );
+                return (vec![], vec![]);
+            }
+            info!(
+                "{} asserting {} deltas for {}: {}",
+                &self.query.name,
+                assertion.deltas.len(),
+                &assertion.operator,
+                &assertion.reason
+            );
+            let deltas = Deltas {
+                deltas_id: deterministic_assertion_deltas_id(&assertion_id, self.config),
+                origin_id: assertion_id.clone(),
+                origin_timestamp: assertion.timestamp,
+                deltas: assertion.deltas.clone(),
+            };
+            let (internal_update, cascaded_topic_and_deltas_ids, handles) =
+                self.router.route_deltas_update(deltas, trace).await;
+            self.store.add_assertion_binding(&assertion_id, cascaded_topic_and_deltas_ids);
+            (internal_update.into_iter().collect(), handles)
+        };
+        if let Some(handle) = self.router.send_to_central(&Update::ManualAssertion(assertion), trace).await {
+            all_handles.push(handle);
+        }
+        all_updates.sort_by_key(|u| if let Update::Retractions(_) = u { 0 } else { 1 });
+        (all_updates, all_handles)
+    }
+
     async fn process_new_event(
         &mut self,
         event: &Event,
+        trace: &TraceContext,
     ) -> (
         Vec<Update>,
         Vec<JoinHandle<Result<Response<ProcessUpdateResponse>, Status>>>,
     ) {
-        self.store.add_new_event(&event);
-        let (new_updates, handles) = self.process_event(&event).await;
+        self.store.add_new_event(event);
+        let (new_updates, handles) = self.process_event(event, trace).await;
         (new_updates, handles)
     }
 }
 
+//The earliest of the watermarks and of the timestamps still to be processed. There is none until every upstream query
+//and every expected source has sent a watermark, since events from sources not heard from can not be waited for.
+//Watermarks from other sources are then ignored. Without expected sources, any one source outside the dataflow will do
+fn compute_finality_point(
+    upstream_set: &BTreeSet<String>,
+    expected_sources: &BTreeSet<String>,
+    watermarks: &BTreeMap<String, u64>,
+    pending: impl IntoIterator<Item = Option<u64>>,
+) -> Option<u64> {
+    let has_all_upstream = upstream_set.iter().all(|q| watermarks.contains_key(q));
+    let has_sources = if expected_sources.is_empty() {
+        watermarks.keys().any(|source| !upstream_set.contains(source))
+    } else {
+        expected_sources.iter().all(|source| watermarks.contains_key(source))
+    };
+    if !has_all_upstream || !has_sources {
+        return None;
+    }
+    watermarks
+        .iter()
+        .filter(|(source, _)| expected_sources.is_empty() || upstream_set.contains(*source) || expected_sources.contains(*source))
+        .map(|(_, timestamp)| *timestamp)
+        .chain(pending.into_iter().flatten())
+        .min()
+}
+
 fn get_deltas_vec_by_edge(deltas: Vec<&Delta>) -> BTreeMap<Edge, Vec<&Delta>> {
     let mut deltas_by_edge = BTreeMap::new();
     for d in deltas {
         let edge = d.to_edge();
-        if !deltas_by_edge.contains_key(&edge) {
-            deltas_by_edge.insert(edge, vec![d]);
-        } else {
-            deltas_by_edge.get_mut(&edge).unwrap().push(d);
-        }
+        deltas_by_edge.entry(edge).or_insert_with(Vec::new).push(d);
     }
     deltas_by_edge
 }
 
+//Spilling needs a file for the events, next to the other logs of the component
+fn create_store(query_name: &str, options: &ComponentOptions) -> Result<Store, String> {
+    let mut store = Store::new();
+    if options.memory_limits.hard_limit_policy == HardLimitPolicy::Spill {
+        match options.get_log_path(query_name, "spill") {
+            Some(path) => store.set_spill(
+                EventSpill::create(&path).map_err(|e| format!("Could not create spill file {:?}: {}", &path, e))?,
+            ),
+            None => warn!("{} can not spill events without a log directory, and refuses input at the hard limit instead", query_name),
+        }
+    }
+    Ok(store)
+}
+
 fn get_deltas_and_deltas_id_by_edge(
     deltas: Vec<DeltaAndDeltasId>,
 ) -> BTreeMap<Edge, BTreeSet<DeltaAndDeltasId>> {
     let mut deltas_by_edge = BTreeMap::new();
     for d in deltas {
         let edge = d.delta.to_edge();
-        if !deltas_by_edge.contains_key(&edge) {
-            deltas_by_edge.insert(edge, BTreeSet::from([d]));
-        } else {
-            deltas_by_edge.get_mut(&edge).unwrap().insert(d);
-        }
+        deltas_by_edge.entry(edge).or_insert_with(BTreeSet::new).insert(d);
     }
     deltas_by_edge
 }
+
+#[test]
+fn test_finality_point_waits_for_upstream_and_pending_updates() {
+    let upstream_set = BTreeSet::from(["q0".to_string()]);
+    let watermarks = |ws: Vec<(&str, u64)>| ws.into_iter().map(|(s, t)| (s.to_string(), t)).collect::<BTreeMap<String, u64>>();
+    let none = BTreeSet::new();
+    //Nothing is final before an upstream query has told how far it is
+    assert_eq!(compute_finality_point(&upstream_set, &none, &watermarks(vec![("producer", 10)]), [None]), None);
+    //Nor before a source outside the dataflow has
+    assert_eq!(compute_finality_point(&upstream_set, &none, &watermarks(vec![("q0", 10)]), [None]), None);
+    let all = watermarks(vec![("producer", 10), ("q0", 7)]);
+    assert_eq!(compute_finality_point(&upstream_set, &none, &all, [None]), Some(7));
+    assert_eq!(compute_finality_point(&upstream_set, &none, &all, [Some(5), None]), Some(5));
+    assert_eq!(compute_finality_point(&none, &none, &watermarks(vec![("producer", 10)]), [Some(12)]), Some(10));
+}
+
+#[test]
+fn test_finality_point_waits_for_every_expected_source() {
+    let upstream_set = BTreeSet::from(["q0".to_string()]);
+    let expected = BTreeSet::from(["producer-0".to_string(), "producer-1".to_string()]);
+    let watermarks = |ws: Vec<(&str, u64)>| ws.into_iter().map(|(s, t)| (s.to_string(), t)).collect::<BTreeMap<String, u64>>();
+    //One producer is not enough while another is expected
+    assert_eq!(compute_finality_point(&upstream_set, &expected, &watermarks(vec![("producer-0", 10), ("q0", 10)]), [None]), None);
+    let all = watermarks(vec![("producer-0", 10), ("producer-1", 8), ("q0", 9)]);
+    assert_eq!(compute_finality_point(&upstream_set, &expected, &all, [None]), Some(8));
+    //A source that is not expected, such as one that was retired, holds nothing back
+    let with_retired = watermarks(vec![("producer-0", 10), ("producer-1", 8), ("q0", 9), ("retired", 2)]);
+    assert_eq!(compute_finality_point(&upstream_set, &expected, &with_retired, [None]), Some(8));
+}
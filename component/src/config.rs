@@ -0,0 +1,631 @@
+/*Copyright 2022 Prediktor AS
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.*/
+
+//! The config file of mbei-component, for instance
+//! ```yaml
+//! version: 1
+//! identity:
+//!   host_number: 0        # If left out, the number at the end of the hostname, as in mbei-component-0
+//!   port: 10000           # Where all hosted queries are served
+//! queries:
+//!   path: all-queries.yaml
+//!   assignments:          # Or hosted: [pickdrop_matched_1], to host the same queries regardless of host number
+//!     0: [pickdrop_matched_1]
+//!     1: [stamp_matched_1]
+//! peers:                  # Urls of the queries updates are sent to, and of central
+//!   stamp_matched_1: http://mbei-component-1.mbei-component:10000
+//!   central: http://mbei-central:10000
+//! application:
+//!   url: http://localhost:9999
+//! storage:
+//!   log_dir: /var/lib/mbei
+//! limits:
+//!   soft_memory_limit_mb: 512    # Approximate size of the store, beyond which it is compacted
+//!   hard_memory_limit_mb: 1024   # Beyond which new events and deltas are refused
+//!   hard_memory_limit_policy: spill  # Or refuse, the default. Spilling moves events to storage.log_dir first
+//! timeouts:
+//!   call_timeout_ms: 5000
+//!   finality_heartbeat_ms: 5000  # How often an unchanged finality point is sent again
+//! watermark_sources: [mbei-producer-0, mbei-producer-1]  # Finality waits for the watermarks of each of these sources
+//! lateness:               # Per query, how far behind the latest timestamp updates may arrive, and what is done with later ones
+//!   stamp_matched_1:
+//!     allowed_lateness: 60000
+//!     policy: park        # Or process, the default, or reject
+//! operators:              # Files with the tokens of the operators allowed to send manual assertions
+//!   alice: operators/alice.token
+//! tls:                    # Mutual TLS on all links, which then use https urls
+//!   ca_certificate: ca.pem
+//!   certificate: component.pem
+//!   key: component.key
+//!   allowed_client_hosts: [mbei-producer]  # Besides the hosts of the peers
+//! replication:            # Hot standbys of the hosted queries, if any. A standby takes over the port of its primary,
+//!                         # so both run on one host: failover covers the primary process failing, not the host
+//!   replicas:             # Urls of the standbys the hosted queries replicate to
+//!     pickdrop_matched_1: http://localhost:10001
+//!   heartbeat_ms: 500
+//!   standby_port: 10001   # Set on standbys, which take over identity.port when their primary is silent for failover_timeout_ms
+//!   failover_timeout_ms: 2000
+//! central: true
+//! ```
+//! Relative paths are resolved from the directory of the config file.
+//! Any value can be overridden by an environment variable named by the path to it in upper case,
+//! such as MBEI_COMPONENT__IDENTITY__HOST_NUMBER=2 or MBEI_COMPONENT__PEERS__CENTRAL=http://localhost:10000.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use log::{debug, info};
+use mbei_core::query::{try_parse_queries, Query};
+use mbei_grpc::tls::TlsOptions;
+use mbei_grpc::tracer::TraceExport;
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value};
+
+use crate::caller::UnavailablePolicy;
+use crate::lateness::{LatePolicy, LatenessOptions};
+use crate::memory::HardLimitPolicy;
+use crate::options::ComponentOptions;
+use crate::router::Router;
+use crate::standby::StandbyOptions;
+use crate::wasm_interpreter::{load_wasm_interpreters, WasmLimits};
+
+pub const CONFIG_VERSION: u64 = 1;
+pub const ENV_PREFIX: &str = "MBEI_COMPONENT__";
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ComponentConfig {
+    pub version: u64,
+    pub identity: IdentityConfig,
+    pub queries: QueriesConfig,
+    #[serde(default)]
+    pub peers: BTreeMap<String, String>,
+    pub application: ApplicationConfig,
+    #[serde(default)]
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub timeouts: TimeoutsConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub observability: ObservabilityConfig,
+    #[serde(default)]
+    pub watermark_sources: BTreeSet<String>,
+    #[serde(default)]
+    pub lateness: BTreeMap<String, LatenessConfig>,
+    #[serde(default)]
+    pub operators: BTreeMap<String, PathBuf>,
+    pub tls: Option<TlsConfig>,
+    #[serde(default)]
+    pub replication: ReplicationConfig,
+    #[serde(default)]
+    pub central: bool,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct IdentityConfig {
+    pub host_number: Option<u16>,
+    pub port: u16,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct QueriesConfig {
+    pub path: PathBuf,
+    pub hosted: Option<Vec<String>>,
+    pub assignments: Option<BTreeMap<u16, Vec<String>>>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ApplicationConfig {
+    pub url: String,
+    //Directory of wasm modules run in process instead of calling the application
+    pub wasm_dir: Option<PathBuf>,
+    pub wasm_fuel: Option<u64>,
+    pub wasm_max_memory_mb: Option<usize>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct StorageConfig {
+    pub log_dir: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TimeoutsConfig {
+    pub call_timeout_ms: Option<u64>,
+    pub call_retries: Option<u32>,
+    pub unavailable_policy: Option<String>,
+    pub full_queue_wait_ms: Option<u64>,
+    pub finality_heartbeat_ms: Option<u64>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub loop_budget: Option<u32>,
+    pub max_queued_events: Option<usize>,
+    pub max_queued_deltas: Option<usize>,
+    pub max_queued_retractions: Option<usize>,
+    pub soft_memory_limit_mb: Option<usize>,
+    pub hard_memory_limit_mb: Option<usize>,
+    pub hard_memory_limit_policy: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ObservabilityConfig {
+    pub metrics_port: Option<u16>,
+    pub trace_file: Option<PathBuf>,
+    pub trace_collector: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LatenessConfig {
+    pub allowed_lateness: Option<u64>,
+    pub policy: Option<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub ca_certificate: PathBuf,
+    pub certificate: PathBuf,
+    pub key: PathBuf,
+    #[serde(default)]
+    pub allowed_client_hosts: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ReplicationConfig {
+    #[serde(default)]
+    pub replicas: BTreeMap<String, String>,
+    pub heartbeat_ms: Option<u64>,
+    pub standby_port: Option<u16>,
+    pub failover_timeout_ms: Option<u64>,
+}
+
+/// What a validated config starts
+pub struct ComponentSetup {
+    pub queries: Vec<Query>,
+    pub hosted_query_names: Vec<String>,
+    pub application_url: String,
+    pub port: u16,
+    pub query_url_map: BTreeMap<String, String>,
+    pub use_central: bool,
+    pub options: ComponentOptions,
+}
+
+impl ComponentConfig {
+    /// Reads the config file, with overrides from the environment variables starting with ENV_PREFIX.
+    pub fn load(path: &Path, env: impl Iterator<Item = (String, String)>) -> Result<ComponentConfig, String> {
+        let content = read_to_string(path).map_err(|e| format!("Could not read config file {:?}: {}", path, e))?;
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        ComponentConfig::parse(&content, base_dir, env).map_err(|e| format!("Invalid config file {:?}: {}", path, e))
+    }
+
+    pub fn parse(
+        content: &str,
+        base_dir: &Path,
+        env: impl Iterator<Item = (String, String)>,
+    ) -> Result<ComponentConfig, String> {
+        let mut value: Value = serde_yaml::from_str(content).map_err(|e| e.to_string())?;
+        for (name, override_value) in env {
+            if let Some(path) = name.strip_prefix(ENV_PREFIX) {
+                debug!("Config overridden by {}", &name);
+                let path: Vec<Value> = path.split("__").map(|key| parse_scalar(&key.to_lowercase())).collect();
+                apply_override(&mut value, &path, parse_scalar(&override_value))
+                    .map_err(|e| format!("Can not apply {}: {}", &name, e))?;
+            }
+        }
+        //The version is checked first, since the fields of another version may not be known
+        match value.get("version") {
+            None => return Err(format!("Missing version, expected version: {}", CONFIG_VERSION)),
+            Some(version) if version.as_u64() != Some(CONFIG_VERSION) => {
+                return Err(format!("Unsupported version {:?}, expected {}", version, CONFIG_VERSION));
+            }
+            _ => {}
+        }
+        let mut config: ComponentConfig = serde_yaml::from_value(value).map_err(|e| e.to_string())?;
+        config.resolve_paths(base_dir);
+        Ok(config)
+    }
+
+    /// Checks the config against the queries, and creates what is needed to start the hosted components.
+    pub fn validate(&self) -> Result<ComponentSetup, String> {
+        if self.identity.port == 0 {
+            return Err("identity.port must be set to the port serving the hosted queries".to_string());
+        }
+        let queries = try_parse_queries(&self.queries.path, bincode::config::standard())?;
+        let all_queries_by_name: BTreeMap<String, Query> =
+            queries.iter().map(|q| (q.name.clone(), q.clone())).collect();
+        let hosted_query_names = self.get_hosted_query_names()?;
+        for query_name in &hosted_query_names {
+            if !all_queries_by_name.contains_key(query_name) {
+                return Err(format!("Hosted query {} is not in {:?}", query_name, &self.queries.path));
+            }
+        }
+        for query_name in self.lateness.keys() {
+            if !all_queries_by_name.contains_key(query_name) {
+                return Err(format!("lateness.{} is not a query in {:?}", query_name, &self.queries.path));
+            }
+        }
+        let use_tls = self.tls.is_some();
+        check_url("application.url", &self.application.url, use_tls)?;
+        for (query_name, url) in &self.peers {
+            check_url(&format!("peers.{}", query_name), url, use_tls)?;
+        }
+        for (query_name, url) in &self.replication.replicas {
+            if !hosted_query_names.contains(query_name) {
+                return Err(format!("replication.replicas.{} is not a hosted query", query_name));
+            }
+            check_url(&format!("replication.replicas.{}", query_name), url, use_tls)?;
+        }
+        if let (Some(soft), Some(hard)) = (self.limits.soft_memory_limit_mb, self.limits.hard_memory_limit_mb) {
+            if soft > hard {
+                return Err("limits.soft_memory_limit_mb must not be above limits.hard_memory_limit_mb".to_string());
+            }
+        }
+        if self.limits.hard_memory_limit_policy.as_deref() == Some("spill") && self.storage.log_dir.is_none() {
+            return Err("limits.hard_memory_limit_policy spill needs storage.log_dir for the spilled events".to_string());
+        }
+        match self.replication.standby_port {
+            Some(_) if !self.replication.replicas.is_empty() => {
+                return Err("Standbys can not have replicas, replication.standby_port and replication.replicas are both set".to_string());
+            }
+            Some(standby_port) if standby_port == self.identity.port => {
+                return Err("replication.standby_port must differ from identity.port, which is taken over".to_string());
+            }
+            None if self.replication.failover_timeout_ms.is_some() => {
+                return Err("replication.failover_timeout_ms is only used by standbys, with replication.standby_port".to_string());
+            }
+            _ => {}
+        }
+        if let Some(tls) = &self.tls {
+            for path in [&tls.ca_certificate, &tls.certificate, &tls.key] {
+                std::fs::metadata(path).map_err(|e| format!("Can not read TLS file {:?}: {}", path, e))?;
+            }
+        }
+        for query_name in &hosted_query_names {
+            for peer in Router::get_required_peers(query_name, &all_queries_by_name, self.central) {
+                if !self.peers.contains_key(&peer) {
+                    return Err(if peer == "central" {
+                        "central is used, but there is no url for it in peers".to_string()
+                    } else {
+                        format!("No url for {} in peers, which {} sends updates to", peer, query_name)
+                    });
+                }
+            }
+        }
+        Ok(ComponentSetup {
+            queries,
+            hosted_query_names,
+            application_url: self.application.url.clone(),
+            port: self.identity.port,
+            query_url_map: self.peers.clone(),
+            use_central: self.central,
+            options: self.create_options()?,
+        })
+    }
+
+    fn get_hosted_query_names(&self) -> Result<Vec<String>, String> {
+        let hosted_query_names = match (&self.queries.hosted, &self.queries.assignments) {
+            (Some(hosted), None) => hosted.clone(),
+            (None, Some(assignments)) => {
+                let host_number = match self.identity.host_number {
+                    Some(host_number) => host_number,
+                    None => get_host_number_from_hostname()?,
+                };
+                info!("Host number is: {}", host_number);
+                match assignments.get(&host_number) {
+                    Some(hosted) => hosted.clone(),
+                    None => {
+                        return Err(format!(
+                            "Host number {} not found in queries.assignments, which has {:?}",
+                            host_number,
+                            assignments.keys().collect::<Vec<_>>()
+                        ));
+                    }
+                }
+            }
+            _ => return Err("Exactly one of queries.hosted and queries.assignments must be set".to_string()),
+        };
+        if hosted_query_names.is_empty() {
+            return Err("No hosted queries".to_string());
+        }
+        Ok(hosted_query_names)
+    }
+
+    fn create_options(&self) -> Result<ComponentOptions, String> {
+        let mut options = ComponentOptions::default();
+        if let Some(loop_budget) = self.limits.loop_budget {
+            options.loop_budget = loop_budget;
+        }
+        if let Some(call_timeout_ms) = self.timeouts.call_timeout_ms {
+            options.caller.call_timeout = Duration::from_millis(call_timeout_ms);
+        }
+        if let Some(call_retries) = self.timeouts.call_retries {
+            options.caller.max_retries = call_retries;
+        }
+        if let Some(unavailable_policy) = &self.timeouts.unavailable_policy {
+            options.caller.unavailable_policy = UnavailablePolicy::from_str(unavailable_policy)?;
+        }
+        if let Some(full_queue_wait_ms) = self.timeouts.full_queue_wait_ms {
+            options.queue_limits.full_queue_wait = Duration::from_millis(full_queue_wait_ms);
+        }
+        if let Some(finality_heartbeat_ms) = self.timeouts.finality_heartbeat_ms {
+            options.finality_heartbeat = Duration::from_millis(finality_heartbeat_ms);
+        }
+        if let Some(max_queued_events) = self.limits.max_queued_events {
+            options.queue_limits.max_events = max_queued_events;
+        }
+        if let Some(max_queued_deltas) = self.limits.max_queued_deltas {
+            options.queue_limits.max_deltas = max_queued_deltas;
+        }
+        if let Some(max_queued_retractions) = self.limits.max_queued_retractions {
+            options.queue_limits.max_retractions = max_queued_retractions;
+        }
+        options.memory_limits.soft_limit_bytes = self.limits.soft_memory_limit_mb.map(|mb| mb * 1024 * 1024);
+        options.memory_limits.hard_limit_bytes = self.limits.hard_memory_limit_mb.map(|mb| mb * 1024 * 1024);
+        if let Some(hard_memory_limit_policy) = &self.limits.hard_memory_limit_policy {
+            options.memory_limits.hard_limit_policy = HardLimitPolicy::from_str(hard_memory_limit_policy)?;
+        }
+        options.watermark_sources = self.watermark_sources.clone();
+        for (query_name, lateness) in &self.lateness {
+            let mut lateness_options = LatenessOptions {
+                allowed_lateness: lateness.allowed_lateness,
+                ..LatenessOptions::default()
+            };
+            if let Some(policy) = &lateness.policy {
+                lateness_options.policy = LatePolicy::from_str(policy)?;
+            }
+            options.lateness.insert(query_name.clone(), lateness_options);
+        }
+        for (operator, token_path) in &self.operators {
+            let token = read_to_string(token_path)
+                .map_err(|e| format!("Can not read the token of operator {} from {:?}: {}", operator, token_path, e))?;
+            if token.trim().is_empty() {
+                return Err(format!("The token of operator {} in {:?} is empty", operator, token_path));
+            }
+            options.operators.insert(operator.clone(), token.trim().to_string());
+        }
+        options.replicas = self.replication.replicas.clone();
+        if let Some(heartbeat_ms) = self.replication.heartbeat_ms {
+            options.replication_heartbeat = Duration::from_millis(heartbeat_ms);
+        }
+        options.standby = self.replication.standby_port.map(|port| StandbyOptions {
+            port,
+            failover_timeout: Duration::from_millis(self.replication.failover_timeout_ms.unwrap_or(2000)),
+        });
+        options.log_dir = self.storage.log_dir.clone();
+        options.metrics_port = self.observability.metrics_port;
+        options.trace_export = match (&self.observability.trace_file, &self.observability.trace_collector) {
+            (Some(_), Some(_)) => {
+                return Err("Only one of observability.trace_file and observability.trace_collector can be set".to_string());
+            }
+            (Some(path), None) => Some(TraceExport::File(path.clone())),
+            (None, Some(url)) => Some(TraceExport::Collector(url.clone())),
+            (None, None) => None,
+        };
+        options.tls = self.tls.as_ref().map(|tls| TlsOptions {
+            ca_certificate_path: tls.ca_certificate.clone(),
+            certificate_path: tls.certificate.clone(),
+            key_path: tls.key.clone(),
+            allowed_client_hosts: tls.allowed_client_hosts.iter().cloned().collect(),
+        });
+        if let Some(wasm_dir) = &self.application.wasm_dir {
+            let mut limits = WasmLimits::default();
+            if let Some(wasm_fuel) = self.application.wasm_fuel {
+                limits.fuel = wasm_fuel;
+            }
+            if let Some(wasm_max_memory_mb) = self.application.wasm_max_memory_mb {
+                limits.max_memory_bytes = wasm_max_memory_mb * 1024 * 1024;
+            }
+            load_wasm_interpreters(wasm_dir, &limits, &mut options.interpreters)?;
+        }
+        Ok(options)
+    }
+
+    fn resolve_paths(&mut self, base_dir: &Path) {
+        //Joining keeps absolute paths as they are
+        self.queries.path = base_dir.join(&self.queries.path);
+        for path in [
+            &mut self.storage.log_dir,
+            &mut self.application.wasm_dir,
+            &mut self.observability.trace_file,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *path = base_dir.join(&path);
+        }
+        if let Some(tls) = &mut self.tls {
+            for path in [&mut tls.ca_certificate, &mut tls.certificate, &mut tls.key] {
+                *path = base_dir.join(&path);
+            }
+        }
+        for path in self.operators.values_mut() {
+            *path = base_dir.join(&path);
+        }
+    }
+}
+
+fn get_host_number_from_hostname() -> Result<u16, String> {
+    let hostname = hostname::get()
+        .map_err(|e| format!("Could not find hostname: {}", e))?
+        .into_string()
+        .map_err(|_| "Could not convert hostname to string".to_string())?;
+    debug!("Hostname is: {}", hostname);
+    hostname
+        .rsplit('-')
+        .next()
+        .and_then(|suffix| suffix.parse().ok())
+        .ok_or(format!(
+            "identity.host_number is not set, and hostname {} does not end with -N, where N is a number",
+            hostname
+        ))
+}
+
+//With TLS the links must be https, since a plaintext url would be read as a request for plaintext
+fn check_url(field: &str, url: &str, use_tls: bool) -> Result<(), String> {
+    let expected_scheme = if use_tls { "https" } else { "http" };
+    match url.parse::<tonic::transport::Uri>() {
+        Ok(uri) if uri.scheme_str() == Some(expected_scheme) && uri.host().is_some() => Ok(()),
+        _ => Err(format!(
+            "{} is not an url such as {}://localhost:10000: {}",
+            field, expected_scheme, url
+        )),
+    }
+}
+
+//Numbers and booleans in environment variables are read as in the config file
+fn parse_scalar(s: &str) -> Value {
+    match serde_yaml::from_str(s) {
+        Ok(Value::Mapping(_)) | Ok(Value::Sequence(_)) | Err(_) => Value::String(s.to_string()),
+        Ok(value) => value,
+    }
+}
+
+fn apply_override(value: &mut Value, path: &[Value], override_value: Value) -> Result<(), String> {
+    if value.is_null() {
+        *value = Value::Mapping(Mapping::new());
+    }
+    let mapping = match value.as_mapping_mut() {
+        Some(mapping) => mapping,
+        None => return Err(format!("{:?} is not a section", path[0])),
+    };
+    match path {
+        [key] => {
+            mapping.insert(key.clone(), override_value);
+            Ok(())
+        }
+        [key, rest @ ..] => {
+            if !mapping.contains_key(key) {
+                mapping.insert(key.clone(), Value::Null);
+            }
+            apply_override(mapping.get_mut(key).unwrap(), rest, override_value)
+        }
+        [] => Err("Empty path".to_string()),
+    }
+}
+
+#[cfg(test)]
+fn create_test_config_dir(name: &str) -> PathBuf {
+    use mbei_core::graph::Graph;
+    let mut dir = std::env::temp_dir();
+    dir.push(format!("mbei-config-{}-{}", name, std::process::id()));
+    let _ = std::fs::create_dir(&dir);
+    let config = bincode::config::standard();
+    let mut queries = std::collections::HashMap::new();
+    for name in ["q1", "q2"] {
+        let query = Query {
+            name: name.to_string(),
+            application: "crane".to_string(),
+            graph: Graph::from_edges(vec![]),
+            optional_edges: BTreeSet::new(),
+            group: BTreeSet::new(),
+            output_edges: BTreeSet::new(),
+            input_nodes: BTreeSet::new(),
+        };
+        queries.insert(name.to_string(), query.to_bytestring(config));
+    }
+    let mut queries_path = dir.clone();
+    queries_path.push("all-queries.yaml");
+    serde_yaml::to_writer(std::fs::File::create(&queries_path).unwrap(), &queries).unwrap();
+    dir
+}
+
+#[test]
+fn test_config_is_overridden_by_environment() {
+    let content = "
+version: 1
+identity:
+  port: 10000
+queries:
+  path: all-queries.yaml
+  assignments:
+    0: [q1]
+    1: [q2]
+application:
+  url: http://localhost:9999
+watermark_sources: [p0, p1]
+";
+    let env = vec![
+        ("MBEI_COMPONENT__IDENTITY__HOST_NUMBER".to_string(), "1".to_string()),
+        ("MBEI_COMPONENT__PEERS__CENTRAL".to_string(), "http://localhost:10000".to_string()),
+        ("MBEI_COMPONENT__CENTRAL".to_string(), "true".to_string()),
+        ("MBEI_COMPONENT__STORAGE__LOG_DIR".to_string(), "logs".to_string()),
+        ("OTHER_VARIABLE".to_string(), "ignored".to_string()),
+    ];
+    let dir = create_test_config_dir("override");
+    let config = ComponentConfig::parse(content, &dir, env.into_iter()).unwrap();
+    assert_eq!(config.identity.host_number, Some(1));
+    assert!(config.central);
+    assert_eq!(config.storage.log_dir, Some(dir.join("logs")));
+    let setup = config.validate().unwrap();
+    assert_eq!(setup.hosted_query_names, vec!["q2".to_string()]);
+    assert_eq!(setup.port, 10000);
+    assert_eq!(setup.query_url_map["central"], "http://localhost:10000");
+    assert_eq!(setup.options.watermark_sources, BTreeSet::from(["p0".to_string(), "p1".to_string()]));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_config_errors_are_reported() {
+    let dir = create_test_config_dir("errors");
+    let check = |content: &str, expected: &str| {
+        let result = ComponentConfig::parse(content, &dir, std::iter::empty()).and_then(|c| c.validate().map(|_| ()));
+        let error = result.expect_err("Config should be rejected");
+        assert!(error.contains(expected), "{} does not contain {}", error, expected);
+    };
+    let valid = "
+version: 1
+identity: {port: 10000}
+queries: {path: all-queries.yaml, hosted: [q1]}
+application: {url: 'http://localhost:9999'}
+";
+    assert!(ComponentConfig::parse(valid, &dir, std::iter::empty()).unwrap().validate().is_ok());
+    check("identity: {port: 10000}", "Missing version");
+    check(&valid.replace("version: 1", "version: 2"), "Unsupported version");
+    check(&valid.replace("port: 10000", "prot: 10000"), "unknown field `prot`");
+    check(&valid.replace("hosted: [q1]", "hosted: [q3]"), "Hosted query q3");
+    check(&valid.replace("hosted: [q1]", "hosted: [q1], assignments: {0: [q1]}"), "Exactly one of");
+    check(&valid.replace("http://localhost:9999", "localhost"), "application.url");
+    check(&(valid.to_string() + "central: true"), "no url for it in peers");
+    check(&(valid.to_string() + "lateness: {q3: {allowed_lateness: 10}}"), "lateness.q3 is not a query");
+    check(&(valid.to_string() + "lateness: {q1: {policy: drop}}"), "Unknown late policy drop");
+    check(&(valid.to_string() + "operators: {alice: alice.token}"), "Can not read the token of operator alice");
+    check(&(valid.to_string() + "replication: {replicas: {q2: 'http://standby:10001'}}"), "replication.replicas.q2 is not a hosted query");
+    check(
+        &(valid.to_string() + "replication: {replicas: {q1: 'http://standby:10001'}, standby_port: 10001}"),
+        "Standbys can not have replicas",
+    );
+    check(&(valid.to_string() + "replication: {standby_port: 10000}"), "must differ from identity.port");
+    check(&(valid.to_string() + "limits: {soft_memory_limit_mb: 2, hard_memory_limit_mb: 1}"), "must not be above");
+    check(&(valid.to_string() + "limits: {hard_memory_limit_policy: spill}"), "needs storage.log_dir");
+    check(&(valid.to_string() + "limits: {hard_memory_limit_policy: drop}"), "Unknown hard limit policy drop");
+    check(
+        &(valid.to_string() + "tls: {ca_certificate: ca.pem, certificate: c.pem, key: c.key}"),
+        "application.url is not an url such as https",
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
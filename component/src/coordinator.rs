@@ -0,0 +1,186 @@
+/*Copyright 2022 Prediktor AS
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.*/
+
+//! Shuts down the whole dataflow without discarding queued or in-flight updates.
+//!
+//! Queries are drained in stages, each stage after the queries which send updates to it have stopped.
+//! A drained component rejects new events, but keeps processing updates until nothing is queued or being processed,
+//! and every update it sent has been acknowledged. It is then stopped, so the next stage receives no more updates.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::Duration;
+
+use log::info;
+use serde::Serialize;
+use tokio::time::Instant;
+use tonic::transport::Channel;
+
+use mbei_core::event::{AdminCommand, Update};
+use mbei_core::query::Query;
+use mbei_grpc::process_update::process_update_client::ProcessUpdateClient;
+use mbei_grpc::process_update::{GetStatusRequest, ProcessUpdateRequest};
+use mbei_grpc::process_update_mapping::{request_from_update, set_request_target_query};
+use mbei_grpc::tls::{create_endpoint, TlsOptions};
+
+use crate::router::Router;
+
+/// The stages the cluster was stopped in, with the number of updates each query accepted before it stopped.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct DrainReport {
+    pub stages: Vec<BTreeMap<String, u64>>,
+}
+
+/// Queries in the order they are drained. Central, when used, receives from all queries and is drained last.
+/// Queries which send updates to each other in a cycle are drained together, with the queries after them.
+pub fn compute_drain_stages(all_queries_by_name: &BTreeMap<String, Query>, use_central: bool) -> Vec<BTreeSet<String>> {
+    let edge_forward_maps = Router::compute_edge_forward_closure(Router::compute_edge_forward_maps(all_queries_by_name), all_queries_by_name);
+    let reachable_map = Router::compute_reachable_map(&edge_forward_maps);
+    let mut remaining: BTreeSet<String> = all_queries_by_name.keys().cloned().collect();
+    let mut stages = vec![];
+    while !remaining.is_empty() {
+        let mut stage: BTreeSet<String> = remaining
+            .iter()
+            .filter(|q| !remaining.iter().any(|sender| sender != *q && reachable_map[sender].contains(*q)))
+            .cloned()
+            .collect();
+        if stage.is_empty() {
+            stage = remaining.clone();
+        }
+        remaining = remaining.difference(&stage).cloned().collect();
+        stages.push(stage);
+    }
+    if use_central {
+        stages.push(BTreeSet::from(["central".to_string()]));
+    }
+    stages
+}
+
+/// Drains and stops every query, and central if used, stage by stage.
+/// Returns when the cluster has reached a consistent stop, or an error if a stage does not drain within the stage timeout.
+pub async fn drain_cluster(
+    all_queries_by_name: &BTreeMap<String, Query>,
+    query_url_map: &BTreeMap<String, String>,
+    use_central: bool,
+    tls: Option<&TlsOptions>,
+    poll_interval: Duration,
+    stage_timeout: Duration,
+) -> Result<DrainReport, String> {
+    let stages = compute_drain_stages(all_queries_by_name, use_central);
+    //Connecting first, so that nothing is stopped when some component can not be reached
+    let mut clients = BTreeMap::new();
+    for query_name in stages.iter().flatten() {
+        let url = query_url_map
+            .get(query_name)
+            .ok_or_else(|| format!("No url for {}", query_name))?;
+        let client = ProcessUpdateClient::connect(create_endpoint(url, tls)?)
+            .await
+            .map_err(|e| format!("Could not connect to {} at {}: {}", query_name, url, e))?;
+        clients.insert(query_name.clone(), client);
+    }
+    let mut report = DrainReport { stages: vec![] };
+    for stage in stages {
+        info!("Draining {:?}", &stage);
+        let mut stage_clients: BTreeMap<String, ProcessUpdateClient<Channel>> = stage
+            .iter()
+            .map(|q| (q.clone(), clients.remove(q).unwrap()))
+            .collect();
+        for (query_name, client) in stage_clients.iter_mut() {
+            send(client, query_name, &Update::Admin(AdminCommand::Drain)).await?;
+        }
+        let n_accepted = await_drained(&mut stage_clients, poll_interval, stage_timeout).await?;
+        for (query_name, client) in stage_clients.iter_mut() {
+            send(client, query_name, &Update::Stop).await?;
+        }
+        info!("Drained and stopped {:?}", &stage);
+        report.stages.push(n_accepted);
+    }
+    info!("Cluster reached a consistent stop");
+    Ok(report)
+}
+
+//Queries in a stage may send updates to each other, so they are only drained together when none of them
+//accepted an update between two polls where all were drained
+async fn await_drained(
+    clients: &mut BTreeMap<String, ProcessUpdateClient<Channel>>,
+    poll_interval: Duration,
+    timeout: Duration,
+) -> Result<BTreeMap<String, u64>, String> {
+    let deadline = Instant::now() + timeout;
+    let mut previous_n_accepted = None;
+    loop {
+        let mut n_accepted = BTreeMap::new();
+        let mut all_drained = true;
+        for (query_name, client) in clients.iter_mut() {
+            let status = client
+                .get_status(GetStatusRequest { target_query: query_name.clone() })
+                .await
+                .map_err(|e| format!("Could not get status of {}: {}", query_name, e))?
+                .into_inner();
+            if !status.draining {
+                //Draining is not kept across a restart
+                send(client, query_name, &Update::Admin(AdminCommand::Drain)).await?;
+            }
+            all_drained &= status.drained;
+            n_accepted.insert(query_name.clone(), status.n_accepted);
+        }
+        if all_drained && previous_n_accepted.as_ref() == Some(&n_accepted) {
+            return Ok(n_accepted);
+        }
+        previous_n_accepted = if all_drained { Some(n_accepted) } else { None };
+        if Instant::now() >= deadline {
+            return Err(format!("{:?} did not drain within {:?}", clients.keys().collect::<Vec<_>>(), timeout));
+        }
+        tokio::time::sleep(poll_interval).await;
+    }
+}
+
+async fn send(client: &mut ProcessUpdateClient<Channel>, query_name: &str, update: &Update) -> Result<(), String> {
+    let mut request: ProcessUpdateRequest = request_from_update(update);
+    set_request_target_query(&mut request, query_name);
+    client
+        .send(request)
+        .await
+        .map(|_| ())
+        .map_err(|e| format!("Could not send to {}: {}", query_name, e))
+}
+
+#[test]
+fn test_drain_stages_follow_routing() {
+    use mbei_core::graph::{Edge, Graph, Node};
+    let edge = |edge_type: &str| Edge::without_timestamp(Node::object_query_node("a", "A"), Node::object_query_node("b", "B"), edge_type);
+    let query = |name: &str, edge_types: Vec<&str>, output_edge_types: Vec<&str>| Query {
+        name: name.to_string(),
+        application: "crane".to_string(),
+        graph: Graph::from_edges(edge_types.into_iter().map(edge).collect()),
+        optional_edges: BTreeSet::new(),
+        group: BTreeSet::new(),
+        output_edges: output_edge_types.into_iter().map(edge).collect(),
+        input_nodes: BTreeSet::new(),
+    };
+    let all_queries_by_name: BTreeMap<String, Query> = vec![
+        query("source", vec!["Input"], vec!["X"]),
+        query("middle", vec!["X"], vec!["Y"]),
+        query("sink", vec!["Y"], vec![]),
+        query("cycle_1", vec!["Z"], vec!["Z"]),
+        query("cycle_2", vec!["Z"], vec!["Z"]),
+    ]
+    .into_iter()
+    .map(|q| (q.name.clone(), q))
+    .collect();
+    let stage = |names: Vec<&str>| names.into_iter().map(|n| n.to_string()).collect::<BTreeSet<String>>();
+    assert_eq!(
+        compute_drain_stages(&all_queries_by_name, true),
+        vec![stage(vec!["source"]), stage(vec!["middle"]), stage(vec!["sink"]), stage(vec!["cycle_1", "cycle_2"]), stage(vec!["central"])]
+    );
+}
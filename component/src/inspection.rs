@@ -0,0 +1,196 @@
+/*Copyright 2022 Prediktor AS
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.*/
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{oneshot, Mutex};
+use tonic::{Request, Response, Status};
+
+use mbei_core::event::Event;
+use mbei_core::graph::Edge;
+use mbei_grpc::inspection::inspection_server::Inspection;
+use mbei_grpc::inspection::{
+    DeltasBinding, GetMatchesRequest, GetMatchesResponse, GetQueueRequest, GetQueueResponse,
+    ListEdgesRequest, ListEdgesResponse, ListEventsRequest, ListEventsResponse,
+    ListQuarantinedUpdatesRequest, ListQuarantinedUpdatesResponse, ListRejectedUpdatesRequest,
+    ListRejectedUpdatesResponse, MatchRecord, OptionalHash, QuarantinedUpdate, RejectedUpdate,
+};
+use mbei_grpc::inspection_mapping::{from_optional_timestamp, to_proto_edge, to_proto_events};
+use mbei_grpc::process_update_mapping::request_from_update;
+use mbei_grpc::process_update_server::{select_target, Queue};
+
+use crate::component::Component;
+use crate::quarantine::describe_update;
+
+/// Queries about the store, answered by the component server between updates, since the component owns the store.
+pub(crate) enum InspectionQuery {
+    Edges(u64, oneshot::Sender<Vec<Edge>>),
+    Events(u64, Option<u64>, oneshot::Sender<Vec<Event>>),
+    Matches(String, oneshot::Sender<Vec<MatchRecord>>),
+    RejectedUpdates(oneshot::Sender<Vec<RejectedUpdate>>),
+    QuarantinedUpdates(oneshot::Sender<Vec<QuarantinedUpdate>>),
+}
+
+pub(crate) fn answer_inspection_query(query: InspectionQuery, component: &Component) {
+    let store = component.get_store();
+    //The requester may have gone away, in which case there is nobody to answer
+    match query {
+        InspectionQuery::Edges(timestamp, responder) => {
+            let _ = responder.send(store.get_edges_at_timestamp(timestamp));
+        }
+        InspectionQuery::Events(from, to, responder) => {
+            let events = store
+                .get_event_ids_in_interval(from, to)
+                .iter()
+                .filter_map(|event_id| store.get_event_by_event_id(event_id))
+                .collect();
+            let _ = responder.send(events);
+        }
+        InspectionQuery::Matches(event_id, responder) => {
+            let matches = store
+                .get_event_output_hash_by_match_hash(&event_id)
+                .into_iter()
+                .map(|(match_hash, output_hash)| MatchRecord {
+                    match_hash,
+                    output_hash: output_hash.map(|hash| OptionalHash { hash }),
+                    bindings: store
+                        .get_deltas_ids_for_event_id_and_match_hash(&event_id, &match_hash)
+                        .into_iter()
+                        .flatten()
+                        .map(|b| DeltasBinding {
+                            topic_name: b.topic_name.clone(),
+                            deltas_id: b.deltas_id.clone(),
+                        })
+                        .collect(),
+                })
+                .collect();
+            let _ = responder.send(matches);
+        }
+        InspectionQuery::RejectedUpdates(responder) => {
+            let rejected_updates = component
+                .get_rejected_updates()
+                .iter()
+                .map(|r| RejectedUpdate {
+                    update: r.update.clone(),
+                    reason: r.reason.clone(),
+                })
+                .collect();
+            let _ = responder.send(rejected_updates);
+        }
+        InspectionQuery::QuarantinedUpdates(responder) => {
+            let quarantined_updates = component
+                .get_quarantined_updates()
+                .into_iter()
+                .map(|q| QuarantinedUpdate {
+                    quarantine_id: q.quarantine_id.clone(),
+                    update: describe_update(&q.update),
+                    update_chain: q.update_chain.clone(),
+                    pending_updates: q.pending_updates.iter().map(describe_update).collect(),
+                    loops: q.loops,
+                })
+                .collect();
+            let _ = responder.send(quarantined_updates);
+        }
+    }
+}
+
+pub(crate) struct InspectedComponent {
+    pub(crate) query_sender: UnboundedSender<InspectionQuery>,
+    pub(crate) queue: Arc<Mutex<Queue>>,
+}
+
+/// Inspects the components served by a process, by query name.
+pub(crate) struct InspectionService {
+    pub(crate) components: BTreeMap<String, InspectedComponent>,
+}
+
+impl InspectionService {
+    async fn ask<T>(&self, query_name: &str, create_query: impl FnOnce(oneshot::Sender<T>) -> InspectionQuery) -> Result<T, Status> {
+        let component = select_target(&self.components, query_name)?;
+        let (responder, receiver) = oneshot::channel();
+        component
+            .query_sender
+            .send(create_query(responder))
+            .map_err(|_| Status::unavailable("Component is not running"))?;
+        receiver
+            .await
+            .map_err(|_| Status::unavailable("Component stopped before answering"))
+    }
+}
+
+#[tonic::async_trait]
+impl Inspection for InspectionService {
+    async fn list_edges(
+        &self,
+        request: Request<ListEdgesRequest>,
+    ) -> Result<Response<ListEdgesResponse>, Status> {
+        let timestamp = request.get_ref().timestamp;
+        let edges = self.ask(&request.get_ref().query_name, |r| InspectionQuery::Edges(timestamp, r)).await?;
+        Ok(Response::new(ListEdgesResponse {
+            edges: edges.iter().map(to_proto_edge).collect(),
+        }))
+    }
+
+    async fn list_events(
+        &self,
+        request: Request<ListEventsRequest>,
+    ) -> Result<Response<ListEventsResponse>, Status> {
+        let request = request.into_inner();
+        let from = request.from_timestamp;
+        let to = from_optional_timestamp(request.to_timestamp);
+        let events = self.ask(&request.query_name, |r| InspectionQuery::Events(from, to, r)).await?;
+        Ok(Response::new(ListEventsResponse {
+            events: to_proto_events(events.iter().collect()),
+        }))
+    }
+
+    async fn get_matches(
+        &self,
+        request: Request<GetMatchesRequest>,
+    ) -> Result<Response<GetMatchesResponse>, Status> {
+        let request = request.into_inner();
+        let event_id = request.event_id;
+        let matches = self.ask(&request.query_name, |r| InspectionQuery::Matches(event_id, r)).await?;
+        Ok(Response::new(GetMatchesResponse { matches }))
+    }
+
+    async fn get_queue(
+        &self,
+        request: Request<GetQueueRequest>,
+    ) -> Result<Response<GetQueueResponse>, Status> {
+        let component = select_target(&self.components, &request.get_ref().query_name)?;
+        let updates = component.queue.lock().await.get_updates();
+        Ok(Response::new(GetQueueResponse {
+            updates: updates.iter().map(request_from_update).collect(),
+        }))
+    }
+
+    async fn list_rejected_updates(
+        &self,
+        request: Request<ListRejectedUpdatesRequest>,
+    ) -> Result<Response<ListRejectedUpdatesResponse>, Status> {
+        let rejected_updates = self.ask(&request.get_ref().query_name, InspectionQuery::RejectedUpdates).await?;
+        Ok(Response::new(ListRejectedUpdatesResponse { rejected_updates }))
+    }
+
+    async fn list_quarantined_updates(
+        &self,
+        request: Request<ListQuarantinedUpdatesRequest>,
+    ) -> Result<Response<ListQuarantinedUpdatesResponse>, Status> {
+        let quarantined_updates = self.ask(&request.get_ref().query_name, InspectionQuery::QuarantinedUpdates).await?;
+        Ok(Response::new(ListQuarantinedUpdatesResponse { quarantined_updates }))
+    }
+}
@@ -0,0 +1,64 @@
+/*Copyright 2022 Prediktor AS
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.*/
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use mbei_core::event::Event;
+use mbei_core::graph::Delta;
+use mbei_core::query::{GroupedQueryMatch, Query};
+
+/// An event interpreter that runs in the same process as the component, avoiding the gRPC round trip to the application.
+/// It receives the same information as an application called over gRPC, and returns the deltas of the interpretation,
+/// or a description of why the interpretation failed.
+pub trait Interpreter: Send + Sync {
+    fn interpret(&self, query: &Query, grouped_match: &GroupedQueryMatch, event: &Event) -> Result<Vec<Delta>, String>;
+}
+
+/// Native interpreters hosted by a component, by the application name used in Query::application.
+#[derive(Clone, Default)]
+pub struct InterpreterRegistry {
+    interpreters_by_application: BTreeMap<String, Arc<dyn Interpreter>>,
+}
+
+impl InterpreterRegistry {
+    pub fn new() -> InterpreterRegistry {
+        InterpreterRegistry {
+            interpreters_by_application: BTreeMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, application: &str, interpreter: Arc<dyn Interpreter>) {
+        self.interpreters_by_application
+            .insert(application.to_string(), interpreter);
+    }
+
+    pub fn get(&self, application: &str) -> Option<&Arc<dyn Interpreter>> {
+        self.interpreters_by_application.get(application)
+    }
+
+    pub fn contains(&self, application: &str) -> bool {
+        self.interpreters_by_application.contains_key(application)
+    }
+}
+
+impl Debug for InterpreterRegistry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_set()
+            .entries(self.interpreters_by_application.keys())
+            .finish()
+    }
+}
@@ -32,7 +32,6 @@ impl ReprocessInterval {
                 to: Some(other_from - 1),
             });
             if other_to.is_none() {
-                ();
             } else if self.to.is_none() {
                 result.push(ReprocessInterval {
                     from: other_to.unwrap() + 1,
@@ -45,14 +44,12 @@ impl ReprocessInterval {
                 });
             } else {
                 //We know that other is finite and ends before self.
-                ();
             }
         } else {
             // self is starting equal to or after other
             if other_to.is_none() {
                 //If the other has no end, then it is contained in other, and there is nothing left.
                 // | other start | self start -- other never ends
-                ();
             } else if self.to.is_none() {
                 // | other start | self start | other end -- self never ends
                 result.push(ReprocessInterval {
@@ -62,7 +59,6 @@ impl ReprocessInterval {
             } else if self.to.unwrap() <= other_to.unwrap() {
                 // Again self is conained in other, this time in a finite setting
                 // | other start | self start self end | other end |
-                ();
             } else if self.from < other_to.unwrap() {
                 // | other start | self start | other end | self end
                 result.push(ReprocessInterval {
@@ -71,7 +67,6 @@ impl ReprocessInterval {
                 });
             } else {
                 //
-                ();
             }
         }
         result
@@ -84,7 +79,7 @@ impl ReprocessInterval {
         if other_from <= self.from && (other_to.is_none() || other_to.unwrap() > self.from) {
             return true;
         }
-        return false;
+        false
     }
 }
 
@@ -101,7 +96,7 @@ pub(crate) fn find_intervals_to_reprocess(
     for upd in updated_edges_with_timestamp {
         let first_upd_reprocess = ReprocessInterval {
             from: upd.from_timestamp.unwrap(),
-            to: upd.to_timestamp.clone(),
+            to: upd.to_timestamp,
         };
         let mut upd_reprocess_vec = vec![first_upd_reprocess];
         for ex in existing_edges_with_timestamp {
@@ -126,7 +121,7 @@ pub(crate) fn find_intervals_to_reprocess(
     for ex in existing_edges_with_timestamp {
         let first_ex_reprocess = ReprocessInterval {
             from: ex.from_timestamp.unwrap(),
-            to: ex.to_timestamp.clone(),
+            to: ex.to_timestamp,
         };
         let mut ex_reprocess_vec = vec![first_ex_reprocess];
         for upd in updated_edges_with_timestamp {
@@ -313,7 +308,7 @@ fn test_subtract_easy1_correct() {
     let res = repr.subtract(0, &Some(2));
 
     assert_eq!(res.len(), 1);
-    let res0 = res.get(0).unwrap();
+    let res0 = res.first().unwrap();
     assert_eq!(res0.from, 3);
     assert_eq!(res0.to, Some(3));
 }
@@ -327,7 +322,7 @@ fn test_subtract_easy2_correct() {
     let res = repr.subtract(0, &Some(2));
 
     assert_eq!(res.len(), 1);
-    let res0 = res.get(0).unwrap();
+    let res0 = res.first().unwrap();
     assert_eq!(res0.from, 3);
     assert_eq!(res0.to, Some(3));
 }
@@ -349,7 +344,7 @@ fn test_subtract_easy4_correct() {
     let res = repr.subtract(0, &Some(2));
 
     assert_eq!(res.len(), 1);
-    let res0 = res.get(0).unwrap();
+    let res0 = res.first().unwrap();
     assert_eq!(res0.from, 3);
     assert_eq!(res0.to, None);
 }
@@ -360,7 +355,7 @@ fn test_subtract_easy5_correct() {
     let res = repr.subtract(2, &None);
 
     assert_eq!(res.len(), 1);
-    let res0 = res.get(0).unwrap();
+    let res0 = res.first().unwrap();
     assert_eq!(res0.from, 1);
     assert_eq!(res0.to, Some(1));
 }
@@ -373,7 +368,7 @@ fn test_subtract_two_results1_correct() {
     };
     let res = repr.subtract(1, &Some(2));
     assert_eq!(res.len(), 2, "Should have two results");
-    let res0 = res.get(0).unwrap();
+    let res0 = res.first().unwrap();
     assert_eq!(res0.from, 0);
     assert_eq!(res0.to, Some(0));
 
@@ -387,7 +382,7 @@ fn test_subtract_two_results2_correct() {
     let repr = ReprocessInterval { from: 0, to: None };
     let res = repr.subtract(1, &Some(2));
     assert_eq!(res.len(), 2, "Should have two results");
-    let res0 = res.get(0).unwrap();
+    let res0 = res.first().unwrap();
     assert_eq!(res0.from, 0);
     assert_eq!(res0.to, Some(0));
 
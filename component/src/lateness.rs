@@ -0,0 +1,177 @@
+/*Copyright 2022 Prediktor AS
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.*/
+
+use std::collections::VecDeque;
+use std::str::FromStr;
+
+use mbei_core::event::Update;
+
+use crate::quarantine::describe_update;
+
+/// What to do with an event or deltas arriving later than allowed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LatePolicy {
+    //Process the update, reprocessing as far back as it requires. Updates before the finality point are parked instead,
+    //since the history they would be processed against has been compacted
+    Process,
+    //Quarantine the update, so that it is retried or discarded by an admin command
+    Park,
+    //Drop the update, and keep a record of why
+    Reject,
+}
+
+impl FromStr for LatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "process" => Ok(LatePolicy::Process),
+            "park" => Ok(LatePolicy::Park),
+            "reject" => Ok(LatePolicy::Reject),
+            other => Err(format!("Unknown late policy {}, expected process, park or reject", other)),
+        }
+    }
+}
+
+impl LatePolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LatePolicy::Process => "process",
+            LatePolicy::Park => "park",
+            LatePolicy::Reject => "reject",
+        }
+    }
+}
+
+/// How late the updates of a query may arrive, in timestamp units behind the latest timestamp it has seen.
+/// Updates before the finality point are always too late, also without a bound.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LatenessOptions {
+    pub allowed_lateness: Option<u64>,
+    pub policy: LatePolicy,
+}
+
+impl Default for LatenessOptions {
+    fn default() -> Self {
+        LatenessOptions {
+            allowed_lateness: None,
+            policy: LatePolicy::Process,
+        }
+    }
+}
+
+//The most recent rejected updates are kept for inspection, and the count of all of them is in the metrics
+const MAX_REJECTED_UPDATES: usize = 1000;
+
+#[derive(Clone, Debug)]
+pub struct RejectedUpdate {
+    pub update: String,
+    pub reason: String,
+}
+
+/// Tells which events and deltas arrive later than allowed, from the latest timestamp seen so far.
+pub(crate) struct LateArrivals {
+    options: LatenessOptions,
+    latest_timestamp: Option<u64>,
+    rejected_updates: VecDeque<RejectedUpdate>,
+}
+
+impl LateArrivals {
+    pub(crate) fn new(options: LatenessOptions) -> LateArrivals {
+        LateArrivals {
+            options,
+            latest_timestamp: None,
+            rejected_updates: VecDeque::new(),
+        }
+    }
+
+    /// The policy and the reason, if the update is too late. Updates in time move the latest timestamp forward.
+    pub(crate) fn check(&mut self, update: &Update, finalized_before: Option<u64>) -> Option<(LatePolicy, String)> {
+        if !matches!(update, Update::Event(_) | Update::Deltas(_)) {
+            return None;
+        }
+        let timestamp = update.timestamp();
+        let lateness = self.latest_timestamp.map_or(0, |latest| latest.saturating_sub(timestamp));
+        let reason = match (finalized_before, self.options.allowed_lateness) {
+            (Some(finalized_before), _) if timestamp < finalized_before => {
+                let policy = match self.options.policy {
+                    LatePolicy::Process => LatePolicy::Park,
+                    policy => policy,
+                };
+                return Some((policy, format!("everything before {} was final", finalized_before)));
+            }
+            (_, Some(allowed_lateness)) if lateness > allowed_lateness => {
+                format!("{} behind the latest timestamp, while {} is allowed", lateness, allowed_lateness)
+            }
+            _ => {
+                self.latest_timestamp = self.latest_timestamp.max(Some(timestamp));
+                return None;
+            }
+        };
+        Some((self.options.policy, reason))
+    }
+
+    pub(crate) fn record_rejected(&mut self, update: &Update, reason: String) {
+        if self.rejected_updates.len() == MAX_REJECTED_UPDATES {
+            self.rejected_updates.pop_front();
+        }
+        self.rejected_updates.push_back(RejectedUpdate {
+            update: describe_update(update),
+            reason,
+        });
+    }
+
+    pub(crate) fn get_rejected_updates(&self) -> &VecDeque<RejectedUpdate> {
+        &self.rejected_updates
+    }
+}
+
+#[test]
+fn test_late_arrivals_are_measured_from_latest_timestamp() {
+    use mbei_core::event::{Event, Retractions};
+    let event = |timestamp| Update::Event(Event {
+        event_id: format!("e{}", timestamp),
+        timestamp,
+        node_id: "abc123".to_string(),
+        payload: vec![],
+    });
+    let mut late_arrivals = LateArrivals::new(LatenessOptions { allowed_lateness: Some(5), policy: LatePolicy::Park });
+    assert_eq!(late_arrivals.check(&event(10), None), None);
+    assert_eq!(late_arrivals.check(&event(5), None), None);
+    assert_eq!(late_arrivals.check(&event(4), None).map(|(policy, _)| policy), Some(LatePolicy::Park));
+    assert_eq!(late_arrivals.check(&event(20), None), None);
+    assert!(late_arrivals.check(&event(12), None).is_some());
+    //Retractions keep the stores consistent, so they are never late
+    let retractions = Update::Retractions(Retractions { retraction_id: "r1".to_string(), timestamp: 1, deltas_ids: vec![] });
+    assert_eq!(late_arrivals.check(&retractions, None), None);
+    //Without a bound, only updates before the finality point are late, and they are parked rather than processed
+    let mut unbounded = LateArrivals::new(LatenessOptions::default());
+    assert_eq!(unbounded.check(&event(10), None), None);
+    assert_eq!(unbounded.check(&event(1), Some(5)).map(|(policy, _)| policy), Some(LatePolicy::Park));
+    assert_eq!(unbounded.check(&event(7), Some(5)), None);
+    let mut rejecting = LateArrivals::new(LatenessOptions { allowed_lateness: None, policy: LatePolicy::Reject });
+    assert_eq!(rejecting.check(&event(1), Some(5)).map(|(policy, _)| policy), Some(LatePolicy::Reject));
+}
+
+#[test]
+fn test_rejected_updates_are_capped() {
+    let mut late_arrivals = LateArrivals::new(LatenessOptions { allowed_lateness: Some(0), policy: LatePolicy::Reject });
+    for i in 0..MAX_REJECTED_UPDATES + 10 {
+        late_arrivals.record_rejected(&Update::Stop, format!("reason {}", i));
+    }
+    let rejected = late_arrivals.get_rejected_updates();
+    assert_eq!(rejected.len(), MAX_REJECTED_UPDATES);
+    //The oldest are dropped first
+    assert_eq!(rejected.front().unwrap().reason, "reason 10");
+}
@@ -13,27 +13,48 @@ See the License for the specific language governing permissions and
 limitations under the License.*/
 
 use std::collections::BTreeMap;
+use std::sync::Arc;
 use std::time::Duration;
 
 use crate::component::Component;
+use crate::inspection::{InspectedComponent, InspectionService};
+use crate::options::ComponentOptions;
 use crate::server::ComponentServer;
+use crate::standby::{serve_standbys, Standby};
 use log::{debug, info};
 use mbei_core::query::Query;
+use mbei_grpc::health_server::Health;
+use mbei_grpc::metrics_server::spawn_metrics_server;
+use mbei_grpc::operators::OperatorTokens;
 use mbei_grpc::process_update_client::await_deliveries;
-use tokio::sync::mpsc::UnboundedSender;
-use tokio::task::JoinHandle;
-use tonic::Status;
-use mbei_grpc::process_update::ProcessUpdateResponse;
+use mbei_grpc::process_update_server::{
+    await_server_handle_with_timeout, create_and_run_server_with_inspection, Queue, ServedQueue,
+};
+use mbei_grpc::replication_server::{ReplicatedLog, Replicator, ServedReplica};
+use mbei_grpc::tracer::{spawn_span_exporter, Tracer};
+use tokio::sync::{Mutex, Semaphore};
 
 pub mod caller;
 mod component;
+pub mod config;
+pub mod coordinator;
+mod inspection;
+pub mod interpreter;
 mod intervals;
+pub mod lateness;
+pub mod memory;
+mod metrics;
+pub mod options;
+pub mod quarantine;
 pub mod router;
 mod server;
+pub mod spill;
+pub mod standby;
 pub mod store;
+pub mod wasm_interpreter;
 
-type JoinHandleType = JoinHandle<Result<tonic::Response<ProcessUpdateResponse>, Status>>;
-
+/// Runs the hosted components until they stop, or returns why they could not be started.
+#[allow(clippy::too_many_arguments)]
 pub async fn start_component_servers(
     queries: Vec<Query>,
     my_query_names: Vec<String>,
@@ -42,70 +63,169 @@ pub async fn start_component_servers(
     query_url_map: BTreeMap<String, String>,
     max_elapsed_time: Option<Duration>,
     use_central: bool,
-) {
+    options: ComponentOptions,
+) -> Result<(), String> {
     let mut all_queries_by_name = BTreeMap::new();
     for query in queries {
         all_queries_by_name.insert(query.name.clone(), query);
     }
     info!("Starting components: {:?}", &my_query_names);
 
+    let metrics_handle = options.metrics_port.map(spawn_metrics_server);
+    let (span_sender, span_exporter_handle) = match &options.trace_export {
+        Some(trace_export) => {
+            let (span_sender, handle) = spawn_span_exporter(trace_export.clone());
+            (Some(span_sender), Some(handle))
+        }
+        None => (None, None),
+    };
     let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
-    let deliveries_handle;
-    deliveries_handle = tokio::spawn(await_deliveries(receiver));
+    let deliveries_handle = tokio::spawn(await_deliveries(receiver));
+    let mut start_error = None;
     {
         //This block is important, since it moves and then drops all senders, causing the recv of the receiver to return None and deliveries handle to finish.
-        let mut component_server_handles = vec![];
-        let mut i = 0;
+        //All components share one server, which puts each update in the queue of the query it targets
+        let mut served_queues = BTreeMap::new();
+        let operators = Arc::new(OperatorTokens::new(&options.operators));
+        let mut inspected_components = BTreeMap::new();
+        let mut component_servers = vec![];
+        let health = Health::default();
+        let mut served_replicas = BTreeMap::new();
+        let took_over = Arc::new(Semaphore::new(0));
         for query_name in &my_query_names {
-            let use_grpc_port = grpc_port + i;
-            let component_server_handle = tokio::spawn(create_owned_component_server_and_run(
+            let arc_queue_mutex = Arc::new(Mutex::new(create_queue(query_name, &options)?));
+            let (new_update_sender, new_update_receiver) = tokio::sync::mpsc::unbounded_channel();
+            let (inspection_query_sender, inspection_query_receiver) = tokio::sync::mpsc::unbounded_channel();
+            inspected_components.insert(
+                query_name.clone(),
+                InspectedComponent { query_sender: inspection_query_sender, queue: arc_queue_mutex.clone() },
+            );
+            let reporter = health.reporter(query_name);
+            let component = Component::new(
                 query_name.clone(),
                 all_queries_by_name.clone(),
+                application_grpc_url.clone(),
                 query_url_map.clone(),
                 use_central,
-                application_grpc_url.clone(),
-                use_grpc_port,
-                max_elapsed_time.clone(),
+                &options,
+                Tracer::new(query_name, span_sender.clone()),
+                reporter.clone(),
+            )?;
+            let replicator = match options.replicas.get(query_name) {
+                Some(url) => Some(Replicator::new(query_name, url, options.tls.as_ref(), options.replication_heartbeat, reporter.clone())?),
+                None => None,
+            };
+            let standby = options.standby.as_ref().map(|standby_options| {
+                let log = Arc::new(std::sync::Mutex::new(ReplicatedLog::default()));
+                let (new_record_sender, new_record_receiver) = tokio::sync::mpsc::unbounded_channel();
+                served_replicas.insert(
+                    query_name.clone(),
+                    ServedReplica { queue: arc_queue_mutex.clone(), log: log.clone(), new_record_sender },
+                );
+                Standby {
+                    log,
+                    new_record_receiver,
+                    failover_timeout: standby_options.failover_timeout,
+                    took_over: took_over.clone(),
+                }
+            });
+            served_queues.insert(
+                query_name.clone(),
+                ServedQueue {
+                    queue: arc_queue_mutex.clone(),
+                    new_update_sender,
+                    outbox: Some(component.get_outbox()),
+                    operators: Some(operators.clone()),
+                    assertion_senders: None,
+                    replicator: replicator.clone(),
+                },
+            );
+            component_servers.push(ComponentServer::new(
+                query_name.clone(),
+                component,
+                arc_queue_mutex,
+                new_update_receiver,
+                inspection_query_receiver,
                 sender.clone(),
+                replicator,
+                options.replication_heartbeat,
+                standby,
             ));
-            component_server_handles.push(component_server_handle);
-            i += 1;
         }
+        let (shutdown_server_sender, shutdown_server_receiver) = tokio::sync::oneshot::channel();
+        let inspection_service = InspectionService { components: inspected_components };
+        //Components and central may send updates to any of the hosted queries
+        let tls = options.tls.as_ref().map(|tls| tls.allowing_hosts_of(query_url_map.values()));
+        let server_handle = match &options.standby {
+            //Standbys serve the port of their primary only when they take over
+            Some(standby_options) => tokio::spawn(serve_standbys(
+                grpc_port,
+                standby_options.port,
+                served_queues,
+                served_replicas,
+                shutdown_server_receiver,
+                inspection_service,
+                health,
+                tls,
+                took_over,
+            )),
+            None => {
+                create_and_run_server_with_inspection(
+                    grpc_port,
+                    served_queues,
+                    shutdown_server_receiver,
+                    inspection_service,
+                    health,
+                    tls.as_ref(),
+                )
+                .await
+            }
+        };
+        info!(
+            "Server for {:?} started on port {} and is ready to serve",
+            &my_query_names, grpc_port
+        );
+        let mut component_server_handles = vec![];
+        for mut component_server in component_servers {
+            component_server_handles.push(tokio::spawn(async move { component_server.run(max_elapsed_time).await }));
+        }
+        //A component which could not start stops, while the others go on
         for h in component_server_handles {
-            h.await.expect("Problem in server");
+            if let Err(e) = h.await.expect("Problem in server") {
+                start_error = Some(e);
+            }
         }
+        //The server is only shut down when all of its components have stopped
+        shutdown_server_sender.send(()).expect("Error sending shutdown");
+        await_server_handle_with_timeout(server_handle, Duration::from_secs(5)).await;
     }
     drop(sender);
+    drop(span_sender);
     debug!(
         "Servers for {:?} was shut down, awaiting deliveries",
         &my_query_names
     );
     deliveries_handle.await.expect("Problem with deliveries");
+    if let Some(span_exporter_handle) = span_exporter_handle {
+        span_exporter_handle.await.expect("Problem exporting spans");
+    }
+    if let Some(metrics_handle) = metrics_handle {
+        metrics_handle.abort();
+    }
     debug!(
         "Finished awaiting deliveries, components {:?} finished",
         &my_query_names
-    )
-}
-
-async fn create_owned_component_server_and_run(
-    my_query_name: String,
-    all_queries_by_name: BTreeMap<String, Query>,
-    query_url_map: BTreeMap<String, String>,
-    use_central: bool,
-    application_grpc_url: String,
-    grpc_port: u16,
-    max_elapsed_time: Option<Duration>,
-    sender: UnboundedSender<JoinHandleType>,
-) {
-    let component = Component::new(
-        my_query_name.clone(),
-        all_queries_by_name,
-        application_grpc_url,
-        query_url_map,
-        use_central,
     );
+    match start_error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
 
-    let mut component_server =
-        ComponentServer::new(my_query_name.clone(), component, grpc_port, sender);
-    component_server.run(max_elapsed_time).await;
+fn create_queue(query_name: &str, options: &ComponentOptions) -> Result<Queue, String> {
+    match options.get_log_path(query_name, "inbox") {
+        Some(inbox_path) => Queue::with_inbox(options.queue_limits.clone(), &inbox_path)
+            .map_err(|e| format!("Could not open inbox {:?}: {}", inbox_path, e)),
+        None => Ok(Queue::with_limits(options.queue_limits.clone())),
+    }
 }
@@ -0,0 +1,218 @@
+/*Copyright 2022 Prediktor AS
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.*/
+
+use std::collections::BTreeMap;
+use std::mem::size_of;
+use std::str::FromStr;
+
+use mbei_core::event::{Deltas, Event};
+use mbei_core::graph::{Delta, Edge, Node};
+
+use crate::store::{DeltaAndDeltasId, TopicNameAndDeltasId};
+
+//Share of the tree nodes of a BTreeMap or BTreeSet taken by each entry, besides the entry itself
+const ENTRY_OVERHEAD: usize = 16;
+
+/// Approximate bytes held by each structure of a store. Bytes of events spilled to disk are not counted.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MemoryUsage {
+    //Events and their timestamp index
+    pub events: usize,
+    //Deltas, by deltas id and by edge, and the ids of retracted updates
+    pub deltas: usize,
+    //Edges in the interval grid, the open edges and the node index
+    pub edges: usize,
+    //Deltas ids of the matches of events and of manual assertions, with their output hashes and generations
+    pub match_bindings: usize,
+}
+
+impl MemoryUsage {
+    pub fn total(&self) -> usize {
+        self.events + self.deltas + self.edges + self.match_bindings
+    }
+
+    pub fn get_bytes_by_structure(&self) -> Vec<(&'static str, usize)> {
+        vec![
+            ("events", self.events),
+            ("deltas", self.deltas),
+            ("edges", self.edges),
+            ("match_bindings", self.match_bindings),
+        ]
+    }
+}
+
+/// What to do when the store grows beyond the hard limit, after compacting it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HardLimitPolicy {
+    //Refuse new events and deltas until the store is below the limit again, which senders see as backpressure
+    Refuse,
+    //Move the earliest events to disk, and refuse new input only if that is not enough
+    Spill,
+}
+
+impl FromStr for HardLimitPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "refuse" => Ok(HardLimitPolicy::Refuse),
+            "spill" => Ok(HardLimitPolicy::Spill),
+            other => Err(format!("Unknown hard limit policy {}, expected refuse or spill", other)),
+        }
+    }
+}
+
+/// Limits on the approximate bytes of the store of a component. Unlimited if left out.
+/// Beyond the soft limit, edges which can not match any events after the finality point are compacted away.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MemoryLimits {
+    pub soft_limit_bytes: Option<usize>,
+    pub hard_limit_bytes: Option<usize>,
+    pub hard_limit_policy: HardLimitPolicy,
+}
+
+impl Default for MemoryLimits {
+    fn default() -> Self {
+        MemoryLimits {
+            soft_limit_bytes: None,
+            hard_limit_bytes: None,
+            hard_limit_policy: HardLimitPolicy::Refuse,
+        }
+    }
+}
+
+impl MemoryLimits {
+    pub fn is_above_soft_limit(&self, usage: &MemoryUsage) -> bool {
+        matches!(self.soft_limit_bytes, Some(limit) if usage.total() > limit)
+    }
+
+    pub fn is_above_hard_limit(&self, usage: &MemoryUsage) -> bool {
+        matches!(self.hard_limit_bytes, Some(limit) if usage.total() > limit)
+    }
+
+    /// What spilling aims for, so that it does not start over with the next event
+    pub(crate) fn get_spill_target(&self) -> usize {
+        self.soft_limit_bytes.or(self.hard_limit_bytes).unwrap_or(usize::MAX)
+    }
+}
+
+/// Bytes held on the heap, which are added to the size of the value itself.
+/// Lengths are used rather than capacities, so that a value removed from the store is counted as when it was added.
+pub(crate) trait HeapSize {
+    fn heap_size(&self) -> usize;
+}
+
+/// Approximate bytes of a value in a map or set of the store.
+pub(crate) fn entry_size<T: HeapSize>(value: &T) -> usize {
+    size_of::<T>() + value.heap_size() + ENTRY_OVERHEAD
+}
+
+/// Approximate bytes of a string key in a map or set of the store.
+pub(crate) fn key_size(key: &str) -> usize {
+    size_of::<String>() + key.len() + ENTRY_OVERHEAD
+}
+
+/// Takes the bytes of a value removed from the store off the bytes of its structure.
+/// Accounting which is off fails debug builds, and stops at zero in release builds rather than wrapping around.
+pub(crate) fn release(bytes: &mut usize, released: usize) {
+    debug_assert!(released <= *bytes, "Releasing {} bytes of {}", released, bytes);
+    *bytes = bytes.saturating_sub(released);
+}
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.len()
+    }
+}
+
+impl HeapSize for u32 {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl<T: HeapSize> HeapSize for Option<T> {
+    fn heap_size(&self) -> usize {
+        self.as_ref().map_or(0, |value| value.heap_size())
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    fn heap_size(&self) -> usize {
+        self.len() * size_of::<T>() + self.iter().map(|value| value.heap_size()).sum::<usize>()
+    }
+}
+
+impl HeapSize for BTreeMap<u64, Option<u64>> {
+    fn heap_size(&self) -> usize {
+        self.len() * (size_of::<(u64, Option<u64>)>() + ENTRY_OVERHEAD)
+    }
+}
+
+impl HeapSize for Node {
+    fn heap_size(&self) -> usize {
+        self.query_node_name.heap_size()
+            + self.instance_node_name.heap_size()
+            + self.node_type.heap_size()
+            + self.value_bytes.as_ref().map_or(0, |value_bytes| value_bytes.len())
+    }
+}
+
+impl HeapSize for Edge {
+    fn heap_size(&self) -> usize {
+        self.src.heap_size() + self.trg.heap_size() + self.edge_type.heap_size()
+    }
+}
+
+impl HeapSize for Delta {
+    fn heap_size(&self) -> usize {
+        self.src.heap_size() + self.trg.heap_size() + self.edge_type.heap_size()
+    }
+}
+
+impl HeapSize for Deltas {
+    fn heap_size(&self) -> usize {
+        self.deltas_id.heap_size() + self.origin_id.heap_size() + self.deltas.iter().map(entry_size).sum::<usize>()
+    }
+}
+
+impl HeapSize for Event {
+    fn heap_size(&self) -> usize {
+        self.event_id.heap_size() + self.node_id.heap_size() + self.payload.len()
+    }
+}
+
+impl HeapSize for DeltaAndDeltasId {
+    fn heap_size(&self) -> usize {
+        self.deltas_id.heap_size() + self.delta.heap_size()
+    }
+}
+
+impl HeapSize for TopicNameAndDeltasId {
+    fn heap_size(&self) -> usize {
+        self.topic_name.heap_size() + self.deltas_id.heap_size()
+    }
+}
+
+#[test]
+fn test_entry_size_grows_with_contents() {
+    let node = Node::material_instance_node("MyBarrel0", "Barrel");
+    let longer_node = Node::material_instance_node("MyBarrelWithALongerName0", "Barrel");
+    assert_eq!(entry_size(&longer_node) - entry_size(&node), "WithALongerName".len());
+    let usage = MemoryUsage { events: 1, deltas: 2, edges: 3, match_bindings: 4 };
+    let limits = MemoryLimits { soft_limit_bytes: Some(5), hard_limit_bytes: Some(10), ..MemoryLimits::default() };
+    assert!(limits.is_above_soft_limit(&usage));
+    assert!(!limits.is_above_hard_limit(&usage));
+    assert!(!MemoryLimits::default().is_above_hard_limit(&usage));
+}
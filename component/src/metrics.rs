@@ -0,0 +1,129 @@
+/*Copyright 2022 Prediktor AS
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.*/
+
+//Metrics are labelled by query, since several components may run in the same process
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, HistogramVec,
+    IntCounterVec, IntGaugeVec,
+};
+
+pub(crate) static UPDATES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "mbei_component_updates_total",
+        "Updates taken from the queue, by type",
+        &["query", "update_type"]
+    )
+    .expect("Could not register metric")
+});
+
+pub(crate) static UPDATE_PROCESSING_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "mbei_component_update_processing_seconds",
+        "Time taken to process an update until consistency, by type",
+        &["query", "update_type"]
+    )
+    .expect("Could not register metric")
+});
+
+pub(crate) static PROCESSED: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "mbei_component_processed_total",
+        "Deltas, events, retractions and reprocessed events handled while reaching consistency",
+        &["query", "kind"]
+    )
+    .expect("Could not register metric")
+});
+
+pub(crate) static QUEUE_LENGTH: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "mbei_component_queue_length",
+        "Updates waiting in the queue, by type",
+        &["query", "update_type"]
+    )
+    .expect("Could not register metric")
+});
+
+pub(crate) static APPLICATION_CALL_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "mbei_component_application_call_seconds",
+        "Time taken by single application calls, including native interpreters",
+        &["query", "application"]
+    )
+    .expect("Could not register metric")
+});
+
+pub(crate) static APPLICATION_CALL_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "mbei_component_application_call_failures_total",
+        "Failed application calls, by reason",
+        &["query", "application", "reason"]
+    )
+    .expect("Could not register metric")
+});
+
+pub(crate) static STORE_SIZE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "mbei_component_store_size",
+        "Number of entries in the store, by collection",
+        &["query", "collection"]
+    )
+    .expect("Could not register metric")
+});
+
+pub(crate) static STORE_BYTES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "mbei_component_store_bytes",
+        "Approximate bytes held by the store, by structure",
+        &["query", "structure"]
+    )
+    .expect("Could not register metric")
+});
+
+pub(crate) static MEMORY_LIMIT_ACTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "mbei_component_memory_limit_actions_total",
+        "Compactions, spilled events and refusals of new input caused by the memory limits of the store",
+        &["query", "action"]
+    )
+    .expect("Could not register metric")
+});
+
+pub(crate) static QUARANTINED: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "mbei_component_quarantined_updates",
+        "Updates currently in quarantine",
+        &["query"]
+    )
+    .expect("Could not register metric")
+});
+
+pub(crate) static FINALIZED_BEFORE: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "mbei_component_finalized_before",
+        "Finality point, before which nothing changes any more",
+        &["query"]
+    )
+    .expect("Could not register metric")
+});
+
+pub(crate) static LATE_ARRIVALS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "mbei_component_late_arrivals_total",
+        "Events and deltas arriving later than allowed, by type and the policy applied",
+        &["query", "update_type", "policy"]
+    )
+    .expect("Could not register metric")
+});
@@ -0,0 +1,100 @@
+/*Copyright 2022 Prediktor AS
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.*/
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::caller::CallerOptions;
+use crate::interpreter::InterpreterRegistry;
+use crate::lateness::LatenessOptions;
+use crate::memory::MemoryLimits;
+use crate::standby::StandbyOptions;
+use mbei_grpc::process_update_server::QueueLimits;
+use mbei_grpc::tls::TlsOptions;
+use mbei_grpc::tracer::TraceExport;
+
+#[derive(Clone, Debug)]
+pub struct ComponentOptions {
+    //Maximum number of iterations used to reach consistency for a single update before it is quarantined
+    pub loop_budget: u32,
+    pub caller: CallerOptions,
+    //Applications that are run in process instead of being called over grpc
+    pub interpreters: InterpreterRegistry,
+    //Port of the HTTP endpoint serving Prometheus metrics, if any
+    pub metrics_port: Option<u16>,
+    //Where spans are exported, if anywhere. Trace context is propagated either way
+    pub trace_export: Option<TraceExport>,
+    pub queue_limits: QueueLimits,
+    //Approximate bytes the store of each component may hold, and what is done beyond them
+    pub memory_limits: MemoryLimits,
+    //Directory of the inbox and outbox logs that keep updates across restarts, if any
+    pub log_dir: Option<PathBuf>,
+    //Certificates for mutual TLS on all links, plaintext if none
+    pub tls: Option<TlsOptions>,
+    //How often the finality point is sent again while it does not move, so that restarted receivers learn it
+    pub finality_heartbeat: Duration,
+    //Sources outside the dataflow, such as producers, whose watermarks finality waits for.
+    //If empty, finality waits for any one such source, and also for every other source it hears from
+    pub watermark_sources: BTreeSet<String>,
+    //How late the updates of each query may arrive, and what is done with those arriving later. Unbounded for queries left out
+    pub lateness: BTreeMap<String, LatenessOptions>,
+    //Tokens of the operators allowed to send manual assertions, by operator. None are allowed without
+    pub operators: BTreeMap<String, String>,
+    //Url of the standby of each query, which its component replicates to. Queries left out have none
+    pub replicas: BTreeMap<String, String>,
+    //How often a primary tells its standby that it is alive, when it has nothing else to replicate,
+    //and at most how often it replicates checkpoints
+    pub replication_heartbeat: Duration,
+    //Set when the components are standbys, which take over the grpc port of their primary when it fails
+    pub standby: Option<StandbyOptions>,
+}
+
+impl Default for ComponentOptions {
+    fn default() -> Self {
+        ComponentOptions {
+            loop_budget: 1000,
+            caller: CallerOptions::default(),
+            interpreters: InterpreterRegistry::new(),
+            metrics_port: None,
+            trace_export: None,
+            queue_limits: QueueLimits::default(),
+            memory_limits: MemoryLimits::default(),
+            log_dir: None,
+            tls: None,
+            finality_heartbeat: Duration::from_secs(5),
+            watermark_sources: BTreeSet::new(),
+            lateness: BTreeMap::new(),
+            operators: BTreeMap::new(),
+            replicas: BTreeMap::new(),
+            replication_heartbeat: Duration::from_millis(500),
+            standby: None,
+        }
+    }
+}
+
+impl ComponentOptions {
+    /// Path of a log of the component, such as its inbox, if logs are kept
+    pub fn get_log_path(&self, query_name: &str, extension: &str) -> Option<PathBuf> {
+        self.log_dir.as_ref().map(|log_dir| {
+            let mut log_path = log_dir.clone();
+            log_path.push(query_name.to_string() + "." + extension);
+            log_path
+        })
+    }
+
+    pub fn get_lateness(&self, query_name: &str) -> LatenessOptions {
+        self.lateness.get(query_name).cloned().unwrap_or_default()
+    }
+}
@@ -0,0 +1,124 @@
+/*Copyright 2022 Prediktor AS
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.*/
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use mbei_core::event::Update;
+
+use crate::intervals::ReprocessInterval;
+
+/// An update whose processing did not reach consistency within the loop budget.
+/// We keep the update together with the part of the cascade that was still pending, so that it can be retried later.
+#[derive(Debug, Clone)]
+pub struct QuarantinedUpdate {
+    pub quarantine_id: String,
+    pub update: Update,
+    pub update_chain: Vec<String>,
+    pub pending_updates: Vec<Update>,
+    pub(crate) reprocess_intervals: Vec<ReprocessInterval>,
+    //Ids retracted earlier in the cascade, whose pending updates are skipped when it is retried
+    pub(crate) retracted_ids: BTreeSet<String>,
+    pub loops: u32,
+    //The update stays pending in the inbox until it is retried or discarded, so that it is not lost on a restart
+    pub(crate) inbox_seq: Option<u64>,
+}
+
+impl QuarantinedUpdate {
+    pub fn reprocess_intervals(&self) -> Vec<(u64, Option<u64>)> {
+        self.reprocess_intervals
+            .iter()
+            .map(|i| (i.from, i.to))
+            .collect()
+    }
+}
+
+pub struct Quarantine {
+    quarantined_by_id: BTreeMap<String, QuarantinedUpdate>,
+}
+
+impl Default for Quarantine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Quarantine {
+    pub fn new() -> Quarantine {
+        Quarantine {
+            quarantined_by_id: BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, quarantined_update: QuarantinedUpdate) {
+        self.quarantined_by_id
+            .insert(quarantined_update.quarantine_id.clone(), quarantined_update);
+    }
+
+    pub fn pop(&mut self, quarantine_id: &str) -> Option<QuarantinedUpdate> {
+        self.quarantined_by_id.remove(quarantine_id)
+    }
+
+    /// Whether an update quarantined or parked is still pending in the inbox under the sequence number
+    pub fn holds_inbox_seq(&self, inbox_seq: u64) -> bool {
+        self.quarantined_by_id.values().any(|q| q.inbox_seq == Some(inbox_seq))
+    }
+
+    pub fn get_all(&self) -> Vec<&QuarantinedUpdate> {
+        self.quarantined_by_id.values().collect()
+    }
+
+    /// The earliest timestamp a retry may still change, which holds back the finality point
+    pub fn get_min_timestamp(&self) -> Option<u64> {
+        self.quarantined_by_id
+            .values()
+            .flat_map(|q| {
+                std::iter::once(q.update.timestamp())
+                    .chain(q.pending_updates.iter().map(|u| u.timestamp()))
+                    .chain(q.reprocess_intervals.iter().map(|i| i.from))
+            })
+            .min()
+    }
+
+    pub fn len(&self) -> usize {
+        self.quarantined_by_id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.quarantined_by_id.is_empty()
+    }
+}
+
+pub(crate) fn describe_update(update: &Update) -> String {
+    match update {
+        Update::Stop => "stop".to_string(),
+        Update::Event(e) => format!("event {} at {}", &e.event_id, e.timestamp),
+        Update::Deltas(ds) => format!(
+            "deltas {} from event {} at {}",
+            &ds.deltas_id, &ds.origin_id, ds.origin_timestamp
+        ),
+        Update::Retractions(rs) => format!(
+            "retractions {} of {:?} at {}",
+            &rs.retraction_id, &rs.deltas_ids, rs.timestamp
+        ),
+        Update::Admin(a) => format!("admin {:?}", a),
+        Update::Watermark(w) => format!("watermark {} from {}", w.timestamp, &w.source_id),
+        Update::EventCorrection(c) => match &c.replacement {
+            Some(e) => format!("correction {} of event {} at {} to {}", &c.correction_id, &c.event_id, c.timestamp, e.timestamp),
+            None => format!("correction {} retracting event {} at {}", &c.correction_id, &c.event_id, c.timestamp),
+        },
+        Update::ManualAssertion(a) if a.revoke => format!("revocation of assertion {} by {} at {}", &a.assertion_id, &a.operator, a.timestamp),
+        Update::ManualAssertion(a) => format!("assertion {} by {} at {}", &a.assertion_id, &a.operator, a.timestamp),
+        Update::Provenance(p) => format!("provenance of deltas {} from event {} at {}", &p.deltas_id, &p.origin_id, p.origin_timestamp),
+    }
+}
@@ -13,77 +13,339 @@ See the License for the specific language governing permissions and
 limitations under the License.*/
 
 use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use log::debug;
+use bincode::config::{standard, Configuration};
+use log::{debug, error, info, warn};
+use tokio::sync::Semaphore;
 use tokio::task::JoinHandle;
 use tonic::{Response, Status};
-use tonic::transport::Channel;
-use uuid::Uuid;
 
 use crate::store::TopicNameAndDeltasId;
-use mbei_core::event::{Deltas, Retractions, Update};
-use mbei_core::graph::Edge;
+use mbei_core::event::{deterministic_retraction_id, deterministic_routed_deltas_id, Deltas, Retractions, Update, Watermark};
+use mbei_core::graph::{Delta, Edge};
 use mbei_core::query::Query;
+use mbei_core::trace::TraceContext;
 
 use mbei_grpc::process_update::process_update_client::ProcessUpdateClient;
+use mbei_grpc::health_server::HealthReporter;
+use mbei_grpc::outbox::{deliver_until_acknowledged, sync_outbox, Outbox};
 use mbei_grpc::process_update::{ProcessUpdateRequest, ProcessUpdateResponse};
 use mbei_grpc::process_update_client::create_process_update_client;
-use mbei_grpc::process_update_mapping::request_from_update;
+use mbei_grpc::tls::TlsOptions;
+use mbei_grpc::process_update_mapping::{delivery_from_request, request_from_traced_update, request_from_update, set_request_target_query};
 
 type JoinHandleType = JoinHandle<Result<Response<ProcessUpdateResponse>, Status>>;
 
+//Deliveries are retried until acknowledged, so routing waits for one to finish when this many are in flight
+const MAX_IN_FLIGHT_SENDS: usize = 1000;
+
 pub struct Router {
     pub query_name: String,
     edge_forward_map: BTreeMap<Edge, BTreeSet<String>>,
     reached_set: BTreeSet<String>,
+    //Queries which send updates to this one, whose watermarks the finality point waits for
+    upstream_set: BTreeSet<String>,
     client_map: BTreeMap<String, ProcessUpdateClient<tonic::transport::Channel>>,
     query_url_map: BTreeMap<String, String>,
-    use_central: bool
+    use_central: bool,
+    config: Configuration,
+    //Used when connecting to receivers, also after reconfiguration
+    max_elapsed_time: Option<Duration>,
+    outbox: Arc<Mutex<Outbox>>,
+    //Requests from a previous run that are resent when the router starts
+    unacknowledged: Vec<(String, ProcessUpdateRequest)>,
+    tls: Option<TlsOptions>,
+    //Receivers are reported unreachable while delivering to them fails
+    health: HealthReporter,
+    //Set for a standby, which holds its updates in the outbox instead of sending them until it takes over
+    standby: bool,
+    //A permit is held by each delivery until it is acknowledged
+    in_flight: Arc<Semaphore>,
 }
 
 impl Router {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         query_name: String,
         all_queries_by_name: BTreeMap<String, Query>,
         query_url_map: BTreeMap<String, String>,
-        use_central: bool
-    ) -> Router {
-        let mut all_edge_forward_maps =
-            Router::compute_edge_forward_maps(&all_queries_by_name);
-        all_edge_forward_maps = Router::compute_edge_forward_closure(all_edge_forward_maps, &all_queries_by_name);
-        let owned_edge_forward_map = all_edge_forward_maps.remove(&query_name).unwrap();
-        let mut reached_set = BTreeSet::new();
-        for qnames in owned_edge_forward_map.values() {
-            for qname in qnames {
-                reached_set.insert(qname.clone());
-            }
-        }
-        debug!("{} forward map {:?}", &query_name, &owned_edge_forward_map);
-        Router {
+        use_central: bool,
+        outbox_path: Option<PathBuf>,
+        tls: Option<TlsOptions>,
+        health: HealthReporter,
+        standby: bool,
+    ) -> Result<Router, String> {
+        let (edge_forward_map, reached_set) = Router::compute_owned_edge_forward_map(&query_name, &all_queries_by_name);
+        let upstream_set = Router::compute_upstream_set(&query_name, &all_queries_by_name);
+        debug!("{} forward map {:?}", &query_name, &edge_forward_map);
+        let (outbox, unacknowledged) = match outbox_path {
+            Some(outbox_path) => Outbox::open(&query_name, &outbox_path)
+                .map_err(|e| format!("Could not open outbox {:?}: {}", outbox_path, e))?,
+            None => (Outbox::new(&query_name), vec![]),
+        };
+        Ok(Router {
             query_name,
-            edge_forward_map:owned_edge_forward_map,
+            edge_forward_map,
             reached_set,
+            upstream_set,
             query_url_map,
             client_map: BTreeMap::new(),
-            use_central
-        }
+            use_central,
+            config: standard(),
+            max_elapsed_time: None,
+            outbox: Arc::new(Mutex::new(outbox)),
+            unacknowledged,
+            tls,
+            health,
+            standby,
+            in_flight: Arc::new(Semaphore::new(MAX_IN_FLIGHT_SENDS)),
+        })
     }
 
-    pub(crate) async fn start(&mut self, max_elapsed_time: Option<Duration>) {
+    /// Connects to the receivers, and resends the requests that were not acknowledged before a restart.
+    pub(crate) async fn start(&mut self, max_elapsed_time: Option<Duration>) -> Result<Vec<JoinHandleType>, String> {
         debug!("Starting router");
-        let mut handle_map = BTreeMap::new();
-        for (q, url) in &self.query_url_map {
-            if q == "central" || self.reached_set.contains(q) {
-                handle_map.insert(q, tokio::spawn(create_process_update_client(url.clone(), max_elapsed_time.clone())));
+        self.max_elapsed_time = max_elapsed_time;
+        self.client_map = self.connect_clients(&self.reached_set, &self.query_url_map).await?;
+        let unacknowledged = std::mem::take(&mut self.unacknowledged);
+        if self.standby {
+            //Kept in the outbox until the standby takes over
+            debug!("Router started");
+            return Ok(vec![]);
+        }
+        if !unacknowledged.is_empty() {
+            info!("{} resending {} unacknowledged updates", &self.query_name, unacknowledged.len());
+        }
+        let handles = self.resend(unacknowledged);
+        debug!("Router started");
+        Ok(handles)
+    }
+
+    /// Sends what a standby held back, and from then on sends its updates.
+    /// Receivers drop what the primary already delivered, as the standby produced the same deltas ids.
+    pub(crate) fn take_over(&mut self) -> Vec<JoinHandleType> {
+        self.standby = false;
+        let held = self.outbox.lock().expect("Outbox lock poisoned").get_unacknowledged();
+        info!("{} took over, sending {} held updates", &self.query_name, held.len());
+        self.resend(held)
+    }
+
+    /// Drops what a standby held back, once its primary has delivered everything up to the same point.
+    pub(crate) fn discard_held(&self) {
+        self.outbox.lock().expect("Outbox lock poisoned").clear();
+    }
+
+    fn resend(&self, requests: Vec<(String, ProcessUpdateRequest)>) -> Vec<JoinHandleType> {
+        let mut handles = vec![];
+        for (receiver, request) in requests {
+            match self.client_map.get(&receiver) {
+                Some(client) => {
+                    let (client, in_flight, outbox, health) = (client.clone(), self.in_flight.clone(), self.outbox.clone(), self.health.clone());
+                    handles.push(tokio::spawn(async move {
+                        let _permit = in_flight.acquire_owned().await.expect("In flight semaphore is never closed");
+                        deliver_until_acknowledged(client, receiver, request, outbox, health).await
+                    }));
+                }
+                None => {
+                    warn!("{} no longer routes to {}, dropping unacknowledged update", &self.query_name, &receiver);
+                    if let Ok(Some(delivery)) = delivery_from_request(&request) {
+                        self.outbox.lock().expect("Outbox lock poisoned").acknowledge(&receiver, &delivery);
+                    }
+                }
             }
         }
-        let mut client_map = BTreeMap::new();
-        for (q, h) in handle_map {
-            client_map.insert(q.clone(), h.await.expect("create client error").expect("create client error"));
+        handles
+    }
+
+    /// Makes what was added to the outbox so far durable, without blocking the runtime.
+    pub(crate) async fn sync_outbox(&self) {
+        if let Err(e) = sync_outbox(&self.outbox).await {
+            error!("{}: {}", &self.query_name, e);
+        }
+    }
+
+    pub(crate) fn get_outbox(&self) -> Arc<Mutex<Outbox>> {
+        self.outbox.clone()
+    }
+
+    pub(crate) fn get_upstream_set(&self) -> &BTreeSet<String> {
+        &self.upstream_set
+    }
+
+    /// Whether the query outputs edges such as that of the delta, which are routed to the queries using them
+    pub(crate) fn is_output_delta(&self, delta: &Delta) -> bool {
+        let mut edge = delta.to_edge();
+        edge.forget_particulars();
+        self.edge_forward_map.contains_key(&edge)
+    }
+
+    /// Sends an update only to central, if it is used.
+    pub(crate) async fn send_to_central(&self, update: &Update, trace: &TraceContext) -> Option<JoinHandleType> {
+        if self.use_central {
+            Some(self.send_central_update(update, trace).await)
+        } else {
+            None
+        }
+    }
+
+    /// Tells the receivers, and central if used, that nothing before the finality point is sent any more.
+    /// Each receiver gets a watermark no later than the updates it has not yet acknowledged, since those may arrive after it.
+    /// Watermarks are not resent when lost, as the next heartbeat repeats them.
+    pub(crate) fn send_watermarks(&self, finalized_before: u64) {
+        if self.standby {
+            return;
+        }
+        for (receiver, client) in &self.client_map {
+            if receiver == &self.query_name {
+                continue;
+            }
+            let min_unacknowledged = self
+                .outbox
+                .lock()
+                .expect("Outbox lock poisoned")
+                .get_min_unacknowledged_timestamp(receiver);
+            let timestamp = min_unacknowledged.map_or(finalized_before, |t| t.min(finalized_before));
+            let mut request = request_from_update(&Update::Watermark(Watermark {
+                source_id: self.query_name.clone(),
+                timestamp,
+            }));
+            set_request_target_query(&mut request, receiver);
+            let mut client = client.clone();
+            let receiver = receiver.clone();
+            tokio::spawn(async move {
+                if let Err(status) = client.send(request).await {
+                    debug!("Could not send watermark to {}: {}", &receiver, status);
+                }
+            });
+        }
+    }
+
+    /// Routes by a new query set or url map. Clients are connected before anything is replaced,
+    /// so every update is routed either by the old or by the new configuration.
+    /// Updates already sent are still delivered to where they were sent.
+    pub(crate) async fn reconfigure(
+        &mut self,
+        all_queries_by_name: Option<&BTreeMap<String, Query>>,
+        query_url_map: Option<BTreeMap<String, String>>,
+    ) -> Result<(), String> {
+        let (edge_forward_map, reached_set) = match all_queries_by_name {
+            Some(all_queries_by_name) => Router::compute_owned_edge_forward_map(&self.query_name, all_queries_by_name),
+            None => (self.edge_forward_map.clone(), self.reached_set.clone()),
+        };
+        let query_url_map = query_url_map.unwrap_or_else(|| self.query_url_map.clone());
+        let mut required = reached_set.clone();
+        if self.use_central {
+            required.insert("central".to_string());
+        }
+        for q in &required {
+            if !query_url_map.contains_key(q) {
+                return Err(format!("No url for {}", q));
+            }
+        }
+        //Nothing is changed until every receiver is connected, so a failed reconfiguration keeps routing as before
+        let client_map = self.connect_clients(&reached_set, &query_url_map).await?;
+        for q in self.client_map.keys() {
+            if !client_map.contains_key(q) {
+                info!("{} no longer routes to {}", &self.query_name, q);
+            }
         }
+        for q in client_map.keys() {
+            if !self.client_map.contains_key(q) {
+                info!("{} now routes to {}", &self.query_name, q);
+            }
+        }
+        if let Some(all_queries_by_name) = all_queries_by_name {
+            self.upstream_set = Router::compute_upstream_set(&self.query_name, all_queries_by_name);
+        }
+        debug!("{} forward map {:?}", &self.query_name, &edge_forward_map);
+        self.edge_forward_map = edge_forward_map;
+        self.reached_set = reached_set;
+        self.query_url_map = query_url_map;
         self.client_map = client_map;
-        debug!("Router started");
+        Ok(())
+    }
+
+    //Clients of central and the reached queries. Current clients are kept if the url is unchanged,
+    //and queries served by the same process share a new client
+    async fn connect_clients(
+        &self,
+        reached_set: &BTreeSet<String>,
+        query_url_map: &BTreeMap<String, String>,
+    ) -> Result<BTreeMap<String, ProcessUpdateClient<tonic::transport::Channel>>, String> {
+        let mut client_map = BTreeMap::new();
+        let mut handle_map = BTreeMap::new();
+        let mut new_query_urls = vec![];
+        for (q, url) in query_url_map {
+            if q == "central" || reached_set.contains(q) {
+                match self.client_map.get(q) {
+                    Some(client) if self.query_url_map.get(q) == Some(url) => {
+                        client_map.insert(q.clone(), client.clone());
+                    }
+                    _ => {
+                        if !handle_map.contains_key(url) {
+                            handle_map.insert(url.clone(), tokio::spawn(create_process_update_client(url.clone(), self.max_elapsed_time, self.tls.clone())));
+                        }
+                        new_query_urls.push((q.clone(), url.clone()));
+                    }
+                }
+            }
+        }
+        let mut clients_by_url = BTreeMap::new();
+        for (url, h) in handle_map {
+            let client = match h.await {
+                Ok(client) => client?,
+                Err(e) => return Err(format!("Could not connect to {}: {}", url, e)),
+            };
+            clients_by_url.insert(url, client);
+        }
+        for (q, url) in new_query_urls {
+            client_map.insert(q, clients_by_url.get(&url).unwrap().clone());
+        }
+        Ok(client_map)
+    }
+
+    /// The queries a query sends updates to, and central if it is used, which all need an url
+    pub(crate) fn get_required_peers(
+        query_name: &str,
+        all_queries_by_name: &BTreeMap<String, Query>,
+        use_central: bool,
+    ) -> BTreeSet<String> {
+        let (_, mut required) = Router::compute_owned_edge_forward_map(query_name, all_queries_by_name);
+        if use_central {
+            required.insert("central".to_string());
+        }
+        required
+    }
+
+    //The other queries which reach the query, directly or through the closure
+    fn compute_upstream_set(query_name: &str, all_queries_by_name: &BTreeMap<String, Query>) -> BTreeSet<String> {
+        let edge_forward_maps = Router::compute_edge_forward_closure(Router::compute_edge_forward_maps(all_queries_by_name), all_queries_by_name);
+        Router::compute_reachable_map(&edge_forward_maps)
+            .into_iter()
+            .filter(|(q, reached)| q != query_name && reached.contains(query_name))
+            .map(|(q, _)| q)
+            .collect()
+    }
+
+    //The forward map of one query, and the queries it reaches
+    fn compute_owned_edge_forward_map(
+        query_name: &str,
+        all_queries_by_name: &BTreeMap<String, Query>,
+    ) -> (BTreeMap<Edge, BTreeSet<String>>, BTreeSet<String>) {
+        let mut all_edge_forward_maps =
+            Router::compute_edge_forward_maps(all_queries_by_name);
+        all_edge_forward_maps = Router::compute_edge_forward_closure(all_edge_forward_maps, all_queries_by_name);
+        let owned_edge_forward_map = all_edge_forward_maps.remove(query_name).unwrap();
+        let mut reached_set = BTreeSet::new();
+        for qnames in owned_edge_forward_map.values() {
+            for qname in qnames {
+                reached_set.insert(qname.clone());
+            }
+        }
+        (owned_edge_forward_map, reached_set)
     }
 
     //Computes triangle and transitive closure..
@@ -109,10 +371,10 @@ impl Router {
                             let include_edges = Router::find_output_edges(q_query, p_query);
                             if !include_edges.is_empty() {
                                 for e in include_edges {
-                                    if !forward_map.contains_key(&e) {
+                                    if !forward_map.contains_key(e) {
                                         forward_map.insert(e.clone(), BTreeSet::from([p.clone()]));
                                     } else {
-                                        forward_map.get_mut(&e).unwrap().insert(p.clone());
+                                        forward_map.get_mut(e).unwrap().insert(p.clone());
                                     }
                                     if !iter_reachable.contains_key(q) {
                                         iter_reachable.insert(q.clone(), BTreeSet::from([p.clone()]));
@@ -139,7 +401,7 @@ impl Router {
     //a--->b and a--->c => possibly b--->c but only if b produces output of interest for c
     fn compute_edge_forward_triangle_closure(mut edge_forward_maps:BTreeMap<String, BTreeMap<Edge, BTreeSet<String>>>, all_queries_by_name: &BTreeMap<String, Query>) -> BTreeMap<String, BTreeMap<Edge, BTreeSet<String>>> {
         let all_reachable = Router::compute_reachable_map(&edge_forward_maps);
-        for (_, rs) in &all_reachable {
+        for rs in all_reachable.values() {
             for r1 in rs {
                 let forward_map = edge_forward_maps.get_mut(r1).unwrap();
                 let r1_query = all_queries_by_name.get(r1).unwrap();
@@ -149,10 +411,10 @@ impl Router {
                         let include_edges = Router::find_output_edges(r1_query, r2_query);
                         if !include_edges.is_empty() {
                             for e in include_edges {
-                                if !forward_map.contains_key(&e) {
+                                if !forward_map.contains_key(e) {
                                     forward_map.insert(e.clone(), BTreeSet::from([r2.clone()]));
                                 } else {
-                                    forward_map.get_mut(&e).unwrap().insert(r2.clone());
+                                    forward_map.get_mut(e).unwrap().insert(r2.clone());
                                 }
                             }
                         }
@@ -241,20 +503,21 @@ impl Router {
         &self,
         query_names_and_update_ids: Vec<TopicNameAndDeltasId>,
         event_timestamp: &u64,
+        trace: &TraceContext,
     ) -> (Vec<Update>, Vec<JoinHandleType>) {
         let mut internal_updates = vec![];
         let mut handles = vec![];
         for qnid in query_names_and_update_ids {
             let retraction_update = Update::Retractions(Retractions {
-                retraction_id: Uuid::new_v4().to_hyphenated().to_string(),
-                timestamp: event_timestamp.clone(),
+                retraction_id: deterministic_retraction_id(&qnid.deltas_id, &qnid.topic_name, self.config),
+                timestamp: *event_timestamp,
                 deltas_ids: vec![qnid.deltas_id.clone()],
             });
-            if &qnid.topic_name == &self.query_name {
+            if qnid.topic_name == self.query_name {
                 internal_updates.push(retraction_update);
             } else {
                 let handle = self
-                    .send_update(&retraction_update, &qnid.topic_name)
+                    .send_update(&retraction_update, &qnid.topic_name, trace)
                     .await;
                 handles.push(handle);
             }
@@ -266,27 +529,46 @@ impl Router {
         &self,
         update: &Update,
         send_to_query_name: &str,
+        trace: &TraceContext,
     ) -> JoinHandleType {
         debug!("{} sending update to {}", &self.query_name, &send_to_query_name);
-        let client = self.client_map.get(send_to_query_name).unwrap().clone();
-        let request = request_from_update(update);
-
-        let handle = tokio::spawn(Router::owning_send(client, request
-        ));
-        handle
-    }
-
-    async fn owning_send(mut client:ProcessUpdateClient<Channel>, request:ProcessUpdateRequest) -> Result<Response<ProcessUpdateResponse>, Status> {
-        client.send(request).await
+        let mut request = request_from_traced_update(update, trace);
+        set_request_target_query(&mut request, send_to_query_name);
+        //The request is in the outbox before the update that caused it is marked as processed
+        let request = self
+            .outbox
+            .lock()
+            .expect("Outbox lock poisoned")
+            .add(send_to_query_name, request);
+        if self.standby {
+            return tokio::spawn(async { Ok(Response::new(ProcessUpdateResponse::default())) });
+        }
+        //A reconfiguration may have removed the receiver, while deltas bound to it are still retracted
+        let client = match self.client_map.get(send_to_query_name) {
+            Some(client) => client.clone(),
+            None => {
+                warn!("{} no longer routes to {}, keeping the update in the outbox", &self.query_name, send_to_query_name);
+                let message = format!("No longer routing to {}", send_to_query_name);
+                return tokio::spawn(async move { Err(Status::unavailable(message)) });
+            }
+        };
+        let permit = self.in_flight.clone().acquire_owned().await.expect("In flight semaphore is never closed");
+        let (receiver, outbox, health) = (send_to_query_name.to_string(), self.outbox.clone(), self.health.clone());
+        tokio::spawn(async move {
+            let result = deliver_until_acknowledged(client, receiver, request, outbox, health).await;
+            drop(permit);
+            result
+        })
     }
 
-    async fn send_central_update(&self, update: &Update) -> JoinHandleType {
-        self.send_update(&update, "central").await
+    async fn send_central_update(&self, update: &Update, trace: &TraceContext) -> JoinHandleType {
+        self.send_update(update, "central", trace).await
     }
 
     pub(crate) async fn route_deltas_update(
         &self,
         deltas: Deltas,
+        trace: &TraceContext,
     ) -> (
         Option<Update>,
         Vec<TopicNameAndDeltasId>,
@@ -303,7 +585,7 @@ impl Router {
             &self.query_name, &deltas.deltas_id, &deltas.origin_id
         );
             let central_handle = self
-                .send_central_update(&Update::Deltas(deltas.clone()))
+                .send_central_update(&Update::Deltas(deltas.clone()), trace)
                 .await;
             let central_cascaded = TopicNameAndDeltasId {
                 topic_name: "central".to_string(),
@@ -321,8 +603,8 @@ impl Router {
             edge_without_particulars = d.to_edge();
             edge_without_particulars.forget_particulars();
             queries = self.edge_forward_map.get(&edge_without_particulars);
-            if queries.is_some() {
-                for q in queries.unwrap() {
+            if let Some(queries) = queries {
+                for q in queries {
                     if !query_deltas.contains_key(q) {
                         query_deltas.insert(q, vec![]);
                     }
@@ -332,12 +614,13 @@ impl Router {
         }
         let mut new_update;
         for (q, ds) in query_deltas {
-            let deltas_id = Uuid::new_v4().to_hyphenated().to_string();
+            let ds = BTreeSet::from_iter(ds);
+            let deltas_id = deterministic_routed_deltas_id(&deltas.deltas_id, q, &ds, self.config);
             new_update = Update::Deltas(Deltas {
                 deltas_id: deltas_id.clone(),
                 origin_id: deltas.origin_id.clone(),
-                origin_timestamp: deltas.origin_timestamp.clone(),
-                deltas: BTreeSet::from_iter(ds.into_iter()),
+                origin_timestamp: deltas.origin_timestamp,
+                deltas: ds,
             });
             cascaded_topic_and_deltas_ids.push(TopicNameAndDeltasId {
                 topic_name: q.clone(),
@@ -354,7 +637,7 @@ impl Router {
                     "{} sent deltas with id {} and event id {} to {}",
                     &self.query_name, &deltas_id, &deltas.origin_id, &q
                 );
-                let handle = self.send_update(&new_update, q).await;
+                let handle = self.send_update(&new_update, q, trace).await;
                 handles.push(handle);
                 //TODO: Add retraction possibility to store..
             }
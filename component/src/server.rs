@@ -1,13 +1,17 @@
 use crate::Component;
-use log::{debug, info};
+use log::{debug, error, info, warn};
 use mbei_core::event::Update;
+use mbei_core::trace::TraceContext;
+use mbei_grpc::health_server::HEARTBEAT_INTERVAL;
 use mbei_grpc::process_update::ProcessUpdateResponse;
-use mbei_grpc::process_update_server::{
-    await_server_handle_with_timeout, create_and_run_server, Queue,
-};
+use mbei_grpc::process_update_server::{Queue, QueueEntry};
+use mbei_grpc::replication_server::{ReplicatedRecord, Replicator};
+use crate::inspection::{answer_inspection_query, InspectionQuery};
+use crate::metrics::{FINALIZED_BEFORE, PROCESSED, QUARANTINED, QUEUE_LENGTH, STORE_BYTES, STORE_SIZE, UPDATES, UPDATE_PROCESSING_SECONDS};
+use crate::standby::Standby;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
 use tokio::time::Instant;
@@ -15,56 +19,98 @@ use tonic::Status;
 
 type JoinHandleType = JoinHandle<Result<tonic::Response<ProcessUpdateResponse>, Status>>;
 
+/// Runs one component, taking updates from its queue. The queue is filled by the server shared by the components of the process.
 pub struct ComponentServer {
     query_name: String,
     component: Component,
-    grpc_port: u16,
+    arc_queue_mutex: Arc<Mutex<Queue>>,
+    new_update_receiver: UnboundedReceiver<()>,
+    inspection_query_receiver: UnboundedReceiver<InspectionQuery>,
     handle_sender: UnboundedSender<JoinHandleType>,
+    //Set for a primary with a standby, which is also used by the server accepting its updates
+    replicator: Option<Replicator>,
+    replication_heartbeat: Duration,
+    //Sequence number and time of the last checkpoint replicated
+    last_checkpoint: Option<(u64, Instant)>,
+    //Set for a standby, until it takes over
+    standby: Option<Standby>,
 }
 
 impl ComponentServer {
-    pub fn new(
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
         query_name: String,
         component: Component,
-        grpc_port: u16,
-        handle_sender: UnboundedSender<
-            JoinHandle<Result<tonic::Response<ProcessUpdateResponse>, Status>>,
-        >,
+        arc_queue_mutex: Arc<Mutex<Queue>>,
+        new_update_receiver: UnboundedReceiver<()>,
+        inspection_query_receiver: UnboundedReceiver<InspectionQuery>,
+        handle_sender: UnboundedSender<JoinHandleType>,
+        replicator: Option<Replicator>,
+        replication_heartbeat: Duration,
+        standby: Option<Standby>,
     ) -> ComponentServer {
         ComponentServer {
             query_name,
             component,
-            grpc_port,
+            arc_queue_mutex,
+            new_update_receiver,
+            inspection_query_receiver,
             handle_sender,
+            replicator,
+            replication_heartbeat,
+            last_checkpoint: None,
+            standby,
         }
     }
 
-    pub async fn run(&mut self, max_elapsed_time: Option<Duration>) {
-        let (new_update_sender, mut new_update_receiver) = tokio::sync::mpsc::unbounded_channel();
-        let queue_mutex = Mutex::new(Queue::new());
-        let arc_queue_mutex = Arc::new(queue_mutex);
-        let (shutdown_server_sender, shutdown_server_receiver) = tokio::sync::oneshot::channel();
-        let server_handle = create_and_run_server(
-            self.grpc_port,
-            arc_queue_mutex.clone(),
-            new_update_sender,
-            shutdown_server_receiver,
-        )
-        .await;
-        info!(
-            "{} component server started and is ready to serve",
-            &self.query_name
-        );
-        self.component.start(max_elapsed_time).await;
+    /// Processes updates until the component stops. Fails if the component could not connect to its application or receivers.
+    pub async fn run(&mut self, max_elapsed_time: Option<Duration>) -> Result<(), String> {
+        let arc_queue_mutex = self.arc_queue_mutex.clone();
+        let handles = match self.component.start(max_elapsed_time).await {
+            Ok(handles) => handles,
+            Err(e) => {
+                error!("{} could not start: {}", &self.query_name, &e);
+                self.component.stop();
+                return Err(e);
+            }
+        };
+        for h in handles {
+            self.handle_sender.send(h).expect("Error sending handle");
+        }
         info!(
             "{} component started and is ready to serve",
             &self.query_name
         );
+        if let Some(standby) = self.standby.take() {
+            if !self.follow_primary(standby).await {
+                debug!("{} shut down", &self.query_name);
+                return Ok(());
+            }
+        }
         loop {
+            self.component.beat();
+            if matches!(&self.replicator, Some(replicator) if replicator.is_fenced()) {
+                //The standby took over, and sends the output from now on
+                error!("{} was replaced by its standby, stopping", &self.query_name);
+                self.component.stop();
+                break;
+            }
+            while let Ok(inspection_query) = self.inspection_query_receiver.try_recv() {
+                answer_inspection_query(inspection_query, &self.component);
+            }
+            self.replicate_checkpoint().await;
             let update_opt;
+            let finality_moved;
             {
                 let mut queue = arc_queue_mutex.lock().await;
-                update_opt = queue.pop_earliest_update();
+                //Before popping, so that the update taken out is still waited for
+                let min_queued_timestamp = queue.get_min_queued_timestamp();
+                finality_moved = self.component.advance_finality(queue.get_watermarks(), min_queued_timestamp);
+                queue.finalized_before = self.component.get_finalized_before();
+                //After finality moved, as it lets the store compact
+                queue.set_memory_exhausted(self.component.enforce_memory_limits());
+                update_opt = queue.pop_earliest_queued_update();
+                self.record_queue_lengths(&queue);
                 info!(
                     "{} has queue lengths retractions: {}, deltas: {}, events: {}",
                     &self.query_name,
@@ -73,51 +119,289 @@ impl ComponentServer {
                     &queue.open_events.len()
                 );
             }
-            if let Some(update) = update_opt {
-                let now = Instant::now();
-                match update {
-                    Update::Stop => {
-                        self.component.stop();
-                        shutdown_server_sender
-                            .send(())
-                            .expect("Error sending shutdown");
-                        info!("{} received stop update, stopping.", &self.query_name);
-                        break;
+            //Accepted updates were replicated before they were queued, so the standby has those taken out
+            if let Some(replicator) = &self.replicator {
+                if let (true, Some(finalized_before)) = (finality_moved, self.component.get_finalized_before()) {
+                    replicator.replicate(&ReplicatedRecord::Finality(finalized_before)).await;
+                }
+                if let Some((update, _)) = &update_opt {
+                    let replicated = replicator.replicate(&ReplicatedRecord::Processing(update.clone())).await;
+                    //The standby stops too, so it must have the stop before the primary is gone
+                    if let (Update::Stop, Some(sequence_number)) = (update, replicated) {
+                        let _ = replicator.await_confirmed(sequence_number).await;
                     }
-                    non_stop_update => {
-                        info!("{} received update", &self.query_name);
-                        let update_type = match &non_stop_update {
-                            Update::Stop => {"stop"}
-                            Update::Event(_) => {"event"}
-                            Update::Deltas(_) => {"deltas"}
-                            Update::Retractions(_) => {"retractions"}
-                        };
-                        let (handles, n_deltas, n_events, n_retractions, n_reprocessing, n_open_edges) = self
-                            .component
-                            .process_update_until_consistency(non_stop_update)
-                            .await;
-                        for h in handles {
-                            self.handle_sender.send(h).expect("Error sending handle");
-                        }
-                        info!(
-                            "{} message processing took {} μs, first update: {}, n_deltas: {}, n_events: {}, n_retractions: {}, n_reprocessing: {}, n_open_edges: {}",
-                            &self.query_name,
-                            now.elapsed().as_micros(),
-                            update_type,
-                            n_deltas,
-                            n_events,
-                            n_retractions,
-                            n_reprocessing,
-                            n_open_edges
+                }
+            }
+            if finality_moved {
+                FINALIZED_BEFORE
+                    .with_label_values(&[&self.query_name])
+                    .set(self.component.get_finalized_before().unwrap() as i64);
+            }
+            self.component.send_finality(finality_moved);
+            if let Some((update, queue_entry)) = update_opt {
+                if !self.process_queued_update(update, queue_entry).await {
+                    break;
+                }
+            } else {
+                let idle_wait = self.get_idle_wait();
+                tokio::select! {
+                    _ = self.new_update_receiver.recv() => {}
+                    //Idle components repeat their finality point as a heartbeat
+                    _ = tokio::time::sleep(idle_wait) => {}
+                    Some(inspection_query) = self.inspection_query_receiver.recv() => {
+                        answer_inspection_query(inspection_query, &self.component);
+                    }
+                }
+            }
+        }
+        debug!("{} shut down", &self.query_name);
+        Ok(())
+    }
+
+    //Processes an update taken from the queue, and marks it as processed. Returns false when the component stops
+    async fn process_queued_update(&mut self, update: Update, queue_entry: Option<QueueEntry>) -> bool {
+        let now = Instant::now();
+        let trace = match &queue_entry {
+            Some(queue_entry) => queue_entry.context.clone().unwrap_or_else(|| TraceContext::for_update(&update)),
+            None => TraceContext::for_update(&update),
+        };
+        if let Some(queue_entry) = &queue_entry {
+            let tracer = self.component.get_tracer();
+            tracer.finish_span(tracer.start_span_at("queue", &trace, queue_entry.enqueued_at));
+        }
+        match update {
+            Update::Stop => {
+                self.component.stop();
+                info!("{} received stop update, stopping.", &self.query_name);
+                return false;
+            }
+            Update::Admin(command) => {
+                info!("{} received admin command {:?}", &self.query_name, &command);
+                let (handles, n_deltas, n_events, n_retractions, n_reprocessing, n_open_edges) = self
+                    .component
+                    .process_admin_command(command)
+                    .await;
+                for h in handles {
+                    self.handle_sender.send(h).expect("Error sending handle");
+                }
+                self.record_processing_metrics("admin", now.elapsed(), n_deltas, n_events, n_retractions, n_reprocessing);
+                info!(
+                    "{} admin command processing took {} μs, n_deltas: {}, n_events: {}, n_retractions: {}, n_reprocessing: {}, n_open_edges: {}",
+                    &self.query_name,
+                    now.elapsed().as_micros(),
+                    n_deltas,
+                    n_events,
+                    n_retractions,
+                    n_reprocessing,
+                    n_open_edges
                 )
+            }
+            non_stop_update => {
+                info!("{} received update", &self.query_name);
+                let update_type = match &non_stop_update {
+                    Update::Stop => {"stop"}
+                    Update::Event(_) => {"event"}
+                    Update::Deltas(_) => {"deltas"}
+                    Update::Retractions(_) => {"retractions"}
+                    Update::Admin(_) => {"admin"}
+                    Update::Watermark(_) => {"watermark"}
+                    Update::EventCorrection(_) => {"correction"}
+                    Update::ManualAssertion(_) => {"manual_assertion"}
+                    Update::Provenance(_) => {"provenance"}
+                };
+                let (handles, n_deltas, n_events, n_retractions, n_reprocessing, n_open_edges) = self
+                    .component
+                    .process_update_until_consistency(non_stop_update, queue_entry.as_ref().and_then(|e| e.inbox_seq), &trace)
+                    .await;
+                for h in handles {
+                    self.handle_sender.send(h).expect("Error sending handle");
+                }
+                self.record_processing_metrics(update_type, now.elapsed(), n_deltas, n_events, n_retractions, n_reprocessing);
+                info!(
+                    "{} message processing took {} μs, first update: {}, n_deltas: {}, n_events: {}, n_retractions: {}, n_reprocessing: {}, n_open_edges: {}, store_bytes: {}",
+                    &self.query_name,
+                    now.elapsed().as_micros(),
+                    update_type,
+                    n_deltas,
+                    n_events,
+                    n_retractions,
+                    n_reprocessing,
+                    n_open_edges,
+                    self.component.get_store().get_memory_usage().total()
+        );
+                let caller_metrics = self.component.get_caller_metrics();
+                info!(
+                    "{} application calls: {}, timeouts: {}, errors: {}, retries: {}, rejected by circuit breaker: {}, held: {}, skipped: {}, native: {}",
+                    &self.query_name,
+                    caller_metrics.n_calls,
+                    caller_metrics.n_timeouts,
+                    caller_metrics.n_errors,
+                    caller_metrics.n_retries,
+                    caller_metrics.n_rejected_by_circuit_breaker,
+                    caller_metrics.n_held,
+                    caller_metrics.n_skipped,
+                    caller_metrics.n_native_calls
+                )
+            }
+        }
+        if let Some(failure) = self.component.get_application_failure() {
+            //The update is not marked as processed, so that it is delivered again when the component is restarted
+            error!("{} stopping, as {}", &self.query_name, failure);
+            self.component.stop();
+            return false;
+        }
+        self.component.sync_outbox().await;
+        let mut queue = self.arc_queue_mutex.lock().await;
+        if let Some(queue_entry) = queue_entry {
+            match queue_entry.inbox_seq {
+                //Quarantined or parked updates are kept in the inbox until they are retried or discarded
+                Some(inbox_seq) if self.component.holds_inbox_seq(inbox_seq) => queue.mark_held(),
+                _ => queue.mark_processed(&queue_entry),
+            }
+        }
+        for inbox_seq in self.component.take_released_inbox_seqs() {
+            queue.mark_released(inbox_seq);
+        }
+        true
+    }
+
+    /// Applies what the primary replicates, until it stops or is silent for longer than the failover timeout.
+    /// Returns whether the standby took over.
+    async fn follow_primary(&mut self, mut standby: Standby) -> bool {
+        info!("{} is a standby", &self.query_name);
+        let mut is_warned = false;
+        loop {
+            self.component.beat();
+            while let Ok(inspection_query) = self.inspection_query_receiver.try_recv() {
+                answer_inspection_query(inspection_query, &self.component);
+            }
+            let record = standby.log.lock().expect("Replicated log lock poisoned").pop();
+            match record {
+                Some(ReplicatedRecord::Processing(update)) => {
+                    //Accepted updates were queued before the primary took them out, unless the standby started later
+                    let taken = self.arc_queue_mutex.lock().await.take_update(&update);
+                    let (update, queue_entry) = taken.unwrap_or((update, None));
+                    if !self.process_queued_update(update, queue_entry).await {
+                        return false;
+                    }
+                }
+                Some(ReplicatedRecord::Finality(finalized_before)) => {
+                    if self.component.apply_finality(finalized_before) {
+                        FINALIZED_BEFORE.with_label_values(&[&self.query_name]).set(finalized_before as i64);
+                    }
+                    self.arc_queue_mutex.lock().await.finalized_before = Some(finalized_before);
+                }
+                Some(ReplicatedRecord::Checkpoint) => self.component.discard_held_output(),
+                Some(_) => {}
+                None => {
+                    let silence = standby.log.lock().expect("Replicated log lock poisoned").get_silence();
+                    let mut wait = standby.failover_timeout.saturating_sub(silence);
+                    if wait.is_zero() {
+                        if standby.log.lock().expect("Replicated log lock poisoned").take_over() {
+                            break;
+                        }
+                        if !is_warned {
+                            error!("{} heard nothing from its primary for {:?}, but can not take over, as it missed records of it", &self.query_name, silence);
+                            is_warned = true;
+                        }
+                        wait = HEARTBEAT_INTERVAL;
+                    }
+                    tokio::select! {
+                        _ = standby.new_record_receiver.recv() => {}
+                        _ = tokio::time::sleep(wait.min(HEARTBEAT_INTERVAL)) => {}
+                        Some(inspection_query) = self.inspection_query_receiver.recv() => {
+                            answer_inspection_query(inspection_query, &self.component);
+                        }
                     }
                 }
-            } else {
-                new_update_receiver.recv().await;
             }
         }
-        debug!("Almost shut down, waiting for server handle");
-        await_server_handle_with_timeout(server_handle, Duration::from_secs(5)).await;
-        debug!("Shut down");
+        warn!("{} heard nothing from its primary for {:?}, taking over", &self.query_name, standby.failover_timeout);
+        for h in self.component.take_over() {
+            self.handle_sender.send(h).expect("Error sending handle");
+        }
+        standby.took_over.add_permits(1);
+        true
+    }
+
+    //Tells the standby that it may drop what it held back, once everything sent was acknowledged. Between updates,
+    //so that no output is being produced. At most once per heartbeat interval, and only when something was replicated since
+    async fn replicate_checkpoint(&mut self) {
+        if let Some(replicator) = &self.replicator {
+            let is_due = match self.last_checkpoint {
+                Some((sequence_number, replicated_at)) => {
+                    replicated_at.elapsed() >= self.replication_heartbeat && replicator.get_last_sequence_number() > sequence_number
+                }
+                None => true,
+            };
+            if is_due && self.component.get_outbox().lock().expect("Outbox lock poisoned").get_n_unacknowledged() == 0 {
+                if let Some(sequence_number) = replicator.replicate(&ReplicatedRecord::Checkpoint).await {
+                    self.last_checkpoint = Some((sequence_number, Instant::now()));
+                }
+            }
+        }
+    }
+
+    //Idle components repeat their finality point, replicate a checkpoint to their standby, and beat for liveness
+    fn get_idle_wait(&self) -> Duration {
+        let idle_wait = self.component.get_finality_heartbeat().min(HEARTBEAT_INTERVAL);
+        match &self.replicator {
+            Some(_) => idle_wait.min(self.replication_heartbeat),
+            None => idle_wait,
+        }
+    }
+
+    fn record_queue_lengths(&self, queue: &Queue) {
+        for (update_type, length) in [
+            ("retractions", queue.open_retractions.len()),
+            ("deltas", queue.open_deltas.len()),
+            ("events", queue.open_events.len()),
+            ("admin", queue.open_admin.len()),
+            ("operator", queue.open_operator_updates.len()),
+        ] {
+            QUEUE_LENGTH
+                .with_label_values(&[&self.query_name, update_type])
+                .set(length as i64);
+        }
+    }
+
+    fn record_processing_metrics(
+        &self,
+        update_type: &str,
+        elapsed: Duration,
+        n_deltas: i32,
+        n_events: i32,
+        n_retractions: i32,
+        n_reprocessing: i32,
+    ) {
+        UPDATES
+            .with_label_values(&[&self.query_name, update_type])
+            .inc();
+        UPDATE_PROCESSING_SECONDS
+            .with_label_values(&[&self.query_name, update_type])
+            .observe(elapsed.as_secs_f64());
+        for (kind, n) in [
+            ("deltas", n_deltas),
+            ("events", n_events),
+            ("retractions", n_retractions),
+            ("reprocessing", n_reprocessing),
+        ] {
+            PROCESSED
+                .with_label_values(&[&self.query_name, kind])
+                .inc_by(n as u64);
+        }
+        for (collection, size) in self.component.get_store().get_sizes() {
+            STORE_SIZE
+                .with_label_values(&[&self.query_name, collection])
+                .set(size as i64);
+        }
+        for (structure, bytes) in self.component.get_store().get_memory_usage().get_bytes_by_structure() {
+            STORE_BYTES
+                .with_label_values(&[&self.query_name, structure])
+                .set(bytes as i64);
+        }
+        QUARANTINED
+            .with_label_values(&[&self.query_name])
+            .set(self.component.get_n_quarantined() as i64);
     }
 }
@@ -0,0 +1,176 @@
+/*Copyright 2022 Prediktor AS
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.*/
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use bincode::config::{standard, Configuration};
+use mbei_core::event::Event;
+
+//Moving the spilled events together is only worth it when the file has grown large
+const SPILL_BYTES_BEFORE_COMPACTION: u64 = 1024 * 1024;
+
+/// Events moved out of the store to a file, to be read back when they are reprocessed.
+/// The file only lives as long as the store, so it is started over when opened, and when no spilled events are left.
+/// It is compacted once at least half of it is taken up by removed events.
+pub struct EventSpill {
+    path: PathBuf,
+    file: File,
+    //Offset and length of each spilled event in the file
+    locations: BTreeMap<String, (u64, usize)>,
+    end: u64,
+    //Bytes of the events still in the file
+    live_bytes: u64,
+    config: Configuration,
+}
+
+impl EventSpill {
+    pub fn create(path: &Path) -> std::io::Result<EventSpill> {
+        let file = OpenOptions::new().create(true).truncate(true).read(true).write(true).open(path)?;
+        Ok(EventSpill {
+            path: path.to_path_buf(),
+            file,
+            locations: BTreeMap::new(),
+            end: 0,
+            live_bytes: 0,
+            config: standard(),
+        })
+    }
+
+    pub fn write(&mut self, event: &Event) {
+        let encoded = bincode::encode_to_vec(event, self.config).expect("Encodable");
+        self.file.seek(SeekFrom::Start(self.end)).expect("Could not seek in spill file");
+        self.file.write_all(&encoded).expect("Could not write to spill file");
+        self.locations.insert(event.event_id.clone(), (self.end, encoded.len()));
+        self.end += encoded.len() as u64;
+        self.live_bytes += encoded.len() as u64;
+    }
+
+    pub fn read(&self, event_id: &str) -> Option<Event> {
+        let (offset, length) = self.locations.get(event_id)?;
+        let mut encoded = vec![0u8; *length];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(*offset)).expect("Could not seek in spill file");
+        file.read_exact(&mut encoded)
+            .unwrap_or_else(|e| panic!("Could not read event {} from {:?}: {}", event_id, &self.path, e));
+        let (event, _) = bincode::decode_from_slice(&encoded, self.config).expect("Decodable");
+        Some(event)
+    }
+
+    /// Forgets the spilled event. Returns whether it was spilled.
+    pub fn remove(&mut self, event_id: &str) -> bool {
+        let (_, length) = match self.locations.remove(event_id) {
+            Some(location) => location,
+            None => return false,
+        };
+        self.live_bytes -= length as u64;
+        if self.locations.is_empty() {
+            self.file.set_len(0).expect("Could not truncate spill file");
+            self.end = 0;
+        } else if self.end >= SPILL_BYTES_BEFORE_COMPACTION && self.live_bytes * 2 <= self.end {
+            self.compact();
+        }
+        true
+    }
+
+    //Moves the events towards the start of the file in the order they were written, so that an event is never overwritten before it is moved
+    fn compact(&mut self) {
+        let mut by_offset: Vec<(u64, usize, String)> =
+            self.locations.iter().map(|(event_id, (offset, length))| (*offset, *length, event_id.clone())).collect();
+        by_offset.sort();
+        let mut end = 0;
+        for (offset, length, event_id) in by_offset {
+            if offset != end {
+                let mut encoded = vec![0u8; length];
+                self.file.seek(SeekFrom::Start(offset)).expect("Could not seek in spill file");
+                self.file.read_exact(&mut encoded).expect("Could not read spill file");
+                self.file.seek(SeekFrom::Start(end)).expect("Could not seek in spill file");
+                self.file.write_all(&encoded).expect("Could not write to spill file");
+                self.locations.insert(event_id, (end, length));
+            }
+            end += length as u64;
+        }
+        self.file.set_len(end).expect("Could not truncate spill file");
+        self.end = end;
+    }
+
+    pub fn contains(&self, event_id: &str) -> bool {
+        self.locations.contains_key(event_id)
+    }
+
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+}
+
+#[test]
+fn test_spilled_events_are_read_back_until_removed() {
+    let mut path = std::env::temp_dir();
+    path.push(format!("mbei-spill-{}.spill", std::process::id()));
+    let event = |event_id: &str, payload: Vec<u8>| Event {
+        event_id: event_id.to_string(),
+        timestamp: 1,
+        node_id: "MyPickDrop0".to_string(),
+        payload,
+    };
+    let mut spill = EventSpill::create(&path).unwrap();
+    spill.write(&event("e1", vec![1, 2, 3]));
+    spill.write(&event("e2", vec![4]));
+    assert_eq!(spill.read("e2"), Some(event("e2", vec![4])));
+    assert_eq!(spill.read("e1"), Some(event("e1", vec![1, 2, 3])));
+    assert!(spill.remove("e1"));
+    assert!(!spill.remove("e1"));
+    assert_eq!(spill.read("e1"), None);
+    assert!(spill.remove("e2"));
+    //The file starts over when nothing is left in it
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+    spill.write(&event("e3", vec![5]));
+    assert_eq!(spill.read("e3"), Some(event("e3", vec![5])));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_spill_file_is_compacted_when_mostly_removed() {
+    let mut path = std::env::temp_dir();
+    path.push(format!("mbei-spill-compaction-{}.spill", std::process::id()));
+    let event = |event_id: &str, byte: u8| Event {
+        event_id: event_id.to_string(),
+        timestamp: 1,
+        node_id: "MyPickDrop0".to_string(),
+        payload: vec![byte; 100 * 1024],
+    };
+    let mut spill = EventSpill::create(&path).unwrap();
+    for i in 0..20u8 {
+        spill.write(&event(&format!("e{:02}", i), i));
+    }
+    let written = std::fs::metadata(&path).unwrap().len();
+    //Removing every other event leaves the remaining ones in half of the file
+    for i in (0..20u8).step_by(2) {
+        assert!(spill.remove(&format!("e{:02}", i)));
+    }
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), written / 2);
+    for i in (1..20u8).step_by(2) {
+        assert_eq!(spill.read(&format!("e{:02}", i)), Some(event(&format!("e{:02}", i), i)));
+    }
+    spill.write(&event("e20", 20));
+    assert_eq!(spill.read("e20"), Some(event("e20", 20)));
+    assert_eq!(spill.read("e19"), Some(event("e19", 19)));
+    std::fs::remove_file(&path).unwrap();
+}
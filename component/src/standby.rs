@@ -0,0 +1,106 @@
+/*Copyright 2022 Prediktor AS
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.*/
+
+//! Hot standbys of components.
+//!
+//! The primary replicates every update it accepts, and acknowledges it once the standby confirmed it, and every update
+//! it takes from its queue before processing it. The standby queues the accepted updates, and processes them in the order
+//! of the primary, so it makes the same application calls and produces the same deltas ids. It holds back what it would
+//! send until the primary reports that its receivers acknowledged everything, and then drops it.
+//! A primary whose standby is down accepts updates until too many wait to be confirmed, and then refuses them.
+//!
+//! A task of the primary replicates, and sends heartbeats while there is nothing else, also while the component is busy.
+//! When the primary has been silent for longer than the failover timeout, the standby takes over its port,
+//! sends what it held back, and continues with the updates the primary accepted but did not process.
+//! Receivers drop the deltas the primary already delivered, so no output is lost or duplicated.
+//! Stopping the primary stops the standby too, so that a planned shutdown does not fail over.
+//!
+//! Taking over moves the standby to the next epoch, and a primary which still replicates to it is refused and stops.
+//! The standby takes over the port of the primary, so both have to run on one host, where the port is only free once
+//! the primary is gone. Failover therefore covers the primary process failing, but not its host. Across hosts,
+//! a primary cut off from its standby would go on serving while the standby took over, and peers would not find the standby.
+//! The standbys of a process serve the port once all of them took over, as none of them can accept updates before.
+//!
+//! A standby which restarts is sent the records of the primary again from the first. The primary keeps a limited number
+//! of them, and once it dropped some, the standby can not catch up: the primary goes on without it, and it never takes over.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+
+use log::info;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::oneshot::Receiver;
+use tokio::sync::Semaphore;
+
+use mbei_grpc::health_server::Health;
+use mbei_grpc::process_update_server::{await_server_handle_with_timeout, create_and_run_server_with_inspection, ServedQueue};
+use mbei_grpc::replication_server::{create_and_run_replication_server, ReplicatedLog, ServedReplica};
+use mbei_grpc::tls::TlsOptions;
+
+use crate::inspection::InspectionService;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StandbyOptions {
+    //Port receiving the records of the primaries until the standbys take over
+    pub port: u16,
+    //How long a primary may be silent before its standby takes over
+    pub failover_timeout: Duration,
+}
+
+/// What a standby component follows its primary with.
+pub(crate) struct Standby {
+    pub(crate) log: Arc<StdMutex<ReplicatedLog>>,
+    pub(crate) new_record_receiver: UnboundedReceiver<()>,
+    pub(crate) failover_timeout: Duration,
+    //Given a permit when the component takes over, so that the port of the primary is served once every standby of the process did
+    pub(crate) took_over: Arc<Semaphore>,
+}
+
+/// Serves replication until a standby takes over, and then the components on the port of their primary until shut down.
+/// The components of a process share the port of their primary, so it is only served once each of them took over.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn serve_standbys(
+    grpc_port: u16,
+    standby_port: u16,
+    served_queues: BTreeMap<String, ServedQueue>,
+    served_replicas: BTreeMap<String, ServedReplica>,
+    mut shutdown_server_receiver: Receiver<()>,
+    inspection_service: InspectionService,
+    health: Health,
+    tls: Option<TlsOptions>,
+    took_over: Arc<Semaphore>,
+) -> Result<(), tonic::transport::Error> {
+    let n_standbys = served_replicas.len() as u32;
+    let (replication_shutdown_sender, replication_shutdown_receiver) = tokio::sync::oneshot::channel();
+    let replication_handle =
+        create_and_run_replication_server(standby_port, served_replicas, replication_shutdown_receiver, health.clone(), tls.as_ref()).await;
+    info!("Standbys receive replication on port {}", standby_port);
+    let is_taken_over = tokio::select! {
+        _ = took_over.acquire_many(n_standbys) => true,
+        _ = &mut shutdown_server_receiver => false,
+    };
+    let result = if is_taken_over {
+        info!("Standbys took over port {}", grpc_port);
+        //Replication is still served, so that a primary which is still alive learns that it was replaced
+        let server_handle =
+            create_and_run_server_with_inspection(grpc_port, served_queues, shutdown_server_receiver, inspection_service, health, tls.as_ref()).await;
+        server_handle.await.expect("Problem in server")
+    } else {
+        Ok(())
+    };
+    let _ = replication_shutdown_sender.send(());
+    await_server_handle_with_timeout(replication_handle, Duration::from_secs(5)).await;
+    result
+}
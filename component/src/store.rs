@@ -13,14 +13,21 @@ See the License for the specific language governing permissions and
 limitations under the License.*/
 
 use std::cmp::max;
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::ops::RangeInclusive;
 use std::time::Instant;
 
 use log::{debug, info};
 
 use mbei_core::event::{Deltas, Event};
-use mbei_core::graph::{Delta, DeltaType, Edge, NodeClass};
+use mbei_core::graph::{edges_from_deltas, Delta, DeltaType, Edge, NodeClass};
+
+use crate::memory::{entry_size, key_size, release, MemoryUsage};
+use crate::spill::EventSpill;
+
+//Ids of compacted deltas kept to recognise duplicates, beyond which the earliest are forgotten. Duplicates arriving later
+//are before the finality point, so the lateness policy of the query handles them
+const MAX_COMPACTED_DELTAS_IDS: usize = 100000;
 
 pub struct Store {
     deltas_by_deltas_id: BTreeMap<String, Deltas>,
@@ -31,10 +38,30 @@ pub struct Store {
     edge_grid: [BTreeMap<u64, BTreeSet<Edge>>;4],
     event_match_hash_and_output_hash: BTreeMap<String, BTreeMap<u64, Option<u64>>>,
     matches_hashes_deltas_ids: BTreeMap<String, Vec<TopicNameAndDeltasId>>,
+    match_generations: BTreeMap<String, u32>,
+    //Where the deltas of each manual assertion were routed, to retract them when it is revoked
+    assertion_deltas_ids: BTreeMap<String, Vec<TopicNameAndDeltasId>>,
     edges_by_node: BTreeMap<String, BTreeSet<Edge>>,
     pub(crate) open_edges: BTreeSet<Edge>,
     watermark: u64,
-    levels: [u64;4]
+    //Events before it are never processed again, so they have been compacted away
+    finalized_before: Option<u64>,
+    levels: [u64;4],
+    //Approximate bytes of each structure, kept up to date as they change
+    usage: MemoryUsage,
+    //Where events are moved when the store is above its hard memory limit, if anywhere
+    spill: Option<EventSpill>,
+    //Finality point edges were last compacted to, since compacting again frees nothing until it moves
+    compacted_before: Option<u64>,
+    //Deltas whose deltas have all been compacted away, which are still recognised as duplicates, in the order they were compacted
+    compacted_deltas_ids: BTreeSet<String>,
+    compacted_deltas_ids_order: VecDeque<String>,
+}
+
+impl Default for Store {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Store {
@@ -48,18 +75,68 @@ impl Store {
             edge_grid: Default::default(),
             event_match_hash_and_output_hash: Default::default(),
             matches_hashes_deltas_ids: Default::default(),
+            match_generations: Default::default(),
+            assertion_deltas_ids: Default::default(),
             edges_by_node: Default::default(),
             open_edges: BTreeSet::new(),
             watermark: 0,
-            levels: [10,100,1000, u64::MAX/2] //Avoids overflow
+            finalized_before: None,
+            levels: [10,100,1000, u64::MAX/2], //Avoids overflow
+            usage: MemoryUsage::default(),
+            spill: None,
+            compacted_before: None,
+            compacted_deltas_ids: BTreeSet::new(),
+            compacted_deltas_ids_order: VecDeque::new(),
         }
     }
 
+    /// Lets the store move events to the spill file, when it is above its hard memory limit
+    pub fn set_spill(&mut self, spill: EventSpill) {
+        self.spill = Some(spill);
+    }
+
     pub fn add_new_event(&mut self, event: &Event) {
-        self.event_ids_by_timestamp.entry(event.timestamp).or_insert(BTreeSet::new()).insert(event.event_id.clone());
+        if self.event_ids_by_timestamp.entry(event.timestamp).or_default().insert(event.event_id.clone()) {
+            self.usage.events += key_size(&event.event_id);
+        }
+        self.drop_event(&event.event_id);
+        self.usage.events += entry_size(event);
         self.events_by_event_id.insert(event.event_id.clone(), event.clone());
     }
 
+    //Drops the event from memory or from the spill file, but not from the timestamp index
+    fn drop_event(&mut self, event_id: &str) {
+        if let Some(event) = self.events_by_event_id.remove(event_id) {
+            release(&mut self.usage.events, entry_size(&event));
+        } else if let Some(spill) = &mut self.spill {
+            if spill.remove(event_id) {
+                release(&mut self.usage.events, spilled_event_size(event_id));
+            }
+        }
+    }
+
+    /// Removes an event and what was recorded about its matches. The deltas ids of the matches should be popped first,
+    /// to retract them. Match generations are kept, so that output produced again for the event gets new deltas ids.
+    pub(crate) fn remove_event(&mut self, event_id: &str) -> Option<Event> {
+        let event = self.get_event_by_event_id(event_id)?;
+        self.drop_event(event_id);
+        if let Some(event_ids) = self.event_ids_by_timestamp.get_mut(&event.timestamp) {
+            if event_ids.remove(event_id) {
+                release(&mut self.usage.events, key_size(event_id));
+            }
+            if event_ids.is_empty() {
+                self.event_ids_by_timestamp.remove(&event.timestamp);
+            }
+        }
+        if let Some(matches) = self.event_match_hash_and_output_hash.remove(event_id) {
+            release(&mut self.usage.match_bindings, key_size(event_id) + entry_size(&matches));
+            for match_hash in matches.keys() {
+                self.remove_match_binding(&create_match_event_string(event_id, match_hash));
+            }
+        }
+        Some(event)
+    }
+
     pub fn add_new_match_updates_binding(
         &mut self,
         event_id: &str,
@@ -67,11 +144,34 @@ impl Store {
         topic_names_deltas_ids: Vec<TopicNameAndDeltasId>,
     ) {
         let match_event_string = create_match_event_string(event_id, match_hash);
+        self.remove_match_binding(&match_event_string);
+        self.usage.match_bindings += key_size(&match_event_string) + entry_size(&topic_names_deltas_ids);
         self.matches_hashes_deltas_ids.insert(match_event_string, topic_names_deltas_ids);
     }
 
+    fn remove_match_binding(&mut self, match_event_string: &str) -> Option<Vec<TopicNameAndDeltasId>> {
+        let topic_names_deltas_ids = self.matches_hashes_deltas_ids.remove(match_event_string)?;
+        release(&mut self.usage.match_bindings, key_size(match_event_string) + entry_size(&topic_names_deltas_ids));
+        Some(topic_names_deltas_ids)
+    }
+
+    //Counts the interpretations of the match for the event, used to keep deltas ids unique when output reappears
+    pub(crate) fn next_match_generation(&mut self, event_id: &str, match_hash: &u64) -> u32 {
+        let match_event_string = create_match_event_string(event_id, match_hash);
+        if !self.match_generations.contains_key(&match_event_string) {
+            self.usage.match_bindings += key_size(&match_event_string) + entry_size(&0u32);
+        }
+        let generation = self.match_generations.entry(match_event_string).or_insert(0);
+        let current = *generation;
+        *generation += 1;
+        current
+    }
+
     pub(crate) fn update_matches(&mut self, event_id: &str, updated_matches_hashes: BTreeMap<u64, Option<u64>>) {
-        self.event_match_hash_and_output_hash.insert(event_id.to_string(), updated_matches_hashes);
+        self.usage.match_bindings += key_size(event_id) + entry_size(&updated_matches_hashes);
+        if let Some(previous) = self.event_match_hash_and_output_hash.insert(event_id.to_string(), updated_matches_hashes) {
+            release(&mut self.usage.match_bindings, key_size(event_id) + entry_size(&previous));
+        }
     }
 
     pub fn pop_deltas_ids_for_event_id_and_match_hash(
@@ -80,7 +180,32 @@ impl Store {
         match_hash: &u64,
     ) -> Option<Vec<TopicNameAndDeltasId>> {
         let match_update_string = create_match_event_string(event_id, match_hash);
-        self.matches_hashes_deltas_ids.remove(&match_update_string)
+        self.remove_match_binding(&match_update_string)
+    }
+
+    pub(crate) fn add_assertion_binding(&mut self, assertion_id: &str, topic_names_deltas_ids: Vec<TopicNameAndDeltasId>) {
+        self.usage.match_bindings += key_size(assertion_id) + entry_size(&topic_names_deltas_ids);
+        if let Some(previous) = self.assertion_deltas_ids.insert(assertion_id.to_string(), topic_names_deltas_ids) {
+            release(&mut self.usage.match_bindings, key_size(assertion_id) + entry_size(&previous));
+        }
+    }
+
+    pub(crate) fn pop_assertion_binding(&mut self, assertion_id: &str) -> Option<Vec<TopicNameAndDeltasId>> {
+        let topic_names_deltas_ids = self.assertion_deltas_ids.remove(assertion_id)?;
+        release(&mut self.usage.match_bindings, key_size(assertion_id) + entry_size(&topic_names_deltas_ids));
+        Some(topic_names_deltas_ids)
+    }
+
+    pub(crate) fn contains_assertion(&self, assertion_id: &str) -> bool {
+        self.assertion_deltas_ids.contains_key(assertion_id)
+    }
+
+    pub fn get_deltas_ids_for_event_id_and_match_hash(
+        &self,
+        event_id: &str,
+        match_hash: &u64,
+    ) -> Option<&Vec<TopicNameAndDeltasId>> {
+        self.matches_hashes_deltas_ids.get(&create_match_event_string(event_id, match_hash))
     }
 
     pub fn get_event_output_hash_by_match_hash(&self, event_id: &str) -> BTreeMap<u64, Option<u64>> {
@@ -114,19 +239,21 @@ impl Store {
         if index_edge_by_node(e) {
             self.delete_node_indexed_edge(e);
         }
-        if e.to_timestamp.is_none() {
-            debug!("Removed from open");
-            self.open_edges.remove(e);
-        } else {
+        if let Some(to_timestamp) = e.to_timestamp {
             debug!("Removed from closed");
             let (index, level) = self.find_edge_index_and_level(e);
             let from_bin = e.from_timestamp.unwrap() / (level);
-            let to_bin = e.to_timestamp.unwrap() / (level);
+            let to_bin = to_timestamp / (level);
 
             self.delete_edge_from_bin(e, index, &from_bin);
             if to_bin != from_bin {
                 self.delete_edge_from_bin(e, index, &to_bin);
             }
+        } else {
+            debug!("Removed from open");
+            if self.open_edges.remove(e) {
+                release(&mut self.usage.edges, entry_size(e));
+            }
         }
     }
 
@@ -134,7 +261,7 @@ impl Store {
         let duration = e.to_timestamp.unwrap() - e.from_timestamp.unwrap();
         for (i, l) in self.levels.iter().enumerate() {
             if 2*l >= duration {
-                return (i, l.clone());
+                return (i, *l);
             }
         }
         panic!("Should never happen");
@@ -142,7 +269,9 @@ impl Store {
 
     fn delete_edge_from_bin(&mut self, e: &Edge, bin_index: usize, bin: &u64) {
         if let Some(edges) = self.edge_grid[bin_index].get_mut(bin) {
-            edges.remove(e);
+            if edges.remove(e) {
+                release(&mut self.usage.edges, entry_size(e));
+            }
         }
     }
 
@@ -150,7 +279,9 @@ impl Store {
         let keys = index_edge_by_keys(e);
         for k in keys {
             if let Some(edges) = self.edges_by_node.get_mut(&k) {
-                edges.remove(e);
+                if edges.remove(e) {
+                    release(&mut self.usage.edges, entry_size(e));
+                }
             }
         }
     }
@@ -182,22 +313,20 @@ impl Store {
                 if e.from_timestamp.unwrap() <= timestamp
                     && (e.to_timestamp.is_none() || e.to_timestamp.unwrap() >= timestamp)
                 {
-                    if e.src.node_class == NodeClass::Material {
-                        if !visited_instance_node_ids
+                    if e.src.node_class == NodeClass::Material
+                        && !visited_instance_node_ids
                             .contains(e.src.instance_node_name.as_ref().unwrap())
                         {
                             new_instance_node_ids
                                 .insert(e.src.instance_node_name.as_ref().unwrap().clone());
                         }
-                    }
-                    if e.trg.node_class == NodeClass::Material {
-                        if !visited_instance_node_ids
+                    if e.trg.node_class == NodeClass::Material
+                        && !visited_instance_node_ids
                             .contains(e.src.instance_node_name.as_ref().unwrap())
                         {
                             new_instance_node_ids
                                 .insert(e.src.instance_node_name.as_ref().unwrap().clone());
                         }
-                    }
                     return_edges.insert(e.clone());
                 }
             }
@@ -210,7 +339,10 @@ impl Store {
         if index_edge_by_node(&e) {
             self.add_node_indexed_edge(e);
         } else if e.to_timestamp.is_none() {
-            self.open_edges.insert(e);
+            let size = entry_size(&e);
+            if self.open_edges.insert(e) {
+                self.usage.edges += size;
+            }
         } else {
             let (index, level) = self.find_edge_index_and_level(&e);
             let from_bin = e.from_timestamp.unwrap() / (level);
@@ -224,13 +356,18 @@ impl Store {
     }
 
     fn add_edge_to_bin(&mut self, edge: Edge, bin_index:usize, bin:u64) {
-        self.edge_grid[bin_index].entry(bin).or_insert(BTreeSet::new()).insert(edge);
+        let size = entry_size(&edge);
+        if self.edge_grid[bin_index].entry(bin).or_default().insert(edge) {
+            self.usage.edges += size;
+        }
     }
 
     fn add_node_indexed_edge(&mut self, edge: Edge) {
         let keys = index_edge_by_keys(&edge);
         for k in keys {
-            self.edges_by_node.entry(k).or_insert(BTreeSet::new()).insert(edge.clone());
+            if self.edges_by_node.entry(k).or_default().insert(edge.clone()) {
+                self.usage.edges += entry_size(&edge);
+            }
         }
     }
 
@@ -252,7 +389,7 @@ impl Store {
                 materials_identifiers.push(e.trg.instance_node_name.as_ref().unwrap().clone());
             }
         }
-        if materials_identifiers.len() > 0 {
+        if !materials_identifiers.is_empty() {
             let mut materials_indexed_edges =
                 self.get_node_indexed_edges_at_timestamp(materials_identifiers, timestamp);
             all_edges.append(&mut materials_indexed_edges);
@@ -281,21 +418,21 @@ impl Store {
         .into_iter()
         .filter(|e| {
             e.from_timestamp.unwrap() <= timestamp && timestamp <= e.to_timestamp.unwrap()
-        }).map(|e| e.clone())
+        }).cloned()
         .collect()
     }
 
     pub fn get_open_edges_at_timestamp(&self, timestamp: u64) -> Vec<Edge> {
         self.open_edges
-            .iter()
-            .map(|e| e.clone())
-            .filter(|e| e.from_timestamp.unwrap() <= timestamp)
+            .iter().filter(|&e| e.from_timestamp.unwrap() <= timestamp).cloned()
             .collect()
     }
 
     pub fn add_retractions(&mut self, retractions: &Vec<String>) {
         for update_id in retractions {
-            self.retracted_deltas_ids.insert(update_id.clone());
+            if self.retracted_deltas_ids.insert(update_id.clone()) {
+                self.usage.deltas += key_size(update_id);
+            }
         }
     }
 
@@ -314,6 +451,160 @@ impl Store {
         return_event_ids
     }
 
+    /// Marks everything before the timestamp as final. Events before it, and what was recorded about their matches,
+    /// are dropped, since they are never reprocessed. Edges and deltas are kept, as later events still match on them.
+    /// Returns the number of events dropped.
+    pub fn finalize(&mut self, finalized_before: u64) -> usize {
+        let later = self.event_ids_by_timestamp.split_off(&finalized_before);
+        let earlier = std::mem::replace(&mut self.event_ids_by_timestamp, later);
+        let mut n_dropped = 0;
+        for event_id in earlier.into_values().flatten() {
+            release(&mut self.usage.events, key_size(&event_id));
+            self.drop_event(&event_id);
+            if let Some(matches) = self.event_match_hash_and_output_hash.remove(&event_id) {
+                release(&mut self.usage.match_bindings, key_size(&event_id) + entry_size(&matches));
+                for match_hash in matches.keys() {
+                    let match_event_string = create_match_event_string(&event_id, match_hash);
+                    self.remove_match_binding(&match_event_string);
+                    if self.match_generations.remove(&match_event_string).is_some() {
+                        release(&mut self.usage.match_bindings, key_size(&match_event_string) + entry_size(&0u32));
+                    }
+                }
+            }
+            n_dropped += 1;
+        }
+        self.finalized_before = Some(finalized_before);
+        n_dropped
+    }
+
+    pub fn get_finalized_before(&self) -> Option<u64> {
+        self.finalized_before
+    }
+
+    /// Drops the edges which closed before the finality point, with the deltas which opened and closed them,
+    /// as no event after the finality point matches them. Edges closing later are computed from the remaining deltas
+    /// as before, since the deltas of an interval do not affect the intervals after it. Returns the number of edges dropped.
+    pub fn compact(&mut self) -> usize {
+        let finalized_before = match self.finalized_before {
+            Some(finalized_before) if self.compacted_before != Some(finalized_before) => finalized_before,
+            _ => return 0,
+        };
+        self.compacted_before = Some(finalized_before);
+        let mut compacted_by_edge = vec![];
+        for (edge, deltas_and_deltas_ids) in &self.deltas_and_deltas_id_by_edge {
+            //Additions belong to the first interval closing after them, so the intervals closing before the finality point
+            //hold the removals before it and the additions up to the last of those
+            let last_removal_opt = deltas_and_deltas_ids
+                .iter()
+                .filter(|d| d.delta.delta_type == DeltaType::Removal && d.delta.timestamp < finalized_before)
+                .map(|d| d.delta.timestamp)
+                .max();
+            if let Some(last_removal) = last_removal_opt {
+                let compacted: Vec<DeltaAndDeltasId> = deltas_and_deltas_ids
+                    .iter()
+                    .filter(|d| match d.delta.delta_type {
+                        DeltaType::Addition => d.delta.timestamp <= last_removal,
+                        DeltaType::Removal => d.delta.timestamp < finalized_before,
+                    })
+                    .cloned()
+                    .collect();
+                compacted_by_edge.push((edge.clone(), compacted));
+            }
+        }
+        let mut n_compacted_edges = 0;
+        let mut compacted_by_deltas_id: BTreeMap<String, Vec<Delta>> = BTreeMap::new();
+        for (edge, compacted) in compacted_by_edge {
+            let deltas: BTreeSet<&Delta> = compacted.iter().map(|d| &d.delta).collect();
+            for compacted_edge in edges_from_deltas(&deltas.into_iter().collect()) {
+                self.delete_edge(&compacted_edge);
+                n_compacted_edges += 1;
+            }
+            let deltas_and_deltas_ids = self.deltas_and_deltas_id_by_edge.get_mut(&edge).unwrap();
+            for d in compacted {
+                deltas_and_deltas_ids.remove(&d);
+                release(&mut self.usage.deltas, entry_size(&d));
+                compacted_by_deltas_id.entry(d.deltas_id).or_default().push(d.delta);
+            }
+            if deltas_and_deltas_ids.is_empty() {
+                self.deltas_and_deltas_id_by_edge.remove(&edge);
+                release(&mut self.usage.deltas, entry_size(&edge));
+            }
+        }
+        for (deltas_id, compacted) in compacted_by_deltas_id {
+            if let Some(mut deltas) = self.deltas_by_deltas_id.remove(&deltas_id) {
+                release(&mut self.usage.deltas, key_size(&deltas_id) + entry_size(&deltas));
+                for delta in &compacted {
+                    deltas.deltas.remove(delta);
+                }
+                if deltas.deltas.is_empty() {
+                    self.add_compacted_deltas_id(deltas_id);
+                } else {
+                    self.usage.deltas += key_size(&deltas_id) + entry_size(&deltas);
+                    self.deltas_by_deltas_id.insert(deltas_id, deltas);
+                }
+            }
+        }
+        n_compacted_edges
+    }
+
+    fn add_compacted_deltas_id(&mut self, deltas_id: String) {
+        if !self.compacted_deltas_ids.insert(deltas_id.clone()) {
+            return;
+        }
+        //Kept twice, in the set and in the order
+        self.usage.deltas += 2 * key_size(&deltas_id);
+        self.compacted_deltas_ids_order.push_back(deltas_id);
+        while self.compacted_deltas_ids_order.len() > MAX_COMPACTED_DELTAS_IDS {
+            let earliest = self.compacted_deltas_ids_order.pop_front().unwrap();
+            self.compacted_deltas_ids.remove(&earliest);
+            release(&mut self.usage.deltas, 2 * key_size(&earliest));
+        }
+    }
+
+    /// Moves the earliest events to the spill file, until the store is within the target or no events are left in memory.
+    /// Returns the number of events spilled, which is none without a spill file.
+    pub fn spill_events(&mut self, target_bytes: usize) -> usize {
+        let spill = match &mut self.spill {
+            Some(spill) => spill,
+            None => return 0,
+        };
+        let mut n_spilled = 0;
+        for event_id in self.event_ids_by_timestamp.values().flatten() {
+            if self.usage.total() <= target_bytes {
+                break;
+            }
+            if let Some(event) = self.events_by_event_id.remove(event_id) {
+                spill.write(&event);
+                release(&mut self.usage.events, entry_size(&event));
+                self.usage.events += spilled_event_size(event_id);
+                n_spilled += 1;
+            }
+        }
+        n_spilled
+    }
+
+    pub fn get_memory_usage(&self) -> MemoryUsage {
+        self.usage
+    }
+
+    /// Number of entries in each collection of the store, for monitoring
+    pub fn get_sizes(&self) -> Vec<(&'static str, usize)> {
+        vec![
+            ("events", self.events_by_event_id.len()),
+            ("spilled_events", self.spill.as_ref().map_or(0, |spill| spill.len())),
+            ("deltas", self.deltas_by_deltas_id.len()),
+            ("edges", self.edge_grid.iter().flat_map(|level| level.values()).map(|edges| edges.len()).sum()),
+            ("open_edges", self.open_edges.len()),
+            ("matches", self.event_match_hash_and_output_hash.values().map(|matches| matches.len()).sum()),
+            ("retracted_ids", self.retracted_deltas_ids.len()),
+            ("assertions", self.assertion_deltas_ids.len()),
+        ]
+    }
+
+    pub fn contains_deltas_id(&self, deltas_id: &str) -> bool {
+        self.deltas_by_deltas_id.contains_key(deltas_id) || self.compacted_deltas_ids.contains(deltas_id)
+    }
+
     pub fn is_update_rectracted(&self, event_or_deltas_id: &str) -> bool {
         self.retracted_deltas_ids.contains(event_or_deltas_id)
     }
@@ -350,31 +641,33 @@ impl Store {
                 delta: d.clone(),
                 deltas_id: deltas.deltas_id.to_string(),
             };
-            if !deltas_by_edge.contains_key(&edge) {
-                deltas_by_edge.insert(edge, BTreeSet::from([delta_and_deltas_id]));
-            } else {
-                deltas_by_edge
-                    .get_mut(&edge)
-                    .unwrap()
-                    .insert(delta_and_deltas_id);
-            }
+            deltas_by_edge
+                .entry(edge)
+                .or_insert_with(BTreeSet::new)
+                .insert(delta_and_deltas_id);
         }
         for (e, ds) in &mut deltas_by_edge {
             if !self.deltas_and_deltas_id_by_edge.contains_key(e) {
-                self.deltas_and_deltas_id_by_edge.insert(e.clone(), (*ds).clone());
-            } else {
-                let existing_mut = self.deltas_and_deltas_id_by_edge.get_mut(e).unwrap();
-                let mut existing_copy = existing_mut.clone();
-                let mut new_copy = (*ds).clone();
-                existing_mut.append(&mut new_copy);
-                ds.append(&mut existing_copy);
+                self.usage.deltas += entry_size(e);
+                self.deltas_and_deltas_id_by_edge.insert(e.clone(), BTreeSet::new());
+            }
+            //Both become the union of the existing and the new deltas
+            let existing_mut = self.deltas_and_deltas_id_by_edge.get_mut(e).unwrap();
+            for d in ds.iter() {
+                if existing_mut.insert(d.clone()) {
+                    self.usage.deltas += entry_size(d);
+                }
             }
+            ds.extend(existing_mut.iter().cloned());
         }
         deltas_by_edge
     }
 
     fn add_deltas_to_deltas_by_deltas_id(&mut self, deltas: &Deltas) {
-        self.deltas_by_deltas_id.insert(deltas.deltas_id.clone(), deltas.clone());
+        self.usage.deltas += key_size(&deltas.deltas_id) + entry_size(deltas);
+        if let Some(previous) = self.deltas_by_deltas_id.insert(deltas.deltas_id.clone(), deltas.clone()) {
+            release(&mut self.usage.deltas, key_size(&previous.deltas_id) + entry_size(&previous));
+        }
     }
 
     pub fn remove_deltas_by_edges_and_get_updated(
@@ -385,7 +678,9 @@ impl Store {
         for (e, remove) in remove_deltas_by_edge {
             if let Some(ds) = self.deltas_and_deltas_id_by_edge.get_mut(&e) {
                 for r in remove {
-                    ds.remove(&r);
+                    if ds.remove(&r) {
+                        release(&mut self.usage.deltas, entry_size(&r));
+                    }
                 }
                 deltas_and_deltas_id_by_edge.insert(e, (*ds).clone());
             }
@@ -411,6 +706,25 @@ impl Store {
         deltas_and_deltas_id_by_edge
     }
 
+    /// Ids of the deltas which opened or closed the edge, as it was matched.
+    pub(crate) fn get_deltas_ids_of_edge(&self, edge: &Edge) -> Vec<String> {
+        let mut key = edge.clone();
+        key.from_timestamp = None;
+        key.to_timestamp = None;
+        let deltas_ids: BTreeSet<&String> = match self.deltas_and_deltas_id_by_edge.get(&key) {
+            Some(ds) => ds
+                .iter()
+                .filter(|d| match d.delta.delta_type {
+                    DeltaType::Addition => edge.from_timestamp == Some(d.delta.timestamp),
+                    DeltaType::Removal => edge.to_timestamp == Some(d.delta.timestamp),
+                })
+                .map(|d| &d.deltas_id)
+                .collect(),
+            None => BTreeSet::new(),
+        };
+        deltas_ids.into_iter().cloned().collect()
+    }
+
     pub fn pop_deltas_by_deltas_ids(&mut self, deltas_ids: &Vec<String>) -> Vec<DeltaAndDeltasId> {
         let deltas = self.get_deltas_by_deltas_ids(deltas_ids);
         self.drop_deltas_ids(deltas_ids);
@@ -419,7 +733,9 @@ impl Store {
 
     pub fn drop_deltas_ids(&mut self, update_ids: &Vec<String>) {
         for update_id in update_ids {
-            self.deltas_by_deltas_id.remove(update_id);
+            if let Some(deltas) = self.deltas_by_deltas_id.remove(update_id) {
+                release(&mut self.usage.deltas, key_size(update_id) + entry_size(&deltas));
+            }
         }
     }
 
@@ -441,8 +757,12 @@ impl Store {
         return_deltas
     }
 
-    pub fn get_event_by_event_id(&self, event_id: &String) -> Option<&Event> {
-        self.events_by_event_id.get(event_id)
+    /// The event, read back from the spill file if it was spilled.
+    pub fn get_event_by_event_id(&self, event_id: &str) -> Option<Event> {
+        match self.events_by_event_id.get(event_id) {
+            Some(event) => Some(event.clone()),
+            None => self.spill.as_ref().and_then(|spill| spill.read(event_id)),
+        }
     }
 
     pub(crate) fn replace_old_match_with_equivalent_new_match(&mut self, event_id: &str, old_match_hash: &u64, new_match_hash: &u64) {
@@ -452,6 +772,11 @@ impl Store {
     }
 }
 
+//The index entry of an event in the spill file, which is all that is kept in memory
+fn spilled_event_size(event_id: &str) -> usize {
+    key_size(event_id) + std::mem::size_of::<(u64, usize)>()
+}
+
 fn create_match_event_string(event_id:&str, match_hash: &u64) -> String {
     let mut match_event_string = event_id.to_string();
     match_event_string += &*match_hash.to_string();
@@ -0,0 +1,268 @@
+/*Copyright 2022 Prediktor AS
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.*/
+
+//! Interpreters shipped as WebAssembly modules, run sandboxed inside the component.
+//!
+//! A module receives the same protobuf encoded ApplicationRequest as an application served over gRPC, and must export:
+//! - `memory`: the linear memory used to exchange requests and responses,
+//! - `alloc(len: i32) -> i32`: returns a pointer to `len` bytes where the request is written,
+//! - `interpret(ptr: i32, len: i32) -> i64`: interprets the request, and returns the pointer of the protobuf encoded
+//!   ApplicationResponse in the upper 32 bits and its length in the lower 32 bits.
+//!
+//! Each call runs in a fresh instance, limited by the fuel and memory given in WasmLimits.
+
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use log::info;
+use prost::Message;
+use wasmi::{Config, Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+use mbei_core::event::Event;
+use mbei_core::graph::Delta;
+use mbei_core::query::{GroupedQueryMatch, Query};
+use mbei_grpc::application_component::ApplicationResponse;
+use mbei_grpc::application_component_mapping::{create_application_request, delta_vec_from_response};
+
+use crate::interpreter::{Interpreter, InterpreterRegistry};
+
+#[derive(Clone, Debug)]
+pub struct WasmLimits {
+    //Fuel available to a single call, roughly the number of executed instructions
+    pub fuel: u64,
+    pub max_memory_bytes: usize,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        WasmLimits {
+            fuel: 100_000_000,
+            max_memory_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+pub struct WasmInterpreter {
+    engine: Engine,
+    module: Module,
+    limits: WasmLimits,
+}
+
+impl WasmInterpreter {
+    pub fn from_bytes(wasm: &[u8], limits: WasmLimits) -> Result<WasmInterpreter, String> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, wasm).map_err(|e| format!("Invalid module: {}", e))?;
+        Ok(WasmInterpreter {
+            engine,
+            module,
+            limits,
+        })
+    }
+
+    pub fn from_file(path: &Path, limits: WasmLimits) -> Result<WasmInterpreter, String> {
+        let wasm = fs::read(path).map_err(|e| format!("Could not read {:?}: {}", path, e))?;
+        WasmInterpreter::from_bytes(&wasm, limits)
+    }
+
+    fn call(&self, request: &[u8]) -> Result<Vec<u8>, String> {
+        let store_limits = StoreLimitsBuilder::new()
+            .memory_size(self.limits.max_memory_bytes)
+            .instances(1)
+            .build();
+        let mut store: Store<StoreLimits> = Store::new(&self.engine, store_limits);
+        store.limiter(|limits| limits);
+        store
+            .add_fuel(self.limits.fuel)
+            .map_err(|e| format!("Could not add fuel: {}", e))?;
+
+        //Interpreters get no imports, so they cannot reach anything outside their own memory
+        let linker = <Linker<StoreLimits>>::new(&self.engine);
+        let instance = linker
+            .instantiate(&mut store, &self.module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(|e| format!("Instantiation failed: {}", e))?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or("Module does not export memory")?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, "alloc")
+            .map_err(|e| format!("Module does not export alloc: {}", e))?;
+        let interpret = instance
+            .get_typed_func::<(i32, i32), i64>(&store, "interpret")
+            .map_err(|e| format!("Module does not export interpret: {}", e))?;
+
+        let request_len = i32::try_from(request.len()).map_err(|_| "Request too large")?;
+        let request_ptr = alloc
+            .call(&mut store, request_len)
+            .map_err(|e| format!("alloc failed: {}", e))?;
+        memory
+            .write(&mut store, request_ptr as u32 as usize, request)
+            .map_err(|e| format!("Could not write request: {}", e))?;
+        let packed = interpret
+            .call(&mut store, (request_ptr, request_len))
+            .map_err(|e| format!("interpret failed: {}", e))? as u64;
+        let response_ptr = (packed >> 32) as usize;
+        let response_len = (packed & 0xffff_ffff) as usize;
+        //The module reports where the response is, so the allocation must not trust it beyond its memory
+        if !matches!(response_ptr.checked_add(response_len), Some(end) if end <= memory.data(&store).len()) {
+            return Err(format!("Response of {} bytes at {} is outside the memory of the module", response_len, response_ptr));
+        }
+        let mut response = vec![0u8; response_len];
+        memory
+            .read(&store, response_ptr, &mut response)
+            .map_err(|e| format!("Could not read response: {}", e))?;
+        Ok(response)
+    }
+}
+
+impl Interpreter for WasmInterpreter {
+    fn interpret(&self, query: &Query, grouped_match: &GroupedQueryMatch, event: &Event) -> Result<Vec<Delta>, String> {
+        let request = create_application_request(
+            query.name.clone(),
+            query.application.clone(),
+            grouped_match,
+            &query.graph,
+            event,
+        );
+        let response = self.call(&request.encode_to_vec())?;
+        let response = ApplicationResponse::decode(response.as_slice())
+            .map_err(|e| format!("Could not decode response: {}", e))?;
+        delta_vec_from_response(response).map_err(|e| format!("Invalid response: {}", e))
+    }
+}
+
+/// Registers each .wasm file in the directory as the interpreter of the application named by the file stem.
+pub fn load_wasm_interpreters(
+    wasm_dir: &Path,
+    limits: &WasmLimits,
+    registry: &mut InterpreterRegistry,
+) -> Result<(), String> {
+    let entries = fs::read_dir(wasm_dir).map_err(|e| format!("Could not read {:?}: {}", wasm_dir, e))?;
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if matches!(path.extension(), Some(e) if e == "wasm") {
+            let application = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .ok_or(format!("Invalid module file name {:?}", &path))?
+                .to_string();
+            let interpreter = WasmInterpreter::from_file(&path, limits.clone())?;
+            info!("Loaded wasm interpreter for application {} from {:?}", &application, &path);
+            registry.register(&application, Arc::new(interpreter));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+fn wasm_from_wat(wat: &str) -> Vec<u8> {
+    wat::parse_str(wat).expect("Invalid wat")
+}
+
+//Echoes the request back, which is fine since an empty request decodes to an empty response
+#[cfg(test)]
+const ECHO_WAT: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 16))
+  (func (export "interpret") (param $ptr i32) (param $len i32) (result i64)
+    (i64.or
+      (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+      (i64.extend_i32_u (local.get $len)))))
+"#;
+
+#[test]
+fn test_wasm_interpreter_echo() {
+    let interpreter = WasmInterpreter::from_bytes(&wasm_from_wat(ECHO_WAT), WasmLimits::default()).unwrap();
+    let request = vec![1u8, 2, 3];
+    assert_eq!(interpreter.call(&request).unwrap(), request);
+}
+
+#[test]
+fn test_wasm_interpreter_runs_out_of_fuel() {
+    let wat = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 0))
+  (func (export "interpret") (param i32 i32) (result i64)
+    (loop $forever (br $forever))
+    (i64.const 0)))
+"#;
+    let limits = WasmLimits {
+        fuel: 10_000,
+        ..Default::default()
+    };
+    let interpreter = WasmInterpreter::from_bytes(&wasm_from_wat(wat), limits).unwrap();
+    assert!(interpreter.call(&[]).is_err());
+}
+
+#[test]
+fn test_wasm_interpreter_memory_limit() {
+    let limits = WasmLimits {
+        max_memory_bytes: 1024,
+        ..Default::default()
+    };
+    let interpreter = WasmInterpreter::from_bytes(&wasm_from_wat(ECHO_WAT), limits).unwrap();
+    assert!(interpreter.call(&[]).is_err());
+}
+
+#[test]
+fn test_wasm_interpreter_rejects_malformed_response() {
+    //An ApplicationResponse with a single delta that has an edge type but no nodes
+    let wat = r#"
+(module
+  (memory (export "memory") 1)
+  (data (i32.const 1024) "\0a\04\1a\02At")
+  (func (export "alloc") (param i32) (result i32) (i32.const 16))
+  (func (export "interpret") (param i32 i32) (result i64)
+    (i64.or (i64.shl (i64.const 1024) (i64.const 32)) (i64.const 6))))
+"#;
+    let interpreter = WasmInterpreter::from_bytes(&wasm_from_wat(wat), WasmLimits::default()).unwrap();
+    let query = Query {
+        name: "q1".to_string(),
+        application: "app".to_string(),
+        graph: mbei_core::graph::Graph::from_edges(vec![]),
+        optional_edges: Default::default(),
+        group: Default::default(),
+        output_edges: Default::default(),
+        input_nodes: Default::default(),
+    };
+    let event = Event {
+        event_id: "e1".to_string(),
+        timestamp: 1,
+        node_id: "n1".to_string(),
+        payload: vec![],
+    };
+    let result = interpreter.interpret(&query, &GroupedQueryMatch { grouped_matches: vec![] }, &event);
+    assert_eq!(result, Err("Invalid response: Delta without a source node".to_string()));
+}
+
+#[test]
+fn test_wasm_interpreter_rejects_response_outside_memory() {
+    let wat = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 16))
+  (func (export "interpret") (param i32 i32) (result i64)
+    (i64.or (i64.shl (i64.const 1024) (i64.const 32)) (i64.const 0xffffffff))))
+"#;
+    let interpreter = WasmInterpreter::from_bytes(&wasm_from_wat(wat), WasmLimits::default()).unwrap();
+    assert_eq!(
+        interpreter.call(&[]),
+        Err("Response of 4294967295 bytes at 1024 is outside the memory of the module".to_string())
+    );
+}
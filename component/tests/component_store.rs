@@ -1,10 +1,11 @@
+use mbei_component::spill::EventSpill;
 use mbei_component::store::Store;
-use mbei_core::event::Event;
+use mbei_core::event::{Deltas, Event};
 use rstest::{fixture, rstest};
 use serial_test::serial;
 #[cfg(test)]
 use std::collections::BTreeSet;
-use mbei_core::graph::{Edge, Node};
+use mbei_core::graph::{edges_from_deltas, Delta, DeltaType, Edge, Node};
 
 #[fixture]
 #[once]
@@ -80,7 +81,7 @@ pub fn abc_events() -> Vec<Event> {
 #[serial]
 fn test_event_iterator_after_first(mut teststore: Store, abc1c2d_events: Vec<Event>) {
     for event in &abc1c2d_events {
-        teststore.add_new_event(&event);
+        teststore.add_new_event(event);
     }
 
     let result = teststore.get_event_ids_in_interval(2, None);
@@ -99,7 +100,7 @@ fn test_event_iterator_after_first(mut teststore: Store, abc1c2d_events: Vec<Eve
 #[serial]
 fn test_event_iterator_from_after_end(mut teststore: Store, abc1c2d_events: Vec<Event>) {
     for event in &abc1c2d_events {
-        teststore.add_new_event(&event);
+        teststore.add_new_event(event);
     }
 
     let result = teststore.get_event_ids_in_interval(1167, None);
@@ -110,7 +111,7 @@ fn test_event_iterator_from_after_end(mut teststore: Store, abc1c2d_events: Vec<
 #[serial]
 fn test_event_iterator_from_before_start(mut teststore: Store, abc1c2d_events: Vec<Event>) {
     for event in &abc1c2d_events {
-        teststore.add_new_event(&event);
+        teststore.add_new_event(event);
     }
 
     let result = teststore.get_event_ids_in_interval(0, None);
@@ -130,7 +131,7 @@ fn test_event_iterator_from_before_start(mut teststore: Store, abc1c2d_events: V
 #[serial]
 fn test_event_iterator_from_before_stops_inside(mut teststore: Store, abc1c2d_events: Vec<Event>) {
     for event in &abc1c2d_events {
-        teststore.add_new_event(&event);
+        teststore.add_new_event(event);
     }
 
     let result = teststore.get_event_ids_in_interval(0, Some(3));
@@ -144,7 +145,7 @@ fn test_event_iterator_from_before_stops_inside(mut teststore: Store, abc1c2d_ev
 #[serial]
 fn test_event_iterator_from_inside_stops_inside(mut teststore: Store, abc1c2d_events: Vec<Event>) {
     for event in &abc1c2d_events {
-        teststore.add_new_event(&event);
+        teststore.add_new_event(event);
     }
 
     let result = teststore.get_event_ids_in_interval(3, Some(915));
@@ -158,7 +159,7 @@ fn test_event_iterator_from_inside_stops_inside(mut teststore: Store, abc1c2d_ev
 #[serial]
 fn test_finds_both_events(mut teststore: Store, abc_events: Vec<Event>) {
     for event in &abc_events {
-        teststore.add_new_event(&event);
+        teststore.add_new_event(event);
     }
 
     let result = teststore.get_event_ids_in_interval(232, None);
@@ -192,4 +193,73 @@ fn test_node_indexed_edges(mut teststore:Store) {
     teststore.update_edges(&vec![edge1, edge2], &vec![]);
     let edges = teststore.get_edges_at_timestamp(5);
     assert_eq!(edges.len(), 2);
-}
\ No newline at end of file
+}
+
+//Adds deltas on a single edge, which holds the earlier deltas, and updates the edges computed from them
+fn add_deltas(store: &mut Store, edge_deltas: &mut BTreeSet<Delta>, deltas_id: &str, timestamp: u64, delta_type: DeltaType) {
+    let delta = Delta {
+        src: Node::material_instance_node("MyBarrel0", "Barrel"),
+        trg: Node::object_instance_node("Somewhere", "SomewhereType"),
+        edge_type: "At".to_string(),
+        timestamp,
+        delta_type,
+    };
+    let deltas = Deltas {
+        deltas_id: deltas_id.to_string(),
+        origin_id: "Origin".to_string(),
+        origin_timestamp: timestamp,
+        deltas: BTreeSet::from([delta.clone()]),
+    };
+    let existing_edges = edges_from_deltas(&edge_deltas.iter().collect());
+    edge_deltas.insert(delta);
+    store.add_deltas_and_get_updated_deltas_by_edge(&deltas);
+    store.update_edges(&edges_from_deltas(&edge_deltas.iter().collect()), &existing_edges);
+}
+
+#[rstest]
+#[serial]
+fn test_memory_usage_returns_after_events_are_finalized(mut teststore: Store, abc1c2d_events: Vec<Event>) {
+    let empty = teststore.get_memory_usage();
+    for event in &abc1c2d_events {
+        teststore.add_new_event(event);
+    }
+    assert!(teststore.get_memory_usage().events > empty.events);
+    teststore.finalize(1001);
+    assert_eq!(teststore.get_memory_usage(), empty);
+}
+
+#[rstest]
+#[serial]
+fn test_compact_drops_edges_closed_before_finality(mut teststore: Store) {
+    let mut edge_deltas = BTreeSet::new();
+    add_deltas(&mut teststore, &mut edge_deltas, "d1", 1, DeltaType::Addition);
+    add_deltas(&mut teststore, &mut edge_deltas, "d2", 5, DeltaType::Removal);
+    add_deltas(&mut teststore, &mut edge_deltas, "d3", 10, DeltaType::Addition);
+    assert_eq!(teststore.get_edges_at_timestamp(3).len(), 1);
+    let before = teststore.get_memory_usage();
+    teststore.finalize(8);
+    assert_eq!(teststore.compact(), 1);
+    assert_eq!(teststore.compact(), 0);
+    assert!(teststore.get_memory_usage().total() < before.total());
+    assert_eq!(teststore.get_edges_at_timestamp(3).len(), 0);
+    assert_eq!(teststore.get_edges_at_timestamp(12).len(), 1);
+    //Compacted deltas are still recognised as received, so that they are not applied again
+    assert!(teststore.contains_deltas_id("d1"));
+    assert!(teststore.contains_deltas_id("d3"));
+}
+
+#[rstest]
+#[serial]
+fn test_spilled_events_are_still_found(mut teststore: Store, abc1c2d_events: Vec<Event>) {
+    let mut path = std::env::temp_dir();
+    path.push(format!("mbei-store-{}.spill", std::process::id()));
+    teststore.set_spill(EventSpill::create(&path).unwrap());
+    for event in &abc1c2d_events {
+        teststore.add_new_event(event);
+    }
+    let before = teststore.get_memory_usage();
+    assert_eq!(teststore.spill_events(0), 5);
+    assert!(teststore.get_memory_usage().events < before.events);
+    assert_eq!(teststore.get_event_by_event_id("B"), Some(abc1c2d_events[1].clone()));
+    std::fs::remove_file(&path).unwrap();
+}
@@ -12,13 +12,14 @@ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 See the License for the specific language governing permissions and
 limitations under the License.*/
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use bincode::{Decode, Encode};
 use bincode::config::Configuration;
-use seahash::hash;
+use seahash::{hash, hash_seeded};
 use serde::{Serialize, Deserialize};
 
-use crate::graph::Delta;
+use crate::graph::{Delta, Edge};
+use crate::query::Query;
 
 #[derive(Serialize, Deserialize, Encode, Decode, Clone, Debug, PartialEq)]
 pub struct Event {
@@ -53,12 +54,110 @@ pub struct Retractions {
     pub deltas_ids: Vec<String>,
 }
 
+#[derive(Serialize, Deserialize, Encode, Decode, Clone, Debug, PartialEq)]
+pub enum AdminCommand {
+    RetryQuarantined(RetryQuarantined),
+    DiscardQuarantined(DiscardQuarantined),
+    Reconfigure(Reconfigure),
+    //Stops accepting events, so that the component runs out of work. Handled by the server, never queued
+    Drain,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Clone, Debug, PartialEq)]
+pub struct RetryQuarantined {
+    pub quarantine_id: String,
+    //Zero means that the configured loop budget is used
+    pub loop_budget: u32,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Clone, Debug, PartialEq)]
+pub struct DiscardQuarantined {
+    pub quarantine_id: String,
+}
+
+//Replaces the queries routed between and the urls of their components. None keeps the current ones
+#[derive(Serialize, Deserialize, Encode, Decode, Clone, Debug, PartialEq)]
+pub struct Reconfigure {
+    pub queries: Option<Vec<Query>>,
+    pub query_url_map: Option<BTreeMap<String, String>>,
+}
+
+/// Declares that the source sends no more updates with timestamps before the timestamp.
+/// Sources are producers, which send heartbeats while idle, and components, which send their finality point.
+/// Watermarks are not queued, so a receiver only keeps the latest one of each source.
+#[derive(Serialize, Deserialize, Encode, Decode, Clone, Debug, PartialEq)]
+pub struct Watermark {
+    pub source_id: String,
+    pub timestamp: u64,
+}
+
+/// Corrects an event sent in error, as after a human or sensor error. The stored event is retracted together with
+/// the deltas it caused, and the replacement, if any, is processed in its place. The timestamp is that of the event
+/// to correct, which is known from inspecting the component.
+#[derive(Serialize, Deserialize, Encode, Decode, Clone, Debug, PartialEq)]
+pub struct EventCorrection {
+    pub correction_id: String,
+    pub event_id: String,
+    pub timestamp: u64,
+    pub replacement: Option<Event>,
+}
+
+impl EventCorrection {
+    //Both the corrected event and its replacement may change what follows them
+    pub fn earliest_timestamp(&self) -> u64 {
+        self.replacement.as_ref().map_or(self.timestamp, |e| e.timestamp.min(self.timestamp))
+    }
+}
+
+/// Edges asserted by an operator who knows better than the interpretations, such as where a barrel physically is.
+/// The deltas are routed like those of an interpretation of the query the assertion is sent to, and recorded by central
+/// together with who asserted them and why. Revoking the assertion, by its id, retracts the deltas again.
+#[derive(Serialize, Deserialize, Encode, Decode, Clone, Debug, PartialEq)]
+pub struct ManualAssertion {
+    pub assertion_id: String,
+    //Set by the component from the token the operator authenticated with
+    pub operator: String,
+    pub reason: String,
+    pub timestamp: u64,
+    //Empty when revoking
+    pub deltas: BTreeSet<Delta>,
+    pub revoke: bool,
+}
+
+/// An edge of a match, with the deltas which opened or closed it, by the ids the component received them under.
+/// Edges of events have no deltas.
+#[derive(Serialize, Deserialize, Encode, Decode, Clone, Debug, PartialEq)]
+pub struct InputEdge {
+    pub edge: Edge,
+    pub deltas_ids: Vec<String>,
+}
+
+/// How a component derived a deltas update from an event and one of its matches. Sent to central, which follows the
+/// input edges back through the provenance of the deltas behind them to explain a delta.
+#[derive(Serialize, Deserialize, Encode, Decode, Clone, Debug, PartialEq)]
+pub struct Provenance {
+    pub deltas_id: String,
+    pub query_name: String,
+    pub application: String,
+    pub origin_id: String,
+    pub origin_timestamp: u64,
+    pub match_hash: u64,
+    pub input_edges: Vec<InputEdge>,
+    //The ids the deltas were routed to other queries under
+    pub routed_deltas_ids: Vec<String>,
+}
+
 #[derive(Serialize, Deserialize, Encode, Decode, Clone, Debug, PartialEq)]
 pub enum Update {
     Stop,
     Event(Event),
     Deltas(Deltas),
     Retractions(Retractions),
+    Admin(AdminCommand),
+    Watermark(Watermark),
+    EventCorrection(EventCorrection),
+    ManualAssertion(ManualAssertion),
+    Provenance(Provenance),
 }
 
 impl Update {
@@ -67,6 +166,10 @@ impl Update {
             Update::Event(e) => {e.timestamp}
             Update::Deltas(ds) => {ds.origin_timestamp }
             Update::Retractions(rt) => {rt.timestamp}
+            Update::Watermark(w) => {w.timestamp}
+            Update::EventCorrection(c) => {c.earliest_timestamp()}
+            Update::ManualAssertion(a) => {a.timestamp}
+            Update::Provenance(p) => {p.origin_timestamp}
             _ => {panic!("Not defined")}
         }
     }
@@ -78,4 +181,77 @@ impl Update {
             _ => {panic!("Not defined")}
         }
     }
-}
\ No newline at end of file
+}
+
+/// Identifier of the deltas produced by interpreting an event on a match.
+/// The generation counts how many times the match has produced output for the event, so that output which is
+/// retracted and later produced again gets a new identifier, while replaying the same input gives the same identifiers.
+pub fn deterministic_deltas_id(
+    origin_id: &str,
+    match_hash: u64,
+    generation: u32,
+    query_name: &str,
+    deltas: &BTreeSet<Delta>,
+    c: Configuration,
+) -> String {
+    let output_hash = hash(bincode::encode_to_vec(deltas, c).expect("Encodable").as_slice());
+    content_addressed_id(
+        bincode::encode_to_vec((origin_id, match_hash, generation, query_name, output_hash), c)
+            .expect("Encodable")
+            .as_slice(),
+    )
+}
+
+/// Identifier of the part of a deltas update that is routed to a target query.
+pub fn deterministic_routed_deltas_id(
+    deltas_id: &str,
+    target_query_name: &str,
+    deltas: &BTreeSet<Delta>,
+    c: Configuration,
+) -> String {
+    let output_hash = hash(bincode::encode_to_vec(deltas, c).expect("Encodable").as_slice());
+    content_addressed_id(
+        bincode::encode_to_vec((deltas_id, target_query_name, output_hash), c)
+            .expect("Encodable")
+            .as_slice(),
+    )
+}
+
+/// Identifier of the deltas asserted by an operator, which central also derives to link them to the assertion.
+pub fn deterministic_assertion_deltas_id(assertion_id: &str, c: Configuration) -> String {
+    content_addressed_id(
+        bincode::encode_to_vec(("assertion", assertion_id), c)
+            .expect("Encodable")
+            .as_slice(),
+    )
+}
+
+/// Identifier of the retraction of a deltas update sent to a target query.
+pub fn deterministic_retraction_id(deltas_id: &str, target_query_name: &str, c: Configuration) -> String {
+    content_addressed_id(
+        bincode::encode_to_vec(("retraction", deltas_id, target_query_name), c)
+            .expect("Encodable")
+            .as_slice(),
+    )
+}
+
+//128 bits from two differently seeded hashes, so that collisions are not a practical concern
+pub(crate) fn content_addressed_id(content: &[u8]) -> String {
+    let high = hash_seeded(content, 1, 2, 3, 4);
+    let low = hash_seeded(content, 5, 6, 7, 8);
+    format!("{:016x}{:016x}", high, low)
+}
+
+#[test]
+fn test_deterministic_deltas_id_depends_on_generation_and_target() {
+    let c = bincode::config::standard();
+    let deltas = BTreeSet::new();
+    let id = deterministic_deltas_id("event", 7, 0, "query", &deltas, c);
+    assert_eq!(id, deterministic_deltas_id("event", 7, 0, "query", &deltas, c));
+    assert_ne!(id, deterministic_deltas_id("event", 7, 1, "query", &deltas, c));
+    assert_ne!(
+        deterministic_routed_deltas_id(&id, "a", &deltas, c),
+        deterministic_routed_deltas_id(&id, "b", &deltas, c)
+    );
+    assert_eq!(id.len(), 32);
+}
@@ -49,8 +49,8 @@ impl TryFrom<&str> for DeltaType {
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         match value {
-            x if x == "Addition" => Ok(DeltaType::Addition),
-            x if x == "Removal" => Ok(DeltaType::Removal),
+            "Addition" => Ok(DeltaType::Addition),
+            "Removal" => Ok(DeltaType::Removal),
             _ => Err(()),
         }
     }
@@ -102,10 +102,10 @@ impl TryFrom<&str> for NodeClass {
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         match value {
-            x if x == "Object" => Ok(NodeClass::Object),
-            x if x == "Event" => Ok(NodeClass::Event),
-            x if x == "Material" => Ok(NodeClass::Material),
-            x if x == "Property" => Ok(NodeClass::Property),
+            "Object" => Ok(NodeClass::Object),
+            "Event" => Ok(NodeClass::Event),
+            "Material" => Ok(NodeClass::Material),
+            "Property" => Ok(NodeClass::Property),
             _ => Err(()),
         }
     }
@@ -322,7 +322,7 @@ impl Edge {
 
     pub fn from_deltas(deltas: Vec<&&Delta>) -> Option<Edge> {
         assert!(!deltas.is_empty());
-        let mut edge = deltas.get(0).unwrap().to_edge();
+        let mut edge = deltas.first().unwrap().to_edge();
         let mut min_from = None;
         let mut min_to = None;
         for d in deltas {
@@ -337,13 +337,13 @@ impl Edge {
                 min_to = Some(d.timestamp);
             }
         }
-        return if min_from.is_none() {
+        if min_from.is_none() {
             None
         } else {
             edge.from_timestamp = min_from;
             edge.to_timestamp = min_to;
             Some(edge)
-        };
+        }
     }
 }
 
@@ -379,7 +379,7 @@ impl Graph {
             }
         }
         Graph {
-            edges: edges,
+            edges,
             incoming,
             outgoing,
         }
@@ -390,9 +390,8 @@ pub fn edges_from_deltas(deltas: &Vec<&Delta>) -> Vec<Edge> {
     let grouped_deltas = group_distinct_delta_intervals(deltas);
     let mut edges = vec![];
     for g in grouped_deltas {
-        let edge_opt = Edge::from_deltas(g);
-        if edge_opt.is_some() {
-            edges.push(edge_opt.unwrap());
+        if let Some(edge) = Edge::from_deltas(g) {
+            edges.push(edge);
         }
     }
     edges
@@ -400,11 +399,11 @@ pub fn edges_from_deltas(deltas: &Vec<&Delta>) -> Vec<Edge> {
 
 fn group_distinct_delta_intervals<'a>(deltas: &'a Vec<&Delta>) -> Vec<Vec<&'a &'a Delta>> {
     let mut additions: Vec<&&Delta> = deltas
-        .into_iter()
+        .iter()
         .filter(|d| d.delta_type == DeltaType::Addition)
         .collect();
     let mut removals: Vec<&&Delta> = deltas
-        .into_iter()
+        .iter()
         .filter(|d| d.delta_type == DeltaType::Removal)
         .collect();
     additions.sort_by_key(|d| d.timestamp);
@@ -415,13 +414,13 @@ fn group_distinct_delta_intervals<'a>(deltas: &'a Vec<&Delta>) -> Vec<Vec<&'a &'
 
     for r in removals {
         groups.push(vec![r]);
-        group_removal_timestamp.push(r.timestamp.clone());
+        group_removal_timestamp.push(r.timestamp);
     }
 
     let mut open_group = vec![];
     'additions: for a in additions {
         for (i, g) in groups.iter_mut().enumerate() {
-            if a.timestamp <= group_removal_timestamp.get(i).unwrap().clone() {
+            if a.timestamp <= *group_removal_timestamp.get(i).unwrap() {
                 g.push(a);
                 continue 'additions;
             }
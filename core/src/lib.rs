@@ -15,3 +15,4 @@ limitations under the License.*/
 pub mod event;
 pub mod graph;
 pub mod query;
+pub mod trace;
@@ -42,7 +42,7 @@ impl Query {
         grouped_query_match: GroupedQueryMatch,
         suffix: String,
     ) -> Query {
-        assert!(grouped_query_match.grouped_matches.len() > 0);
+        assert!(!grouped_query_match.grouped_matches.is_empty());
 
         let image_homomorphism = grouped_query_match.get_image_homomorphism();
         let node_image_homomorphism = grouped_query_match.get_node_image_homomorphism();
@@ -91,7 +91,7 @@ impl Query {
 
         for (query_edge, matched_edges) in &image_homomorphism {
             let mut edges_to_add;
-            if matched_edges.len() > 0 {
+            if !matched_edges.is_empty() {
                 edges_to_add = matched_edges
                     .iter()
                     .map(|x| (*x).clone())
@@ -101,16 +101,16 @@ impl Query {
                 let trg_image = node_image_homomorphism.get(&query_edge.trg).unwrap();
                 edges_to_add = vec![];
 
-                if src_image.len() > 0 {
-                    if trg_image.len() > 0 {
+                if !src_image.is_empty() {
+                    if !trg_image.is_empty() {
                         for s in src_image {
                             for t in trg_image {
                                 edges_to_add.push(Edge {
                                     src: (*s).clone(),
                                     trg: (*t).clone(),
                                     edge_type: query_edge.edge_type.clone(),
-                                    from_timestamp: query_edge.from_timestamp.clone(),
-                                    to_timestamp: query_edge.to_timestamp.clone(),
+                                    from_timestamp: query_edge.from_timestamp,
+                                    to_timestamp: query_edge.to_timestamp,
                                 });
                             }
                         }
@@ -120,19 +120,19 @@ impl Query {
                                 src: (*s).clone(),
                                 trg: query_edge.trg.clone(),
                                 edge_type: query_edge.edge_type.clone(),
-                                from_timestamp: query_edge.from_timestamp.clone(),
-                                to_timestamp: query_edge.to_timestamp.clone(),
+                                from_timestamp: query_edge.from_timestamp,
+                                to_timestamp: query_edge.to_timestamp,
                             });
                         }
                     }
-                } else if trg_image.len() > 0 {
+                } else if !trg_image.is_empty() {
                     for t in trg_image {
                         edges_to_add.push(Edge {
                             src: query_edge.src.clone(),
                             trg: (*t).clone(),
                             edge_type: query_edge.edge_type.clone(),
-                            from_timestamp: query_edge.from_timestamp.clone(),
-                            to_timestamp: query_edge.to_timestamp.clone(),
+                            from_timestamp: query_edge.from_timestamp,
+                            to_timestamp: query_edge.to_timestamp,
                         });
                     }
                 } else {
@@ -140,7 +140,7 @@ impl Query {
                 }
             }
             //Identity belongs to query nodes..
-            for mut e in edges_to_add.iter_mut() {
+            for e in edges_to_add.iter_mut() {
                 e.src.query_node_name = query_edge.src.query_node_name.clone();
                 e.trg.query_node_name = query_edge.trg.query_node_name.clone();
             }
@@ -172,7 +172,7 @@ impl Query {
     pub fn find_all_grouped_matches(&self, g: &Graph) -> Vec<GroupedQueryMatch> {
         let ungrouped_matches = self.find_all_matches(g);
         if self.group.is_empty() {
-            return ungrouped_matches.into_iter().map(|m| GroupedQueryMatch{ grouped_matches: vec![m]}).collect();
+            ungrouped_matches.into_iter().map(|m| GroupedQueryMatch{ grouped_matches: vec![m]}).collect()
         } else {
             let mut grouped_matches_map: BTreeMap<Vec<(Node, Node)>, Vec<QueryMatch>> =
                 BTreeMap::new();
@@ -193,30 +193,25 @@ impl Query {
                 let mut grouped_node_mapping: Vec<(Node, Node)> =
                     grouped_node_mapping.into_iter().collect();
                 grouped_node_mapping.sort();
-                if !grouped_matches_map.contains_key(&grouped_node_mapping) {
-                    grouped_matches_map.insert(grouped_node_mapping, vec![m]);
-                } else {
-                    grouped_matches_map
-                        .get_mut(&grouped_node_mapping)
-                        .unwrap()
-                        .push(m);
-                }
+                grouped_matches_map
+                    .entry(grouped_node_mapping)
+                    .or_default()
+                    .push(m);
             }
-            return grouped_matches_map
+            grouped_matches_map
                 .into_values()
                 .map(|m| GroupedQueryMatch { grouped_matches: m })
-                .collect();
+                .collect()
         }
     }
 
     pub fn find_all_matches(&self, g: &Graph) -> Vec<QueryMatch> {
-        let mut matches: Vec<QueryMatch> = Vec::new();
-        matches.push(QueryMatch {
+        let mut matches: Vec<QueryMatch> = vec![QueryMatch {
             homomorphism: BTreeMap::new(),
-        });
+        }];
         //Matches only contain edges which are not matched yet, i.e. those without identities
         for e in &self.graph.edges {
-            if e.src.instance_node_name == None || e.trg.instance_node_name == None {
+            if e.src.instance_node_name.is_none() || e.trg.instance_node_name.is_none() {
                 matches = self.extend_matches(e, matches, g);
             }
         }
@@ -243,9 +238,7 @@ impl Query {
                         }
                         if k_included_in_m && !m_included_in_k {
                             excluded.insert(j);
-                        } else if m_included_in_k && !k_included_in_m {
-                            excluded.insert(i);
-                        } else if m_included_in_k && k_included_in_m {
+                        } else if m_included_in_k {
                             excluded.insert(i);
                         }
                     }
@@ -253,12 +246,10 @@ impl Query {
             }
         }
         let mut non_redundant_matches = vec![];
-        let mut i = 0;
-        for m in matches {
+        for (i, m) in matches.into_iter().enumerate() {
             if !excluded.contains(&i) {
                 non_redundant_matches.push(m);
             }
-            i += 1;
         }
 
         if non_redundant_matches.len() == 1 {
@@ -268,9 +259,9 @@ impl Query {
                     nonempty.push(m);
                 }
             }
-            return nonempty;
+            nonempty
         } else {
-            return non_redundant_matches;
+            non_redundant_matches
         }
     }
 
@@ -292,9 +283,9 @@ impl Query {
             'cloop: for c in candidates.iter() {
                 if e.src.instance_node_name.is_none() {
                     for incoming_src in &self.graph.incoming[&e.src] {
-                        if incoming_src != e && m.homomorphism.contains_key(&incoming_src) {
+                        if incoming_src != e && m.homomorphism.contains_key(incoming_src) {
                             if let Some(mapped_incoming_src) =
-                                m.homomorphism.get(&incoming_src).unwrap()
+                                m.homomorphism.get(incoming_src).unwrap()
                             {
                                 // a --d-->b--e-->h => f(a) --f(d)-->f(b)--f(e)-->f(h)
                                 if mapped_incoming_src.trg != c.src {
@@ -304,9 +295,9 @@ impl Query {
                         }
                     }
                     for outgoing_src in &self.graph.outgoing[&e.src] {
-                        if outgoing_src != e && m.homomorphism.contains_key(&outgoing_src) {
+                        if outgoing_src != e && m.homomorphism.contains_key(outgoing_src) {
                             if let Some(mapped_outgoing_src) =
-                                m.homomorphism.get(&outgoing_src).unwrap()
+                                m.homomorphism.get(outgoing_src).unwrap()
                             {
                                 // a<--d--b--e-->h => f(a)<--f(d)--f(b)--f(e)-->f(h)
                                 if mapped_outgoing_src.src != c.src {
@@ -319,9 +310,9 @@ impl Query {
 
                 if e.trg.instance_node_name.is_none() {
                     for incoming_trg in &self.graph.incoming[&e.trg] {
-                        if incoming_trg != e && m.homomorphism.contains_key(&incoming_trg) {
+                        if incoming_trg != e && m.homomorphism.contains_key(incoming_trg) {
                             if let Some(mapped_incoming_trg) =
-                                m.homomorphism.get(&incoming_trg).unwrap()
+                                m.homomorphism.get(incoming_trg).unwrap()
                             {
                                 // a--d-->b<--e--h => f(a)--f(d)-->f(b)<--f(e)--f(h)
                                 if mapped_incoming_trg.trg != c.trg {
@@ -331,9 +322,9 @@ impl Query {
                         }
                     }
                     for outgoing_trg in &self.graph.outgoing[&e.trg] {
-                        if outgoing_trg != e && m.homomorphism.contains_key(&outgoing_trg) {
+                        if outgoing_trg != e && m.homomorphism.contains_key(outgoing_trg) {
                             if let Some(mapped_outgoing_trg) =
-                                m.homomorphism.get(&outgoing_trg).unwrap()
+                                m.homomorphism.get(outgoing_trg).unwrap()
                             {
                                 // a<--d--b<--e--h => f(a)<--f(d)--f(b)<--f(e)--f(h)
                                 if mapped_outgoing_trg.src != c.trg {
@@ -437,12 +428,22 @@ impl GroupedQueryMatch {
 }
 
 pub fn parse_queries(p: &Path, config: Configuration) -> Vec<Query> {
-    let queries_file_reader = File::open(p).expect("File exists");
-    let queries_enc: HashMap<String, String> =
-        serde_yaml::from_reader(queries_file_reader).expect("Can parse YAML");
+    try_parse_queries(p, config).expect("Can parse queries")
+}
+
+/// Like parse_queries, but reports what is wrong with the file instead of panicking
+pub fn try_parse_queries(p: &Path, config: Configuration) -> Result<Vec<Query>, String> {
+    let queries_file_reader =
+        File::open(p).map_err(|e| format!("Could not open queries file {:?}: {}", p, e))?;
+    let queries_enc: HashMap<String, String> = serde_yaml::from_reader(queries_file_reader)
+        .map_err(|e| format!("Could not parse queries file {:?}: {}", p, e))?;
     queries_enc
         .iter()
-        .map(|(_, qstring)| Query::from_bytestring(qstring, config))
+        .map(|(name, qstring)| {
+            bincode::decode_from_slice(qstring.as_bytes(), config)
+                .map(|(query, _)| query)
+                .map_err(|e| format!("Could not decode query {} in {:?}: {}", name, p, e))
+        })
         .collect()
 }
 
@@ -0,0 +1,122 @@
+/*Copyright 2022 Prediktor AS
+
+Licensed under the Apache License, Version 2.0 (the "License");
+you may not use this file except in compliance with the License.
+You may obtain a copy of the License at
+
+   http://www.apache.org/licenses/LICENSE-2.0
+
+Unless required by applicable law or agreed to in writing, software
+distributed under the License is distributed on an "AS IS" BASIS,
+WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+See the License for the specific language governing permissions and
+limitations under the License.*/
+
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::event::{content_addressed_id, Update};
+
+/// The trace an update belongs to, and the span that caused it to be sent.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TraceContext {
+    pub trace_id: String,
+    pub parent_span_id: Option<String>,
+}
+
+impl TraceContext {
+    /// Traces are keyed by the origin event, so every component agrees on the trace id without coordination.
+    pub fn for_origin(origin_id: &str) -> TraceContext {
+        TraceContext {
+            trace_id: content_addressed_id(("trace:".to_string() + origin_id).as_bytes()),
+            parent_span_id: None,
+        }
+    }
+
+    /// Starts a new trace for an update that arrived without trace context.
+    pub fn for_update(update: &Update) -> TraceContext {
+        match update {
+            Update::Event(e) => TraceContext::for_origin(&e.event_id),
+            Update::Deltas(ds) => TraceContext::for_origin(&ds.origin_id),
+            Update::Retractions(rs) => TraceContext::for_origin(&rs.retraction_id),
+            Update::EventCorrection(c) => TraceContext::for_origin(&c.event_id),
+            Update::ManualAssertion(a) => TraceContext::for_origin(&a.assertion_id),
+            Update::Provenance(p) => TraceContext::for_origin(&p.origin_id),
+            Update::Stop | Update::Admin(_) | Update::Watermark(_) => TraceContext::for_origin(""),
+        }
+    }
+
+    pub fn child_of(span: &Span) -> TraceContext {
+        TraceContext {
+            trace_id: span.trace_id.clone(),
+            parent_span_id: Some(span.span_id.clone()),
+        }
+    }
+}
+
+/// A timed unit of work in a trace, such as queueing, matching, an application call or routing.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Span {
+    pub trace_id: String,
+    pub span_id: String,
+    pub parent_span_id: Option<String>,
+    pub name: String,
+    pub service: String,
+    pub start_unix_nanos: u64,
+    pub end_unix_nanos: u64,
+    pub attributes: BTreeMap<String, String>,
+}
+
+impl Span {
+    /// A span starting now. The end is set when the span is finished.
+    pub fn start(name: &str, service: &str, context: &TraceContext) -> Span {
+        Span::start_at(name, service, context, SystemTime::now())
+    }
+
+    pub fn start_at(name: &str, service: &str, context: &TraceContext, start: SystemTime) -> Span {
+        let start_unix_nanos = unix_nanos(start);
+        Span {
+            trace_id: context.trace_id.clone(),
+            span_id: Uuid::new_v4().to_simple().to_string()[..16].to_string(),
+            parent_span_id: context.parent_span_id.clone(),
+            name: name.to_string(),
+            service: service.to_string(),
+            start_unix_nanos,
+            end_unix_nanos: start_unix_nanos,
+            attributes: BTreeMap::new(),
+        }
+    }
+
+    pub fn with_attribute(mut self, key: &str, value: impl ToString) -> Span {
+        self.attributes.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    pub fn finish(mut self) -> Span {
+        self.end_unix_nanos = unix_nanos(SystemTime::now());
+        self
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+#[test]
+fn test_trace_context_is_keyed_by_origin_event() {
+    let context = TraceContext::for_origin("myevent");
+    assert_eq!(context, TraceContext::for_origin("myevent"));
+    assert_ne!(context.trace_id, TraceContext::for_origin("otherevent").trace_id);
+    assert_eq!(context.trace_id.len(), 32);
+    let span = Span::start("process_update", "myquery", &context).finish();
+    let child_context = TraceContext::child_of(&span);
+    assert_eq!(child_context.trace_id, context.trace_id);
+    assert_eq!(child_context.parent_span_id, Some(span.span_id.clone()));
+    assert_eq!(span.span_id.len(), 16);
+    assert!(span.end_unix_nanos >= span.start_unix_nanos);
+}
@@ -12,12 +12,13 @@ pub fn create_application_request(
         query_name,
         application_name,
         matches: to_query_matches(grouped_match),
-        query_graph: to_query_graph(&query_graph),
+        query_graph: to_query_graph(query_graph),
         event: Some(to_proto_event(event)),
     }
 }
 
-pub fn delta_vec_from_response(application_response:crate::application_component::ApplicationResponse) -> Vec<mbei_core::graph::Delta> {
+/// The deltas of a response, or why the application did not respond with valid ones
+pub fn delta_vec_from_response(application_response:crate::application_component::ApplicationResponse) -> Result<Vec<mbei_core::graph::Delta>, String> {
     from_proto_delta_vec(application_response.deltas)
 }
 
@@ -27,7 +28,7 @@ fn to_query_matches(
     grouped_query_match
         .grouped_matches
         .iter()
-        .map(|m| to_query_match(m))
+        .map(to_query_match)
         .collect()
 }
 
@@ -52,7 +53,7 @@ fn to_match_tuple(
 }
 
 fn to_query_graph(graph: &mbei_core::graph::Graph) -> Vec<crate::application_component::QueryEdge> {
-    graph.edges.iter().map(|e| to_query_edge(e)).collect()
+    graph.edges.iter().map(to_query_edge).collect()
 }
 
 fn to_query_edge(edge: &mbei_core::graph::Edge) -> crate::application_component::QueryEdge {
@@ -73,18 +74,12 @@ fn to_instance_edge(edge: &mbei_core::graph::Edge) -> crate::application_compone
 }
 
 fn to_query_node(node: &mbei_core::graph::Node) -> crate::application_component::QueryNode {
-    let instance_node_id = match &node.instance_node_name {
-        Some(node_name) => Some(crate::application_component::OptionalString {
+    let instance_node_id = node.instance_node_name.as_ref().map(|node_name| crate::application_component::OptionalString {
             s: node_name.clone(),
-        }),
-        None => None,
-    };
-    let node_type = match &node.node_type {
-        Some(node_type) => Some(crate::application_component::OptionalString {
+        });
+    let node_type = node.node_type.as_ref().map(|node_type| crate::application_component::OptionalString {
             s: node_type.clone(),
-        }),
-        None => None,
-    };
+        });
 
     crate::application_component::QueryNode {
         query_node_name: node.query_node_name.as_ref().unwrap().clone(),
@@ -0,0 +1,113 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use bincode::{Decode, Encode};
+
+/// Identifies a request from a sender which delivers exactly once.
+/// Sequence numbers start at 1 and are counted separately for each receiver.
+#[derive(Encode, Decode, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Delivery {
+    pub sender_id: String,
+    pub sequence_number: u64,
+}
+
+/// Sequence numbers received from one sender.
+/// Everything up to `contiguous` has been received, as well as the sequence numbers in `ahead`,
+/// so the gaps are the numbers between `contiguous` and the largest in `ahead`.
+#[derive(Encode, Decode, Clone, Debug, Default, PartialEq)]
+pub struct ReceivedSequence {
+    pub contiguous: u64,
+    pub ahead: BTreeSet<u64>,
+}
+
+impl ReceivedSequence {
+    pub fn contains(&self, sequence_number: u64) -> bool {
+        sequence_number <= self.contiguous || self.ahead.contains(&sequence_number)
+    }
+
+    pub fn insert(&mut self, sequence_number: u64) {
+        if sequence_number <= self.contiguous {
+            return;
+        }
+        self.ahead.insert(sequence_number);
+        while self.ahead.remove(&(self.contiguous + 1)) {
+            self.contiguous += 1;
+        }
+    }
+
+    pub fn get_missing(&self) -> Vec<u64> {
+        match self.ahead.iter().next_back() {
+            Some(last) => (self.contiguous + 1..*last)
+                .filter(|s| !self.ahead.contains(s))
+                .collect(),
+            None => vec![],
+        }
+    }
+}
+
+/// Sequence numbers received by one receiver, by sender, used to drop resent requests.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReceivedDeliveries {
+    received_by_sender: BTreeMap<String, ReceivedSequence>,
+}
+
+impl ReceivedDeliveries {
+    pub fn is_received(&self, delivery: &Delivery) -> bool {
+        match self.received_by_sender.get(&delivery.sender_id) {
+            Some(received) => received.contains(delivery.sequence_number),
+            None => false,
+        }
+    }
+
+    pub fn insert(&mut self, delivery: &Delivery) {
+        self.received_by_sender
+            .entry(delivery.sender_id.clone())
+            .or_default()
+            .insert(delivery.sequence_number);
+    }
+
+    /// Sequence numbers not yet received although later ones have been, by sender
+    pub fn get_missing(&self) -> BTreeMap<String, Vec<u64>> {
+        self.received_by_sender
+            .iter()
+            .map(|(sender_id, received)| (sender_id.clone(), received.get_missing()))
+            .filter(|(_, missing)| !missing.is_empty())
+            .collect()
+    }
+
+    pub(crate) fn to_vec(&self) -> Vec<(String, ReceivedSequence)> {
+        self.received_by_sender
+            .iter()
+            .map(|(sender_id, received)| (sender_id.clone(), received.clone()))
+            .collect()
+    }
+
+    pub(crate) fn from_vec(received: Vec<(String, ReceivedSequence)>) -> ReceivedDeliveries {
+        ReceivedDeliveries {
+            received_by_sender: received.into_iter().collect(),
+        }
+    }
+}
+
+#[test]
+fn test_received_deliveries_detect_duplicates_and_gaps() {
+    let delivery = |sender_id: &str, sequence_number| Delivery {
+        sender_id: sender_id.to_string(),
+        sequence_number,
+    };
+    let mut received = ReceivedDeliveries::default();
+    received.insert(&delivery("q1", 1));
+    received.insert(&delivery("q1", 4));
+    received.insert(&delivery("q1", 2));
+    received.insert(&delivery("q2", 1));
+    assert!(received.is_received(&delivery("q1", 2)));
+    assert!(received.is_received(&delivery("q1", 4)));
+    assert!(!received.is_received(&delivery("q1", 3)));
+    assert!(!received.is_received(&delivery("q2", 2)));
+    assert_eq!(received.get_missing(), BTreeMap::from([("q1".to_string(), vec![3])]));
+    received.insert(&delivery("q1", 3));
+    assert!(received.get_missing().is_empty());
+    assert_eq!(
+        received.to_vec()[0],
+        ("q1".to_string(), ReceivedSequence { contiguous: 4, ahead: BTreeSet::new() })
+    );
+}
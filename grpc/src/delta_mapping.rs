@@ -1,15 +1,16 @@
-pub(crate) fn from_proto_delta_vec(deltas: Vec<crate::delta::Delta>) -> Vec<mbei_core::graph::Delta> {
-    deltas.into_iter().map(|pd| from_proto_delta(pd)).collect()
+pub(crate) fn from_proto_delta_vec(deltas: Vec<crate::delta::Delta>) -> Result<Vec<mbei_core::graph::Delta>, String> {
+    deltas.into_iter().map(from_proto_delta).collect()
 }
 
-pub(crate) fn from_proto_delta(proto_delta: crate::delta::Delta) -> mbei_core::graph::Delta {
-    mbei_core::graph::Delta {
-        src: from_instance_node(proto_delta.src.unwrap()),
-        trg: from_instance_node(proto_delta.trg.unwrap()),
+/// The delta, or why the proto delta is not a valid one
+pub(crate) fn from_proto_delta(proto_delta: crate::delta::Delta) -> Result<mbei_core::graph::Delta, String> {
+    Ok(mbei_core::graph::Delta {
+        src: from_instance_node(proto_delta.src.ok_or_else(|| "Delta without a source node".to_string())?)?,
+        trg: from_instance_node(proto_delta.trg.ok_or_else(|| "Delta without a target node".to_string())?)?,
         edge_type: proto_delta.edge_type,
         timestamp: proto_delta.timestamp,
-        delta_type: from_proto_delta_type(proto_delta.delta_type),
-    }
+        delta_type: from_proto_delta_type(proto_delta.delta_type)?,
+    })
 }
 
 pub(crate) fn to_proto_delta(delta:&mbei_core::graph::Delta) -> crate::delta::Delta {
@@ -22,25 +23,22 @@ pub(crate) fn to_proto_delta(delta:&mbei_core::graph::Delta) -> crate::delta::De
     }
 }
 
-pub(crate) fn from_proto_delta_type(proto_delta_type: i32) -> mbei_core::graph::DeltaType {
-    match proto_delta_type.try_into() {
-        Ok(delta_type) => delta_type,
-        _ => panic!("Could not convert to delta type"),
-    }
+pub(crate) fn from_proto_delta_type(proto_delta_type: i32) -> Result<mbei_core::graph::DeltaType, String> {
+    proto_delta_type.try_into().map_err(|_| format!("Unknown delta type {}", proto_delta_type))
 }
 
 pub(crate) fn to_proto_delta_type(delta_type:&mbei_core::graph::DeltaType) -> i32 {
     delta_type.clone() as i32
 }
 
-pub fn from_instance_node(instance_node: crate::delta::InstanceNode) -> mbei_core::graph::Node {
-    mbei_core::graph::Node {
+pub fn from_instance_node(instance_node: crate::delta::InstanceNode) -> Result<mbei_core::graph::Node, String> {
+    Ok(mbei_core::graph::Node {
         query_node_name: None,
         instance_node_name: Some(instance_node.instance_node_id),
         node_type: Some(instance_node.node_type),
-        node_class: from_proto_node_class(instance_node.node_class),
+        node_class: from_proto_node_class(instance_node.node_class)?,
         value_bytes: from_optional_bytes(instance_node.value),
-    }
+    })
 }
 
 fn from_optional_bytes(optional_bytes_opt: Option<crate::delta::OptionalBytes>) -> Option<Vec<u8>> {
@@ -50,18 +48,12 @@ fn from_optional_bytes(optional_bytes_opt: Option<crate::delta::OptionalBytes>)
     }
 }
 
-pub(crate) fn from_proto_node_class(proto_node_class: i32) -> mbei_core::graph::NodeClass {
-    match proto_node_class.try_into() {
-        Ok(delta_type) => delta_type,
-        _ => panic!("Could not convert to delta type"),
-    }
+pub(crate) fn from_proto_node_class(proto_node_class: i32) -> Result<mbei_core::graph::NodeClass, String> {
+    proto_node_class.try_into().map_err(|_| format!("Unknown node class {}", proto_node_class))
 }
 
 pub(crate) fn to_instance_node(node: &mbei_core::graph::Node) -> crate::delta::InstanceNode {
-    let value = match &node.value_bytes {
-        Some(value) => Some(crate::delta::OptionalBytes { b: value.clone() }),
-        None => None,
-    };
+    let value = node.value_bytes.as_ref().map(|value| crate::delta::OptionalBytes { b: value.clone() });
 
     crate::delta::InstanceNode {
         instance_node_id: node.instance_node_name.as_ref().unwrap().clone(),
@@ -0,0 +1,293 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures_util::Stream;
+use log::{info, warn};
+use tokio::sync::Notify;
+use tonic::{Request, Response, Status};
+
+use crate::health::health_check_response::ServingStatus;
+use crate::health::health_server::Health as HealthProtocol;
+use crate::health::{HealthCheckRequest, HealthCheckResponse};
+
+//How often processing loops beat at the least, also while idle
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+//A processing loop that has not beaten for this long is taken to be stuck
+const DEFAULT_LIVENESS_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What a component, or central, reports about itself.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ComponentHealth {
+    //Started and not stopped
+    pub live: bool,
+    //When the processing loop last went round, None while it has not started, as when connecting
+    pub heartbeat: Option<Instant>,
+    //The caller and the router are connected
+    pub ready: bool,
+    //Receivers and applications which the last attempt could not reach
+    pub unreachable_peers: BTreeSet<String>,
+    //Refusing new events and deltas, as the store is above its hard memory limit
+    pub memory_exhausted: bool,
+}
+
+impl ComponentHealth {
+    pub fn is_degraded(&self) -> bool {
+        !self.unreachable_peers.is_empty() || self.memory_exhausted
+    }
+
+    pub fn is_live(&self, liveness_timeout: Duration) -> bool {
+        match self.heartbeat {
+            Some(heartbeat) => self.live && heartbeat.elapsed() < liveness_timeout,
+            None => self.live,
+        }
+    }
+}
+
+/// Health of the components, or of central, served by one process.
+/// The health protocol has no degraded status, so services which include peers report not serving while a peer is unreachable,
+/// or while the store of a component is above its hard memory limit:
+/// - `liveness` is serving while the processing loops of all components have beaten within the liveness timeout
+/// - `readiness` is serving once all components are connected to their application and receivers
+/// - the name of a component is serving while it is live, ready and not degraded, and the empty name while all are
+#[derive(Clone)]
+pub struct Health {
+    components: Arc<Mutex<BTreeMap<String, ComponentHealth>>>,
+    changed: Arc<Notify>,
+    liveness_timeout: Duration,
+}
+
+impl Default for Health {
+    fn default() -> Health {
+        Health::with_liveness_timeout(DEFAULT_LIVENESS_TIMEOUT)
+    }
+}
+
+impl Health {
+    pub fn with_liveness_timeout(liveness_timeout: Duration) -> Health {
+        Health {
+            components: Arc::new(Mutex::new(BTreeMap::new())),
+            changed: Arc::new(Notify::new()),
+            liveness_timeout,
+        }
+    }
+
+    /// Registers a component, which is neither live nor ready until it reports so.
+    pub fn reporter(&self, name: &str) -> HealthReporter {
+        self.components.lock().expect("Health lock poisoned").insert(name.to_string(), ComponentHealth::default());
+        self.changed.notify_waiters();
+        HealthReporter {
+            health: self.clone(),
+            name: name.to_string(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<ComponentHealth> {
+        self.components.lock().expect("Health lock poisoned").get(name).cloned()
+    }
+
+    /// The status of a service name, None if it is not known.
+    pub fn get_status(&self, service: &str) -> Option<ServingStatus> {
+        let components = self.components.lock().expect("Health lock poisoned");
+        let serving = |ok: bool| if ok { ServingStatus::Serving } else { ServingStatus::NotServing };
+        let is_live = |c: &ComponentHealth| c.is_live(self.liveness_timeout);
+        let is_healthy = |c: &ComponentHealth| is_live(c) && c.ready && !c.is_degraded();
+        match service {
+            "liveness" => Some(serving(components.values().all(is_live))),
+            "readiness" => Some(serving(components.values().all(|c| c.ready))),
+            "" => Some(serving(components.values().all(is_healthy))),
+            name => components.get(name).map(|c| serving(is_healthy(c))),
+        }
+    }
+}
+
+/// Updates the health of one component. Reporters are cheap to clone, so that the tasks delivering its updates can report too.
+#[derive(Clone)]
+pub struct HealthReporter {
+    health: Health,
+    name: String,
+}
+
+impl HealthReporter {
+    /// A reporter for a component whose health is not served, as in tests.
+    pub fn detached(name: &str) -> HealthReporter {
+        Health::default().reporter(name)
+    }
+
+    pub fn set_live(&self, live: bool) {
+        self.update(|c| {
+            c.live = live;
+            c.heartbeat = None;
+        });
+    }
+
+    /// Tells that the processing loop still goes round. Watchers are only woken if it had been taken to be stuck.
+    pub fn beat(&self) {
+        let recovered = {
+            let mut components = self.health.components.lock().expect("Health lock poisoned");
+            let component = components.entry(self.name.clone()).or_default();
+            let was_live = component.is_live(self.health.liveness_timeout);
+            component.heartbeat = Some(Instant::now());
+            !was_live && component.live
+        };
+        if recovered {
+            info!("{} processes again", &self.name);
+            self.health.changed.notify_waiters();
+        }
+    }
+
+    pub fn set_ready(&self, ready: bool) {
+        self.update(|c| c.ready = ready);
+    }
+
+    pub fn set_peer_reachable(&self, peer: &str, reachable: bool) {
+        let changed = self.update(|c| {
+            if reachable {
+                c.unreachable_peers.remove(peer);
+            } else {
+                c.unreachable_peers.insert(peer.to_string());
+            }
+        });
+        match (changed, reachable) {
+            (true, true) => info!("{} reaches {} again", &self.name, peer),
+            (true, false) => warn!("{} can not reach {}, and is degraded", &self.name, peer),
+            _ => {}
+        }
+    }
+
+    pub fn set_memory_exhausted(&self, memory_exhausted: bool) {
+        self.update(|c| c.memory_exhausted = memory_exhausted);
+    }
+
+    fn update(&self, f: impl FnOnce(&mut ComponentHealth)) -> bool {
+        let changed = {
+            let mut components = self.health.components.lock().expect("Health lock poisoned");
+            let component = components.entry(self.name.clone()).or_default();
+            let before = component.clone();
+            f(component);
+            *component != before
+        };
+        if changed {
+            self.health.changed.notify_waiters();
+        }
+        changed
+    }
+}
+
+/// Serves the standard gRPC health protocol.
+pub struct HealthService {
+    health: Health,
+}
+
+impl HealthService {
+    pub fn new(health: Health) -> HealthService {
+        HealthService { health }
+    }
+}
+
+type WatchStream = Pin<Box<dyn Stream<Item = Result<HealthCheckResponse, Status>> + Send + 'static>>;
+
+#[tonic::async_trait]
+impl HealthProtocol for HealthService {
+    async fn check(&self, request: Request<HealthCheckRequest>) -> Result<Response<HealthCheckResponse>, Status> {
+        match self.health.get_status(&request.get_ref().service) {
+            Some(status) => Ok(Response::new(HealthCheckResponse { status: status as i32 })),
+            None => Err(Status::not_found(format!("Unknown service {}", &request.get_ref().service))),
+        }
+    }
+
+    type WatchStream = WatchStream;
+
+    /// Sends the status of the service, and then every change to it.
+    async fn watch(&self, request: Request<HealthCheckRequest>) -> Result<Response<Self::WatchStream>, Status> {
+        let initial = (self.health.clone(), request.into_inner().service, None);
+        let stream = futures_util::stream::unfold(initial, |(health, service, last_status)| async move {
+            loop {
+                let changed = health.changed.clone();
+                //Created before checking, so that a change between the check and the wait is not missed
+                let notified = changed.notified();
+                let status = health.get_status(&service).unwrap_or(ServingStatus::ServiceUnknown);
+                if last_status != Some(status) {
+                    let response = Ok(HealthCheckResponse { status: status as i32 });
+                    return Some((response, (health, service, Some(status))));
+                }
+                //Heartbeats going stale wake no one, so the status is also checked again periodically
+                let _ = tokio::time::timeout(HEARTBEAT_INTERVAL, notified).await;
+            }
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+#[test]
+fn test_health_reports_liveness_readiness_and_degradation() {
+    let health = Health::default();
+    let q1 = health.reporter("q1");
+    let q2 = health.reporter("q2");
+    assert_eq!(health.get_status("liveness"), Some(ServingStatus::NotServing));
+    q1.set_live(true);
+    q2.set_live(true);
+    assert_eq!(health.get_status("liveness"), Some(ServingStatus::Serving));
+    assert_eq!(health.get_status("readiness"), Some(ServingStatus::NotServing));
+    q1.set_ready(true);
+    q2.set_ready(true);
+    assert_eq!(health.get_status("readiness"), Some(ServingStatus::Serving));
+    assert_eq!(health.get_status(""), Some(ServingStatus::Serving));
+    q2.set_peer_reachable("central", false);
+    assert!(health.get("q2").unwrap().is_degraded());
+    assert_eq!(health.get_status("q1"), Some(ServingStatus::Serving));
+    assert_eq!(health.get_status("q2"), Some(ServingStatus::NotServing));
+    assert_eq!(health.get_status(""), Some(ServingStatus::NotServing));
+    //Degraded components are still live and ready
+    assert_eq!(health.get_status("readiness"), Some(ServingStatus::Serving));
+    q2.set_peer_reachable("central", true);
+    assert_eq!(health.get_status(""), Some(ServingStatus::Serving));
+    q1.set_memory_exhausted(true);
+    assert_eq!(health.get_status("q1"), Some(ServingStatus::NotServing));
+    q1.set_memory_exhausted(false);
+    assert_eq!(health.get_status("q1"), Some(ServingStatus::Serving));
+    assert_eq!(health.get_status("q3"), None);
+}
+
+#[test]
+fn test_liveness_follows_heartbeats() {
+    let health = Health::with_liveness_timeout(Duration::from_millis(100));
+    let q1 = health.reporter("q1");
+    q1.set_live(true);
+    //Live before the first heartbeat, while connecting
+    std::thread::sleep(Duration::from_millis(150));
+    assert_eq!(health.get_status("liveness"), Some(ServingStatus::Serving));
+    q1.beat();
+    assert_eq!(health.get_status("liveness"), Some(ServingStatus::Serving));
+    std::thread::sleep(Duration::from_millis(150));
+    assert_eq!(health.get_status("liveness"), Some(ServingStatus::NotServing));
+    q1.beat();
+    assert_eq!(health.get_status("liveness"), Some(ServingStatus::Serving));
+    q1.set_live(false);
+    q1.beat();
+    assert_eq!(health.get_status("liveness"), Some(ServingStatus::NotServing));
+}
+
+#[test]
+fn test_health_watch_sends_changes() {
+    use futures_util::StreamExt;
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let health = Health::default();
+        let q1 = health.reporter("q1");
+        let service = HealthService::new(health.clone());
+        let request = Request::new(HealthCheckRequest { service: "q1".to_string() });
+        let mut stream = service.watch(request).await.unwrap().into_inner();
+        assert_eq!(stream.next().await.unwrap().unwrap().status, ServingStatus::NotServing as i32);
+        q1.set_live(true);
+        //No change to the status of q1 until it is also ready
+        q1.set_ready(true);
+        assert_eq!(stream.next().await.unwrap().unwrap().status, ServingStatus::Serving as i32);
+        let request = Request::new(HealthCheckRequest { service: "q2".to_string() });
+        let mut unknown = service.watch(request).await.unwrap().into_inner();
+        assert_eq!(unknown.next().await.unwrap().unwrap().status, ServingStatus::ServiceUnknown as i32);
+        let status = service.check(Request::new(HealthCheckRequest { service: "q2".to_string() })).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    });
+}
@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use bincode::config::{standard, Configuration};
+use bincode::{Decode, Encode};
+use log::info;
+use mbei_core::event::Update;
+
+use crate::delivery::{Delivery, ReceivedDeliveries, ReceivedSequence};
+use crate::record_log::{LogSync, RecordLog};
+
+//Rewriting the log is only worth it when it has grown large, and to at least twice the size it was rewritten to,
+//since the pending updates are written again
+const LOG_BYTES_BEFORE_COMPACTION: u64 = 16 * 1024 * 1024;
+
+#[derive(Encode, Decode)]
+enum InboxRecord {
+    Accepted(u64, Option<Delivery>, Update),
+    Processed(u64),
+    //Written first when the log is rewritten, since accepted records are dropped once processed
+    Received(Vec<(String, ReceivedSequence)>),
+}
+
+/// Append-only log of accepted updates.
+/// An update is appended and synced before it is acknowledged, and marked as processed when the component is done with it.
+/// Appending does not sync, so that the server can sync after it has unlocked the queue.
+/// On restart, the updates that were accepted but not processed are replayed.
+/// The log also keeps the deliveries received, so that requests resent after a restart are still dropped.
+pub struct Inbox {
+    log: RecordLog,
+    next_seq: u64,
+    pending: BTreeMap<u64, (Option<Delivery>, Update)>,
+    //Size of the log when it was last rewritten
+    compacted_len: u64,
+    config: Configuration,
+}
+
+/// An opened inbox, with the updates still pending in it and the deliveries received so far
+pub type OpenedInbox = (Inbox, Vec<(u64, Update)>, ReceivedDeliveries);
+
+impl Inbox {
+    /// Opens or creates the log, returning the pending updates in the order they were accepted
+    /// and the deliveries received so far. A record which can not be decoded is an error, as the updates after it would be lost.
+    pub fn open(path: &Path) -> std::io::Result<OpenedInbox> {
+        let config = standard();
+        let (log, records) = RecordLog::open(path)?;
+        let mut pending = BTreeMap::new();
+        let mut received = ReceivedDeliveries::default();
+        for encoded in records {
+            let (record, _) = bincode::decode_from_slice(&encoded, config)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Could not decode a record of {:?}: {}", path, e)))?;
+            match record {
+                InboxRecord::Accepted(seq, delivery, update) => {
+                    if let Some(delivery) = &delivery {
+                        received.insert(delivery);
+                    }
+                    pending.insert(seq, (delivery, update));
+                }
+                InboxRecord::Processed(seq) => {
+                    pending.remove(&seq);
+                }
+                InboxRecord::Received(sequences) => {
+                    received = ReceivedDeliveries::from_vec(sequences);
+                }
+            }
+        }
+        let mut inbox = Inbox {
+            log,
+            next_seq: pending.keys().next_back().map(|seq| seq + 1).unwrap_or(0),
+            pending,
+            compacted_len: 0,
+            config,
+        };
+        inbox.rewrite(&received)?;
+        let pending_updates: Vec<(u64, Update)> = inbox
+            .pending
+            .iter()
+            .map(|(seq, (_, update))| (*seq, update.clone()))
+            .collect();
+        info!("Opened inbox {:?} with {} pending updates", path, pending_updates.len());
+        Ok((inbox, pending_updates, received))
+    }
+
+    /// Appends an accepted update, which is only pending once it was written.
+    pub fn append(&mut self, update: &Update, delivery: Option<Delivery>) -> std::io::Result<u64> {
+        let seq = self.next_seq;
+        let record = InboxRecord::Accepted(seq, delivery.clone(), update.clone());
+        self.log.append_unsynced(&self.encode(&record))?;
+        self.next_seq += 1;
+        self.pending.insert(seq, (delivery, update.clone()));
+        Ok(seq)
+    }
+
+    /// Marks an update as processed. The deliveries received so far are kept if the log is rewritten.
+    pub fn mark_processed(&mut self, seq: u64, received: &ReceivedDeliveries) -> std::io::Result<()> {
+        if self.pending.remove(&seq).is_none() {
+            return Ok(());
+        }
+        //Not synced, as an update processed again after a crash gives the same output
+        let encoded = self.encode(&InboxRecord::Processed(seq));
+        self.log.append_unsynced(&encoded)?;
+        if self.log.len() >= LOG_BYTES_BEFORE_COMPACTION.max(2 * self.compacted_len) {
+            self.rewrite(received)?;
+        }
+        Ok(())
+    }
+
+    pub fn get_n_pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub(crate) fn get_sync(&self) -> LogSync {
+        self.log.get_sync()
+    }
+
+    fn rewrite(&mut self, received: &ReceivedDeliveries) -> std::io::Result<()> {
+        let mut records = vec![];
+        let sequences = received.to_vec();
+        if !sequences.is_empty() {
+            records.push(self.encode(&InboxRecord::Received(sequences)));
+        }
+        for (seq, (delivery, update)) in &self.pending {
+            records.push(self.encode(&InboxRecord::Accepted(*seq, delivery.clone(), update.clone())));
+        }
+        self.log.rewrite(&records)?;
+        self.compacted_len = self.log.len();
+        Ok(())
+    }
+
+    fn encode(&self, record: &InboxRecord) -> Vec<u8> {
+        bincode::encode_to_vec(record, self.config).expect("Encodable")
+    }
+}
+
+#[cfg(test)]
+fn create_test_event(event_id: &str) -> Update {
+    Update::Event(mbei_core::event::Event {
+        event_id: event_id.to_string(),
+        timestamp: 1,
+        node_id: "abc123".to_string(),
+        payload: vec![1, 2, 3],
+    })
+}
+
+#[test]
+fn test_inbox_replays_unprocessed_updates() {
+    let path = crate::record_log::create_test_log_path("inbox-replay");
+    let received = ReceivedDeliveries::default();
+    {
+        let (mut inbox, pending, _) = Inbox::open(&path).unwrap();
+        assert!(pending.is_empty());
+        let s1 = inbox.append(&create_test_event("e1"), None).unwrap();
+        inbox.append(&create_test_event("e2"), None).unwrap();
+        inbox.append(&create_test_event("e3"), None).unwrap();
+        inbox.mark_processed(s1, &received).unwrap();
+    }
+    let (mut inbox, pending, _) = Inbox::open(&path).unwrap();
+    let replayed: Vec<Update> = pending.iter().map(|(_, u)| u.clone()).collect();
+    assert_eq!(replayed, vec![create_test_event("e2"), create_test_event("e3")]);
+    //Sequence numbers continue after the replayed updates
+    assert_eq!(inbox.append(&create_test_event("e4"), None).unwrap(), 3);
+    for (seq, _) in pending {
+        inbox.mark_processed(seq, &received).unwrap();
+    }
+    inbox.mark_processed(3, &received).unwrap();
+    assert_eq!(inbox.get_n_pending(), 0);
+    drop(inbox);
+    let (_, pending, _) = Inbox::open(&path).unwrap();
+    assert!(pending.is_empty());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_inbox_keeps_received_deliveries_after_rewrite() {
+    let path = crate::record_log::create_test_log_path("inbox-deliveries");
+    let delivery = |sequence_number| Delivery {
+        sender_id: "q1".to_string(),
+        sequence_number,
+    };
+    let mut received = ReceivedDeliveries::default();
+    {
+        let (mut inbox, _, _) = Inbox::open(&path).unwrap();
+        let s1 = inbox.append(&create_test_event("e1"), Some(delivery(1))).unwrap();
+        received.insert(&delivery(1));
+        inbox.append(&create_test_event("e3"), Some(delivery(3))).unwrap();
+        received.insert(&delivery(3));
+        inbox.mark_processed(s1, &received).unwrap();
+    }
+    let (mut inbox, pending, replayed_received) = Inbox::open(&path).unwrap();
+    assert_eq!(replayed_received, received);
+    //Opening rewrote the log with the received deliveries first
+    inbox.mark_processed(pending[0].0, &received).unwrap();
+    drop(inbox);
+    let (_, pending, replayed_received) = Inbox::open(&path).unwrap();
+    assert!(pending.is_empty());
+    assert!(replayed_received.is_received(&delivery(3)));
+    assert_eq!(replayed_received.get_missing()["q1"], vec![2]);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_inbox_with_corrupt_record_is_not_opened() {
+    let path = crate::record_log::create_test_log_path("inbox-corrupt");
+    {
+        let (mut log, _) = RecordLog::open(&path).unwrap();
+        log.append_unsynced(b"not an inbox record").unwrap();
+    }
+    let error = Inbox::open(&path).err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    std::fs::remove_file(&path).unwrap();
+}
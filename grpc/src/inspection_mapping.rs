@@ -0,0 +1,39 @@
+use crate::delta_mapping::{from_instance_node, to_instance_node};
+use crate::event_mapping::{from_proto_event, to_proto_event};
+use crate::delta::OptionalTimestamp;
+
+pub fn to_proto_edge(edge: &mbei_core::graph::Edge) -> crate::delta::Edge {
+    crate::delta::Edge {
+        src: Some(to_instance_node(&edge.src)),
+        trg: Some(to_instance_node(&edge.trg)),
+        edge_type: edge.edge_type.clone(),
+        from_timestamp: to_optional_timestamp(edge.from_timestamp),
+        to_timestamp: to_optional_timestamp(edge.to_timestamp),
+    }
+}
+
+pub fn from_proto_edge(proto_edge: crate::delta::Edge) -> Result<mbei_core::graph::Edge, String> {
+    Ok(mbei_core::graph::Edge {
+        src: from_instance_node(proto_edge.src.ok_or_else(|| "Edge without a source node".to_string())?)?,
+        trg: from_instance_node(proto_edge.trg.ok_or_else(|| "Edge without a target node".to_string())?)?,
+        edge_type: proto_edge.edge_type,
+        from_timestamp: from_optional_timestamp(proto_edge.from_timestamp),
+        to_timestamp: from_optional_timestamp(proto_edge.to_timestamp),
+    })
+}
+
+pub fn to_proto_events(events: Vec<&mbei_core::event::Event>) -> Vec<crate::event::Event> {
+    events.into_iter().map(to_proto_event).collect()
+}
+
+pub fn from_proto_events(proto_events: Vec<crate::event::Event>) -> Vec<mbei_core::event::Event> {
+    proto_events.into_iter().map(from_proto_event).collect()
+}
+
+pub fn to_optional_timestamp(timestamp: Option<u64>) -> Option<OptionalTimestamp> {
+    timestamp.map(|timestamp| OptionalTimestamp { timestamp })
+}
+
+pub fn from_optional_timestamp(optional_timestamp: Option<OptionalTimestamp>) -> Option<u64> {
+    optional_timestamp.map(|t| t.timestamp)
+}
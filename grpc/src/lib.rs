@@ -1,3 +1,6 @@
+//Interceptors and services return tonic::Status as their error, which is larger than clippy prefers
+#![allow(clippy::result_large_err)]
+
 pub mod delta {
     tonic::include_proto!("delta");
 }
@@ -14,9 +17,37 @@ pub mod process_update {
     tonic::include_proto!("process_update");
 }
 
+pub mod inspection {
+    tonic::include_proto!("inspection");
+}
+
+pub mod health {
+    tonic::include_proto!("grpc.health.v1");
+}
+
+pub mod subscription {
+    tonic::include_proto!("subscription");
+}
+
+pub mod replication {
+    tonic::include_proto!("replication");
+}
+
 mod delta_mapping;
 mod event_mapping;
 pub mod application_component_mapping;
+pub mod delivery;
+pub mod health_server;
+pub mod inbox;
+pub mod inspection_mapping;
+pub mod metrics_server;
+pub mod operators;
+pub mod outbox;
 pub mod process_update_mapping;
 pub mod process_update_client;
 pub mod process_update_server;
+mod record_log;
+pub mod replication_server;
+pub mod subscription_server;
+pub mod tls;
+pub mod tracer;
@@ -0,0 +1,44 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use log::{error, info};
+use prometheus::{Encoder, TextEncoder};
+use tokio::task::JoinHandle;
+
+/// Serves the metrics of the process in Prometheus text format on /metrics.
+/// All components and central in a process register their metrics in the default registry, so one server per process is enough.
+pub fn spawn_metrics_server(metrics_port: u16) -> JoinHandle<()> {
+    let address: SocketAddr = ("[::]:".to_owned() + &metrics_port.to_string())
+        .parse()
+        .expect("Error parsing metrics address");
+    tokio::spawn(async move {
+        let make_service = make_service_fn(|_connection| async {
+            Ok::<_, Infallible>(service_fn(serve_metrics))
+        });
+        info!("Serving metrics at {}", &address);
+        if let Err(e) = Server::bind(&address).serve(make_service).await {
+            error!("Metrics server failed: {}", e);
+        }
+    })
+}
+
+async fn serve_metrics(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if request.uri().path() != "/metrics" {
+        let mut not_found = Response::new(Body::empty());
+        *not_found.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(not_found);
+    }
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    encoder
+        .encode(&prometheus::gather(), &mut buffer)
+        .expect("Could not encode metrics");
+    let response = Response::builder()
+        .header(CONTENT_TYPE, encoder.format_type())
+        .body(Body::from(buffer))
+        .expect("Could not create response");
+    Ok(response)
+}
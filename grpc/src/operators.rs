@@ -0,0 +1,60 @@
+use std::collections::BTreeMap;
+
+use tonic::metadata::MetadataValue;
+use tonic::{Request, Status};
+
+use crate::process_update::ProcessUpdateRequest;
+
+/// Header carrying the token an operator authenticates with when sending manual assertions.
+pub const OPERATOR_TOKEN_HEADER: &str = "x-mbei-operator-token";
+
+/// Operators allowed to send manual assertions to a component, by their token.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OperatorTokens {
+    operators_by_token: BTreeMap<String, String>,
+}
+
+impl OperatorTokens {
+    pub fn new(tokens_by_operator: &BTreeMap<String, String>) -> OperatorTokens {
+        OperatorTokens {
+            operators_by_token: tokens_by_operator
+                .iter()
+                .map(|(operator, token)| (token.clone(), operator.clone()))
+                .collect(),
+        }
+    }
+
+    /// The operator whose token the request carries. No operators are allowed if there are no tokens.
+    pub fn authenticate<T>(&self, request: &Request<T>) -> Result<String, Status> {
+        let token = request
+            .metadata()
+            .get(OPERATOR_TOKEN_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| Status::unauthenticated("Manual assertions require an operator token"))?;
+        self.operators_by_token
+            .get(token)
+            .cloned()
+            .ok_or_else(|| Status::unauthenticated("Unknown operator token"))
+    }
+}
+
+/// A request authenticated as the operator with the token.
+pub fn with_operator_token(request: ProcessUpdateRequest, token: &str) -> Result<Request<ProcessUpdateRequest>, String> {
+    let value = MetadataValue::from_str(token).map_err(|e| format!("Invalid operator token: {}", e))?;
+    let mut request = Request::new(request);
+    request.metadata_mut().insert(OPERATOR_TOKEN_HEADER, value);
+    Ok(request)
+}
+
+#[test]
+fn test_operators_are_authenticated_by_token() {
+    let tokens = OperatorTokens::new(&BTreeMap::from([("alice".to_string(), "secret".to_string())]));
+    let request = |token: Option<&str>| match token {
+        Some(token) => with_operator_token(ProcessUpdateRequest::default(), token).unwrap(),
+        None => Request::new(ProcessUpdateRequest::default()),
+    };
+    assert_eq!(tokens.authenticate(&request(Some("secret"))).unwrap(), "alice");
+    assert_eq!(tokens.authenticate(&request(Some("guess"))).unwrap_err().code(), tonic::Code::Unauthenticated);
+    assert_eq!(tokens.authenticate(&request(None)).unwrap_err().code(), tonic::Code::Unauthenticated);
+    assert!(OperatorTokens::default().authenticate(&request(Some("secret"))).is_err());
+}
@@ -0,0 +1,300 @@
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use backoff::ExponentialBackoffBuilder;
+use bincode::config::{standard, Configuration};
+use bincode::{Decode, Encode};
+use log::{debug, error, info, warn};
+use prost::Message;
+use tonic::transport::Channel;
+use tonic::{Code, Response, Status};
+
+use mbei_core::event::Update;
+
+use crate::delivery::Delivery;
+use crate::health_server::HealthReporter;
+use crate::process_update::process_update_client::ProcessUpdateClient;
+use crate::process_update::{ProcessUpdateRequest, ProcessUpdateResponse};
+use crate::process_update_mapping::{delivery_from_request, set_request_delivery, update_from_request};
+use crate::record_log::RecordLog;
+
+//Rewriting the log is only worth it when it has grown large, and to at least twice the size it was rewritten to,
+//since the unacknowledged requests are written again
+const LOG_BYTES_BEFORE_COMPACTION: u64 = 16 * 1024 * 1024;
+
+#[derive(Encode, Decode)]
+enum OutboxRecord {
+    //The receiver and the encoded request
+    Sent(Delivery, String, Vec<u8>),
+    Acknowledged(String, Delivery),
+    //Written first on every rewrite, since acknowledged requests no longer tell how far numbering got
+    Sender(String, Vec<(String, u64)>),
+}
+
+/// Requests sent but not yet acknowledged, numbered per receiver so that receivers can drop duplicates and detect gaps.
+/// An outbox backed by a log keeps its sender id and numbering across restarts, so receivers recognize requests resent from the log.
+/// Records are appended without syncing, so that the outbox can be locked on the runtime. The log is synced off it by `sync_outbox`,
+/// before a request is delivered and before the update that caused it is marked as processed.
+pub struct Outbox {
+    sender_id: String,
+    next_sequence_numbers: BTreeMap<String, u64>,
+    unacknowledged: BTreeMap<(String, Delivery), ProcessUpdateRequest>,
+    log: Option<RecordLog>,
+    //Size of the log when it was last rewritten
+    compacted_len: u64,
+    config: Configuration,
+}
+
+impl Outbox {
+    pub fn new(sender_name: &str) -> Outbox {
+        Outbox {
+            sender_id: sender_name.to_string() + "/" + &uuid::Uuid::new_v4().to_string(),
+            next_sequence_numbers: BTreeMap::new(),
+            unacknowledged: BTreeMap::new(),
+            log: None,
+            compacted_len: 0,
+            config: standard(),
+        }
+    }
+
+    /// An outbox backed by a log. Requests that were not acknowledged before a restart are returned with their receivers, to be resent.
+    /// A record which can not be decoded is an error, as the requests after it would be lost.
+    pub fn open(sender_name: &str, path: &Path) -> std::io::Result<(Outbox, Vec<(String, ProcessUpdateRequest)>)> {
+        let (log, records) = RecordLog::open(path)?;
+        let mut outbox = Outbox::new(sender_name);
+        for encoded in records {
+            let (record, _) = bincode::decode_from_slice(&encoded, outbox.config)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Could not decode a record of {:?}: {}", path, e)))?;
+            match record {
+                OutboxRecord::Sent(delivery, receiver, encoded) => {
+                    let request = ProcessUpdateRequest::decode(encoded.as_slice())
+                        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Could not decode a request of {:?}: {}", path, e)))?;
+                    let next_sequence_number = outbox.next_sequence_numbers.entry(receiver.clone()).or_insert(1);
+                    *next_sequence_number = (*next_sequence_number).max(delivery.sequence_number + 1);
+                    outbox.sender_id = delivery.sender_id.clone();
+                    outbox.unacknowledged.insert((receiver, delivery), request);
+                }
+                OutboxRecord::Acknowledged(receiver, delivery) => {
+                    outbox.unacknowledged.remove(&(receiver, delivery));
+                }
+                OutboxRecord::Sender(sender_id, next_sequence_numbers) => {
+                    outbox.sender_id = sender_id;
+                    outbox.next_sequence_numbers = next_sequence_numbers.into_iter().collect();
+                }
+            }
+        }
+        outbox.log = Some(log);
+        outbox.rewrite()?;
+        info!("Opened outbox {:?} with {} unacknowledged requests", path, outbox.unacknowledged.len());
+        let unacknowledged = outbox.get_unacknowledged();
+        Ok((outbox, unacknowledged))
+    }
+
+    /// Numbers the request for the receiver, and keeps it until it is acknowledged.
+    pub fn add(&mut self, receiver: &str, mut request: ProcessUpdateRequest) -> ProcessUpdateRequest {
+        let next_sequence_number = self.next_sequence_numbers.entry(receiver.to_string()).or_insert(1);
+        let delivery = Delivery {
+            sender_id: self.sender_id.clone(),
+            sequence_number: *next_sequence_number,
+        };
+        *next_sequence_number += 1;
+        set_request_delivery(&mut request, &delivery);
+        let encoded = self.encode(&OutboxRecord::Sent(delivery.clone(), receiver.to_string(), request.encode_to_vec()));
+        if let Some(log) = &mut self.log {
+            //Still delivered, but not resent after a restart
+            if let Err(e) = log.append_unsynced(&encoded) {
+                error!("Could not write request {} to {} to the outbox: {}", delivery.sequence_number, receiver, e);
+            }
+        }
+        self.unacknowledged.insert((receiver.to_string(), delivery), request.clone());
+        request
+    }
+
+    /// Forgets a request once the receiver has it. Not synced, as a request resent after a restart is dropped by the receiver.
+    pub fn acknowledge(&mut self, receiver: &str, delivery: &Delivery) {
+        let key = (receiver.to_string(), delivery.clone());
+        if self.unacknowledged.remove(&key).is_none() {
+            return;
+        }
+        let encoded = self.encode(&OutboxRecord::Acknowledged(key.0, key.1));
+        let compact = matches!(&self.log, Some(log) if log.len() >= LOG_BYTES_BEFORE_COMPACTION.max(2 * self.compacted_len));
+        let result = if compact {
+            self.rewrite()
+        } else if let Some(log) = &mut self.log {
+            log.append_unsynced(&encoded)
+        } else {
+            Ok(())
+        };
+        if let Err(e) = result {
+            error!("Could not write the acknowledgement of {} by {} to the outbox: {}", delivery.sequence_number, receiver, e);
+        }
+    }
+
+    /// The requests not yet acknowledged, with their receivers.
+    pub fn get_unacknowledged(&self) -> Vec<(String, ProcessUpdateRequest)> {
+        self.unacknowledged
+            .iter()
+            .map(|((receiver, _), request)| (receiver.clone(), request.clone()))
+            .collect()
+    }
+
+    /// Forgets every request, as a standby does with what it held back once its primary has delivered the same.
+    pub fn clear(&mut self) {
+        self.unacknowledged.clear();
+        if let Err(e) = self.rewrite() {
+            error!("Could not rewrite the outbox: {}", e);
+        }
+    }
+
+    pub fn get_n_unacknowledged(&self) -> usize {
+        self.unacknowledged.len()
+    }
+
+    /// The earliest timestamp of the updates to the receiver which are not yet acknowledged.
+    /// A watermark sent to the receiver must not pass it, as the watermark may arrive first.
+    pub fn get_min_unacknowledged_timestamp(&self, receiver: &str) -> Option<u64> {
+        self.unacknowledged
+            .iter()
+            .filter(|((r, _), _)| r == receiver)
+            .filter_map(|(_, request)| match update_from_request(request) {
+                Ok(update @ (Update::Event(_) | Update::Deltas(_) | Update::Retractions(_))) => Some(update.timestamp()),
+                _ => None,
+            })
+            .min()
+    }
+
+    fn rewrite(&mut self) -> std::io::Result<()> {
+        let sender = OutboxRecord::Sender(
+            self.sender_id.clone(),
+            self.next_sequence_numbers.iter().map(|(receiver, n)| (receiver.clone(), *n)).collect(),
+        );
+        let mut records = vec![self.encode(&sender)];
+        records.extend(self.unacknowledged.iter().map(|((receiver, delivery), request)| {
+            self.encode(&OutboxRecord::Sent(delivery.clone(), receiver.clone(), request.encode_to_vec()))
+        }));
+        if let Some(log) = &mut self.log {
+            log.rewrite(&records)?;
+            self.compacted_len = log.len();
+        }
+        Ok(())
+    }
+
+    fn encode(&self, record: &OutboxRecord) -> Vec<u8> {
+        bincode::encode_to_vec(record, self.config).expect("Encodable")
+    }
+}
+
+/// Syncs what was appended to the log of the outbox so far, off the runtime and without holding the outbox lock.
+/// Concurrent syncs are committed together, so syncing before each delivery batches the requests sent meanwhile.
+pub async fn sync_outbox(outbox: &Arc<Mutex<Outbox>>) -> Result<(), String> {
+    let log_sync = outbox.lock().expect("Outbox lock poisoned").log.as_ref().map(RecordLog::get_sync);
+    match log_sync {
+        Some(log_sync) => tokio::task::spawn_blocking(move || log_sync.sync())
+            .await
+            .map_err(|e| format!("Could not sync outbox: {}", e))?
+            .map_err(|e| format!("Could not sync outbox: {}", e)),
+        None => Ok(()),
+    }
+}
+
+/// Sends a request from the outbox, retrying with backoff until it is acknowledged.
+/// Resending is safe since the receiver drops requests it has already accepted.
+/// The receiver is reported as unreachable while sending fails for other reasons than a full queue.
+/// A request the receiver rejects as such is dropped from the outbox and its error returned, as resending cannot succeed.
+pub async fn deliver_until_acknowledged(
+    client: ProcessUpdateClient<Channel>,
+    receiver: String,
+    request: ProcessUpdateRequest,
+    outbox: Arc<Mutex<Outbox>>,
+    health: HealthReporter,
+) -> Result<Response<ProcessUpdateResponse>, Status> {
+    //Numbering continues from the log after a restart, so a request must not reach the receiver before it is in the log
+    if let Err(e) = sync_outbox(&outbox).await {
+        error!("Before delivering to {}: {}", &receiver, e);
+    }
+    let op = || async {
+        let result = client.clone().send(request.clone()).await.map_err(|status| {
+            if is_permanent(&status) {
+                return backoff::Error::permanent(status);
+            }
+            if status.code() == Code::ResourceExhausted {
+                debug!("Receiving queue is full, will retry: {}", status.message());
+            } else {
+                warn!("Could not deliver update, will retry: {}", status);
+                health.set_peer_reachable(&receiver, false);
+            }
+            backoff::Error::transient(status)
+        });
+        if result.is_ok() {
+            health.set_peer_reachable(&receiver, true);
+        }
+        result
+    };
+    let backoff = ExponentialBackoffBuilder::new()
+        .with_initial_interval(Duration::from_millis(10))
+        .with_max_interval(Duration::from_secs(5))
+        .with_max_elapsed_time(None)
+        .build();
+    let result = backoff::future::retry(backoff, op).await;
+    if let Err(status) = &result {
+        error!("{} rejected update, dropping it: {}", &receiver, status);
+    }
+    if let Ok(Some(delivery)) = delivery_from_request(&request) {
+        outbox.lock().expect("Outbox lock poisoned").acknowledge(&receiver, &delivery);
+    }
+    result
+}
+
+//Errors about the request itself or the state of the receiver, rather than about reaching it
+fn is_permanent(status: &Status) -> bool {
+    matches!(status.code(), Code::InvalidArgument | Code::NotFound | Code::FailedPrecondition)
+}
+
+#[test]
+fn test_outbox_resends_unacknowledged_requests_after_restart() {
+    let path = crate::record_log::create_test_log_path("outbox");
+    let stop = crate::process_update_mapping::request_from_update(&mbei_core::event::Update::Stop);
+    let first_sender_id;
+    {
+        let (mut outbox, unacknowledged) = Outbox::open("q1", &path).unwrap();
+        assert!(unacknowledged.is_empty());
+        let r1 = outbox.add("q2", stop.clone());
+        let r2 = outbox.add("q2", stop.clone());
+        let r3 = outbox.add("central", stop.clone());
+        let d1 = delivery_from_request(&r1).unwrap().unwrap();
+        first_sender_id = d1.sender_id.clone();
+        assert_eq!(d1.sequence_number, 1);
+        assert_eq!(delivery_from_request(&r2).unwrap().unwrap().sequence_number, 2);
+        //Receivers are numbered separately
+        assert_eq!(delivery_from_request(&r3).unwrap().unwrap().sequence_number, 1);
+        outbox.acknowledge("q2", &d1);
+        assert_eq!(outbox.get_n_unacknowledged(), 2);
+    }
+    let (mut outbox, unacknowledged) = Outbox::open("q1", &path).unwrap();
+    let resent: Vec<(String, u64)> = unacknowledged
+        .iter()
+        .map(|(receiver, request)| (receiver.clone(), delivery_from_request(request).unwrap().unwrap().sequence_number))
+        .collect();
+    assert_eq!(resent, vec![("central".to_string(), 1), ("q2".to_string(), 2)]);
+    let r4 = outbox.add("q2", stop.clone());
+    let d4 = delivery_from_request(&r4).unwrap().unwrap();
+    //Numbering continues, so the receiver does not take the request for a duplicate
+    assert_eq!(d4.sender_id, first_sender_id);
+    assert_eq!(d4.sequence_number, 3);
+    for (receiver, request) in unacknowledged {
+        outbox.acknowledge(&receiver, &delivery_from_request(&request).unwrap().unwrap());
+    }
+    outbox.acknowledge("q2", &d4);
+    assert_eq!(outbox.get_n_unacknowledged(), 0);
+    drop(outbox);
+    //The sender id and numbering are kept once every request was acknowledged
+    let (mut outbox, unacknowledged) = Outbox::open("q1", &path).unwrap();
+    assert!(unacknowledged.is_empty());
+    let d5 = delivery_from_request(&outbox.add("q2", stop)).unwrap().unwrap();
+    assert_eq!(d5.sender_id, first_sender_id);
+    assert_eq!(d5.sequence_number, 4);
+    std::fs::remove_file(&path).unwrap();
+}
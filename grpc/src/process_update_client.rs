@@ -5,19 +5,23 @@ use std::time::Duration;
 use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::sync::oneshot::Sender;
 use tokio::task::JoinHandle;
-use tonic::Status;
+use tonic::{Code, Response, Status};
 use tonic::transport::Channel;
-use crate::process_update::ProcessUpdateResponse;
+use crate::process_update::{ProcessUpdateRequest, ProcessUpdateResponse};
+use crate::tls::{create_endpoint, TlsOptions};
 
 type JoinHandleType = JoinHandle<Result<tonic::Response<ProcessUpdateResponse>, Status>>;
 
+/// Connects to the url, retrying with backoff for at most the maximum elapsed time.
 pub async fn create_process_update_client(
     url: String,
     max_elapsed_time: Option<Duration>,
-) -> Option<ProcessUpdateClient<Channel>> {
+    tls: Option<TlsOptions>,
+) -> Result<ProcessUpdateClient<Channel>, String> {
     debug!("Process update client with url {} starting", url);
+    let endpoint = create_endpoint(&url, tls.as_ref())?;
     let op = || async {
-        let client = ProcessUpdateClient::connect(url.to_string()).await?;
+        let client = ProcessUpdateClient::connect(endpoint.clone()).await?;
         Ok(client)
     };
 
@@ -25,20 +29,43 @@ pub async fn create_process_update_client(
         .with_max_elapsed_time(max_elapsed_time)
         .with_max_interval(Duration::from_secs(10))
         .build();
-    let client = Some(
-        backoff::future::retry(backoff, op)
-            .await
-            .expect("Could not connect to backend grpc service in maximum allotted time"),
-    );
+    let client = backoff::future::retry(backoff, op)
+        .await
+        .map_err(|e: tonic::transport::Error| format!("Could not connect to {} in maximum allotted time: {}", url, e))?;
     debug!("Process update client with url {} started", url);
-    client
+    Ok(client)
+}
+
+/// Sends an update, retrying with backoff for as long as the receiving queue is full.
+/// Other errors are returned immediately.
+pub async fn send_respecting_backpressure(
+    client: ProcessUpdateClient<Channel>,
+    request: ProcessUpdateRequest,
+) -> Result<Response<ProcessUpdateResponse>, Status> {
+    let op = || async {
+        client.clone().send(request.clone()).await.map_err(|status| {
+            if status.code() == Code::ResourceExhausted {
+                debug!("Receiving queue is full, will retry: {}", status.message());
+                backoff::Error::transient(status)
+            } else {
+                backoff::Error::permanent(status)
+            }
+        })
+    };
+    let backoff = ExponentialBackoffBuilder::new()
+        .with_initial_interval(Duration::from_millis(10))
+        .with_max_interval(Duration::from_secs(1))
+        .with_max_elapsed_time(None)
+        .build();
+    backoff::future::retry(backoff, op).await
 }
 
 pub async fn await_deliveries(mut receiver: UnboundedReceiver<JoinHandleType>) {
     loop {
         let handle_opt = receiver.recv().await;
         if let Some(handle) = handle_opt {
-            handle.await.expect("Error sending").expect("Error sending");
+            //Rejected updates were already reported and dropped when delivering them
+            let _ = handle.await.expect("Error sending");
         } else {
             break;
         }
@@ -1,12 +1,16 @@
 use std::collections::BTreeSet;
 use crate::delta_mapping::{from_proto_delta, to_proto_delta};
 use crate::event_mapping::{from_proto_event, to_proto_event};
+use crate::inspection_mapping::{from_proto_edge, to_proto_edge};
+use crate::process_update::admin::Command;
 use crate::process_update::process_update_request::Update;
-use crate::process_update::{ProcessUpdateRequest, Stop};
+use crate::delivery::Delivery;
+use crate::process_update::{Admin, DiscardQuarantined, Drain, EventCorrection, InputEdge, ManualAssertion, ProcessUpdateRequest, Provenance, Reconfigure, RetryQuarantined, Stop, TraceContext, Watermark};
 
-pub fn update_from_request(request: &ProcessUpdateRequest) -> mbei_core::event::Update {
-    let update = request.update.as_ref().unwrap();
-    match update {
+/// The update of a request, or why the request does not hold a valid one
+pub fn update_from_request(request: &ProcessUpdateRequest) -> Result<mbei_core::event::Update, String> {
+    let update = request.update.as_ref().ok_or_else(|| "Request without an update".to_string())?;
+    let update = match update {
         Update::Stop(_) => {
             mbei_core::event::Update::Stop
         }
@@ -14,16 +18,89 @@ pub fn update_from_request(request: &ProcessUpdateRequest) -> mbei_core::event::
             mbei_core::event::Update::Event(from_proto_event(e.clone()))
         }
         Update::Deltas(ds) => {
-            mbei_core::event::Update::Deltas(from_proto_deltas(ds.clone()))
+            mbei_core::event::Update::Deltas(from_proto_deltas(ds.clone())?)
         }
         Update::Retractions(rs) => {
             mbei_core::event::Update::Retractions(from_proto_retractions(rs.clone()))
         }
-    }
+        Update::Admin(a) => {
+            mbei_core::event::Update::Admin(from_proto_admin(a.clone())?)
+        }
+        Update::Watermark(w) => {
+            mbei_core::event::Update::Watermark(mbei_core::event::Watermark {
+                source_id: w.source_id.clone(),
+                timestamp: w.timestamp,
+            })
+        }
+        Update::EventCorrection(c) => {
+            mbei_core::event::Update::EventCorrection(mbei_core::event::EventCorrection {
+                correction_id: c.correction_id.clone(),
+                event_id: c.event_id.clone(),
+                timestamp: c.timestamp,
+                replacement: c.replacement.clone().map(from_proto_event),
+            })
+        }
+        Update::ManualAssertion(a) => {
+            mbei_core::event::Update::ManualAssertion(mbei_core::event::ManualAssertion {
+                assertion_id: a.assertion_id.clone(),
+                operator: a.operator.clone(),
+                reason: a.reason.clone(),
+                timestamp: a.timestamp,
+                deltas: a.deltas.iter().cloned().map(from_proto_delta).collect::<Result<_, _>>()?,
+                revoke: a.revoke,
+            })
+        }
+        Update::Provenance(p) => {
+            mbei_core::event::Update::Provenance(from_proto_provenance(p.clone())?)
+        }
+    };
+    Ok(update)
 }
 
 pub fn request_from_update(update: &mbei_core::event::Update) -> ProcessUpdateRequest {
-    ProcessUpdateRequest { update: Some(to_proto_update(update)) }
+    ProcessUpdateRequest { update: Some(to_proto_update(update)), trace_context: None, delivery: None, target_query: "".to_string() }
+}
+
+pub fn request_from_traced_update(update: &mbei_core::event::Update, trace_context: &mbei_core::trace::TraceContext) -> ProcessUpdateRequest {
+    ProcessUpdateRequest { update: Some(to_proto_update(update)), trace_context: Some(to_proto_trace_context(trace_context)), delivery: None, target_query: "".to_string() }
+}
+
+pub fn trace_context_from_request(request: &ProcessUpdateRequest) -> Option<mbei_core::trace::TraceContext> {
+    request.trace_context.as_ref().map(|t| mbei_core::trace::TraceContext {
+        trace_id: t.trace_id.clone(),
+        parent_span_id: if t.parent_span_id.is_empty() { None } else { Some(t.parent_span_id.clone()) },
+    })
+}
+
+/// The delivery of the request, if it has one. Sequence numbers start at 1, so a delivery numbered 0 is invalid,
+/// rather than taken for one already received.
+pub fn delivery_from_request(request: &ProcessUpdateRequest) -> Result<Option<Delivery>, String> {
+    match &request.delivery {
+        Some(d) if d.sequence_number == 0 => Err(format!("Delivery from {} has sequence number 0", &d.sender_id)),
+        Some(d) => Ok(Some(Delivery {
+            sender_id: d.sender_id.clone(),
+            sequence_number: d.sequence_number,
+        })),
+        None => Ok(None),
+    }
+}
+
+pub fn set_request_target_query(request: &mut ProcessUpdateRequest, query_name: &str) {
+    request.target_query = query_name.to_string();
+}
+
+pub fn set_request_delivery(request: &mut ProcessUpdateRequest, delivery: &Delivery) {
+    request.delivery = Some(crate::process_update::Delivery {
+        sender_id: delivery.sender_id.clone(),
+        sequence_number: delivery.sequence_number,
+    });
+}
+
+fn to_proto_trace_context(trace_context: &mbei_core::trace::TraceContext) -> TraceContext {
+    TraceContext {
+        trace_id: trace_context.trace_id.clone(),
+        parent_span_id: trace_context.parent_span_id.clone().unwrap_or_default(),
+    }
 }
 
 fn to_proto_update(update: &mbei_core::event::Update) -> Update {
@@ -32,16 +109,65 @@ fn to_proto_update(update: &mbei_core::event::Update) -> Update {
         mbei_core::event::Update::Event(e) => {Update::Event(to_proto_event(e))}
         mbei_core::event::Update::Deltas(ds) => {Update::Deltas(to_proto_deltas(ds))}
         mbei_core::event::Update::Retractions(rs) => {Update::Retractions(to_proto_retractions(rs))}
+        mbei_core::event::Update::Admin(a) => {Update::Admin(to_proto_admin(a))}
+        mbei_core::event::Update::Watermark(w) => {Update::Watermark(Watermark { source_id: w.source_id.clone(), timestamp: w.timestamp })}
+        mbei_core::event::Update::EventCorrection(c) => {Update::EventCorrection(EventCorrection {
+            correction_id: c.correction_id.clone(),
+            event_id: c.event_id.clone(),
+            timestamp: c.timestamp,
+            replacement: c.replacement.as_ref().map(to_proto_event),
+        })}
+        mbei_core::event::Update::ManualAssertion(a) => {Update::ManualAssertion(ManualAssertion {
+            assertion_id: a.assertion_id.clone(),
+            operator: a.operator.clone(),
+            reason: a.reason.clone(),
+            timestamp: a.timestamp,
+            deltas: a.deltas.iter().map(to_proto_delta).collect(),
+            revoke: a.revoke,
+        })}
+        mbei_core::event::Update::Provenance(p) => {Update::Provenance(to_proto_provenance(p))}
+    }
+}
+
+fn from_proto_provenance(p: Provenance) -> Result<mbei_core::event::Provenance, String> {
+    Ok(mbei_core::event::Provenance {
+        deltas_id: p.deltas_id,
+        query_name: p.query_name,
+        application: p.application,
+        origin_id: p.origin_id,
+        origin_timestamp: p.origin_timestamp,
+        match_hash: p.match_hash,
+        input_edges: p.input_edges.into_iter().map(|i| Ok(mbei_core::event::InputEdge {
+            edge: from_proto_edge(i.edge.ok_or_else(|| "Input edge without an edge".to_string())?)?,
+            deltas_ids: i.deltas_ids,
+        })).collect::<Result<_, String>>()?,
+        routed_deltas_ids: p.routed_deltas_ids,
+    })
+}
+
+fn to_proto_provenance(p: &mbei_core::event::Provenance) -> Provenance {
+    Provenance {
+        deltas_id: p.deltas_id.clone(),
+        query_name: p.query_name.clone(),
+        application: p.application.clone(),
+        origin_id: p.origin_id.clone(),
+        origin_timestamp: p.origin_timestamp,
+        match_hash: p.match_hash,
+        input_edges: p.input_edges.iter().map(|i| InputEdge {
+            edge: Some(to_proto_edge(&i.edge)),
+            deltas_ids: i.deltas_ids.clone(),
+        }).collect(),
+        routed_deltas_ids: p.routed_deltas_ids.clone(),
     }
 }
 
-fn from_proto_deltas(ds:crate::process_update::Deltas) -> mbei_core::event::Deltas {
-    mbei_core::event::Deltas {
+fn from_proto_deltas(ds:crate::process_update::Deltas) -> Result<mbei_core::event::Deltas, String> {
+    Ok(mbei_core::event::Deltas {
         deltas_id: ds.deltas_id,
         origin_id: ds.origin_id,
         origin_timestamp: ds.origin_timestamp,
-        deltas: BTreeSet::from_iter(ds.deltas.into_iter().map(from_proto_delta)),
-    }
+        deltas: ds.deltas.into_iter().map(from_proto_delta).collect::<Result<BTreeSet<_>, _>>()?,
+    })
 }
 
 fn to_proto_deltas(ds:&mbei_core::event::Deltas) -> crate::process_update::Deltas {
@@ -67,4 +193,64 @@ fn to_proto_retractions(rs : &mbei_core::event::Retractions) -> crate::process_u
         origin_timestamp: rs.timestamp,
         delta_ids: rs.deltas_ids.clone()
     }
-}
\ No newline at end of file
+}
+
+fn from_proto_admin(a:Admin) -> Result<mbei_core::event::AdminCommand, String> {
+    let command = a.command.ok_or_else(|| "Admin request without a command".to_string())?;
+    let admin_command = match command {
+        Command::RetryQuarantined(r) => {
+            mbei_core::event::AdminCommand::RetryQuarantined(mbei_core::event::RetryQuarantined {
+                quarantine_id: r.quarantine_id,
+                loop_budget: r.loop_budget
+            })
+        }
+        Command::DiscardQuarantined(d) => {
+            mbei_core::event::AdminCommand::DiscardQuarantined(mbei_core::event::DiscardQuarantined {
+                quarantine_id: d.quarantine_id
+            })
+        }
+        Command::Reconfigure(r) => {
+            mbei_core::event::AdminCommand::Reconfigure(mbei_core::event::Reconfigure {
+                queries: if r.queries_yaml.is_empty() {
+                    None
+                } else {
+                    Some(serde_yaml::from_str(&r.queries_yaml).map_err(|e| format!("Invalid queries: {}", e))?)
+                },
+                query_url_map: if r.query_url_map.is_empty() {
+                    None
+                } else {
+                    Some(r.query_url_map.into_iter().collect())
+                }
+            })
+        }
+        Command::Drain(_) => mbei_core::event::AdminCommand::Drain,
+    };
+    Ok(admin_command)
+}
+
+fn to_proto_admin(a:&mbei_core::event::AdminCommand) -> Admin {
+    let command = match a {
+        mbei_core::event::AdminCommand::RetryQuarantined(r) => {
+            Command::RetryQuarantined(RetryQuarantined {
+                quarantine_id: r.quarantine_id.clone(),
+                loop_budget: r.loop_budget
+            })
+        }
+        mbei_core::event::AdminCommand::DiscardQuarantined(d) => {
+            Command::DiscardQuarantined(DiscardQuarantined {
+                quarantine_id: d.quarantine_id.clone()
+            })
+        }
+        mbei_core::event::AdminCommand::Reconfigure(r) => {
+            Command::Reconfigure(Reconfigure {
+                queries_yaml: match &r.queries {
+                    Some(queries) => serde_yaml::to_string(queries).expect("Serializable"),
+                    None => "".to_string(),
+                },
+                query_url_map: r.query_url_map.clone().unwrap_or_default().into_iter().collect()
+            })
+        }
+        mbei_core::event::AdminCommand::Drain => Command::Drain(Drain {}),
+    };
+    Admin { command: Some(command) }
+}
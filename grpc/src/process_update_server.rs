@@ -1,33 +1,116 @@
+use std::collections::{BTreeMap, VecDeque};
 use std::net::SocketAddr;
+use std::path::Path;
 use std::sync::{Arc};
-use std::time::Duration;
+use std::sync::Mutex as StdMutex;
+use std::time::{Duration, SystemTime};
+use tokio::time::Instant;
 use tonic::{Request, Response, Status};
-use mbei_core::event::{Deltas, Event, Retractions, Update};
+use mbei_core::event::{AdminCommand, Deltas, Event, Provenance, Retractions, Update, Watermark};
+use mbei_core::trace::TraceContext;
 use tokio::sync::mpsc::{UnboundedSender};
 use tokio::sync::oneshot::Receiver;
 use tokio::task::JoinHandle;
 use tonic::transport::{Error, Server};
-use crate::process_update::{ProcessUpdateRequest, ProcessUpdateResponse};
+use crate::process_update::{GetStatusRequest, GetStatusResponse, ProcessUpdateRequest, ProcessUpdateResponse};
 use crate::process_update::process_update_server::{ProcessUpdate, ProcessUpdateServer};
-use crate::process_update_mapping::update_from_request;
+use crate::delivery::{Delivery, ReceivedDeliveries};
+use crate::health::health_server::HealthServer;
+use crate::health_server::{Health, HealthService};
+use crate::inbox::Inbox;
+use crate::operators::OperatorTokens;
+use crate::outbox::Outbox;
+use crate::record_log::LogSync;
+use crate::replication_server::{ReplicatedRecord, Replicator};
+use crate::process_update_mapping::{delivery_from_request, trace_context_from_request, update_from_request};
+use crate::inspection::inspection_server::{Inspection, InspectionServer};
+use crate::subscription::subscription_server::SubscriptionServer;
+use crate::subscription_server::{ChangeLog, SubscriptionService};
+use crate::tls::{configure_server, PeerIdentityCheck, TlsOptions};
 use futures_util::FutureExt;
-use log::warn;
-use tokio::sync::Mutex;
-
-pub async fn create_and_run_server(grpc_port:u16, arc_queue_mutex: Arc<Mutex<Queue>>, new_update_sender:UnboundedSender<()>, shutdown_server_receiver: Receiver<()>) -> JoinHandle<Result<(), Error>> {
-    let service = ProcessUpdateService {
-            sender: Mutex::new(new_update_sender),
-            queue: arc_queue_mutex,
-    };
+use log::{debug, error, info, warn};
+use tokio::sync::{Mutex, Notify};
+
+/// Serves the health protocol on the same port as the process update service.
+pub async fn create_and_run_server(grpc_port:u16, served_queues: BTreeMap<String, ServedQueue>, shutdown_server_receiver: Receiver<()>, health: Health, tls: Option<&TlsOptions>) -> JoinHandle<Result<(), Error>> {
+    let svc = ProcessUpdateServer::with_interceptor(ProcessUpdateService::new(served_queues), PeerIdentityCheck::new(tls));
+    let address = create_server_address(grpc_port);
+    let mut server = configure_server(Server::builder(), tls).expect("Could not configure server");
+    tokio::spawn(
+        server
+            .add_service(svc)
+            .add_service(HealthServer::with_interceptor(HealthService::new(health), PeerIdentityCheck::new(tls)))
+            .serve_with_shutdown(address, shutdown_server_receiver.map(|_| ()))
+    )
+}
+
+/// Serves subscriptions to the changes in a log, and the health protocol, on the same port as the process update service.
+pub async fn create_and_run_server_with_subscriptions<L: ChangeLog>(grpc_port:u16, served_queues: BTreeMap<String, ServedQueue>, shutdown_server_receiver: Receiver<()>, subscription_service: SubscriptionService<L>, health: Health, tls: Option<&TlsOptions>) -> JoinHandle<Result<(), Error>> {
+    let svc = ProcessUpdateServer::with_interceptor(ProcessUpdateService::new(served_queues), PeerIdentityCheck::new(tls));
+    let address = create_server_address(grpc_port);
+    let mut server = configure_server(Server::builder(), tls).expect("Could not configure server");
+    tokio::spawn(
+        server
+            .add_service(svc)
+            .add_service(SubscriptionServer::with_interceptor(subscription_service, PeerIdentityCheck::new(tls)))
+            .add_service(HealthServer::with_interceptor(HealthService::new(health), PeerIdentityCheck::new(tls)))
+            .serve_with_shutdown(address, shutdown_server_receiver.map(|_| ()))
+    )
+}
+
+/// Serves the inspection service and the health protocol on the same port as the process update service.
+pub async fn create_and_run_server_with_inspection<I: Inspection>(grpc_port:u16, served_queues: BTreeMap<String, ServedQueue>, shutdown_server_receiver: Receiver<()>, inspection_service: I, health: Health, tls: Option<&TlsOptions>) -> JoinHandle<Result<(), Error>> {
+    let svc = ProcessUpdateServer::with_interceptor(ProcessUpdateService::new(served_queues), PeerIdentityCheck::new(tls));
+    let address = create_server_address(grpc_port);
+    let mut server = configure_server(Server::builder(), tls).expect("Could not configure server");
+    tokio::spawn(
+        server
+            .add_service(svc)
+            .add_service(InspectionServer::with_interceptor(inspection_service, PeerIdentityCheck::new(tls)))
+            .add_service(HealthServer::with_interceptor(HealthService::new(health), PeerIdentityCheck::new(tls)))
+            .serve_with_shutdown(address, shutdown_server_receiver.map(|_| ()))
+    )
+}
+
+/// Looks up what a request is targeted at by query name. An empty name is allowed when there is only one target.
+pub fn select_target<'a, T>(targets: &'a BTreeMap<String, T>, query_name: &str) -> Result<&'a T, Status> {
+    if query_name.is_empty() {
+        if targets.len() == 1 {
+            Ok(targets.values().next().unwrap())
+        } else {
+            Err(Status::invalid_argument("A target query is required, since several components are served"))
+        }
+    } else {
+        targets
+            .get(query_name)
+            .ok_or_else(|| Status::not_found(format!("No component for query {} is served here", query_name)))
+    }
+}
+
+pub(crate) fn create_server_address(grpc_port:u16) -> SocketAddr {
     let address_string = "[::]:".to_owned() + &grpc_port.to_string();
-    let address: SocketAddr = address_string
+    address_string
         .parse()
-        .expect("Error parsing server address");
-    let svc = ProcessUpdateServer::new(service);
-    let server_handle = tokio::spawn(
-        Server::builder().add_service(svc).serve_with_shutdown(address, shutdown_server_receiver.map(|_| ()))
-    );
-    server_handle
+        .expect("Error parsing server address")
+}
+
+//Syncs the inbox once the queue is unlocked, so that an update is durable before it is acknowledged without holding up the queue
+pub(crate) async fn sync_inbox(inbox_sync: Option<LogSync>) -> Result<(), Status> {
+    if let Some(inbox_sync) = inbox_sync {
+        tokio::task::spawn_blocking(move || inbox_sync.sync())
+            .await
+            .map_err(|e| Status::internal(format!("Could not sync inbox: {}", e)))?
+            .map_err(|e| Status::unavailable(format!("Could not sync inbox: {}", e)))?;
+    }
+    Ok(())
+}
+
+//Waits for the standby of the component, if it has one, to confirm the update, so that it is not acknowledged before the standby has it
+async fn await_replicated(served_queue: &ServedQueue, sequence_number: Option<u64>) -> Result<(), Status> {
+    match (&served_queue.replicator, sequence_number) {
+        (Some(replicator), Some(sequence_number)) => replicator.await_confirmed(sequence_number).await,
+        _ => Ok(()),
+    }
 }
 
 pub async fn await_server_handle_with_timeout(server_handle:JoinHandle<Result<(), Error>>, timeout:Duration) {
@@ -38,9 +121,29 @@ pub async fn await_server_handle_with_timeout(server_handle:JoinHandle<Result<()
     }
 }
 
-pub struct ProcessUpdateService {
-    pub sender: Mutex<UnboundedSender<()>>,
+/// The queue of a component, or of central, and the sender which tells its loop about new updates.
+/// The outbox of a component tells whether the updates it sent have all been acknowledged.
+pub struct ServedQueue {
     pub queue: Arc<Mutex<Queue>>,
+    pub new_update_sender: UnboundedSender<()>,
+    pub outbox: Option<Arc<StdMutex<Outbox>>>,
+    //Operators allowed to send manual assertions, authenticated by a component
+    pub operators: Option<Arc<OperatorTokens>>,
+    //Clients allowed to forward the manual assertions operators authenticated with them, as central receives them from the components
+    pub assertion_senders: Option<PeerIdentityCheck>,
+    //Replicates accepted updates to the standby of the component, if it has one, which confirms them before they are acknowledged
+    pub replicator: Option<Replicator>,
+}
+
+/// Puts updates into the queue of their target query. Each queue has its own limits and ordering.
+pub struct ProcessUpdateService {
+    served_queues: BTreeMap<String, ServedQueue>,
+}
+
+impl ProcessUpdateService {
+    pub fn new(served_queues: BTreeMap<String, ServedQueue>) -> ProcessUpdateService {
+        ProcessUpdateService { served_queues }
+    }
 }
 
 #[tonic::async_trait]
@@ -49,41 +152,352 @@ impl ProcessUpdate for ProcessUpdateService {
         &self,
         request: Request<ProcessUpdateRequest>,
     ) -> Result<Response<ProcessUpdateResponse>, Status> {
-        let new_update = update_from_request(request.get_ref());
-        let queue_size;
-        {
-            let mut q = self.queue.lock().await;
-            q.insert_update(new_update);
-            queue_size = q.get_queue_size() as u32;
+        let served_queue = select_target(&self.served_queues, &request.get_ref().target_query)?;
+        let mut new_update = update_from_request(request.get_ref()).map_err(Status::invalid_argument)?;
+        let delivery = delivery_from_request(request.get_ref()).map_err(Status::invalid_argument)?;
+        if matches!(&served_queue.replicator, Some(replicator) if replicator.is_fenced()) {
+            return Err(Status::unavailable("The component was replaced by its standby"));
+        }
+        if let (Update::ManualAssertion(assertion), Some(operators)) = (&mut new_update, &served_queue.operators) {
+            assertion.operator = operators.authenticate(&request)?;
+        }
+        if let (Update::ManualAssertion(_), Some(assertion_senders)) = (&new_update, &served_queue.assertion_senders) {
+            assertion_senders.require_allowed(&request)?;
+        }
+        if let Update::Admin(AdminCommand::Drain) = &new_update {
+            let mut q = served_queue.queue.lock().await;
+            if !q.draining {
+                info!("Draining, new events are rejected from now on");
+                q.draining = true;
+            }
+            return Ok(Response::new(ProcessUpdateResponse {
+                queue_size: q.get_queue_size() as u32
+            }));
+        }
+        if let Update::Watermark(watermark) = new_update {
+            let (queue_size, replicated) = {
+                let mut q = served_queue.queue.lock().await;
+                let replicated = match &served_queue.replicator {
+                    Some(replicator) => replicator.try_replicate(&ReplicatedRecord::Accepted(Update::Watermark(watermark.clone()), None, None))?,
+                    None => None,
+                };
+                q.insert_watermark(watermark);
+                (q.get_queue_size() as u32, replicated)
+            };
+            let _ = served_queue.new_update_sender.send(());
+            await_replicated(served_queue, replicated).await?;
+            return Ok(Response::new(ProcessUpdateResponse { queue_size }));
         }
+        let trace_context = trace_context_from_request(request.get_ref());
+        let full_queue_wait;
+        let space_available;
         {
-            let s = self.sender.lock().await;
-            s.send(()).expect("Error sending new update message");
+            let q = served_queue.queue.lock().await;
+            full_queue_wait = q.limits.full_queue_wait;
+            space_available = q.space_available.clone();
         }
+        let deadline = Instant::now() + full_queue_wait;
+        let (queue_size, inbox_sync, replicated) = loop {
+            //Created before checking, so that a pop between the check and the wait is not missed
+            let notified = space_available.notified();
+            {
+                let mut q = served_queue.queue.lock().await;
+                if let Some(delivery) = &delivery {
+                    //A resend of an update that was accepted, but whose acknowledgement did not reach the sender
+                    if q.received.is_received(delivery) {
+                        debug!("Acknowledging duplicate delivery {} from {}", delivery.sequence_number, delivery.sender_id);
+                        let queue_size = q.get_queue_size() as u32;
+                        //The first delivery may still be syncing, or not yet be confirmed by the standby
+                        let inbox_sync = q.get_inbox_sync();
+                        let replicated = served_queue.replicator.as_ref().map(Replicator::get_last_sequence_number);
+                        drop(q);
+                        sync_inbox(inbox_sync).await?;
+                        await_replicated(served_queue, replicated).await?;
+                        return Ok(Response::new(ProcessUpdateResponse { queue_size }));
+                    }
+                }
+                if q.draining && matches!(new_update, Update::Event(_) | Update::EventCorrection(_) | Update::ManualAssertion(_)) {
+                    return Err(Status::failed_precondition("Draining, new events and operator updates are not accepted"));
+                }
+                match q.get_refusal(&new_update) {
+                    None => {
+                        //While the queue is locked, so that the standby gets updates in the order they are queued and taken out
+                        let replicated = match &served_queue.replicator {
+                            Some(replicator) => {
+                                let record = ReplicatedRecord::Accepted(new_update.clone(), trace_context.clone(), delivery.clone());
+                                replicator.try_replicate(&record)?
+                            }
+                            None => None,
+                        };
+                        q.insert_traced_update(new_update, trace_context, delivery)
+                            .map_err(|e| Status::unavailable(format!("Could not write to the inbox: {}", e)))?;
+                        break (q.get_queue_size() as u32, q.get_inbox_sync(), replicated);
+                    }
+                    Some(refusal) => {
+                        if Instant::now() >= deadline {
+                            debug!("Rejecting update, {}", refusal);
+                            return Err(Status::resource_exhausted(format!("The {}", refusal)));
+                        }
+                    }
+                }
+            }
+            let _ = tokio::time::timeout_at(deadline, notified).await;
+        };
+        sync_inbox(inbox_sync).await?;
+        //Other components on the same server may still be running when this one has stopped
+        let _ = served_queue.new_update_sender.send(());
+        await_replicated(served_queue, replicated).await?;
         Ok(Response::new(ProcessUpdateResponse {
             queue_size
         }))
     }
+
+    async fn get_status(
+        &self,
+        request: Request<GetStatusRequest>,
+    ) -> Result<Response<GetStatusResponse>, Status> {
+        let served_queue = select_target(&self.served_queues, &request.get_ref().target_query)?;
+        let n_unacknowledged = match &served_queue.outbox {
+            Some(outbox) => outbox.lock().expect("Outbox lock poisoned").get_n_unacknowledged(),
+            None => 0,
+        };
+        let q = served_queue.queue.lock().await;
+        Ok(Response::new(GetStatusResponse {
+            draining: q.draining,
+            drained: q.draining && q.is_idle() && n_unacknowledged == 0,
+            n_accepted: q.n_accepted,
+            queue_size: q.get_queue_size() as u32,
+            n_unacknowledged: n_unacknowledged as u32,
+            finalized_before: q.finalized_before.unwrap_or(0),
+            memory_exhausted: q.memory_exhausted,
+        }))
+    }
+}
+
+/// When an update entered the queue, the trace context it was sent with, if any, and its place in the inbox.
+pub struct QueueEntry {
+    pub context: Option<TraceContext>,
+    pub enqueued_at: SystemTime,
+    pub inbox_seq: Option<u64>,
+}
+
+/// Bounds on the number of queued updates of each type. Stop and admin updates are always accepted.
+#[derive(Clone, Debug)]
+pub struct QueueLimits {
+    pub max_events: usize,
+    pub max_deltas: usize,
+    pub max_retractions: usize,
+    //How long a send waits for room in a full queue before it is rejected as resource exhausted
+    pub full_queue_wait: Duration,
+}
+
+impl Default for QueueLimits {
+    fn default() -> Self {
+        QueueLimits {
+            max_events: 100000,
+            max_deltas: 100000,
+            max_retractions: 100000,
+            full_queue_wait: Duration::from_millis(100),
+        }
+    }
 }
 
 pub struct Queue {
     pub open_events: Vec<Event>,
     pub open_deltas: Vec<Deltas>,
     pub open_retractions: Vec<Retractions>,
+    pub open_admin: Vec<AdminCommand>,
+    //Event corrections and manual assertions
+    pub open_operator_updates: Vec<Update>,
+    //Only sent to central
+    pub open_provenance: Vec<Provenance>,
     pub stop: bool,
+    //Set by a drain command. Events are rejected from then on, and the queue is not persisted, so it ends with a restart
+    pub draining: bool,
+    //Set while the store of the component is above its hard memory limit. New events and deltas wait, as in a full queue
+    memory_exhausted: bool,
+    //Updates accepted through the service, and those popped but not yet marked as processed
+    pub n_accepted: u64,
+    n_in_progress: usize,
+    //Set by the loop taking updates from the queue, when nothing before it can change any more
+    pub finalized_before: Option<u64>,
+    //The latest watermark of each source, which is kept instead of being queued
+    watermarks: BTreeMap<String, u64>,
+    //Updates with the same key are popped in the order they arrived, apart from events and deltas with equal ids but different timestamps
+    entries: BTreeMap<String, VecDeque<QueueEntry>>,
+    limits: QueueLimits,
+    space_available: Arc<Notify>,
+    inbox: Option<Inbox>,
+    pub(crate) received: ReceivedDeliveries,
+}
 
+impl Default for Queue {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Queue {
     pub fn new() -> Queue{
+        Queue::with_limits(QueueLimits::default())
+    }
+
+    pub fn with_limits(limits: QueueLimits) -> Queue {
         Queue {
             open_events: vec![],
             open_deltas: vec![],
             open_retractions: vec![],
-            stop: false
+            open_admin: vec![],
+            open_operator_updates: vec![],
+            open_provenance: vec![],
+            stop: false,
+            draining: false,
+            memory_exhausted: false,
+            n_accepted: 0,
+            n_in_progress: 0,
+            finalized_before: None,
+            watermarks: BTreeMap::new(),
+            entries: BTreeMap::new(),
+            limits,
+            space_available: Arc::new(Notify::new()),
+            inbox: None,
+            received: ReceivedDeliveries::default(),
+        }
+    }
+
+    /// A queue backed by an inbox log. Updates that were accepted but not processed before a restart are queued again,
+    /// and deliveries received before the restart are still recognized when resent.
+    pub fn with_inbox(limits: QueueLimits, inbox_path: &Path) -> std::io::Result<Queue> {
+        let mut queue = Queue::with_limits(limits);
+        let (inbox, pending_updates, received) = Inbox::open(inbox_path)?;
+        queue.received = received;
+        if !pending_updates.is_empty() {
+            info!("Replaying {} updates from inbox {:?}", pending_updates.len(), inbox_path);
+        }
+        for (seq, update) in pending_updates {
+            queue.insert_entry(update, None, Some(seq));
+        }
+        queue.inbox = Some(inbox);
+        Ok(queue)
+    }
+
+    pub(crate) fn get_inbox_sync(&self) -> Option<LogSync> {
+        self.inbox.as_ref().map(|inbox| inbox.get_sync())
+    }
+
+    /// Called when an update popped from the queue has been processed, so that it is not replayed.
+    pub fn mark_processed(&mut self, entry: &QueueEntry) {
+        self.n_in_progress = self.n_in_progress.saturating_sub(1);
+        if let Some(seq) = entry.inbox_seq {
+            self.mark_released(seq);
+        }
+    }
+
+    /// Called when an update popped from the queue is held by the component, such as in quarantine.
+    /// It is no longer in progress, but stays in the inbox, so that it is replayed after a restart until it is released.
+    pub fn mark_held(&mut self) {
+        self.n_in_progress = self.n_in_progress.saturating_sub(1);
+    }
+
+    /// Called when an update held by the component is done with, so that it is not replayed.
+    pub fn mark_released(&mut self, inbox_seq: u64) {
+        if let Some(inbox) = &mut self.inbox {
+            //Harmless, as an update processed again after a restart gives the same output
+            if let Err(e) = inbox.mark_processed(inbox_seq, &self.received) {
+                error!("Could not mark update {} of the inbox as processed: {}", inbox_seq, e);
+            }
+        }
+    }
+
+    /// Nothing is queued, and every update popped with an entry has been marked as processed.
+    pub fn is_idle(&self) -> bool {
+        self.get_queue_size() == 0 && self.n_in_progress == 0
+    }
+
+    /// The latest watermark of each source heard from. Watermarks are not persisted, sources repeat them as heartbeats.
+    pub fn get_watermarks(&self) -> &BTreeMap<String, u64> {
+        &self.watermarks
+    }
+
+    /// The earliest timestamp of the queued events, deltas, retractions and operator updates
+    pub fn get_min_queued_timestamp(&self) -> Option<u64> {
+        let events = self.open_events.iter().map(|e| e.timestamp);
+        let deltas = self.open_deltas.iter().map(|d| d.origin_timestamp);
+        let retractions = self.open_retractions.iter().map(|r| r.timestamp);
+        let operator_updates = self.open_operator_updates.iter().map(|u| u.timestamp());
+        let provenance = self.open_provenance.iter().map(|p| p.origin_timestamp);
+        events.chain(deltas).chain(retractions).chain(operator_updates).chain(provenance).min()
+    }
+
+    //Watermarks of a source only move forward, also when an older one arrives late
+    fn insert_watermark(&mut self, watermark: Watermark) {
+        let timestamp = self.watermarks.entry(watermark.source_id).or_insert(watermark.timestamp);
+        *timestamp = (*timestamp).max(watermark.timestamp);
+    }
+
+    /// Sequence numbers not yet received although later ones have been, by sender.
+    /// Gaps are filled when the senders resend, so persistent gaps point to a sender which lost its outbox.
+    pub fn get_missing_deliveries(&self) -> BTreeMap<String, Vec<u64>> {
+        self.received.get_missing()
+    }
+
+    /// Refuses new events and deltas while set, and lets those waiting in while not.
+    pub fn set_memory_exhausted(&mut self, memory_exhausted: bool) {
+        if self.memory_exhausted && !memory_exhausted {
+            self.space_available.notify_waiters();
+        }
+        self.memory_exhausted = memory_exhausted;
+    }
+
+    /// Why there is no room for the update, if there is not.
+    fn get_refusal(&self, update: &Update) -> Option<&'static str> {
+        match update {
+            //Retractions are let in, as they free memory
+            Update::Event(_) | Update::Deltas(_) | Update::EventCorrection(_) | Update::ManualAssertion(_) if self.memory_exhausted => {
+                Some("store is above its memory limit")
+            }
+            Update::Event(_) if self.open_events.len() >= self.limits.max_events => Some("events queue is full"),
+            Update::Deltas(_) if self.open_deltas.len() >= self.limits.max_deltas => Some("deltas queue is full"),
+            Update::Retractions(_) if self.open_retractions.len() >= self.limits.max_retractions => Some("retractions queue is full"),
+            //As many as there are deltas
+            Update::Provenance(_) if self.open_provenance.len() >= self.limits.max_deltas => Some("provenance queue is full"),
+            _ => None,
         }
     }
 
+    //The update is in the inbox before it is acknowledged, and not queued if it could not be written there
+    fn insert_traced_update(&mut self, update: Update, context: Option<TraceContext>, delivery: Option<Delivery>) -> std::io::Result<()> {
+        let inbox_seq = match (&mut self.inbox, &update) {
+            (Some(_), Update::Stop) => None,
+            (Some(inbox), update) => Some(inbox.append(update, delivery.clone())?),
+            (None, _) => None,
+        };
+        if let Some(delivery) = &delivery {
+            let previous = Delivery {
+                sender_id: delivery.sender_id.clone(),
+                sequence_number: delivery.sequence_number - 1,
+            };
+            if previous.sequence_number > 0 && !self.received.is_received(&previous) {
+                debug!("Delivery {} from {} arrived before {}", delivery.sequence_number, delivery.sender_id, previous.sequence_number);
+            }
+            self.received.insert(delivery);
+        }
+        self.n_accepted += 1;
+        self.insert_entry(update, context, inbox_seq);
+        Ok(())
+    }
+
+    fn insert_entry(&mut self, update: Update, context: Option<TraceContext>, inbox_seq: Option<u64>) {
+        if let Some(key) = entry_key(&update) {
+            self.entries.entry(key).or_default().push_back(QueueEntry {
+                context,
+                enqueued_at: SystemTime::now(),
+                inbox_seq,
+            });
+        }
+        self.insert_update(update);
+    }
+
     fn insert_update(&mut self, update: Update) {
         match update {
             Update::Stop => {self.stop = true;}
@@ -96,16 +510,115 @@ impl Queue {
             Update::Retractions(rs) => {
                 self.open_retractions.push(rs);
             }
+            Update::Admin(a) => {
+                self.open_admin.push(a);
+            }
+            operator_update @ (Update::EventCorrection(_) | Update::ManualAssertion(_)) => {
+                self.open_operator_updates.push(operator_update);
+            }
+            Update::Provenance(p) => {
+                self.open_provenance.push(p);
+            }
+            Update::Watermark(w) => {
+                self.insert_watermark(w);
+            }
         };
     }
 
     fn get_queue_size(&self) -> usize {
-        self.open_events.len() + self.open_deltas.len() + self.open_retractions.len()
+        self.open_events.len() + self.open_deltas.len() + self.open_retractions.len() + self.open_admin.len() + self.open_operator_updates.len() + self.open_provenance.len()
+    }
+
+    /// The queued updates, in the order they would be popped.
+    pub fn get_updates(&self) -> Vec<Update> {
+        let mut updates = vec![];
+        if self.stop {
+            updates.push(Update::Stop);
+        }
+        updates.extend(self.open_admin.iter().map(|a| Update::Admin(a.clone())));
+        updates.extend(self.open_operator_updates.iter().cloned());
+        let mut retractions: Vec<&Retractions> = self.open_retractions.iter().collect();
+        retractions.sort_by_key(|r| r.timestamp);
+        updates.extend(retractions.into_iter().map(|r| Update::Retractions(r.clone())));
+        let mut deltas: Vec<&Deltas> = self.open_deltas.iter().collect();
+        deltas.sort_by_key(|d| d.origin_timestamp);
+        updates.extend(deltas.into_iter().map(|d| Update::Deltas(d.clone())));
+        let mut provenance: Vec<&Provenance> = self.open_provenance.iter().collect();
+        provenance.sort_by_key(|p| p.origin_timestamp);
+        updates.extend(provenance.into_iter().map(|p| Update::Provenance(p.clone())));
+        let mut events: Vec<&Event> = self.open_events.iter().collect();
+        events.sort_by_key(|e| e.timestamp);
+        updates.extend(events.into_iter().map(|e| Update::Event(e.clone())));
+        updates
     }
 
     pub fn pop_earliest_update(&mut self) -> Option<Update> {
+        self.pop_earliest_queued_update().map(|(update, _)| update)
+    }
+
+    /// Pops the earliest update together with its entry, if it arrived through the service or the inbox.
+    pub fn pop_earliest_queued_update(&mut self) -> Option<(Update, Option<QueueEntry>)> {
+        let update = self.pop_earliest()?;
+        self.space_available.notify_waiters();
+        let entry = self.pop_entry(&update);
+        Some((update, entry))
+    }
+
+    /// Takes out an update which the primary of a standby took from its queue, together with its entry. None if it is not queued.
+    pub fn take_update(&mut self, update: &Update) -> Option<(Update, Option<QueueEntry>)> {
+        let is_taken = match update {
+            Update::Event(e) => take_equal(&mut self.open_events, e),
+            Update::Deltas(ds) => take_equal(&mut self.open_deltas, ds),
+            Update::Retractions(rs) => take_equal(&mut self.open_retractions, rs),
+            Update::Admin(a) => take_equal(&mut self.open_admin, a),
+            Update::EventCorrection(_) | Update::ManualAssertion(_) => take_equal(&mut self.open_operator_updates, update),
+            Update::Provenance(p) => take_equal(&mut self.open_provenance, p),
+            Update::Stop => std::mem::take(&mut self.stop),
+            Update::Watermark(_) => false,
+        };
+        if !is_taken {
+            return None;
+        }
+        self.space_available.notify_waiters();
+        let entry = self.pop_entry(update);
+        Some((update.clone(), entry))
+    }
+
+    /// Queues an update the primary of a standby accepted, as if it had been sent to the standby.
+    pub(crate) fn accept_replicated(&mut self, update: Update, context: Option<TraceContext>, delivery: Option<Delivery>) -> std::io::Result<()> {
+        match update {
+            Update::Watermark(watermark) => {
+                self.insert_watermark(watermark);
+                Ok(())
+            }
+            update => self.insert_traced_update(update, context, delivery),
+        }
+    }
+
+    fn pop_entry(&mut self, update: &Update) -> Option<QueueEntry> {
+        let entry = entry_key(update).and_then(|key| {
+            let entries = self.entries.get_mut(&key)?;
+            let entry = entries.pop_front();
+            if entries.is_empty() {
+                self.entries.remove(&key);
+            }
+            entry
+        });
+        if entry.is_some() {
+            self.n_in_progress += 1;
+        }
+        entry
+    }
+
+    fn pop_earliest(&mut self) -> Option<Update> {
         if self.stop {
             Some(Update::Stop)
+        } else if !self.open_admin.is_empty() {
+            //Admin commands are applied in the order they arrive
+            Some(Update::Admin(self.open_admin.remove(0)))
+        } else if !self.open_operator_updates.is_empty() {
+            //Operators correct what was already processed, so their updates are also applied in the order they arrive
+            Some(self.open_operator_updates.remove(0))
         } else if !self.open_retractions.is_empty() {
             let (min_index, _) = self.open_retractions.iter().enumerate().min_by_key(|(_, r)|r.timestamp).unwrap();
             let retractions = self.open_retractions.swap_remove(min_index);
@@ -114,6 +627,10 @@ impl Queue {
             let (min_index, _) = self.open_deltas.iter().enumerate().min_by_key(|(_, r)|r.origin_timestamp).unwrap();
             let deltas = self.open_deltas.swap_remove(min_index);
             Some(Update::Deltas(deltas))
+        } else if !self.open_provenance.is_empty() {
+            let (min_index, _) = self.open_provenance.iter().enumerate().min_by_key(|(_, p)|p.origin_timestamp).unwrap();
+            let provenance = self.open_provenance.swap_remove(min_index);
+            Some(Update::Provenance(provenance))
         } else if !self.open_events.is_empty() {
             let (min_index, _) = self.open_events.iter().enumerate().min_by_key(|(_, r)|r.timestamp).unwrap();
             let event = self.open_events.swap_remove(min_index);
@@ -126,6 +643,29 @@ impl Queue {
     }
 }
 
+fn take_equal<T: PartialEq>(updates: &mut Vec<T>, update: &T) -> bool {
+    match updates.iter().position(|u| u == update) {
+        Some(index) => {
+            updates.remove(index);
+            true
+        }
+        None => false,
+    }
+}
+
+fn entry_key(update: &Update) -> Option<String> {
+    match update {
+        Update::Event(e) => Some("event:".to_string() + &e.event_id),
+        Update::Deltas(ds) => Some("deltas:".to_string() + &ds.deltas_id),
+        Update::Retractions(rs) => Some("retractions:".to_string() + &rs.retraction_id),
+        Update::Admin(_) => Some("admin".to_string()),
+        Update::EventCorrection(c) => Some("correction:".to_string() + &c.correction_id),
+        Update::ManualAssertion(a) => Some("assertion:".to_string() + &a.assertion_id),
+        Update::Provenance(p) => Some("provenance:".to_string() + &p.deltas_id),
+        Update::Stop | Update::Watermark(_) => None,
+    }
+}
+
 #[test]
 fn test_queue() {
     let mut queue = Queue::new();
@@ -167,4 +707,459 @@ fn test_queue() {
     assert_eq!(u3.unwrap(), e1);
     assert_eq!(u4.unwrap(), e2);
     assert_eq!(u5, None)
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_queue_admin_before_retractions() {
+    let mut queue = Queue::new();
+    let r1 = Update::Retractions(Retractions {
+        retraction_id: "r1".to_string(),
+        timestamp: 1,
+        deltas_ids: vec![]
+    });
+    let a1 = Update::Admin(AdminCommand::DiscardQuarantined(mbei_core::event::DiscardQuarantined {
+        quarantine_id: "q1".to_string()
+    }));
+    let a2 = Update::Admin(AdminCommand::DiscardQuarantined(mbei_core::event::DiscardQuarantined {
+        quarantine_id: "q2".to_string()
+    }));
+    queue.insert_update(r1.clone());
+    queue.insert_update(a1.clone());
+    queue.insert_update(a2.clone());
+    assert_eq!(queue.get_queue_size(), 3);
+    assert_eq!(queue.pop_earliest_update().unwrap(), a1);
+    assert_eq!(queue.pop_earliest_update().unwrap(), a2);
+    assert_eq!(queue.pop_earliest_update().unwrap(), r1);
+    assert_eq!(queue.pop_earliest_update(), None)
+}
+
+#[test]
+fn test_queue_operator_updates_in_arrival_order_before_retractions() {
+    let mut queue = Queue::new();
+    let r1 = Update::Retractions(Retractions {
+        retraction_id: "r1".to_string(),
+        timestamp: 1,
+        deltas_ids: vec![]
+    });
+    let correction = |correction_id: &str, timestamp, replacement_timestamp: Option<u64>| Update::EventCorrection(mbei_core::event::EventCorrection {
+        correction_id: correction_id.to_string(),
+        event_id: "e1".to_string(),
+        timestamp,
+        replacement: replacement_timestamp.map(|timestamp| Event {
+            event_id: "e1".to_string(),
+            timestamp,
+            node_id: "abc123".to_string(),
+            payload: vec![]
+        }),
+    });
+    let c1 = correction("c1", 7, Some(4));
+    let c2 = correction("c2", 3, None);
+    queue.insert_update(r1.clone());
+    queue.insert_update(c1.clone());
+    queue.insert_update(c2.clone());
+    assert_eq!(queue.get_queue_size(), 3);
+    //The replacement is earlier than the event it corrects
+    assert_eq!(queue.get_min_queued_timestamp(), Some(1));
+    assert_eq!(queue.get_updates(), vec![c1.clone(), c2.clone(), r1.clone()]);
+    assert_eq!(queue.pop_earliest_update().unwrap(), c1);
+    assert_eq!(queue.pop_earliest_update().unwrap(), c2);
+    assert_eq!(queue.get_min_queued_timestamp(), Some(1));
+    assert_eq!(queue.pop_earliest_update().unwrap(), r1);
+    assert_eq!(queue.pop_earliest_update(), None)
+}
+
+#[test]
+fn test_queue_get_updates_in_pop_order() {
+    let mut queue = Queue::new();
+    let e1 = Update::Event(Event {
+        event_id: "e1".to_string(),
+        timestamp: 7,
+        node_id: "abc123".to_string(),
+        payload: vec![]
+    });
+    let e2 = Update::Event(Event {
+        event_id: "e2".to_string(),
+        timestamp: 2,
+        node_id: "abc123".to_string(),
+        payload: vec![]
+    });
+    let r1 = Update::Retractions(Retractions {
+        retraction_id: "r1".to_string(),
+        timestamp: 9,
+        deltas_ids: vec![]
+    });
+    queue.insert_update(e1.clone());
+    queue.insert_update(e2.clone());
+    queue.insert_update(r1.clone());
+    let updates = queue.get_updates();
+    assert_eq!(updates, vec![r1.clone(), e2.clone(), e1.clone()]);
+    assert_eq!(queue.pop_earliest_update().unwrap(), r1);
+    assert_eq!(queue.pop_earliest_update().unwrap(), e2);
+    assert_eq!(queue.pop_earliest_update().unwrap(), e1);
+}
+
+#[test]
+fn test_queue_pops_trace_with_update() {
+    let mut queue = Queue::new();
+    let e1 = Update::Event(Event {
+        event_id: "e1".to_string(),
+        timestamp: 1,
+        node_id: "abc123".to_string(),
+        payload: vec![]
+    });
+    let e2 = Update::Event(Event {
+        event_id: "e2".to_string(),
+        timestamp: 2,
+        node_id: "abc123".to_string(),
+        payload: vec![]
+    });
+    let context = TraceContext::for_origin("e1");
+    queue.insert_traced_update(e1.clone(), Some(context.clone()), None).unwrap();
+    queue.insert_update(e2.clone());
+    let (u1, t1) = queue.pop_earliest_queued_update().unwrap();
+    assert_eq!(u1, e1);
+    assert_eq!(t1.unwrap().context, Some(context));
+    let (u2, t2) = queue.pop_earliest_queued_update().unwrap();
+    assert_eq!(u2, e2);
+    assert!(t2.is_none());
+    assert!(queue.entries.is_empty());
+}
+
+#[cfg(test)]
+fn create_test_service(limits: QueueLimits) -> (ProcessUpdateService, Arc<Mutex<Queue>>, tokio::sync::mpsc::UnboundedReceiver<()>) {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let queue = Arc::new(Mutex::new(Queue::with_limits(limits)));
+    let service = ProcessUpdateService::new(BTreeMap::from([(
+        "q1".to_string(),
+        ServedQueue {
+            queue: queue.clone(),
+            new_update_sender: sender,
+            outbox: None,
+            operators: Some(Arc::new(OperatorTokens::new(&BTreeMap::from([("alice".to_string(), "secret".to_string())])))),
+            assertion_senders: None,
+            replicator: None,
+        },
+    )]));
+    (service, queue, receiver)
+}
+
+#[cfg(test)]
+fn create_test_event_request(event_id: &str) -> ProcessUpdateRequest {
+    crate::process_update_mapping::request_from_update(&Update::Event(Event {
+        event_id: event_id.to_string(),
+        timestamp: 1,
+        node_id: "abc123".to_string(),
+        payload: vec![]
+    }))
+}
+
+#[test]
+fn test_request_without_update_or_valid_command_is_invalid() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let (service, queue, _receiver) = create_test_service(QueueLimits::default());
+        let mut without_command = create_test_event_request("e1");
+        without_command.update = Some(crate::process_update::process_update_request::Update::Admin(crate::process_update::Admin { command: None }));
+        let mut without_update = create_test_event_request("e1");
+        without_update.update = None;
+        let mut invalid_queries = create_test_event_request("e1");
+        invalid_queries.update = Some(crate::process_update::process_update_request::Update::Admin(crate::process_update::Admin {
+            command: Some(crate::process_update::admin::Command::Reconfigure(crate::process_update::Reconfigure {
+                queries_yaml: "[unclosed".to_string(),
+                query_url_map: Default::default(),
+            })),
+        }));
+        for request in [without_command, without_update, invalid_queries] {
+            let status = service.send(Request::new(request)).await.unwrap_err();
+            assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        }
+        assert_eq!(queue.lock().await.get_queue_size(), 0);
+    });
+}
+
+#[test]
+fn test_full_queue_rejects_update_of_full_type() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let limits = QueueLimits {
+            max_events: 1,
+            full_queue_wait: Duration::from_millis(0),
+            ..QueueLimits::default()
+        };
+        let (service, queue, _receiver) = create_test_service(limits);
+        service.send(Request::new(create_test_event_request("e1"))).await.unwrap();
+        let status = service.send(Request::new(create_test_event_request("e2"))).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        let stop = crate::process_update_mapping::request_from_update(&Update::Stop);
+        assert!(service.send(Request::new(stop)).await.is_ok());
+        assert_eq!(queue.lock().await.open_events.len(), 1);
+    });
+}
+
+#[test]
+fn test_full_queue_delays_update_until_popped() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let limits = QueueLimits {
+            max_events: 1,
+            full_queue_wait: Duration::from_secs(10),
+            ..QueueLimits::default()
+        };
+        let (service, queue, _receiver) = create_test_service(limits);
+        service.send(Request::new(create_test_event_request("e1"))).await.unwrap();
+        let popper = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            queue.lock().await.pop_earliest_update()
+        });
+        let response = service.send(Request::new(create_test_event_request("e2"))).await.unwrap();
+        assert_eq!(response.get_ref().queue_size, 1);
+        assert!(popper.await.unwrap().is_some());
+    });
+}
+
+#[test]
+fn test_memory_exhausted_queue_delays_events_until_cleared() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let limits = QueueLimits {
+            full_queue_wait: Duration::from_secs(10),
+            ..QueueLimits::default()
+        };
+        let (service, queue, _receiver) = create_test_service(limits);
+        queue.lock().await.set_memory_exhausted(true);
+        let retractions = crate::process_update_mapping::request_from_update(&Update::Retractions(Retractions {
+            retraction_id: "r1".to_string(),
+            timestamp: 1,
+            deltas_ids: vec!["d1".to_string()],
+        }));
+        assert!(service.send(Request::new(retractions)).await.is_ok());
+        let clearer = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            queue.lock().await.set_memory_exhausted(false);
+            queue
+        });
+        service.send(Request::new(create_test_event_request("e1"))).await.unwrap();
+        assert_eq!(clearer.await.unwrap().lock().await.open_events.len(), 1);
+    });
+}
+
+#[test]
+fn test_queue_replays_unprocessed_updates_from_inbox() {
+    let mut inbox_path = std::env::temp_dir();
+    inbox_path.push(format!("mbei-queue-inbox-{}", std::process::id()));
+    let _ = std::fs::remove_file(&inbox_path);
+    let e1 = Update::Event(Event {
+        event_id: "e1".to_string(),
+        timestamp: 1,
+        node_id: "abc123".to_string(),
+        payload: vec![]
+    });
+    let e2 = Update::Event(Event {
+        event_id: "e2".to_string(),
+        timestamp: 2,
+        node_id: "abc123".to_string(),
+        payload: vec![]
+    });
+    {
+        let mut queue = Queue::with_inbox(QueueLimits::default(), &inbox_path).unwrap();
+        queue.insert_traced_update(e1.clone(), None, None).unwrap();
+        queue.insert_traced_update(e2.clone(), None, None).unwrap();
+        queue.insert_traced_update(Update::Stop, None, None).unwrap();
+        queue.stop = false;
+        let (u1, entry) = queue.pop_earliest_queued_update().unwrap();
+        assert_eq!(u1, e1);
+        queue.mark_processed(&entry.unwrap());
+        //e2 is popped, but the component crashes before it is processed
+        queue.pop_earliest_queued_update().unwrap();
+    }
+    let mut queue = Queue::with_inbox(QueueLimits::default(), &inbox_path).unwrap();
+    assert_eq!(queue.get_updates(), vec![e2.clone()]);
+    let (u2, entry) = queue.pop_earliest_queued_update().unwrap();
+    assert_eq!(u2, e2);
+    queue.mark_processed(&entry.unwrap());
+    drop(queue);
+    let mut queue = Queue::with_inbox(QueueLimits::default(), &inbox_path).unwrap();
+    assert!(queue.get_updates().is_empty());
+    //An update held by the component, such as in quarantine, is replayed until it is released
+    queue.insert_traced_update(e1.clone(), None, None).unwrap();
+    let (_, entry) = queue.pop_earliest_queued_update().unwrap();
+    let inbox_seq = entry.unwrap().inbox_seq.unwrap();
+    queue.mark_held();
+    assert!(queue.is_idle());
+    drop(queue);
+    let mut queue = Queue::with_inbox(QueueLimits::default(), &inbox_path).unwrap();
+    assert_eq!(queue.get_updates(), vec![e1.clone()]);
+    queue.mark_released(inbox_seq);
+    drop(queue);
+    let queue = Queue::with_inbox(QueueLimits::default(), &inbox_path).unwrap();
+    assert!(queue.get_updates().is_empty());
+    std::fs::remove_file(&inbox_path).unwrap();
+}
+
+#[test]
+fn test_service_drops_duplicate_deliveries() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let (service, queue, _receiver) = create_test_service(QueueLimits::default());
+        let delivery = |sequence_number| Delivery {
+            sender_id: "q1/abc".to_string(),
+            sequence_number,
+        };
+        let mut r1 = create_test_event_request("e1");
+        crate::process_update_mapping::set_request_delivery(&mut r1, &delivery(1));
+        let mut r3 = create_test_event_request("e3");
+        crate::process_update_mapping::set_request_delivery(&mut r3, &delivery(3));
+        service.send(Request::new(r1.clone())).await.unwrap();
+        service.send(Request::new(r3)).await.unwrap();
+        //The acknowledgement was lost, so the sender resends
+        let response = service.send(Request::new(r1)).await.unwrap();
+        assert_eq!(response.get_ref().queue_size, 2);
+        //Numbering starts at 1, so a delivery numbered 0 is invalid rather than a duplicate
+        let mut r0 = create_test_event_request("e0");
+        crate::process_update_mapping::set_request_delivery(&mut r0, &delivery(0));
+        let status = service.send(Request::new(r0)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        let queue = queue.lock().await;
+        assert_eq!(queue.open_events.len(), 2);
+        assert_eq!(queue.get_missing_deliveries(), BTreeMap::from([("q1/abc".to_string(), vec![2])]));
+    });
+}
+
+#[test]
+fn test_service_routes_updates_to_target_queue() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+        let q1 = Arc::new(Mutex::new(Queue::new()));
+        let q2 = Arc::new(Mutex::new(Queue::new()));
+        let service = ProcessUpdateService::new(BTreeMap::from([
+            ("q1".to_string(), ServedQueue { queue: q1.clone(), new_update_sender: sender.clone(), outbox: None, operators: None, assertion_senders: None, replicator: None }),
+            ("q2".to_string(), ServedQueue { queue: q2.clone(), new_update_sender: sender, outbox: None, operators: None, assertion_senders: None, replicator: None }),
+        ]));
+        let mut request = create_test_event_request("e1");
+        crate::process_update_mapping::set_request_target_query(&mut request, "q2");
+        service.send(Request::new(request)).await.unwrap();
+        assert!(q1.lock().await.open_events.is_empty());
+        assert_eq!(q2.lock().await.open_events.len(), 1);
+        let status = service.send(Request::new(create_test_event_request("e2"))).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        let mut request = create_test_event_request("e3");
+        crate::process_update_mapping::set_request_target_query(&mut request, "q3");
+        let status = service.send(Request::new(request)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+    });
+}
+
+#[test]
+fn test_draining_queue_rejects_events_until_drained() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let (service, queue, _receiver) = create_test_service(QueueLimits::default());
+        let get_status = || async { service.get_status(Request::new(GetStatusRequest::default())).await.unwrap().into_inner() };
+        service.send(Request::new(create_test_event_request("e1"))).await.unwrap();
+        let drain = crate::process_update_mapping::request_from_update(&Update::Admin(AdminCommand::Drain));
+        service.send(Request::new(drain)).await.unwrap();
+        let status = service.send(Request::new(create_test_event_request("e2"))).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        //Updates from other components are still accepted
+        let deltas = crate::process_update_mapping::request_from_update(&Update::Deltas(Deltas {
+            deltas_id: "d1".to_string(),
+            origin_id: "e0".to_string(),
+            origin_timestamp: 1,
+            deltas: Default::default()
+        }));
+        service.send(Request::new(deltas)).await.unwrap();
+        let status = get_status().await;
+        assert!(status.draining && !status.drained);
+        assert_eq!((status.n_accepted, status.queue_size), (2, 2));
+        let (_, entry) = queue.lock().await.pop_earliest_queued_update().unwrap();
+        let (_, last_entry) = queue.lock().await.pop_earliest_queued_update().unwrap();
+        queue.lock().await.mark_processed(&entry.unwrap());
+        //The last update is popped, but still being processed
+        assert!(!get_status().await.drained);
+        queue.lock().await.mark_processed(&last_entry.unwrap());
+        assert!(get_status().await.drained);
+    });
+}
+
+#[test]
+fn test_watermarks_are_kept_per_source_instead_of_queued() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let (service, queue, mut receiver) = create_test_service(QueueLimits::default());
+        let watermark = |source_id: &str, timestamp| crate::process_update_mapping::request_from_update(&Update::Watermark(Watermark {
+            source_id: source_id.to_string(),
+            timestamp,
+        }));
+        service.send(Request::new(watermark("producer", 5))).await.unwrap();
+        service.send(Request::new(watermark("q0", 3))).await.unwrap();
+        //A late watermark does not move the source back
+        service.send(Request::new(watermark("producer", 4))).await.unwrap();
+        assert!(receiver.try_recv().is_ok());
+        service.send(Request::new(create_test_event_request("e1"))).await.unwrap();
+        let queue = queue.lock().await;
+        assert_eq!(queue.get_watermarks(), &BTreeMap::from([("producer".to_string(), 5), ("q0".to_string(), 3)]));
+        assert_eq!(queue.get_queue_size(), 1);
+        assert_eq!(queue.n_accepted, 1);
+        assert_eq!(queue.get_min_queued_timestamp(), Some(1));
+    });
+}
+
+#[test]
+fn test_service_sets_operator_of_manual_assertions_from_token() {
+    use crate::operators::with_operator_token;
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let (service, queue, _receiver) = create_test_service(QueueLimits::default());
+        let assertion = crate::process_update_mapping::request_from_update(&Update::ManualAssertion(mbei_core::event::ManualAssertion {
+            assertion_id: "a1".to_string(),
+            operator: "mallory".to_string(),
+            reason: "The barrel is at the ramp".to_string(),
+            timestamp: 5,
+            deltas: Default::default(),
+            revoke: false,
+        }));
+        let status = service.send(Request::new(assertion.clone())).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::Unauthenticated);
+        service.send(with_operator_token(assertion, "secret").unwrap()).await.unwrap();
+        let popped = queue.lock().await.pop_earliest_update();
+        match popped {
+            Some(Update::ManualAssertion(a)) => assert_eq!(a.operator, "alice"),
+            other => panic!("Expected the assertion, got {:?}", other),
+        }
+    });
+}
+
+#[test]
+fn test_service_rejects_manual_assertions_from_clients_not_allowed_to_send_them() {
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+        let queue = Arc::new(Mutex::new(Queue::new()));
+        let service = ProcessUpdateService::new(BTreeMap::from([(
+            "central".to_string(),
+            ServedQueue {
+                queue: queue.clone(),
+                new_update_sender: sender,
+                outbox: None,
+                operators: None,
+                assertion_senders: Some(PeerIdentityCheck::for_hosts(std::collections::BTreeSet::from(["component".to_string()]))),
+                replicator: None,
+            },
+        )]));
+        let assertion = crate::process_update_mapping::request_from_update(&Update::ManualAssertion(mbei_core::event::ManualAssertion {
+            assertion_id: "a1".to_string(),
+            operator: "mallory".to_string(),
+            reason: "Forged".to_string(),
+            timestamp: 5,
+            deltas: Default::default(),
+            revoke: false,
+        }));
+        //A client without a certificate can not forge the operator
+        let status = service.send(Request::new(assertion)).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::PermissionDenied);
+        //Other updates are still accepted from it
+        service.send(Request::new(create_test_event_request("e1"))).await.unwrap();
+        assert_eq!(queue.lock().await.get_queue_size(), 1);
+    });
+}
@@ -0,0 +1,148 @@
+use std::fs::{rename, File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use log::warn;
+
+/// Append-only file of length prefixed records, synced on every append unless the appender syncs itself.
+/// Used by the inbox and the outbox to keep updates across restarts, which encode their records themselves.
+pub(crate) struct RecordLog {
+    path: PathBuf,
+    file: Arc<File>,
+    //Bytes of the records in the log
+    len: u64,
+}
+
+/// Syncs the records appended to a log so far, without access to the log itself.
+/// Concurrent syncs of the same file are committed together by the file system.
+#[derive(Clone)]
+pub(crate) struct LogSync(Arc<File>);
+
+impl LogSync {
+    pub(crate) fn sync(&self) -> std::io::Result<()> {
+        self.0.sync_data()
+    }
+}
+
+impl RecordLog {
+    /// Opens or creates the log, returning the records written so far.
+    pub(crate) fn open(path: &Path) -> std::io::Result<(RecordLog, Vec<Vec<u8>>)> {
+        let (records, valid_length) = read_records(path)?;
+        let mut file = OpenOptions::new().create(true).truncate(false).read(true).write(true).open(path)?;
+        //A record that was only partly written before a crash was never acknowledged
+        if file.metadata()?.len() > valid_length {
+            warn!("Truncating incomplete record at the end of {:?}", path);
+            file.set_len(valid_length)?;
+        }
+        file.seek(SeekFrom::End(0))?;
+        let log = RecordLog {
+            path: path.to_path_buf(),
+            file: Arc::new(file),
+            len: valid_length,
+        };
+        Ok((log, records))
+    }
+
+    /// Appends a record, which is durable once the sync of the log returns.
+    pub(crate) fn append_unsynced(&mut self, record: &[u8]) -> std::io::Result<()> {
+        self.write(record)
+    }
+
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub(crate) fn get_sync(&self) -> LogSync {
+        LogSync(self.file.clone())
+    }
+
+    /// Replaces the content of the log, by writing a new file which then replaces the old one.
+    /// The old file is kept, and appended to, if the new one could not be written.
+    pub(crate) fn rewrite(&mut self, records: &Vec<Vec<u8>>) -> std::io::Result<()> {
+        let mut rewritten_path = self.path.clone();
+        rewritten_path.set_extension("rewriting");
+        let mut rewritten = RecordLog {
+            path: self.path.clone(),
+            file: Arc::new(File::create(&rewritten_path)?),
+            len: 0,
+        };
+        for record in records {
+            rewritten.write(record)?;
+        }
+        rewritten.file.sync_data()?;
+        rename(&rewritten_path, &self.path)?;
+        *self = rewritten;
+        Ok(())
+    }
+
+    fn write(&mut self, record: &[u8]) -> std::io::Result<()> {
+        let mut framed = (record.len() as u32).to_le_bytes().to_vec();
+        framed.extend(record);
+        if let Err(e) = (&*self.file).write_all(&framed) {
+            //A partly written record would hide the records appended after it
+            let _ = self.file.set_len(self.len);
+            let _ = (&*self.file).seek(SeekFrom::Start(self.len));
+            return Err(e);
+        }
+        self.len += framed.len() as u64;
+        Ok(())
+    }
+}
+
+/// Records, and the length of the log up to the last complete record.
+fn read_records(path: &Path) -> std::io::Result<(Vec<Vec<u8>>, u64)> {
+    let mut records = vec![];
+    let mut valid_length = 0;
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok((vec![], 0)),
+        Err(e) => return Err(e),
+    };
+    loop {
+        let mut length_bytes = [0u8; 4];
+        if file.read_exact(&mut length_bytes).is_err() {
+            break;
+        }
+        let mut encoded = vec![0u8; u32::from_le_bytes(length_bytes) as usize];
+        if file.read_exact(&mut encoded).is_err() {
+            break;
+        }
+        valid_length += 4 + encoded.len() as u64;
+        records.push(encoded);
+    }
+    Ok((records, valid_length))
+}
+
+#[cfg(test)]
+pub(crate) fn create_test_log_path(name: &str) -> PathBuf {
+    let mut path = std::env::temp_dir();
+    path.push(format!("mbei-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+#[test]
+fn test_record_log_ignores_incomplete_last_record() {
+    let path = create_test_log_path("incomplete");
+    {
+        let (mut log, records) = RecordLog::open(&path).unwrap();
+        assert!(records.is_empty());
+        log.append_unsynced(b"first").unwrap();
+        log.append_unsynced(b"second").unwrap();
+    }
+    let length = std::fs::metadata(&path).unwrap().len();
+    OpenOptions::new().write(true).open(&path).unwrap().set_len(length - 3).unwrap();
+    let (mut log, records) = RecordLog::open(&path).unwrap();
+    assert_eq!(records, vec![b"first".to_vec()]);
+    log.append_unsynced(b"third").unwrap();
+    drop(log);
+    let (mut log, records) = RecordLog::open(&path).unwrap();
+    assert_eq!(records, vec![b"first".to_vec(), b"third".to_vec()]);
+    log.rewrite(&vec![b"third".to_vec()]).unwrap();
+    assert_eq!(log.len(), 4 + 5);
+    drop(log);
+    let (_, records) = RecordLog::open(&path).unwrap();
+    assert_eq!(records, vec![b"third".to_vec()]);
+    std::fs::remove_file(&path).unwrap();
+}
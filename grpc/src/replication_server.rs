@@ -0,0 +1,543 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::Duration;
+
+use futures_util::FutureExt;
+use log::{debug, error, info, warn};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{Permit, UnboundedSender};
+use tokio::sync::oneshot::Receiver;
+use tokio::sync::{mpsc, watch, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tonic::transport::{Channel, Error, Server};
+use tonic::{Code, Request, Response, Status};
+
+use mbei_core::event::Update;
+use mbei_core::trace::TraceContext;
+
+use crate::delivery::Delivery;
+use crate::health::health_server::HealthServer;
+use crate::health_server::{Health, HealthReporter, HealthService};
+use crate::process_update_mapping::{
+    delivery_from_request, request_from_traced_update, request_from_update, set_request_delivery,
+    trace_context_from_request, update_from_request,
+};
+use crate::process_update_server::{create_server_address, select_target, sync_inbox, Queue};
+use crate::replication::replicate_request::Record;
+use crate::replication::replication_client::ReplicationClient;
+use crate::replication::replication_server::{Replication, ReplicationServer};
+use crate::replication::{ReplicateRequest, ReplicateResponse};
+use crate::tls::{configure_server, create_endpoint, PeerIdentityCheck, TlsOptions};
+
+//A standby which does not answer within this time is taken to be down, and is tried again after the retry interval
+const REPLICATION_TIMEOUT: Duration = Duration::from_secs(1);
+const REPLICATION_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+//How long an accepted update waits for the standby to confirm it, before its sender is told to send it again
+const CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(5);
+//Records waiting to be replicated, beyond which the primary accepts no more updates until the standby catches up
+const MAX_PENDING_RECORDS: usize = 10000;
+//Confirmed records kept for a restarted standby to catch up from. Once more were replicated, it can not
+const MAX_REPLAYED_RECORDS: usize = 100000;
+//Primaries replicate in this epoch. A standby which takes over moves on to the next, so that its primary is refused
+const PRIMARY_EPOCH: u64 = 0;
+
+/// What a primary replicates to its standby, in the order it happened.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplicatedRecord {
+    //An update the primary accepted, with the trace context and delivery it was sent with
+    Accepted(Update, Option<TraceContext>, Option<Delivery>),
+    //An update the primary took from its queue to process
+    Processing(Update),
+    Finality(u64),
+    //Everything the primary sent so far was acknowledged by its receivers
+    Checkpoint,
+    Heartbeat,
+}
+
+/// Replicates the records of the component of a query to its standby, from a task of its own which also sends the heartbeats.
+/// Handles are cheap to clone, so that the server accepting updates and the loop processing them share the task.
+#[derive(Clone)]
+pub struct Replicator {
+    query_name: String,
+    primary_id: String,
+    //Locked while a record is numbered and handed to the task, so that the task gets the records in the order they are numbered
+    next_sequence_number: Arc<StdMutex<u64>>,
+    record_sender: mpsc::Sender<ReplicateRequest>,
+    //Every record up to it was confirmed by the standby
+    confirmed: watch::Receiver<u64>,
+    //Set when the standby took over, after which the primary must stop
+    fenced: Arc<AtomicBool>,
+}
+
+impl Replicator {
+    /// Connects lazily, as the standby may start after the primary. Heartbeats are sent when nothing was replicated for the interval.
+    pub fn new(query_name: &str, url: &str, tls: Option<&TlsOptions>, heartbeat: Duration, health: HealthReporter) -> Result<Replicator, String> {
+        let endpoint = create_endpoint(url, tls)?.timeout(REPLICATION_TIMEOUT).connect_timeout(REPLICATION_TIMEOUT);
+        let primary_id = query_name.to_string() + "/" + &uuid::Uuid::new_v4().to_string();
+        let (record_sender, record_receiver) = mpsc::channel(MAX_PENDING_RECORDS);
+        let (confirmed_sender, confirmed) = watch::channel(0);
+        let fenced = Arc::new(AtomicBool::new(false));
+        let task = ReplicationTask {
+            query_name: query_name.to_string(),
+            primary_id: primary_id.clone(),
+            client: ReplicationClient::new(endpoint.connect_lazy()),
+            record_receiver,
+            replayed: ReplayedRecords::default(),
+            confirmed: confirmed_sender,
+            heartbeat,
+            health,
+            fenced: fenced.clone(),
+        };
+        tokio::spawn(task.run());
+        Ok(Replicator {
+            query_name: query_name.to_string(),
+            primary_id,
+            next_sequence_number: Arc::new(StdMutex::new(1)),
+            record_sender,
+            confirmed,
+            fenced,
+        })
+    }
+
+    /// Replicates the record, waiting while too many are pending. Returns its sequence number, unless the standby is no longer replicated to.
+    pub async fn replicate(&self, record: &ReplicatedRecord) -> Option<u64> {
+        let permit = self.record_sender.reserve().await.ok()?;
+        Some(self.send(permit, record))
+    }
+
+    /// Replicates the record, unless too many are pending. Returns its sequence number, unless the standby is no longer replicated to.
+    pub fn try_replicate(&self, record: &ReplicatedRecord) -> Result<Option<u64>, Status> {
+        match self.record_sender.try_reserve() {
+            Ok(permit) => Ok(Some(self.send(permit, record))),
+            Err(TrySendError::Full(_)) => Err(Status::resource_exhausted(format!(
+                "The standby of {} is {} records behind",
+                &self.query_name, MAX_PENDING_RECORDS
+            ))),
+            Err(TrySendError::Closed(_)) => Ok(None),
+        }
+    }
+
+    fn send(&self, permit: Permit<ReplicateRequest>, record: &ReplicatedRecord) -> u64 {
+        let mut next_sequence_number = self.next_sequence_number.lock().expect("Replicator lock poisoned");
+        let sequence_number = *next_sequence_number;
+        permit.send(to_replicate_request(record, &self.query_name, &self.primary_id, sequence_number));
+        *next_sequence_number += 1;
+        sequence_number
+    }
+
+    /// Waits until the standby confirmed every record up to the sequence number, so that the update it replicated can be acknowledged.
+    /// Returns at once when the standby is no longer replicated to, as the primary then goes on without it.
+    pub async fn await_confirmed(&self, sequence_number: u64) -> Result<(), Status> {
+        let mut confirmed = self.confirmed.clone();
+        let confirmation = async {
+            while *confirmed.borrow() < sequence_number {
+                if confirmed.changed().await.is_err() {
+                    break;
+                }
+            }
+        };
+        if tokio::time::timeout(CONFIRMATION_TIMEOUT, confirmation).await.is_err() {
+            return Err(Status::unavailable(format!("The standby of {} has not confirmed the update yet", &self.query_name)));
+        }
+        match self.is_fenced() {
+            true => Err(Status::unavailable(format!("{} was replaced by its standby", &self.query_name))),
+            false => Ok(()),
+        }
+    }
+
+    pub fn get_last_sequence_number(&self) -> u64 {
+        *self.next_sequence_number.lock().expect("Replicator lock poisoned") - 1
+    }
+
+    pub fn is_fenced(&self) -> bool {
+        self.fenced.load(Ordering::SeqCst)
+    }
+}
+
+/// The records a primary took from its channel: those not yet confirmed by the standby, and the confirmed ones
+/// kept for a standby which restarts to catch up from. Records are sent one at a time, so only the last one can be unconfirmed.
+struct ReplayedRecords {
+    records: VecDeque<ReplicateRequest>,
+    first_sequence_number: u64,
+    //Of the record the standby is sent next
+    next_sequence_number: u64,
+}
+
+impl Default for ReplayedRecords {
+    fn default() -> Self {
+        ReplayedRecords {
+            records: VecDeque::new(),
+            first_sequence_number: 1,
+            next_sequence_number: 1,
+        }
+    }
+}
+
+impl ReplayedRecords {
+    fn get_next(&self) -> Option<&ReplicateRequest> {
+        self.records.get((self.next_sequence_number - self.first_sequence_number) as usize)
+    }
+
+    fn push(&mut self, request: ReplicateRequest) {
+        self.records.push_back(request);
+    }
+
+    //Moves on to the record the standby expects, an earlier one when it restarted. Returns false when that one was dropped
+    fn resume_from(&mut self, next_sequence_number: u64) -> bool {
+        if next_sequence_number < self.first_sequence_number {
+            return false;
+        }
+        self.next_sequence_number = next_sequence_number.min(self.first_sequence_number + self.records.len() as u64);
+        while self.records.len() > MAX_REPLAYED_RECORDS && self.first_sequence_number < self.next_sequence_number {
+            self.records.pop_front();
+            self.first_sequence_number += 1;
+        }
+        true
+    }
+}
+
+struct ReplicationTask {
+    query_name: String,
+    primary_id: String,
+    client: ReplicationClient<Channel>,
+    record_receiver: mpsc::Receiver<ReplicateRequest>,
+    replayed: ReplayedRecords,
+    confirmed: watch::Sender<u64>,
+    heartbeat: Duration,
+    health: HealthReporter,
+    fenced: Arc<AtomicBool>,
+}
+
+impl ReplicationTask {
+    //Sends the records in order until the component stops, the standby can not catch up, or it took over.
+    //The handles learn of the latter two as the channel closes
+    async fn run(mut self) {
+        let mut is_warned = false;
+        loop {
+            let request = match self.replayed.get_next() {
+                Some(request) => request.clone(),
+                None => match tokio::time::timeout(self.heartbeat, self.record_receiver.recv()).await {
+                    Ok(Some(request)) => {
+                        self.replayed.push(request.clone());
+                        request
+                    }
+                    Ok(None) => return,
+                    Err(_) => self.get_heartbeat(),
+                },
+            };
+            match self.client.replicate(request).await {
+                Ok(response) => {
+                    is_warned = false;
+                    self.health.set_peer_reachable("standby", true);
+                    let next_sequence_number = response.into_inner().next_sequence_number;
+                    if next_sequence_number < self.replayed.next_sequence_number {
+                        info!("The standby of {} catches up from record {}", &self.query_name, next_sequence_number);
+                    }
+                    if !self.replayed.resume_from(next_sequence_number) {
+                        error!(
+                            "The standby of {} restarted and can not catch up, as the records before {} were dropped. Updates are acknowledged without it",
+                            &self.query_name, self.replayed.first_sequence_number
+                        );
+                        self.health.set_peer_reachable("standby", false);
+                        return;
+                    }
+                    //A restarted standby lost the records it confirmed before, but gets them again before any later one
+                    let confirmed = self.replayed.next_sequence_number - 1;
+                    if confirmed > *self.confirmed.borrow() {
+                        let _ = self.confirmed.send(confirmed);
+                    }
+                }
+                Err(status) if status.code() == Code::Aborted => {
+                    error!("{} was replaced by its standby: {}", &self.query_name, status.message());
+                    self.fenced.store(true, Ordering::SeqCst);
+                    return;
+                }
+                Err(status) => {
+                    if !is_warned {
+                        warn!("Could not replicate to the standby of {}, will retry: {}", &self.query_name, status);
+                        is_warned = true;
+                    }
+                    self.health.set_peer_reachable("standby", false);
+                    tokio::time::sleep(REPLICATION_RETRY_INTERVAL).await;
+                }
+            }
+        }
+    }
+
+    //Heartbeats carry the sequence number of the next record, so that a standby which missed records learns of it
+    fn get_heartbeat(&self) -> ReplicateRequest {
+        to_replicate_request(&ReplicatedRecord::Heartbeat, &self.query_name, &self.primary_id, self.replayed.next_sequence_number)
+    }
+}
+
+/// The records a standby received from its primary and has not yet applied.
+/// Accepted updates are not kept here, since they go straight into the queue of the standby.
+pub struct ReplicatedLog {
+    primary_id: Option<String>,
+    next_sequence_number: u64,
+    //Whether the standby has every record of its primary, so that it is equivalent to it and may take over
+    is_in_sync: bool,
+    epoch: u64,
+    records: VecDeque<ReplicatedRecord>,
+    last_received_at: Instant,
+}
+
+impl Default for ReplicatedLog {
+    fn default() -> Self {
+        ReplicatedLog {
+            primary_id: None,
+            next_sequence_number: 1,
+            is_in_sync: false,
+            epoch: PRIMARY_EPOCH,
+            records: VecDeque::new(),
+            last_received_at: Instant::now(),
+        }
+    }
+}
+
+impl ReplicatedLog {
+    pub fn pop(&mut self) -> Option<ReplicatedRecord> {
+        self.records.pop_front()
+    }
+
+    /// How long ago the primary was last heard from, or the log was created.
+    pub fn get_silence(&self) -> Duration {
+        self.last_received_at.elapsed()
+    }
+
+    /// Moves on to the next epoch, so that the primary is refused from now on, unless the standby missed records of it.
+    /// Returns whether the standby may take over.
+    pub fn take_over(&mut self) -> bool {
+        if self.is_in_sync {
+            self.epoch += 1;
+        }
+        self.is_in_sync
+    }
+
+    //Whether the record is the next one, and not a resend, a heartbeat or a record after a gap, which the primary
+    //learns of from the sequence number it is answered with. Numbering starts over when the primary restarts
+    fn receive(&mut self, primary_id: &str, epoch: u64, sequence_number: u64, is_heartbeat: bool) -> Result<bool, Status> {
+        if epoch < self.epoch {
+            return Err(Status::aborted(format!("The standby took over from {} in epoch {}", primary_id, self.epoch)));
+        }
+        self.last_received_at = Instant::now();
+        if self.primary_id.as_deref() != Some(primary_id) {
+            info!("Replicating from {}", primary_id);
+            self.primary_id = Some(primary_id.to_string());
+            self.next_sequence_number = 1;
+            self.is_in_sync = false;
+        }
+        if is_heartbeat || sequence_number != self.next_sequence_number {
+            //Heartbeats carry the sequence number of the next record
+            let next_sequence_number = if is_heartbeat { sequence_number } else { sequence_number + 1 };
+            if next_sequence_number > self.next_sequence_number {
+                self.is_in_sync = false;
+            } else if is_heartbeat {
+                self.is_in_sync = true;
+            }
+            return Ok(false);
+        }
+        self.next_sequence_number += 1;
+        self.is_in_sync = true;
+        Ok(true)
+    }
+}
+
+/// The queue of a standby component, which accepted updates go into, and the log of the other records.
+pub struct ServedReplica {
+    pub queue: Arc<Mutex<Queue>>,
+    pub log: Arc<StdMutex<ReplicatedLog>>,
+    pub new_record_sender: UnboundedSender<()>,
+}
+
+/// Receives the records of the primaries of the standby components served by one process.
+pub struct ReplicationService {
+    served_replicas: BTreeMap<String, ServedReplica>,
+}
+
+impl ReplicationService {
+    pub fn new(served_replicas: BTreeMap<String, ServedReplica>) -> ReplicationService {
+        ReplicationService { served_replicas }
+    }
+}
+
+#[tonic::async_trait]
+impl Replication for ReplicationService {
+    async fn replicate(&self, request: Request<ReplicateRequest>) -> Result<Response<ReplicateResponse>, Status> {
+        let request = request.into_inner();
+        let served_replica = select_target(&self.served_replicas, &request.target_query)?;
+        let record = from_replicate_request(&request).map_err(Status::invalid_argument)?;
+        let is_heartbeat = matches!(record, ReplicatedRecord::Heartbeat);
+        let (inbox_sync, next_sequence_number) = {
+            //Locked first, so that the component never sees a record before the update it refers to is queued
+            let mut queue = served_replica.queue.lock().await;
+            let mut log = served_replica.log.lock().expect("Replicated log lock poisoned");
+            if !log.receive(&request.primary_id, request.epoch, request.sequence_number, is_heartbeat)? {
+                if !is_heartbeat {
+                    debug!("Dropping record {}, expecting {}", request.sequence_number, log.next_sequence_number);
+                }
+                return Ok(Response::new(ReplicateResponse { next_sequence_number: log.next_sequence_number }));
+            }
+            match record {
+                ReplicatedRecord::Accepted(update, context, delivery) => {
+                    if let Err(e) = queue.accept_replicated(update, context, delivery) {
+                        //Expected again, so that the primary retries it
+                        log.next_sequence_number -= 1;
+                        return Err(Status::unavailable(format!("Could not write to the inbox: {}", e)));
+                    }
+                }
+                record => log.records.push_back(record),
+            }
+            (queue.get_inbox_sync(), log.next_sequence_number)
+        };
+        sync_inbox(inbox_sync).await?;
+        let _ = served_replica.new_record_sender.send(());
+        Ok(Response::new(ReplicateResponse { next_sequence_number }))
+    }
+}
+
+/// Serves replication to the standby components, and the health protocol, until they take over.
+pub async fn create_and_run_replication_server(grpc_port: u16, served_replicas: BTreeMap<String, ServedReplica>, shutdown_server_receiver: Receiver<()>, health: Health, tls: Option<&TlsOptions>) -> JoinHandle<Result<(), Error>> {
+    let svc = ReplicationServer::with_interceptor(ReplicationService::new(served_replicas), PeerIdentityCheck::new(tls));
+    let address = create_server_address(grpc_port);
+    let mut server = configure_server(Server::builder(), tls).expect("Could not configure server");
+    tokio::spawn(
+        server
+            .add_service(svc)
+            .add_service(HealthServer::with_interceptor(HealthService::new(health), PeerIdentityCheck::new(tls)))
+            .serve_with_shutdown(address, shutdown_server_receiver.map(|_| ())),
+    )
+}
+
+fn to_replicate_request(record: &ReplicatedRecord, query_name: &str, primary_id: &str, sequence_number: u64) -> ReplicateRequest {
+    let record = match record {
+        ReplicatedRecord::Accepted(update, context, delivery) => {
+            let mut request = match context {
+                Some(context) => request_from_traced_update(update, context),
+                None => request_from_update(update),
+            };
+            if let Some(delivery) = delivery {
+                set_request_delivery(&mut request, delivery);
+            }
+            Some(Record::Accepted(request))
+        }
+        ReplicatedRecord::Processing(update) => Some(Record::Processing(request_from_update(update))),
+        ReplicatedRecord::Finality(finalized_before) => Some(Record::FinalizedBefore(*finalized_before)),
+        ReplicatedRecord::Checkpoint => Some(Record::Checkpoint(true)),
+        ReplicatedRecord::Heartbeat => None,
+    };
+    ReplicateRequest {
+        target_query: query_name.to_string(),
+        primary_id: primary_id.to_string(),
+        sequence_number,
+        epoch: PRIMARY_EPOCH,
+        record,
+    }
+}
+
+fn from_replicate_request(request: &ReplicateRequest) -> Result<ReplicatedRecord, String> {
+    let record = match &request.record {
+        Some(Record::Accepted(request)) => ReplicatedRecord::Accepted(
+            update_from_request(request)?,
+            trace_context_from_request(request),
+            delivery_from_request(request)?,
+        ),
+        Some(Record::Processing(request)) => ReplicatedRecord::Processing(update_from_request(request)?),
+        Some(Record::FinalizedBefore(finalized_before)) => ReplicatedRecord::Finality(*finalized_before),
+        Some(Record::Checkpoint(_)) => ReplicatedRecord::Checkpoint,
+        None => ReplicatedRecord::Heartbeat,
+    };
+    Ok(record)
+}
+
+#[test]
+fn test_replicated_log_drops_resends_catches_up_and_fences_the_primary_off() {
+    let mut log = ReplicatedLog::default();
+    //A standby which missed the first records is answered with the one it expects, and can not take over until it has them
+    assert!(!log.receive("q1/a", 0, 2, false).unwrap());
+    assert_eq!(log.next_sequence_number, 1);
+    assert!(!log.take_over());
+    assert!(log.receive("q1/a", 0, 1, false).unwrap());
+    assert!(log.receive("q1/a", 0, 2, false).unwrap());
+    assert!(!log.receive("q1/a", 0, 2, false).unwrap());
+    //Heartbeats tell the standby whether it missed records
+    assert!(!log.receive("q1/a", 0, 4, true).unwrap());
+    assert!(!log.is_in_sync);
+    assert!(log.receive("q1/a", 0, 3, false).unwrap());
+    assert!(!log.receive("q1/a", 0, 4, true).unwrap());
+    assert!(log.is_in_sync);
+    //A restarted primary numbers its records from the start
+    assert!(log.receive("q1/b", 0, 1, false).unwrap());
+    assert!(log.take_over());
+    assert_eq!(log.receive("q1/b", 0, 2, false).unwrap_err().code(), Code::Aborted);
+}
+
+#[test]
+fn test_replayed_records_are_sent_again_to_a_restarted_standby_until_dropped() {
+    let request = |sequence_number| to_replicate_request(&ReplicatedRecord::Checkpoint, "q1", "q1/a", sequence_number);
+    let mut replayed = ReplayedRecords::default();
+    for sequence_number in 1..=3 {
+        replayed.push(request(sequence_number));
+    }
+    assert_eq!(replayed.get_next(), Some(&request(1)));
+    assert!(replayed.resume_from(4));
+    assert_eq!(replayed.get_next(), None);
+    //A restarted standby expects the first record again
+    assert!(replayed.resume_from(1));
+    assert_eq!(replayed.get_next(), Some(&request(1)));
+    //Confirmed records beyond the limit are dropped, so that a standby restarting later can not catch up
+    let last = MAX_REPLAYED_RECORDS as u64 + 2;
+    for sequence_number in 4..=last {
+        replayed.push(request(sequence_number));
+    }
+    assert!(replayed.resume_from(last + 1));
+    assert!(!replayed.resume_from(2));
+    assert!(replayed.resume_from(3));
+    assert_eq!(replayed.get_next(), Some(&request(3)));
+}
+
+#[test]
+fn test_replication_service_queues_accepted_updates_and_logs_the_rest() {
+    use mbei_core::event::Event;
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let queue = Arc::new(Mutex::new(Queue::new()));
+        let log = Arc::new(StdMutex::new(ReplicatedLog::default()));
+        let (new_record_sender, _new_record_receiver) = tokio::sync::mpsc::unbounded_channel();
+        let service = ReplicationService::new(BTreeMap::from([(
+            "q1".to_string(),
+            ServedReplica { queue: queue.clone(), log: log.clone(), new_record_sender },
+        )]));
+        let event = Update::Event(Event { event_id: "e1".to_string(), timestamp: 1, node_id: "abc123".to_string(), payload: vec![] });
+        let delivery = Delivery { sender_id: "producer".to_string(), sequence_number: 1 };
+        //Heartbeats carry the sequence number of the next record
+        let records = [
+            (ReplicatedRecord::Accepted(event.clone(), None, Some(delivery.clone())), 1),
+            (ReplicatedRecord::Heartbeat, 2),
+            (ReplicatedRecord::Processing(event.clone()), 2),
+            (ReplicatedRecord::Checkpoint, 3),
+        ];
+        for (record, sequence_number) in records {
+            let request = to_replicate_request(&record, "q1", "q1/a", sequence_number);
+            let response = service.replicate(Request::new(request.clone())).await.unwrap().into_inner();
+            let expected = if record == ReplicatedRecord::Heartbeat { sequence_number } else { sequence_number + 1 };
+            assert_eq!(response.next_sequence_number, expected);
+            //Resends are dropped
+            service.replicate(Request::new(request)).await.unwrap();
+        }
+        assert_eq!(queue.lock().await.get_updates(), vec![event.clone()]);
+        {
+            let mut log = log.lock().unwrap();
+            assert_eq!(log.pop(), Some(ReplicatedRecord::Processing(event.clone())));
+            assert_eq!(log.pop(), Some(ReplicatedRecord::Checkpoint));
+            assert_eq!(log.pop(), None);
+        }
+        let mut queue = queue.lock().await;
+        //The delivery is known to the standby, so a resend after it took over is dropped
+        assert!(queue.received.is_received(&delivery));
+        assert!(queue.take_update(&event).is_some());
+        assert!(queue.take_update(&event).is_none());
+    });
+}
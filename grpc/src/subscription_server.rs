@@ -0,0 +1,254 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures_util::Stream;
+use tokio::sync::Notify;
+use tonic::{Request, Response, Status};
+
+use mbei_core::graph::Delta;
+
+use crate::delta_mapping::{from_proto_delta, to_proto_delta};
+use crate::subscription::subscription_server::Subscription;
+use crate::subscription::{SubscribeRequest, StateChange as ProtoStateChange};
+
+//Changes read from the log at a time, before those matching the filter are sent
+const CHANGES_PER_READ: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChangeType {
+    Addition,
+    Removal,
+    //Of a delta stored earlier
+    Retraction,
+}
+
+/// A change to what is stored, numbered by a cursor which increases in the order the changes were stored.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StateChange {
+    pub cursor: u64,
+    pub change_type: ChangeType,
+    pub deltas_id: String,
+    pub delta: Delta,
+}
+
+/// Which changes a subscriber gets. Empty sets match everything.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SubscriptionFilter {
+    pub edge_types: BTreeSet<String>,
+    pub node_types: BTreeSet<String>,
+    pub instance_node_ids: BTreeSet<String>,
+}
+
+impl SubscriptionFilter {
+    pub fn matches(&self, delta: &Delta) -> bool {
+        let nodes = [&delta.src, &delta.trg];
+        let listed = |set: &BTreeSet<String>, value: Option<&String>| matches!(value, Some(v) if set.contains(v));
+        (self.edge_types.is_empty() || self.edge_types.contains(&delta.edge_type))
+            && (self.node_types.is_empty() || nodes.iter().any(|n| listed(&self.node_types, n.node_type.as_ref())))
+            && (self.instance_node_ids.is_empty()
+                || nodes.iter().any(|n| listed(&self.instance_node_ids, n.instance_node_name.as_ref())))
+    }
+}
+
+/// Where the changes are kept, so that subscribers can resume after the last change they received.
+/// Cursors are consecutive, so a gap before the first change after a cursor means the log pruned the changes in it.
+pub trait ChangeLog: Send + Sync + 'static {
+    /// At most limit changes after the cursor, in cursor order. Called off the async runtime, as it may block.
+    fn get_changes_after(&self, cursor: u64, limit: usize) -> Result<Vec<StateChange>, String>;
+}
+
+/// Tells subscribers that changes were added to the log, or that no more are, so that their streams end.
+/// Notifiers are cheap to clone, so that the loop storing the changes keeps one.
+#[derive(Clone, Default)]
+pub struct ChangeNotifier {
+    changed: Arc<Notify>,
+    closed: Arc<AtomicBool>,
+}
+
+impl ChangeNotifier {
+    pub fn notify(&self) {
+        self.changed.notify_waiters();
+    }
+
+    /// Ends the streams once they have sent the changes in the log, as the server does not stop while they are open.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.changed.notify_waiters();
+    }
+}
+
+/// Serves the changes in a log, first those after the cursor of the subscriber and then new ones as they are added.
+pub struct SubscriptionService<L: ChangeLog> {
+    log: Arc<L>,
+    notifier: ChangeNotifier,
+}
+
+impl<L: ChangeLog> SubscriptionService<L> {
+    pub fn new(log: L, notifier: ChangeNotifier) -> SubscriptionService<L> {
+        SubscriptionService {
+            log: Arc::new(log),
+            notifier,
+        }
+    }
+}
+
+struct SubscriptionState<L: ChangeLog> {
+    log: Arc<L>,
+    notifier: ChangeNotifier,
+    filter: SubscriptionFilter,
+    cursor: u64,
+    pending: VecDeque<StateChange>,
+}
+
+type SubscribeStream = Pin<Box<dyn Stream<Item = Result<ProtoStateChange, Status>> + Send + 'static>>;
+
+#[tonic::async_trait]
+impl<L: ChangeLog> Subscription for SubscriptionService<L> {
+    type SubscribeStream = SubscribeStream;
+
+    async fn subscribe(&self, request: Request<SubscribeRequest>) -> Result<Response<Self::SubscribeStream>, Status> {
+        let request = request.into_inner();
+        let initial = SubscriptionState {
+            log: self.log.clone(),
+            notifier: self.notifier.clone(),
+            filter: SubscriptionFilter {
+                edge_types: request.edge_types.into_iter().collect(),
+                node_types: request.node_types.into_iter().collect(),
+                instance_node_ids: request.instance_node_ids.into_iter().collect(),
+            },
+            cursor: request.cursor,
+            pending: VecDeque::new(),
+        };
+        let stream = futures_util::stream::unfold(Some(initial), |state| async move {
+            let mut state = state?;
+            loop {
+                if let Some(change) = state.pending.pop_front() {
+                    return Some((Ok(to_proto_state_change(&change)), Some(state)));
+                }
+                let changed = state.notifier.changed.clone();
+                //Created before reading, so that a change between the read and the wait is not missed
+                let notified = changed.notified();
+                let closed = state.notifier.closed.load(Ordering::SeqCst);
+                let log = state.log.clone();
+                let cursor = state.cursor;
+                let read = tokio::task::spawn_blocking(move || log.get_changes_after(cursor, CHANGES_PER_READ)).await;
+                let changes = match read.map_err(|e| e.to_string()).and_then(|changes| changes) {
+                    Ok(changes) => changes,
+                    Err(e) => return Some((Err(Status::internal(e)), None)),
+                };
+                if let Some(first) = changes.first().filter(|first| first.cursor > cursor + 1) {
+                    let message = format!("Changes after cursor {} were pruned, the oldest kept has cursor {}", cursor, first.cursor);
+                    return Some((Err(Status::out_of_range(message)), None));
+                }
+                match changes.last() {
+                    Some(last) => state.cursor = last.cursor,
+                    None if closed => return None,
+                    None => notified.await,
+                }
+                let filter = &state.filter;
+                state.pending.extend(changes.into_iter().filter(|c| filter.matches(&c.delta)));
+            }
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+fn to_proto_state_change(change: &StateChange) -> ProtoStateChange {
+    let change_type = match change.change_type {
+        ChangeType::Addition => crate::subscription::ChangeType::Addition,
+        ChangeType::Removal => crate::subscription::ChangeType::Removal,
+        ChangeType::Retraction => crate::subscription::ChangeType::Retraction,
+    };
+    ProtoStateChange {
+        cursor: change.cursor,
+        change_type: change_type as i32,
+        deltas_id: change.deltas_id.clone(),
+        delta: Some(to_proto_delta(&change.delta)),
+    }
+}
+
+pub fn from_proto_state_change(change: ProtoStateChange) -> Result<StateChange, Status> {
+    let change_type = match crate::subscription::ChangeType::from_i32(change.change_type) {
+        Some(crate::subscription::ChangeType::Addition) => ChangeType::Addition,
+        Some(crate::subscription::ChangeType::Removal) => ChangeType::Removal,
+        Some(crate::subscription::ChangeType::Retraction) => ChangeType::Retraction,
+        None => return Err(Status::invalid_argument(format!("Unknown change type {}", change.change_type))),
+    };
+    let delta = change.delta.ok_or_else(|| Status::invalid_argument("Change without a delta"))?;
+    Ok(StateChange {
+        cursor: change.cursor,
+        change_type,
+        deltas_id: change.deltas_id,
+        delta: from_proto_delta(delta).map_err(Status::invalid_argument)?,
+    })
+}
+
+#[test]
+fn test_subscribers_resume_after_cursor_and_get_new_changes() {
+    use futures_util::StreamExt;
+    use mbei_core::graph::{DeltaType, Node};
+    use std::sync::Mutex;
+
+    struct VecChangeLog(Mutex<Vec<StateChange>>);
+
+    impl ChangeLog for Arc<VecChangeLog> {
+        fn get_changes_after(&self, cursor: u64, limit: usize) -> Result<Vec<StateChange>, String> {
+            let changes = self.0.lock().unwrap();
+            Ok(changes.iter().filter(|c| c.cursor > cursor).take(limit).cloned().collect())
+        }
+    }
+
+    let change = |cursor, trg: Node, change_type| StateChange {
+        cursor,
+        change_type,
+        deltas_id: format!("d{}", cursor),
+        delta: Delta {
+            src: Node::material_instance_node("MyBarrel0", "Barrel"),
+            trg,
+            edge_type: "At".to_string(),
+            timestamp: cursor,
+            delta_type: DeltaType::Addition,
+        },
+    };
+    let crane = Node::object_instance_node("MyCrane0", "Crane");
+    let platform = Node::object_instance_node("MyPlatform0", "Platform");
+    let log = Arc::new(VecChangeLog(Mutex::new(vec![
+        change(1, platform.clone(), ChangeType::Addition),
+        change(2, crane.clone(), ChangeType::Addition),
+        change(3, crane.clone(), ChangeType::Addition),
+    ])));
+    let notifier = ChangeNotifier::default();
+    let service = SubscriptionService::new(log.clone(), notifier.clone());
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.block_on(async {
+        let request = SubscribeRequest {
+            edge_types: vec!["At".to_string()],
+            node_types: vec!["Crane".to_string()],
+            instance_node_ids: vec![],
+            cursor: 2,
+        };
+        let mut stream = service.subscribe(Request::new(request)).await.unwrap().into_inner();
+        assert_eq!(from_proto_state_change(stream.next().await.unwrap().unwrap()).unwrap(), change(3, crane.clone(), ChangeType::Addition));
+        //Changes to other nodes are skipped
+        log.0.lock().unwrap().push(change(4, platform.clone(), ChangeType::Retraction));
+        log.0.lock().unwrap().push(change(5, crane.clone(), ChangeType::Retraction));
+        notifier.notify();
+        assert_eq!(from_proto_state_change(stream.next().await.unwrap().unwrap()).unwrap(), change(5, crane.clone(), ChangeType::Retraction));
+        //Subscribers behind the changes the log kept are told so, instead of missing them
+        log.0.lock().unwrap().remove(0);
+        let request = SubscribeRequest { edge_types: vec![], node_types: vec![], instance_node_ids: vec![], cursor: 0 };
+        let mut behind = service.subscribe(Request::new(request)).await.unwrap().into_inner();
+        assert_eq!(behind.next().await.unwrap().unwrap_err().code(), tonic::Code::OutOfRange);
+        assert!(behind.next().await.is_none());
+        notifier.close();
+        assert!(stream.next().await.is_none());
+    });
+}
+
+#[test]
+fn test_changes_of_unknown_type_are_invalid() {
+    let change = ProtoStateChange { cursor: 1, change_type: 99, deltas_id: "d1".to_string(), delta: None };
+    assert_eq!(from_proto_state_change(change).unwrap_err().code(), tonic::Code::InvalidArgument);
+}
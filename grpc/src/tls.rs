@@ -0,0 +1,178 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use tonic::transport::{Endpoint, Server, Uri};
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+/// Certificates for mutual TLS on gRPC links. Links are plaintext http when there are none.
+/// Servers require clients to present a certificate issued by the same ca as their own,
+/// and clients require the certificate of a server to be valid for the host in its url.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TlsOptions {
+    pub ca_certificate_path: PathBuf,
+    pub certificate_path: PathBuf,
+    pub key_path: PathBuf,
+    //Hosts that clients must present a certificate for. Any client with a certificate from the ca is accepted if empty
+    pub allowed_client_hosts: BTreeSet<String>,
+}
+
+impl TlsOptions {
+    /// Also allows the clients with certificates for the hosts of the urls, typically those of the peers in a query url map
+    pub fn allowing_hosts_of<'a>(&self, urls: impl Iterator<Item = &'a String>) -> TlsOptions {
+        let mut tls = self.clone();
+        tls.allowed_client_hosts.extend(urls.filter_map(|url| get_host(url)));
+        tls
+    }
+}
+
+pub fn get_host(url: &str) -> Option<String> {
+    Uri::from_str(url).ok().and_then(|uri| uri.host().map(|host| host.trim_matches(|c| c == '[' || c == ']').to_string()))
+}
+
+/// An endpoint for the url, using TLS if configured
+pub fn create_endpoint(url: &str, tls: Option<&TlsOptions>) -> Result<Endpoint, String> {
+    let endpoint = Endpoint::from_str(url).map_err(|e| format!("Invalid url {}: {}", url, e))?;
+    match tls {
+        Some(tls) => configure_client_tls(endpoint, url, tls),
+        None => Ok(endpoint),
+    }
+}
+
+pub fn configure_server(server: Server, tls: Option<&TlsOptions>) -> Result<Server, String> {
+    match tls {
+        Some(tls) => configure_server_tls(server, tls),
+        None => Ok(server),
+    }
+}
+
+/// Intercepts requests to reject clients whose certificate is not for one of the allowed hosts.
+#[derive(Clone, Default)]
+pub struct PeerIdentityCheck {
+    allowed_client_hosts: Arc<BTreeSet<String>>,
+}
+
+impl PeerIdentityCheck {
+    pub fn new(tls: Option<&TlsOptions>) -> PeerIdentityCheck {
+        PeerIdentityCheck {
+            allowed_client_hosts: Arc::new(tls.map(|tls| tls.allowed_client_hosts.clone()).unwrap_or_default()),
+        }
+    }
+
+    /// Only the clients with a certificate for one of the hosts, which unlike other checks excludes plaintext connections.
+    pub fn for_hosts(hosts: BTreeSet<String>) -> PeerIdentityCheck {
+        PeerIdentityCheck {
+            allowed_client_hosts: Arc::new(hosts),
+        }
+    }
+
+    /// Rejects requests from clients without a certificate for one of the allowed hosts, or without any certificate.
+    pub fn require_allowed<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        match request.peer_certs() {
+            Some(certificates) if self.is_allowed(certificates.first().map(|c| c.get_ref())) => Ok(()),
+            _ => Err(Status::permission_denied("Client certificate is not for a host allowed to send this")),
+        }
+    }
+
+    pub fn check(&self, request: Request<()>) -> Result<Request<()>, Status> {
+        if self.allowed_client_hosts.is_empty() {
+            return Ok(request);
+        }
+        match request.peer_certs() {
+            Some(certificates) if self.is_allowed(certificates.first().map(|c| c.get_ref())) => Ok(request),
+            Some(_) => Err(Status::permission_denied("Client certificate is not for an allowed host")),
+            //Plaintext connections are not checked, a server with TLS configured does not accept them
+            None => Ok(request),
+        }
+    }
+
+}
+
+impl Interceptor for PeerIdentityCheck {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        self.check(request)
+    }
+}
+
+impl PeerIdentityCheck {
+    #[cfg(feature = "tls")]
+    fn is_allowed(&self, certificate_der: Option<&[u8]>) -> bool {
+        let certificate = match certificate_der.and_then(|der| webpki::EndEntityCert::from(der).ok()) {
+            Some(certificate) => certificate,
+            None => return false,
+        };
+        self.allowed_client_hosts.iter().any(|host| match webpki::DNSNameRef::try_from_ascii_str(host) {
+            Ok(dns_name) => certificate.verify_is_valid_for_dns_name(dns_name).is_ok(),
+            Err(_) => false,
+        })
+    }
+
+    #[cfg(not(feature = "tls"))]
+    fn is_allowed(&self, _certificate_der: Option<&[u8]>) -> bool {
+        false
+    }
+}
+
+#[cfg(feature = "tls")]
+fn read_certificates(tls: &TlsOptions) -> Result<(tonic::transport::Certificate, tonic::transport::Identity), String> {
+    use tonic::transport::{Certificate, Identity};
+    let read = |path: &PathBuf| std::fs::read(path).map_err(|e| format!("Could not read {:?}: {}", path, e));
+    let ca_certificate = Certificate::from_pem(read(&tls.ca_certificate_path)?);
+    let identity = Identity::from_pem(read(&tls.certificate_path)?, read(&tls.key_path)?);
+    Ok((ca_certificate, identity))
+}
+
+#[cfg(feature = "tls")]
+fn configure_client_tls(endpoint: Endpoint, url: &str, tls: &TlsOptions) -> Result<Endpoint, String> {
+    use tonic::transport::ClientTlsConfig;
+    let host = get_host(url).ok_or(format!("No host in url {}", url))?;
+    let (ca_certificate, identity) = read_certificates(tls)?;
+    let tls_config = ClientTlsConfig::new()
+        .domain_name(host)
+        .ca_certificate(ca_certificate)
+        .identity(identity);
+    endpoint
+        .tls_config(tls_config)
+        .map_err(|e| format!("Invalid TLS config for {}: {}", url, e))
+}
+
+#[cfg(feature = "tls")]
+fn configure_server_tls(server: Server, tls: &TlsOptions) -> Result<Server, String> {
+    use tonic::transport::ServerTlsConfig;
+    let (ca_certificate, identity) = read_certificates(tls)?;
+    let tls_config = ServerTlsConfig::new()
+        .identity(identity)
+        .client_ca_root(ca_certificate);
+    server
+        .tls_config(tls_config)
+        .map_err(|e| format!("Invalid TLS config: {}", e))
+}
+
+#[cfg(not(feature = "tls"))]
+fn configure_client_tls(_endpoint: Endpoint, _url: &str, _tls: &TlsOptions) -> Result<Endpoint, String> {
+    Err("TLS is configured, but mbei-grpc was built without the tls feature".to_string())
+}
+
+#[cfg(not(feature = "tls"))]
+fn configure_server_tls(_server: Server, _tls: &TlsOptions) -> Result<Server, String> {
+    Err("TLS is configured, but mbei-grpc was built without the tls feature".to_string())
+}
+
+#[test]
+fn test_get_host_of_url() {
+    assert_eq!(get_host("https://mbei-component-0.mbei-component:10000"), Some("mbei-component-0.mbei-component".to_string()));
+    assert_eq!(get_host("http://[::1]:10000"), Some("::1".to_string()));
+    let tls = TlsOptions {
+        ca_certificate_path: PathBuf::from("ca.pem"),
+        certificate_path: PathBuf::from("component.pem"),
+        key_path: PathBuf::from("component.key"),
+        allowed_client_hosts: BTreeSet::from(["producer".to_string()]),
+    };
+    let urls = ["https://central:10000".to_string(), "https://mbei-component-1:10000".to_string()];
+    assert_eq!(
+        tls.allowing_hosts_of(urls.iter()).allowed_client_hosts,
+        BTreeSet::from(["central".to_string(), "mbei-component-1".to_string(), "producer".to_string()])
+    );
+}
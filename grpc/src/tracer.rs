@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Client, Method, Request};
+use log::{debug, warn};
+use mbei_core::trace::{Span, TraceContext};
+use serde_json::{json, Value};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+
+const MAX_SPANS_PER_EXPORT: usize = 512;
+
+/// Where finished spans are exported, encoded as OTLP JSON.
+#[derive(Clone, Debug)]
+pub enum TraceExport {
+    /// Appends one export request per line, as read by the file receivers of OpenTelemetry collectors
+    File(PathBuf),
+    /// Posts to the OTLP/HTTP endpoint of a collector, for instance http://localhost:4318
+    Collector(String),
+}
+
+/// Creates spans for one component or central.
+/// Without an exporter spans are still created, so that trace context is propagated, but they are dropped when finished.
+#[derive(Clone)]
+pub struct Tracer {
+    service: String,
+    span_sender: Option<UnboundedSender<Span>>,
+}
+
+impl Tracer {
+    pub fn new(service: &str, span_sender: Option<UnboundedSender<Span>>) -> Tracer {
+        Tracer {
+            service: service.to_string(),
+            span_sender,
+        }
+    }
+
+    pub fn start_span(&self, name: &str, context: &TraceContext) -> Span {
+        Span::start(name, &self.service, context)
+    }
+
+    pub fn start_span_at(&self, name: &str, context: &TraceContext, start: SystemTime) -> Span {
+        Span::start_at(name, &self.service, context, start)
+    }
+
+    pub fn finish_span(&self, span: Span) {
+        if let Some(span_sender) = &self.span_sender {
+            //The exporter only stops when all tracers are dropped
+            let _ = span_sender.send(span.finish());
+        }
+    }
+}
+
+/// Exports spans until all senders are dropped. Spans are batched as they arrive.
+pub fn spawn_span_exporter(trace_export: TraceExport) -> (UnboundedSender<Span>, JoinHandle<()>) {
+    let (span_sender, span_receiver) = tokio::sync::mpsc::unbounded_channel();
+    let handle = tokio::spawn(export_spans(span_receiver, trace_export));
+    (span_sender, handle)
+}
+
+async fn export_spans(mut span_receiver: UnboundedReceiver<Span>, trace_export: TraceExport) {
+    let client = Client::new();
+    while let Some(span) = span_receiver.recv().await {
+        let mut spans = vec![span];
+        while spans.len() < MAX_SPANS_PER_EXPORT {
+            match span_receiver.try_recv() {
+                Ok(span) => spans.push(span),
+                Err(_) => break,
+            }
+        }
+        debug!("Exporting {} spans", spans.len());
+        let body = to_otlp_json(&spans).to_string();
+        match &trace_export {
+            TraceExport::File(path) => {
+                let written = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .and_then(|mut file| file.write_all((body + "\n").as_bytes()));
+                if let Err(e) = written {
+                    warn!("Could not write spans to {:?}: {}", path, e);
+                }
+            }
+            TraceExport::Collector(url) => {
+                let request = Request::builder()
+                    .method(Method::POST)
+                    .uri(url.trim_end_matches('/').to_string() + "/v1/traces")
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(body))
+                    .expect("Could not create export request");
+                match client.request(request).await {
+                    Ok(response) if response.status().is_success() => {}
+                    Ok(response) => {
+                        warn!("Collector at {} rejected spans with status {}", url, response.status());
+                    }
+                    Err(e) => {
+                        warn!("Could not export spans to collector at {}: {}", url, e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Encodes spans as an OTLP ExportTraceServiceRequest, with one resource per service.
+pub fn to_otlp_json(spans: &Vec<Span>) -> Value {
+    let mut spans_by_service: BTreeMap<&str, Vec<&Span>> = BTreeMap::new();
+    for span in spans {
+        spans_by_service.entry(&span.service).or_default().push(span);
+    }
+    let resource_spans: Vec<Value> = spans_by_service
+        .into_iter()
+        .map(|(service, spans)| {
+            json!({
+                "resource": {
+                    "attributes": [to_otlp_attribute("service.name", service)]
+                },
+                "scopeSpans": [{
+                    "scope": {"name": "mbei"},
+                    "spans": spans.into_iter().map(to_otlp_span).collect::<Vec<Value>>()
+                }]
+            })
+        })
+        .collect();
+    json!({ "resourceSpans": resource_spans })
+}
+
+fn to_otlp_span(span: &Span) -> Value {
+    json!({
+        "traceId": span.trace_id,
+        "spanId": span.span_id,
+        "parentSpanId": span.parent_span_id.clone().unwrap_or_default(),
+        "name": span.name,
+        //Internal
+        "kind": 1,
+        "startTimeUnixNano": span.start_unix_nanos.to_string(),
+        "endTimeUnixNano": span.end_unix_nanos.to_string(),
+        "attributes": span
+            .attributes
+            .iter()
+            .map(|(k, v)| to_otlp_attribute(k, v))
+            .collect::<Vec<Value>>()
+    })
+}
+
+fn to_otlp_attribute(key: &str, value: &str) -> Value {
+    json!({"key": key, "value": {"stringValue": value}})
+}
+
+#[test]
+fn test_to_otlp_json_groups_spans_by_service() {
+    let context = TraceContext::for_origin("myevent");
+    let queueing = Span::start("queue", "q1", &context).with_attribute("update_type", "event");
+    let processing = Span::start("process_update", "q1", &TraceContext::child_of(&queueing));
+    let storing = Span::start("store", "central", &context);
+    let otlp = to_otlp_json(&vec![queueing.clone(), processing.clone(), storing]);
+    let resource_spans = otlp["resourceSpans"].as_array().unwrap();
+    assert_eq!(resource_spans.len(), 2);
+    let q1 = resource_spans
+        .iter()
+        .find(|r| r["resource"]["attributes"][0]["value"]["stringValue"] == "q1")
+        .unwrap();
+    let q1_spans = q1["scopeSpans"][0]["spans"].as_array().unwrap();
+    assert_eq!(q1_spans.len(), 2);
+    assert_eq!(q1_spans[0]["traceId"], context.trace_id.as_str());
+    assert_eq!(q1_spans[0]["attributes"][0]["key"], "update_type");
+    assert_eq!(q1_spans[1]["parentSpanId"], queueing.span_id.as_str());
+}
@@ -0,0 +1,27 @@
+#!/bin/bash
+# Generates the certificates used by the TLS tests: a ca, a certificate for localhost where all
+# tests run, one for a host that is not a peer, and one from a ca that is not trusted.
+set -e
+cd "$(dirname "$0")"
+DAYS=36500
+
+ca() {
+    openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out "$1.key"
+    openssl req -x509 -new -key "$1.key" -subj "/CN=$1" -days $DAYS -out "$1.pem" \
+        -addext "basicConstraints=critical,CA:TRUE" -addext "keyUsage=critical,keyCertSign,cRLSign"
+}
+
+certificate() {
+    openssl genpkey -algorithm EC -pkeyopt ec_paramgen_curve:P-256 -out "$1.key"
+    openssl req -new -key "$1.key" -subj "/CN=$2" -out "$1.csr"
+    printf "subjectAltName=DNS:%s\nbasicConstraints=CA:FALSE\nkeyUsage=digitalSignature\nextendedKeyUsage=serverAuth,clientAuth\n" "$2" > "$1.ext"
+    openssl x509 -req -in "$1.csr" -CA "$3.pem" -CAkey "$3.key" -CAcreateserial -days $DAYS -extfile "$1.ext" -out "$1.pem"
+    rm "$1.csr" "$1.ext"
+}
+
+ca ca
+ca untrusted-ca
+certificate localhost localhost ca
+certificate intruder intruder ca
+certificate untrusted localhost untrusted-ca
+rm -f ./*.srl
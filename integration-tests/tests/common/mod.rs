@@ -0,0 +1,337 @@
+use std::collections::BTreeMap;
+use std::fs::{create_dir, metadata, read_to_string, remove_file};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::{PathBuf};
+use std::thread;
+use std::thread::{sleep, JoinHandle};
+use std::time::Duration;
+use tokio::runtime::{Builder, Runtime};
+
+use mbei_central::options::CentralOptions;
+use mbei_central::{start_central, Central, Derivation, ManualAssertionRecord};
+use mbei_component::options::ComponentOptions;
+use mbei_component::start_component_servers;
+use mbei_core::graph::Delta;
+use mbei_core::query::Query;
+use mbei_grpc::tracer::TraceExport;
+use mbei_scenario_server::create_tonic_server;
+use mbei_testdata::factory_scenario_builder::{
+    crane_pickdrops, cranes, matched_pickdrop_query, matched_stamp_query, platforms,
+    ramps, stamp_assemblies, stamps, SimpleFactoryScenario,
+};
+use mbei_testdata::producer::TestdataProducer;
+
+pub async fn create_testdata_producer(query_url_map:BTreeMap<String, String>) -> TestdataProducer {
+    TestdataProducer::new(query_url_map, Some(Duration::from_secs(10)), true).await.expect("Could not create producer")
+}
+
+pub fn app_port() -> u16 {
+    9999
+}
+
+pub fn create_app_grpc_url(port:u16) -> String {
+    "http://[::1]:".to_string() + &port.to_string()
+}
+
+pub fn central_port() -> u16 {
+    10000
+}
+
+pub fn metrics_port() -> u16 {
+    9100
+}
+
+/// Spans of all OTLP JSON lines in a trace file, as (trace id, span id, parent span id, name, service).
+pub fn read_spans(trace_path: &PathBuf) -> Vec<(String, String, String, String, String)> {
+    let content = read_to_string(trace_path).expect("Could not read trace file");
+    let mut spans = vec![];
+    for line in content.lines() {
+        let export: serde_json::Value = serde_json::from_str(line).expect("Could not parse trace line");
+        for resource_spans in export["resourceSpans"].as_array().unwrap() {
+            let service = resource_spans["resource"]["attributes"][0]["value"]["stringValue"].as_str().unwrap();
+            for span in resource_spans["scopeSpans"][0]["spans"].as_array().unwrap() {
+                spans.push((
+                    span["traceId"].as_str().unwrap().to_string(),
+                    span["spanId"].as_str().unwrap().to_string(),
+                    span["parentSpanId"].as_str().unwrap().to_string(),
+                    span["name"].as_str().unwrap().to_string(),
+                    service.to_string(),
+                ));
+            }
+        }
+    }
+    spans
+}
+
+pub fn get_metrics(port: u16) -> String {
+    let mut stream = TcpStream::connect(("localhost", port)).expect("Could not connect to metrics server");
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .expect("Could not send metrics request");
+    let mut response = String::new();
+    stream.read_to_string(&mut response).expect("Could not read metrics response");
+    response
+}
+
+pub fn create_application_grpc_server(port:u16) -> JoinHandle<()> {
+    create_tonic_server(port)
+}
+
+pub fn create_query_url_map(qnames:&Vec<String>) -> BTreeMap<String, String> {
+    let mut query_url_map = BTreeMap::new();
+    for (i, qname) in (1u16..).zip(qnames) {
+        let port = 10000 + i;
+        query_url_map.insert(qname.clone(), "http://[::1]:".to_owned() + &port.to_string());
+    }
+    query_url_map.insert("central".to_string(), "http://[::1]:".to_owned() + &central_port().to_string());
+    query_url_map
+}
+
+/// Urls for servers with TLS, whose certificates are for localhost
+pub fn create_tls_query_url_map(qnames: &Vec<String>) -> BTreeMap<String, String> {
+    create_query_url_map(qnames)
+        .into_iter()
+        .map(|(qname, url)| (qname, url.replace("http://[::1]", "https://localhost")))
+        .collect()
+}
+
+/// All queries served by one server, on the port of the first query
+pub fn create_multiplexed_query_url_map(qnames: &Vec<String>) -> BTreeMap<String, String> {
+    let mut query_url_map = BTreeMap::new();
+    for qname in qnames {
+        query_url_map.insert(qname.clone(), "http://[::1]:".to_owned() + &multiplexed_port().to_string());
+    }
+    query_url_map.insert("central".to_string(), "http://[::1]:".to_owned() + &central_port().to_string());
+    query_url_map
+}
+
+pub fn multiplexed_port() -> u16 {
+    10001
+}
+
+pub fn create_query_port_map(qnames: &Vec<String>) -> BTreeMap<String, u16> {
+    let mut query_port_map = BTreeMap::new();
+    for (i, qname) in (1u16..).zip(qnames) {
+        query_port_map.insert(qname.clone(), 10000 + i);
+    }
+    query_port_map
+}
+
+pub fn create_central(central_db_path: PathBuf, grpc_port: u16) -> JoinHandle<()> {
+    create_central_with_trace_export(central_db_path, grpc_port, None)
+}
+
+pub fn create_central_with_trace_export(central_db_path: PathBuf, grpc_port: u16, trace_export: Option<TraceExport>) -> JoinHandle<()> {
+    let options = CentralOptions {
+        trace_export,
+        ..CentralOptions::default()
+    };
+    create_central_with_options(central_db_path, grpc_port, options)
+}
+
+pub fn create_central_with_options(central_db_path: PathBuf, grpc_port: u16, options: CentralOptions) -> JoinHandle<()> {
+    let parent_dir = central_db_path.parent();
+    if metadata(parent_dir.unwrap()).is_err() {
+        create_dir(parent_dir.unwrap()).expect("Create parent dir failed");
+    }
+    if metadata(central_db_path.as_path()).is_ok() {
+        remove_file(central_db_path.as_path()).expect("Removal failed");
+    }
+    let handle = thread::spawn(move || {
+        start_central(central_db_path, grpc_port, options).expect("Could not start central");
+    });
+    sleep(Duration::from_secs(3));
+    handle
+}
+
+pub fn create_components(
+    app_grpc_url: String,
+    queries: &[Query],
+) -> JoinHandle<()> {
+    create_components_with_options(app_grpc_url, queries, ComponentOptions::default())
+}
+
+pub fn create_components_with_options(
+    app_grpc_url: String,
+    queries: &[Query],
+    options: ComponentOptions,
+) -> JoinHandle<()> {
+    let my_query_names: Vec<String> = queries
+        .iter()
+        .map(|q| q.name.clone())
+        .collect();
+    let query_url_map = create_query_url_map(&my_query_names);
+    create_components_with_url_map(app_grpc_url, queries, query_url_map, options)
+}
+
+pub fn create_components_with_url_map(
+    app_grpc_url: String,
+    queries: &[Query],
+    query_url_map: BTreeMap<String, String>,
+    options: ComponentOptions,
+) -> JoinHandle<()> {
+    let my_query_names: Vec<String> = queries
+        .iter()
+        .map(|q| q.name.clone())
+        .collect();
+    let queries_cloned = queries.to_vec();
+    let handle = thread::spawn(move || {
+        run_components(queries_cloned, my_query_names, app_grpc_url, query_url_map, options);
+    });
+
+    sleep(Duration::from_secs(3));
+    handle
+}
+
+/// Hosts all queries in one process behind a single gRPC port
+pub fn create_multiplexed_components(
+    app_grpc_url: String,
+    queries: &[Query],
+) -> JoinHandle<()> {
+    let my_query_names: Vec<String> = queries
+        .iter()
+        .map(|q| q.name.clone())
+        .collect();
+    let queries_cloned = queries.to_vec();
+    let handle = thread::spawn(move || {
+        let rt = create_runtime(queries_cloned.len());
+        let query_url_map = create_multiplexed_query_url_map(&my_query_names);
+        rt.block_on(start_component_servers(queries_cloned, my_query_names, app_grpc_url, multiplexed_port(), query_url_map, Some(Duration::from_secs(15)), true, ComponentOptions::default()))
+            .expect("Could not start components");
+    });
+
+    sleep(Duration::from_secs(3));
+    handle
+}
+
+pub fn get_all_deltas(central_db_path: PathBuf) -> Vec<Delta> {
+    let testing_central = Central::new(central_db_path);
+    testing_central.get_all_deltas()
+}
+
+pub fn get_finality(central_db_path: PathBuf) -> BTreeMap<String, u64> {
+    let testing_central = Central::new(central_db_path);
+    testing_central.get_finality()
+}
+
+pub fn explain_edge(central_db_path: PathBuf, src_name: &str, trg_name: &str, edge_type: &str, timestamp: u64) -> Vec<Derivation> {
+    let testing_central = Central::new(central_db_path);
+    testing_central.explain_edge(src_name, trg_name, edge_type, timestamp)
+}
+
+pub fn get_manual_assertions(central_db_path: PathBuf) -> Vec<ManualAssertionRecord> {
+    let testing_central = Central::new(central_db_path);
+    testing_central.get_manual_assertions()
+}
+
+/// Components on a runtime of their own, which crash when it is shut down in the background
+pub fn create_crashable_components(app_grpc_url: String, queries: &[Query], options: ComponentOptions) -> Runtime {
+    let my_query_names: Vec<String> = queries.iter().map(|q| q.name.clone()).collect();
+    let query_url_map = create_query_url_map(&my_query_names);
+    let query_port_map = create_query_port_map(&my_query_names);
+    let rt = create_runtime(queries.len());
+    for query_name in my_query_names {
+        rt.spawn(start_component_servers(queries.to_vec(), vec![query_name.clone()], app_grpc_url.clone(), query_port_map[&query_name], query_url_map.clone(), Some(Duration::from_secs(15)), true, options.clone()));
+    }
+    sleep(Duration::from_secs(3));
+    rt
+}
+
+fn run_components(queries: Vec<Query>, my_query_names:Vec<String>, application_grpc_url:String, query_url_map: BTreeMap<String, String>, options: ComponentOptions) {
+    let rt = create_runtime(queries.len());
+    let query_port_map = create_query_port_map(&my_query_names);
+    rt.block_on(async {
+        let mut handles = vec![];
+        let mut options = options;
+        for query_name in my_query_names {
+            let handle = rt.spawn(start_component_servers(queries.to_vec(), vec![query_name.clone()], application_grpc_url.clone(), *query_port_map.get(&query_name).unwrap(), query_url_map.clone(), Some(Duration::from_secs(15)), true, options.clone()));
+            //All components share the process registry, so only the first one serves metrics
+            options.metrics_port = None;
+            handles.push(handle);
+        }
+        for handle in handles {
+            handle.await.expect("Problem in component").expect("Could not start component");
+        }
+    });
+}
+
+fn create_runtime(num_components: usize) -> tokio::runtime::Runtime {
+    let threads = num_components + 1; //One additional thread for following up sent messages
+    let rt = Builder::new_multi_thread()
+        .worker_threads(threads)
+        .enable_io()
+        .enable_time()
+        .build()
+        .expect("Could not create runtime");
+    rt
+}
+
+pub fn three_crane_scenario() -> SimpleFactoryScenario {
+    let two_cranes = cranes(2);
+    let two_pickdrops = crane_pickdrops(2);
+    let three_platforms = platforms(3);
+    let three_stamp_assemblies = stamp_assemblies(3);
+    let three_stamps = stamps(3);
+    let my_ramp = ramps(1).pop().unwrap();
+
+    let matched_pickdrop_query_1 = matched_pickdrop_query(
+        two_cranes.first().unwrap().clone(),
+        two_pickdrops.first().unwrap().clone(),
+        vec![
+            three_platforms.first().unwrap().clone(),
+            three_platforms.get(1).unwrap().clone(),
+            three_stamp_assemblies.first().unwrap().clone(),
+            three_stamp_assemblies.get(1).unwrap().clone(),
+            my_ramp.clone(),
+        ],
+        "_matched_1",
+    );
+
+    let matched_pickdrop_query_2 = matched_pickdrop_query(
+        two_cranes.get(1).unwrap().clone(),
+        two_pickdrops.get(1).unwrap().clone(),
+        vec![
+            three_platforms.get(1).unwrap().clone(),
+            three_platforms.get(2).unwrap().clone(),
+            three_stamp_assemblies.get(1).unwrap().clone(),
+            three_stamp_assemblies.get(2).unwrap().clone(),
+            my_ramp.clone(),
+        ],
+        "_matched_2",
+    );
+
+    let matched_stamp_query_1 = matched_stamp_query(
+        three_stamp_assemblies.first().unwrap().clone(),
+        three_stamps.first().unwrap().clone(),
+        "_matched_1",
+    );
+
+    let matched_stamp_query_2 = matched_stamp_query(
+        three_stamp_assemblies.get(1).unwrap().clone(),
+        three_stamps.get(1).unwrap().clone(),
+        "_matched_2",
+    );
+
+    let matched_stamp_query_3 = matched_stamp_query(
+        three_stamp_assemblies.get(2).unwrap().clone(),
+        three_stamps.get(2).unwrap().clone(),
+        "_matched_3",
+    );
+
+    SimpleFactoryScenario {
+        cranes: two_cranes,
+        crane_pickdrops: two_pickdrops,
+        platforms: three_platforms,
+        ramps: vec![my_ramp],
+        stamps: three_stamps,
+        stamp_assemblies: three_stamp_assemblies,
+        queries: vec![
+            matched_pickdrop_query_1,
+            matched_pickdrop_query_2,
+            matched_stamp_query_1,
+            matched_stamp_query_2,
+            matched_stamp_query_3,
+        ],
+    }
+}
@@ -1,3 +1,6 @@
+//Tests take their rstest fixtures as arguments
+#![allow(clippy::too_many_arguments)]
+
 use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::thread::{sleep, JoinHandle};
@@ -14,6 +17,7 @@ use mbei_testdata::message_creator::MessageCreator;
 use crate::common::{app_port, central_port, create_app_grpc_url, create_application_grpc_server, create_central, create_components, create_query_url_map, create_testdata_producer, get_all_deltas};
 
 #[cfg(test)]
+#[allow(dead_code)]
 mod common;
 
 #[fixture]
@@ -64,11 +68,11 @@ fn query_url_map(factory_scenario: ComplexFactoryScenario) -> BTreeMap<String, S
 
 #[fixture]
 fn components(
-    app_grpc_url: &String,
+    app_grpc_url: &str,
     factory_scenario: ComplexFactoryScenario,
 ) -> JoinHandle<()> {
     let all_queries = factory_scenario.all_queries();
-    create_components(app_grpc_url.clone(), &all_queries)
+    create_components(app_grpc_url.to_string(), &all_queries)
 }
 
 #[rstest]
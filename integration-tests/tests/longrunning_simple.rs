@@ -1,3 +1,6 @@
+//Tests take their rstest fixtures as arguments
+#![allow(clippy::too_many_arguments)]
+
 use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::thread::{sleep, JoinHandle};
@@ -14,6 +17,7 @@ use mbei_testdata::simple_factory_scenario::SimpleFactoryScenarioSimulator;
 use crate::common::{app_port, central_port, create_app_grpc_url, create_application_grpc_server, create_central, create_components, create_query_url_map, create_testdata_producer, get_all_deltas, three_crane_scenario};
 
 #[cfg(test)]
+#[allow(dead_code)]
 mod common;
 
 #[fixture]
@@ -66,10 +70,10 @@ pub fn factory_scenario() -> SimpleFactoryScenario {
 
 #[fixture]
 fn components(
-    app_grpc_url: &String,
+    app_grpc_url: &str,
     factory_scenario: SimpleFactoryScenario,
 ) -> JoinHandle<()> {
-    create_components(app_grpc_url.clone(), &factory_scenario.queries)
+    create_components(app_grpc_url.to_string(), &factory_scenario.queries)
 }
 
 #[rstest]
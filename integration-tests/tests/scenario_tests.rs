@@ -1,3 +1,6 @@
+//Tests take their rstest fixtures as arguments
+#![allow(clippy::too_many_arguments)]
+
 use std::collections::{BTreeMap, BTreeSet};
 use mbei_component::router::Router;
 use crate::common::three_crane_scenario;
@@ -5,6 +8,7 @@ use mbei_core::graph::{Edge, Graph, Node, NodeClass};
 use mbei_testdata::factory_scenario_builder::complex_factory_scenario_builder;
 
 #[cfg(test)]
+#[allow(dead_code)]
 mod common;
 
 #[test]
@@ -114,7 +118,7 @@ fn test_conveyor_matching() {
         Edge { src: Node { query_node_name: None, instance_node_name: Some("MyBarrel-4d23214e-86d8-4c69-9b7e-65925c3190e3".to_string()), node_type: Some("Barrel".to_string()), node_class: NodeClass::Material, value_bytes: None }, trg: Node { query_node_name: None, instance_node_name: Some("MyBarrel-4d23214e-86d8-4c69-9b7e-65925c3190e3_barrel_type".to_string()), node_type: Some("BarrelMaterialType".to_string()), node_class: NodeClass::Property, value_bytes: Some(vec![1]) }, edge_type: "HasMaterialType".to_string(), from_timestamp: Some(4), to_timestamp: None }];
 
     let graph = Graph::from_edges(edges);
-    let query = scenario.conveyor_queries.get(0).unwrap();
+    let query = scenario.conveyor_queries.first().unwrap();
     let matches = query.find_all_grouped_matches(&graph);
     assert_eq!(matches.len(), 1);
 }
\ No newline at end of file
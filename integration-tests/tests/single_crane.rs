@@ -1,3 +1,6 @@
+//Tests take their rstest fixtures as arguments
+#![allow(clippy::too_many_arguments)]
+
 use std::collections::{BTreeMap, BTreeSet};
 use std::path::PathBuf;
 use std::thread::{JoinHandle, sleep};
@@ -6,16 +9,36 @@ use std::time::Duration;
 use bincode::config::Configuration;
 use rstest::{fixture, rstest};
 use serial_test::serial;
+use tonic::transport::Channel;
 
-use mbei_core::event::Event;
+use mbei_core::event::{AdminCommand, DiscardQuarantined, Event, EventCorrection, ManualAssertion, Reconfigure, RetryQuarantined, Update};
+use mbei_core::trace::TraceContext;
 #[cfg(test)]
-use mbei_core::graph::{Delta, DeltaType};
+use mbei_core::graph::{Delta, DeltaType, Node};
+use mbei_component::lateness::{LatePolicy, LatenessOptions};
+use mbei_component::options::ComponentOptions;
+use mbei_component::standby::StandbyOptions;
+use mbei_grpc::health::health_check_response::ServingStatus;
+use mbei_grpc::health::health_client::HealthClient;
+use mbei_grpc::health::HealthCheckRequest;
+use mbei_grpc::inspection::inspection_client::InspectionClient;
+use mbei_grpc::inspection::{GetMatchesRequest, GetQueueRequest, ListEdgesRequest, ListEventsRequest, ListQuarantinedUpdatesRequest, ListRejectedUpdatesRequest};
+use mbei_grpc::process_update::process_update_client::ProcessUpdateClient;
+use mbei_grpc::process_update::GetStatusRequest;
+use mbei_grpc::subscription::subscription_client::SubscriptionClient;
+use mbei_grpc::subscription::SubscribeRequest;
+use mbei_grpc::subscription_server::{from_proto_state_change, ChangeType};
+use mbei_grpc::tracer::TraceExport;
+use mbei_scenario_server::create_interpreter_registry;
 use mbei_scenario_server::crane::{CraneEvent, CraneEventType};
+use mbei_central::DerivationSource;
+use mbei_testdata::producer::TestdataProducer;
 use mbei_testdata::factory_scenario_builder::{barrels, crane_pickdrops, cranes, SimpleFactoryScenario, matched_pickdrop_query, platforms, ramps};
 
-use crate::common::{app_port, central_port, create_app_grpc_url, create_application_grpc_server, create_central, create_components, create_query_url_map, create_testdata_producer, get_all_deltas};
+use crate::common::{app_port, central_port, create_app_grpc_url, create_application_grpc_server, create_central, create_central_with_trace_export, create_components, create_components_with_options, create_crashable_components, create_query_url_map, create_testdata_producer, explain_edge, get_all_deltas, get_finality, get_manual_assertions, get_metrics, metrics_port, read_spans};
 
 #[cfg(test)]
+#[allow(dead_code)]
 mod common;
 
 #[fixture]
@@ -92,9 +115,19 @@ fn query_url_map(factory_scenario: SimpleFactoryScenario) -> BTreeMap<String, St
 
 
 #[fixture]
-fn components(app_grpc_url: &String,
+fn components(app_grpc_url: &str,
               factory_scenario: SimpleFactoryScenario) -> JoinHandle<()> {
-    create_components( app_grpc_url.clone(), &factory_scenario.queries)
+    create_components( app_grpc_url.to_string(), &factory_scenario.queries)
+}
+
+#[fixture]
+fn native_components(factory_scenario: SimpleFactoryScenario) -> JoinHandle<()> {
+    let options = ComponentOptions {
+        interpreters: create_interpreter_registry(),
+        ..ComponentOptions::default()
+    };
+    //No application server listens here, all calls must be handled by the native interpreters
+    create_components_with_options(create_app_grpc_url(app_port() - 1), &factory_scenario.queries, options)
 }
 
 #[rstest]
@@ -111,9 +144,9 @@ async fn test_simple_delta_and_event(start_logging: (),
     let _ = (app_grpc_server, start_logging); //avoid warning in compile
     let producer = create_testdata_producer(query_url_map).await;
     let my_barrel = barrels(1).pop().unwrap();
-    let my_platform = factory_scenario.platforms.get(0).unwrap();
-    let my_crane = factory_scenario.cranes.get(0).unwrap();
-    let my_pickdrop = factory_scenario.crane_pickdrops.get(0).unwrap();
+    let my_platform = factory_scenario.platforms.first().unwrap();
+    let my_crane = factory_scenario.cranes.first().unwrap();
+    let my_pickdrop = factory_scenario.crane_pickdrops.first().unwrap();
     let my_barrel_at_my_platform = Delta {
         src: my_barrel.clone(),
         trg: my_platform.clone(),
@@ -131,7 +164,7 @@ async fn test_simple_delta_and_event(start_logging: (),
         event_id: "myevent".to_string(),
         timestamp: 3u64,
         node_id: my_pickdrop.instance_node_name.as_ref().unwrap().clone(),
-        payload: payload,
+        payload,
     };
 
     producer.send_deltas_now("mydelta", "pickdrop_matched", vec![my_barrel_at_my_platform.clone()]).await;
@@ -184,10 +217,10 @@ async fn test_simple_delta_and_event_and_distractor(start_logging: (),
     let mut the_barrels = barrels(2);
     let my_barrel = the_barrels.pop().unwrap();
     let my_other_barrel = the_barrels.pop().unwrap();
-    let my_platform = factory_scenario.platforms.get(0).unwrap();
-    let my_crane = factory_scenario.cranes.get(0).unwrap();
-    let my_pickdrop = factory_scenario.crane_pickdrops.get(0).unwrap();
-    let my_ramp = factory_scenario.ramps.get(0).unwrap();
+    let my_platform = factory_scenario.platforms.first().unwrap();
+    let my_crane = factory_scenario.cranes.first().unwrap();
+    let my_pickdrop = factory_scenario.crane_pickdrops.first().unwrap();
+    let my_ramp = factory_scenario.ramps.first().unwrap();
     let my_barrel_at_my_platform = Delta {
         src: my_barrel.clone(),
         trg: my_platform.clone(),
@@ -212,7 +245,7 @@ async fn test_simple_delta_and_event_and_distractor(start_logging: (),
         event_id: "myevent".to_string(),
         timestamp: 3u64,
         node_id: my_pickdrop.instance_node_name.as_ref().unwrap().clone(),
-        payload: payload,
+        payload,
     };
 
     producer.send_deltas_now("mydelta", "pickdrop_matched",
@@ -269,9 +302,9 @@ async fn test_out_of_sequence_reprocessing(start_logging: (),
     let mut the_barrels = barrels(2);
     let my_barrel = the_barrels.pop().unwrap();
     let my_other_barrel = the_barrels.pop().unwrap();
-    let my_platform = factory_scenario.platforms.get(0).unwrap();
-    let my_crane = factory_scenario.cranes.get(0).unwrap();
-    let my_pickdrop = factory_scenario.crane_pickdrops.get(0).unwrap();
+    let my_platform = factory_scenario.platforms.first().unwrap();
+    let my_crane = factory_scenario.cranes.first().unwrap();
+    let my_pickdrop = factory_scenario.crane_pickdrops.first().unwrap();
     let my_barrel_at_my_platform = Delta {
         src: my_barrel.clone(),
         trg: my_platform.clone(),
@@ -296,7 +329,7 @@ async fn test_out_of_sequence_reprocessing(start_logging: (),
         event_id: "myevent".to_string(),
         timestamp: 1u64,
         node_id: my_pickdrop.instance_node_name.as_ref().unwrap().clone(),
-        payload: payload,
+        payload,
     };
 
     producer.send_deltas_now("mydelta", "pickdrop_matched",
@@ -353,8 +386,8 @@ async fn test_out_of_sequence_retraction(start_logging: (),
     let producer = create_testdata_producer(query_url_map).await;
     let mut the_barrels = barrels(1);
     let my_barrel = the_barrels.pop().unwrap();
-    let my_platform = factory_scenario.platforms.get(0).unwrap();
-    let my_pickdrop = factory_scenario.crane_pickdrops.get(0).unwrap();
+    let my_platform = factory_scenario.platforms.first().unwrap();
+    let my_pickdrop = factory_scenario.crane_pickdrops.first().unwrap();
     let my_barrel_at_my_platform = Delta {
         src: my_barrel.clone(),
         trg: my_platform.clone(),
@@ -373,7 +406,7 @@ async fn test_out_of_sequence_retraction(start_logging: (),
         event_id: "myevent".to_string(),
         timestamp: 3u64,
         node_id: my_pickdrop.instance_node_name.as_ref().unwrap().clone(),
-        payload: payload,
+        payload,
     };
 
     let my_barrel_not_at_my_platform = Delta {
@@ -426,9 +459,9 @@ async fn test_back_and_forth(start_logging: (),
     let producer = create_testdata_producer(query_url_map).await;
 
     let my_barrel = barrels(1).pop().unwrap();
-    let my_platform = factory_scenario.platforms.get(0).unwrap();
-    let my_crane = factory_scenario.cranes.get(0).unwrap();
-    let my_pickdrop = factory_scenario.crane_pickdrops.get(0).unwrap();
+    let my_platform = factory_scenario.platforms.first().unwrap();
+    let my_crane = factory_scenario.cranes.first().unwrap();
+    let my_pickdrop = factory_scenario.crane_pickdrops.first().unwrap();
     let my_barrel_at_my_platform = Delta {
         src: my_barrel.clone(),
         trg: my_platform.clone(),
@@ -506,4 +539,1076 @@ async fn test_back_and_forth(start_logging: (),
     assert_eq!(deltas.len(), expected_deltas.len());
     assert_eq!(BTreeSet::from_iter(deltas), BTreeSet::from_iter(expected_deltas));
     sleep(Duration::from_secs(3));
-}
\ No newline at end of file
+}
+
+#[fixture]
+fn metered_components(app_grpc_url: &str,
+                      factory_scenario: SimpleFactoryScenario) -> JoinHandle<()> {
+    let options = ComponentOptions {
+        metrics_port: Some(metrics_port()),
+        ..ComponentOptions::default()
+    };
+    create_components_with_options(app_grpc_url.to_string(), &factory_scenario.queries, options)
+}
+
+#[fixture]
+fn component_trace_path(testdata_path: PathBuf) -> PathBuf {
+    create_empty_trace_path(testdata_path, "component_trace.jsonl")
+}
+
+#[fixture]
+fn central_trace_path(testdata_path: PathBuf) -> PathBuf {
+    create_empty_trace_path(testdata_path, "central_trace.jsonl")
+}
+
+fn create_empty_trace_path(mut trace_path: PathBuf, file_name: &str) -> PathBuf {
+    trace_path.push(file_name);
+    if trace_path.exists() {
+        std::fs::remove_file(&trace_path).expect("Removal failed");
+    }
+    trace_path
+}
+
+#[fixture]
+fn traced_components(app_grpc_url: &str,
+                     factory_scenario: SimpleFactoryScenario,
+                     component_trace_path: PathBuf) -> JoinHandle<()> {
+    let options = ComponentOptions {
+        trace_export: Some(TraceExport::File(component_trace_path)),
+        ..ComponentOptions::default()
+    };
+    create_components_with_options(app_grpc_url.to_string(), &factory_scenario.queries, options)
+}
+
+#[fixture]
+fn traced_central(central_db_path: PathBuf, central_trace_path: PathBuf) -> JoinHandle<()> {
+    create_central_with_trace_export(central_db_path, central_port(), Some(TraceExport::File(central_trace_path)))
+}
+
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_simple_delta_and_event_native_interpreters(start_logging: (),
+                                                         config: Configuration,
+                                                         native_components: JoinHandle<()>,
+                                                         central: JoinHandle<()>,
+                                                         query_url_map: BTreeMap<String, String>,
+                                                         factory_scenario: SimpleFactoryScenario,
+                                                         central_db_path: PathBuf) {
+    let _ = &start_logging; //avoid warning in compile
+    let producer = create_testdata_producer(query_url_map).await;
+    let my_barrel = barrels(1).pop().unwrap();
+    let my_platform = factory_scenario.platforms.first().unwrap();
+    let my_crane = factory_scenario.cranes.first().unwrap();
+    let my_pickdrop = factory_scenario.crane_pickdrops.first().unwrap();
+    let my_barrel_at_my_platform = Delta {
+        src: my_barrel.clone(),
+        trg: my_platform.clone(),
+        edge_type: "At".to_string(),
+        timestamp: 1u64,
+        delta_type: DeltaType::Addition,
+    };
+    let crane_event = CraneEvent {
+        instance_node_id: my_platform.instance_node_name.as_ref().unwrap().clone(),
+        crane_event_type: CraneEventType::PickUp,
+    };
+    let payload = bincode::encode_to_vec(crane_event, config)
+        .expect("Encodable");
+    let pickup_barrel_at_platform = Event {
+        event_id: "myevent".to_string(),
+        timestamp: 3u64,
+        node_id: my_pickdrop.instance_node_name.as_ref().unwrap().clone(),
+        payload,
+    };
+
+    producer.send_deltas_now("mydelta", "pickdrop_matched", vec![my_barrel_at_my_platform.clone()]).await;
+    producer.send_event_now( "pickdrop_matched", pickup_barrel_at_platform).await;
+    sleep(Duration::from_secs(5));
+
+    producer.send_stop_now("pickdrop_matched").await;
+    producer.send_stop_now("central").await;
+
+    native_components.join().expect("Error joining component");
+    central.join().expect("Error joining central");
+
+    let deltas = get_all_deltas(central_db_path);
+    let expected_deltas =
+        vec![
+            my_barrel_at_my_platform.clone(),
+            Delta {
+                src: my_barrel.clone(),
+                trg: my_platform.clone(),
+                edge_type: "At".to_string(),
+                timestamp: 3,
+                delta_type: DeltaType::Removal,
+            },
+            Delta {
+                src: my_barrel.clone(),
+                trg: my_crane.clone(),
+                edge_type: "At".to_string(),
+                timestamp: 4,
+                delta_type: DeltaType::Addition,
+            }];
+    assert_eq!(deltas.len(), expected_deltas.len());
+    assert_eq!(BTreeSet::from_iter(deltas), BTreeSet::from_iter(expected_deltas));
+    sleep(Duration::from_secs(3));
+}
+
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_inspect_component_state(start_logging: (),
+                                      app_grpc_server: &JoinHandle<()>,
+                                      config: Configuration,
+                                      components: JoinHandle<()>,
+                                      central: JoinHandle<()>,
+                                      query_url_map: BTreeMap<String, String>,
+                                      factory_scenario: SimpleFactoryScenario) {
+    let _ = (app_grpc_server, start_logging); //avoid warning in compile
+    let producer = create_testdata_producer(query_url_map.clone()).await;
+    let my_barrel = barrels(1).pop().unwrap();
+    let my_platform = factory_scenario.platforms.first().unwrap();
+    let my_pickdrop = factory_scenario.crane_pickdrops.first().unwrap();
+    let my_barrel_at_my_platform = Delta {
+        src: my_barrel.clone(),
+        trg: my_platform.clone(),
+        edge_type: "At".to_string(),
+        timestamp: 1u64,
+        delta_type: DeltaType::Addition,
+    };
+    let crane_event = CraneEvent {
+        instance_node_id: my_platform.instance_node_name.as_ref().unwrap().clone(),
+        crane_event_type: CraneEventType::PickUp,
+    };
+    let payload = bincode::encode_to_vec(crane_event, config)
+        .expect("Encodable");
+    let pickup_barrel_at_platform = Event {
+        event_id: "myevent".to_string(),
+        timestamp: 3u64,
+        node_id: my_pickdrop.instance_node_name.as_ref().unwrap().clone(),
+        payload,
+    };
+
+    producer.send_deltas_now("mydelta", "pickdrop_matched", vec![my_barrel_at_my_platform.clone()]).await;
+    producer.send_event_now( "pickdrop_matched", pickup_barrel_at_platform).await;
+    sleep(Duration::from_secs(5));
+
+    let mut client = InspectionClient::connect(query_url_map.get("pickdrop_matched").unwrap().clone())
+        .await
+        .expect("Could not connect");
+    let edges_before_event = client.list_edges(ListEdgesRequest { timestamp: 2, query_name: "pickdrop_matched".to_string() }).await.unwrap().into_inner().edges;
+    assert_eq!(edges_before_event.len(), 1);
+    let events = client.list_events(ListEventsRequest { from_timestamp: 0, to_timestamp: None, query_name: "pickdrop_matched".to_string() }).await.unwrap().into_inner().events;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events.first().unwrap().event_id, "myevent");
+    let matches = client.get_matches(GetMatchesRequest { event_id: "myevent".to_string(), query_name: "pickdrop_matched".to_string() }).await.unwrap().into_inner().matches;
+    assert_eq!(matches.len(), 1);
+    assert!(matches.first().unwrap().bindings.iter().any(|b| b.topic_name == "central"));
+    let queue = client.get_queue(GetQueueRequest { query_name: "".to_string() }).await.unwrap().into_inner().updates;
+    assert!(queue.is_empty());
+
+    producer.send_stop_now("pickdrop_matched").await;
+    producer.send_stop_now("central").await;
+
+    components.join().expect("Error joining component");
+    central.join().expect("Error joining central");
+    sleep(Duration::from_secs(3));
+}
+
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_metrics_endpoint(start_logging: (),
+                               app_grpc_server: &JoinHandle<()>,
+                               metered_components: JoinHandle<()>,
+                               central: JoinHandle<()>,
+                               query_url_map: BTreeMap<String, String>,
+                               factory_scenario: SimpleFactoryScenario) {
+    let _ = (app_grpc_server, start_logging); //avoid warning in compile
+    let producer = create_testdata_producer(query_url_map.clone()).await;
+    let my_barrel = barrels(1).pop().unwrap();
+    let my_platform = factory_scenario.platforms.first().unwrap();
+    let my_barrel_at_my_platform = Delta {
+        src: my_barrel.clone(),
+        trg: my_platform.clone(),
+        edge_type: "At".to_string(),
+        timestamp: 1u64,
+        delta_type: DeltaType::Addition,
+    };
+
+    producer.send_deltas_now("mydelta", "pickdrop_matched", vec![my_barrel_at_my_platform]).await;
+    sleep(Duration::from_secs(5));
+
+    let metrics = get_metrics(metrics_port());
+    assert!(metrics.starts_with("HTTP/1.1 200"));
+    //Other tests in this binary share the process registry, so only the presence of the series is checked
+    assert!(metrics.contains("mbei_component_updates_total{query=\"pickdrop_matched\",update_type=\"deltas\"}"));
+    assert!(metrics.contains("mbei_component_store_size{collection=\"edges\",query=\"pickdrop_matched\"}"));
+
+    producer.send_stop_now("pickdrop_matched").await;
+    producer.send_stop_now("central").await;
+
+    metered_components.join().expect("Error joining component");
+    central.join().expect("Error joining central");
+    sleep(Duration::from_secs(3));
+}
+
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_trace_event_through_components_and_central(start_logging: (),
+                                                         app_grpc_server: &JoinHandle<()>,
+                                                         config: Configuration,
+                                                         component_trace_path: PathBuf,
+                                                         central_trace_path: PathBuf,
+                                                         traced_components: JoinHandle<()>,
+                                                         traced_central: JoinHandle<()>,
+                                                         query_url_map: BTreeMap<String, String>,
+                                                         factory_scenario: SimpleFactoryScenario) {
+    let _ = (app_grpc_server, start_logging); //avoid warning in compile
+    let producer = create_testdata_producer(query_url_map.clone()).await;
+    let my_barrel = barrels(1).pop().unwrap();
+    let my_platform = factory_scenario.platforms.first().unwrap();
+    let my_pickdrop = factory_scenario.crane_pickdrops.first().unwrap();
+    let my_barrel_at_my_platform = Delta {
+        src: my_barrel.clone(),
+        trg: my_platform.clone(),
+        edge_type: "At".to_string(),
+        timestamp: 1u64,
+        delta_type: DeltaType::Addition,
+    };
+    let crane_event = CraneEvent {
+        instance_node_id: my_platform.instance_node_name.as_ref().unwrap().clone(),
+        crane_event_type: CraneEventType::PickUp,
+    };
+    let payload = bincode::encode_to_vec(crane_event, config)
+        .expect("Encodable");
+    let pickup_barrel_at_platform = Event {
+        event_id: "myevent".to_string(),
+        timestamp: 3u64,
+        node_id: my_pickdrop.instance_node_name.as_ref().unwrap().clone(),
+        payload,
+    };
+
+    producer.send_deltas_now("mydelta", "pickdrop_matched", vec![my_barrel_at_my_platform.clone()]).await;
+    producer.send_event_now( "pickdrop_matched", pickup_barrel_at_platform).await;
+    sleep(Duration::from_secs(5));
+
+    producer.send_stop_now("pickdrop_matched").await;
+    producer.send_stop_now("central").await;
+
+    traced_components.join().expect("Error joining component");
+    traced_central.join().expect("Error joining central");
+
+    let trace_id = TraceContext::for_origin("myevent").trace_id;
+    let component_spans: Vec<_> = read_spans(&component_trace_path).into_iter().filter(|s| s.0 == trace_id).collect();
+    let central_spans: Vec<_> = read_spans(&central_trace_path).into_iter().filter(|s| s.0 == trace_id).collect();
+    for name in ["queue", "process_update", "match", "application_call", "route_deltas"] {
+        assert!(component_spans.iter().any(|s| s.3 == name && s.4 == "pickdrop_matched"), "missing span {}", name);
+    }
+    //The deltas stored by central were routed from the component, in the same trace
+    let route_span_ids: BTreeSet<&String> = component_spans.iter().filter(|s| s.3 == "route_deltas").map(|s| &s.1).collect();
+    let central_store_spans: Vec<_> = central_spans.iter().filter(|s| s.3 == "store" && s.4 == "central").collect();
+    assert!(!central_store_spans.is_empty());
+    assert!(central_store_spans.iter().all(|s| route_span_ids.contains(&s.2)));
+    sleep(Duration::from_secs(3));
+}
+
+#[fixture]
+fn moved_central_db_path(testdata_path: PathBuf) -> PathBuf {
+    let mut sqlite_db_path = testdata_path.clone();
+    sqlite_db_path.push("moved_central.db");
+    sqlite_db_path
+}
+
+#[fixture]
+fn moved_central(moved_central_db_path: PathBuf) -> JoinHandle<()> {
+    create_central(moved_central_db_path, central_port() + 50)
+}
+
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_reconfigure_routes_to_moved_central(start_logging: (),
+                                                  app_grpc_server: &JoinHandle<()>,
+                                                  config: Configuration,
+                                                  components: JoinHandle<()>,
+                                                  central: JoinHandle<()>,
+                                                  moved_central: JoinHandle<()>,
+                                                  query_url_map: BTreeMap<String, String>,
+                                                  factory_scenario: SimpleFactoryScenario,
+                                                  central_db_path: PathBuf,
+                                                  moved_central_db_path: PathBuf) {
+    let _ = (app_grpc_server, start_logging); //avoid warning in compile
+    let producer = create_testdata_producer(query_url_map.clone()).await;
+    let mut moved_query_url_map = query_url_map.clone();
+    moved_query_url_map.insert("central".to_string(), create_app_grpc_url(central_port() + 50));
+    let moved_producer = create_testdata_producer(moved_query_url_map.clone()).await;
+    let reconfigure = Update::Admin(AdminCommand::Reconfigure(Reconfigure {
+        queries: Some(factory_scenario.queries.clone()),
+        query_url_map: Some(moved_query_url_map),
+    }));
+    producer.send_update(&reconfigure, "pickdrop_matched".to_string()).await.await.unwrap().unwrap();
+
+    let my_barrel = barrels(1).pop().unwrap();
+    let my_platform = factory_scenario.platforms.first().unwrap();
+    let my_crane = factory_scenario.cranes.first().unwrap();
+    let my_pickdrop = factory_scenario.crane_pickdrops.first().unwrap();
+    let my_barrel_at_my_platform = Delta {
+        src: my_barrel.clone(),
+        trg: my_platform.clone(),
+        edge_type: "At".to_string(),
+        timestamp: 1u64,
+        delta_type: DeltaType::Addition,
+    };
+    let crane_event = CraneEvent {
+        instance_node_id: my_platform.instance_node_name.as_ref().unwrap().clone(),
+        crane_event_type: CraneEventType::PickUp,
+    };
+    let payload = bincode::encode_to_vec(crane_event, config)
+        .expect("Encodable");
+    let pickup_barrel_at_platform = Event {
+        event_id: "myevent".to_string(),
+        timestamp: 3u64,
+        node_id: my_pickdrop.instance_node_name.as_ref().unwrap().clone(),
+        payload,
+    };
+
+    producer.send_deltas_now("mydelta", "pickdrop_matched", vec![my_barrel_at_my_platform.clone()]).await;
+    producer.send_event_now( "pickdrop_matched", pickup_barrel_at_platform).await;
+    sleep(Duration::from_secs(5));
+
+    producer.send_stop_now("pickdrop_matched").await;
+    producer.send_stop_now("central").await;
+    moved_producer.send_stop_now("central").await;
+
+    components.join().expect("Error joining component");
+    central.join().expect("Error joining central");
+    moved_central.join().expect("Error joining moved central");
+
+    //The producer still sends its deltas to the original central, the deltas of the component go to the moved one
+    let deltas = get_all_deltas(central_db_path);
+    assert_eq!(deltas, vec![my_barrel_at_my_platform.clone()]);
+    let moved_deltas = get_all_deltas(moved_central_db_path);
+    let expected_moved_deltas =
+        vec![
+            Delta {
+                src: my_barrel.clone(),
+                trg: my_platform.clone(),
+                edge_type: "At".to_string(),
+                timestamp: 3,
+                delta_type: DeltaType::Removal,
+            },
+            Delta {
+                src: my_barrel.clone(),
+                trg: my_crane.clone(),
+                edge_type: "At".to_string(),
+                timestamp: 4,
+                delta_type: DeltaType::Addition,
+            }];
+    assert_eq!(BTreeSet::from_iter(moved_deltas), BTreeSet::from_iter(expected_moved_deltas));
+    sleep(Duration::from_secs(3));
+}
+
+async fn await_health_status(client: &mut HealthClient<Channel>, service: &str, expected: ServingStatus) {
+    let mut statuses = client
+        .watch(HealthCheckRequest { service: service.to_string() })
+        .await
+        .expect("Could not watch health")
+        .into_inner();
+    let reached = async {
+        while let Some(response) = statuses.message().await.expect("Health watch failed") {
+            if response.status == expected as i32 {
+                return;
+            }
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(30), reached).await.unwrap_or_else(|_| panic!("{} did not become {:?}", service, expected));
+}
+
+async fn check_health(client: &mut HealthClient<Channel>, service: &str) -> i32 {
+    client.check(HealthCheckRequest { service: service.to_string() }).await.expect("Health check failed").into_inner().status
+}
+
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_health_follows_connection_to_central(start_logging: (),
+                                                   app_grpc_server: &JoinHandle<()>,
+                                                   config: Configuration,
+                                                   components: JoinHandle<()>,
+                                                   query_url_map: BTreeMap<String, String>,
+                                                   factory_scenario: SimpleFactoryScenario,
+                                                   central_db_path: PathBuf) {
+    let _ = (app_grpc_server, start_logging); //avoid warning in compile
+    let mut client = HealthClient::connect(query_url_map.get("pickdrop_matched").unwrap().clone())
+        .await
+        .expect("Could not connect");
+    //Central is not started, so the router is still connecting
+    assert_eq!(check_health(&mut client, "liveness").await, ServingStatus::Serving as i32);
+    assert_eq!(check_health(&mut client, "readiness").await, ServingStatus::NotServing as i32);
+    let central = create_central(central_db_path.clone(), central_port());
+    await_health_status(&mut client, "readiness", ServingStatus::Serving).await;
+    assert_eq!(check_health(&mut client, "").await, ServingStatus::Serving as i32);
+
+    let producer = create_testdata_producer(query_url_map).await;
+    let my_platform = factory_scenario.platforms.first().unwrap();
+    let my_pickdrop = factory_scenario.crane_pickdrops.first().unwrap();
+    let my_barrel_at_my_platform = Delta {
+        src: barrels(1).pop().unwrap(),
+        trg: my_platform.clone(),
+        edge_type: "At".to_string(),
+        timestamp: 1u64,
+        delta_type: DeltaType::Addition,
+    };
+    let crane_event = CraneEvent {
+        instance_node_id: my_platform.instance_node_name.as_ref().unwrap().clone(),
+        crane_event_type: CraneEventType::PickUp,
+    };
+    let pickup_barrel_at_platform = Event {
+        event_id: "myevent".to_string(),
+        timestamp: 3u64,
+        node_id: my_pickdrop.instance_node_name.as_ref().unwrap().clone(),
+        payload: bincode::encode_to_vec(crane_event, config).expect("Encodable"),
+    };
+    producer.send_deltas_now("mydelta", "pickdrop_matched", vec![my_barrel_at_my_platform]).await;
+    producer.send_stop_now("central").await;
+    central.join().expect("Error joining central");
+    producer.send_event_now("pickdrop_matched", pickup_barrel_at_platform).await;
+    //Degraded while the updates for central can not be delivered, but still live and ready
+    await_health_status(&mut client, "pickdrop_matched", ServingStatus::NotServing).await;
+    assert_eq!(check_health(&mut client, "readiness").await, ServingStatus::Serving as i32);
+    assert_eq!(check_health(&mut client, "liveness").await, ServingStatus::Serving as i32);
+
+    let central = create_central(central_db_path.clone(), central_port());
+    await_health_status(&mut client, "pickdrop_matched", ServingStatus::Serving).await;
+    producer.send_stop_now("pickdrop_matched").await;
+    producer.send_stop_now("central").await;
+    components.join().expect("Error joining component");
+    central.join().expect("Error joining central");
+    //The restarted central starts from an empty database, and only receives the deltas resent to it
+    assert_eq!(get_all_deltas(central_db_path).len(), 2);
+}
+
+async fn await_finalized_before(client: &mut ProcessUpdateClient<Channel>, expected: u64) {
+    for _ in 0..50 {
+        let status = client.get_status(GetStatusRequest { target_query: "pickdrop_matched".to_string() }).await.unwrap().into_inner();
+        if status.finalized_before == expected {
+            return;
+        }
+        sleep(Duration::from_millis(100));
+    }
+    panic!("Finality point did not reach {}", expected);
+}
+
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_finality_follows_watermarks(start_logging: (),
+                                          app_grpc_server: &JoinHandle<()>,
+                                          config: Configuration,
+                                          components: JoinHandle<()>,
+                                          central: JoinHandle<()>,
+                                          query_url_map: BTreeMap<String, String>,
+                                          factory_scenario: SimpleFactoryScenario,
+                                          central_db_path: PathBuf) {
+    let _ = (app_grpc_server, start_logging); //avoid warning in compile
+    let producer = create_testdata_producer(query_url_map.clone()).await;
+    let my_platform = factory_scenario.platforms.first().unwrap();
+    let my_pickdrop = factory_scenario.crane_pickdrops.first().unwrap();
+    let my_barrel_at_my_platform = Delta {
+        src: barrels(1).pop().unwrap(),
+        trg: my_platform.clone(),
+        edge_type: "At".to_string(),
+        timestamp: 1u64,
+        delta_type: DeltaType::Addition,
+    };
+    let crane_event = CraneEvent {
+        instance_node_id: my_platform.instance_node_name.as_ref().unwrap().clone(),
+        crane_event_type: CraneEventType::PickUp,
+    };
+    let pickup_barrel_at_platform = Event {
+        event_id: "myevent".to_string(),
+        timestamp: 3u64,
+        node_id: my_pickdrop.instance_node_name.as_ref().unwrap().clone(),
+        payload: bincode::encode_to_vec(crane_event, config).expect("Encodable"),
+    };
+    producer.send_deltas_now("mydelta", "pickdrop_matched", vec![my_barrel_at_my_platform]).await;
+    producer.send_event_now("pickdrop_matched", pickup_barrel_at_platform).await;
+    sleep(Duration::from_secs(2));
+
+    let url = query_url_map.get("pickdrop_matched").unwrap().clone();
+    let mut client = ProcessUpdateClient::connect(url.clone()).await.expect("Could not connect");
+    let mut inspection_client = InspectionClient::connect(url).await.expect("Could not connect");
+    //Nothing is final until the producer has told how far it is
+    await_finalized_before(&mut client, 0).await;
+    producer.send_watermark_now(3).await;
+    await_finalized_before(&mut client, 3).await;
+    let list_events = || ListEventsRequest { from_timestamp: 0, to_timestamp: None, query_name: "pickdrop_matched".to_string() };
+    assert_eq!(inspection_client.list_events(list_events()).await.unwrap().into_inner().events.len(), 1);
+    producer.send_watermark_now(10).await;
+    await_finalized_before(&mut client, 10).await;
+    //The event is compacted away, while the edges later events match on are kept
+    assert!(inspection_client.list_events(list_events()).await.unwrap().into_inner().events.is_empty());
+    let edges = inspection_client.list_edges(ListEdgesRequest { timestamp: 10, query_name: "pickdrop_matched".to_string() }).await.unwrap().into_inner().edges;
+    assert_eq!(edges.len(), 1);
+    sleep(Duration::from_secs(1));
+
+    producer.send_stop_now("pickdrop_matched").await;
+    producer.send_stop_now("central").await;
+    components.join().expect("Error joining component");
+    central.join().expect("Error joining central");
+    let finality = get_finality(central_db_path);
+    assert_eq!(finality.get("testdata-producer"), Some(&10));
+    assert_eq!(finality.get("pickdrop_matched"), Some(&10));
+}
+
+#[fixture]
+fn rejecting_components(app_grpc_url: &str,
+                        factory_scenario: SimpleFactoryScenario) -> JoinHandle<()> {
+    let mut options = ComponentOptions::default();
+    options.lateness.insert("pickdrop_matched".to_string(), LatenessOptions { allowed_lateness: Some(5), policy: LatePolicy::Reject });
+    create_components_with_options(app_grpc_url.to_string(), &factory_scenario.queries, options)
+}
+
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_late_deltas_are_rejected(start_logging: (),
+                                       app_grpc_server: &JoinHandle<()>,
+                                       config: Configuration,
+                                       rejecting_components: JoinHandle<()>,
+                                       central: JoinHandle<()>,
+                                       query_url_map: BTreeMap<String, String>,
+                                       factory_scenario: SimpleFactoryScenario) {
+    let _ = (app_grpc_server, start_logging); //avoid warning in compile
+    let producer = create_testdata_producer(query_url_map.clone()).await;
+    let my_platform = factory_scenario.platforms.first().unwrap();
+    let my_pickdrop = factory_scenario.crane_pickdrops.first().unwrap();
+    let crane_event = CraneEvent {
+        instance_node_id: my_platform.instance_node_name.as_ref().unwrap().clone(),
+        crane_event_type: CraneEventType::PickUp,
+    };
+    let pickup_at_platform = Event {
+        event_id: "myevent".to_string(),
+        timestamp: 20u64,
+        node_id: my_pickdrop.instance_node_name.as_ref().unwrap().clone(),
+        payload: bincode::encode_to_vec(crane_event, config).expect("Encodable"),
+    };
+    //Arrives 19 behind the event, while 5 is allowed
+    let late_barrel_at_my_platform = Delta {
+        src: barrels(1).pop().unwrap(),
+        trg: my_platform.clone(),
+        edge_type: "At".to_string(),
+        timestamp: 1u64,
+        delta_type: DeltaType::Addition,
+    };
+    producer.send_event_now("pickdrop_matched", pickup_at_platform).await;
+    sleep(Duration::from_secs(1));
+    producer.send_deltas_now("mydelta", "pickdrop_matched", vec![late_barrel_at_my_platform]).await;
+    sleep(Duration::from_secs(2));
+
+    let mut client = InspectionClient::connect(query_url_map.get("pickdrop_matched").unwrap().clone())
+        .await
+        .expect("Could not connect");
+    let edges = client.list_edges(ListEdgesRequest { timestamp: 20, query_name: "pickdrop_matched".to_string() }).await.unwrap().into_inner().edges;
+    assert!(edges.is_empty());
+    let rejected = client.list_rejected_updates(ListRejectedUpdatesRequest { query_name: "pickdrop_matched".to_string() }).await.unwrap().into_inner().rejected_updates;
+    assert_eq!(rejected.len(), 1);
+    assert!(rejected[0].reason.contains("19 behind"));
+
+    producer.send_stop_now("pickdrop_matched").await;
+    producer.send_stop_now("central").await;
+    rejecting_components.join().expect("Error joining component");
+    central.join().expect("Error joining central");
+    sleep(Duration::from_secs(3));
+}
+
+#[fixture]
+fn quarantining_components(app_grpc_url: &str,
+                           factory_scenario: SimpleFactoryScenario) -> JoinHandle<()> {
+    //Any cascade which needs more than one iteration is quarantined
+    let options = ComponentOptions {
+        loop_budget: 0,
+        ..ComponentOptions::default()
+    };
+    create_components_with_options(app_grpc_url.to_string(), &factory_scenario.queries, options)
+}
+
+async fn list_quarantined(client: &mut InspectionClient<Channel>) -> Vec<mbei_grpc::inspection::QuarantinedUpdate> {
+    client.list_quarantined_updates(ListQuarantinedUpdatesRequest { query_name: "pickdrop_matched".to_string() }).await.unwrap().into_inner().quarantined_updates
+}
+
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_quarantined_updates_are_listed_retried_and_discarded(start_logging: (),
+                                                                   app_grpc_server: &JoinHandle<()>,
+                                                                   config: Configuration,
+                                                                   quarantining_components: JoinHandle<()>,
+                                                                   central: JoinHandle<()>,
+                                                                   query_url_map: BTreeMap<String, String>,
+                                                                   factory_scenario: SimpleFactoryScenario,
+                                                                   central_db_path: PathBuf) {
+    let _ = (app_grpc_server, start_logging); //avoid warning in compile
+    let producer = create_testdata_producer(query_url_map.clone()).await;
+    let mut my_barrels = barrels(2);
+    let my_other_barrel = my_barrels.pop().unwrap();
+    let my_barrel = my_barrels.pop().unwrap();
+    let my_platform = factory_scenario.platforms.first().unwrap();
+    let my_crane = factory_scenario.cranes.first().unwrap();
+    let my_pickdrop = factory_scenario.crane_pickdrops.first().unwrap();
+    let pickup_at = |event_id: &str, timestamp: u64| Event {
+        event_id: event_id.to_string(),
+        timestamp,
+        node_id: my_pickdrop.instance_node_name.as_ref().unwrap().clone(),
+        payload: bincode::encode_to_vec(CraneEvent {
+            instance_node_id: my_platform.instance_node_name.as_ref().unwrap().clone(),
+            crane_event_type: CraneEventType::PickUp,
+        }, config).expect("Encodable"),
+    };
+    let at_my_platform = |barrel: &Node, timestamp: u64| Delta {
+        src: barrel.clone(),
+        trg: my_platform.clone(),
+        edge_type: "At".to_string(),
+        timestamp,
+        delta_type: DeltaType::Addition,
+    };
+    let mut client = InspectionClient::connect(query_url_map.get("pickdrop_matched").unwrap().clone())
+        .await
+        .expect("Could not connect");
+
+    //The delta arrives after the event it should have matched, and reprocessing the event is more than the budget allows
+    producer.send_event_now("pickdrop_matched", pickup_at("myevent", 3)).await;
+    sleep(Duration::from_secs(1));
+    producer.send_deltas_now("mydelta", "pickdrop_matched", vec![at_my_platform(&my_barrel, 1)]).await;
+    sleep(Duration::from_secs(2));
+    let quarantined = list_quarantined(&mut client).await;
+    assert_eq!(quarantined.len(), 1);
+    assert!(quarantined[0].update.contains("deltas mydelta"));
+    let retry = Update::Admin(AdminCommand::RetryQuarantined(RetryQuarantined {
+        quarantine_id: quarantined[0].quarantine_id.clone(),
+        loop_budget: 100,
+    }));
+    producer.send_update(&retry, "pickdrop_matched".to_string()).await.await.unwrap().unwrap();
+    sleep(Duration::from_secs(2));
+    assert!(list_quarantined(&mut client).await.is_empty());
+
+    producer.send_event_now("pickdrop_matched", pickup_at("myotherevent", 10)).await;
+    sleep(Duration::from_secs(1));
+    producer.send_deltas_now("myotherdelta", "pickdrop_matched", vec![at_my_platform(&my_other_barrel, 5)]).await;
+    sleep(Duration::from_secs(2));
+    let quarantined = list_quarantined(&mut client).await;
+    assert_eq!(quarantined.len(), 1);
+    let discard = Update::Admin(AdminCommand::DiscardQuarantined(DiscardQuarantined {
+        quarantine_id: quarantined[0].quarantine_id.clone(),
+    }));
+    producer.send_update(&discard, "pickdrop_matched".to_string()).await.await.unwrap().unwrap();
+    sleep(Duration::from_secs(2));
+    assert!(list_quarantined(&mut client).await.is_empty());
+
+    producer.send_stop_now("pickdrop_matched").await;
+    producer.send_stop_now("central").await;
+    quarantining_components.join().expect("Error joining component");
+    central.join().expect("Error joining central");
+
+    //Both barrels were picked up before quarantining, discarding only dropped the deltas still pending in the cascade
+    let deltas = get_all_deltas(central_db_path);
+    let expected_deltas = vec![
+        at_my_platform(&my_barrel, 1),
+        Delta {
+            src: my_barrel.clone(),
+            trg: my_platform.clone(),
+            edge_type: "At".to_string(),
+            timestamp: 3,
+            delta_type: DeltaType::Removal,
+        },
+        Delta {
+            src: my_barrel.clone(),
+            trg: my_crane.clone(),
+            edge_type: "At".to_string(),
+            timestamp: 4,
+            delta_type: DeltaType::Addition,
+        },
+        at_my_platform(&my_other_barrel, 5),
+        Delta {
+            src: my_other_barrel.clone(),
+            trg: my_platform.clone(),
+            edge_type: "At".to_string(),
+            timestamp: 10,
+            delta_type: DeltaType::Removal,
+        },
+        Delta {
+            src: my_other_barrel.clone(),
+            trg: my_crane.clone(),
+            edge_type: "At".to_string(),
+            timestamp: 11,
+            delta_type: DeltaType::Addition,
+        },
+    ];
+    assert_eq!(BTreeSet::from_iter(deltas), BTreeSet::from_iter(expected_deltas));
+    sleep(Duration::from_secs(3));
+}
+
+async fn send_pickup_and_correction(producer: &TestdataProducer,
+                                    config: Configuration,
+                                    factory_scenario: &SimpleFactoryScenario,
+                                    replacement_timestamp: Option<u64>) {
+    let my_platform = factory_scenario.platforms.first().unwrap();
+    let my_pickdrop = factory_scenario.crane_pickdrops.first().unwrap();
+    let my_barrel_at_my_platform = Delta {
+        src: barrels(1).pop().unwrap(),
+        trg: my_platform.clone(),
+        edge_type: "At".to_string(),
+        timestamp: 1u64,
+        delta_type: DeltaType::Addition,
+    };
+    let crane_event = CraneEvent {
+        instance_node_id: my_platform.instance_node_name.as_ref().unwrap().clone(),
+        crane_event_type: CraneEventType::PickUp,
+    };
+    let pickup_barrel_at_platform = Event {
+        event_id: "myevent".to_string(),
+        timestamp: 3u64,
+        node_id: my_pickdrop.instance_node_name.as_ref().unwrap().clone(),
+        payload: bincode::encode_to_vec(crane_event, config).expect("Encodable"),
+    };
+    let replacement = replacement_timestamp.map(|timestamp| Event { timestamp, ..pickup_barrel_at_platform.clone() });
+    producer.send_deltas_now("mydelta", "pickdrop_matched", vec![my_barrel_at_my_platform]).await;
+    producer.send_event_now("pickdrop_matched", pickup_barrel_at_platform).await;
+    sleep(Duration::from_secs(3));
+    producer.send_event_correction_now("pickdrop_matched", EventCorrection {
+        correction_id: "mycorrection".to_string(),
+        event_id: "myevent".to_string(),
+        timestamp: 3,
+        replacement,
+    }).await;
+    sleep(Duration::from_secs(3));
+}
+
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_event_correction_replaces_output(start_logging: (),
+                                               app_grpc_server: &JoinHandle<()>,
+                                               config: Configuration,
+                                               components: JoinHandle<()>,
+                                               central: JoinHandle<()>,
+                                               query_url_map: BTreeMap<String, String>,
+                                               factory_scenario: SimpleFactoryScenario,
+                                               central_db_path: PathBuf) {
+    let _ = (app_grpc_server, start_logging); //avoid warning in compile
+    let producer = create_testdata_producer(query_url_map).await;
+    //The pickup was recorded two time units too early
+    send_pickup_and_correction(&producer, config, &factory_scenario, Some(5)).await;
+
+    producer.send_stop_now("pickdrop_matched").await;
+    producer.send_stop_now("central").await;
+    components.join().expect("Error joining component");
+    central.join().expect("Error joining central");
+
+    let my_barrel = barrels(1).pop().unwrap();
+    let my_platform = factory_scenario.platforms.first().unwrap();
+    let my_crane = factory_scenario.cranes.first().unwrap();
+    let deltas = get_all_deltas(central_db_path);
+    let timestamps: BTreeSet<(u64, DeltaType)> = deltas.iter().map(|d| (d.timestamp, d.delta_type.clone())).collect();
+    assert_eq!(timestamps, BTreeSet::from([(1, DeltaType::Addition), (5, DeltaType::Removal), (6, DeltaType::Addition)]));
+    assert!(deltas.iter().any(|d| d.src == my_barrel && &d.trg == my_platform && d.delta_type == DeltaType::Removal));
+    assert!(deltas.iter().any(|d| d.src == my_barrel && &d.trg == my_crane && d.timestamp == 6));
+    sleep(Duration::from_secs(3));
+}
+
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_event_retraction_removes_output(start_logging: (),
+                                              app_grpc_server: &JoinHandle<()>,
+                                              config: Configuration,
+                                              components: JoinHandle<()>,
+                                              central: JoinHandle<()>,
+                                              query_url_map: BTreeMap<String, String>,
+                                              factory_scenario: SimpleFactoryScenario,
+                                              central_db_path: PathBuf) {
+    let _ = (app_grpc_server, start_logging); //avoid warning in compile
+    let producer = create_testdata_producer(query_url_map.clone()).await;
+    send_pickup_and_correction(&producer, config, &factory_scenario, None).await;
+
+    let mut client = InspectionClient::connect(query_url_map.get("pickdrop_matched").unwrap().clone())
+        .await
+        .expect("Could not connect");
+    let events = client.list_events(ListEventsRequest { from_timestamp: 0, to_timestamp: None, query_name: "pickdrop_matched".to_string() }).await.unwrap().into_inner().events;
+    assert!(events.is_empty());
+
+    producer.send_stop_now("pickdrop_matched").await;
+    producer.send_stop_now("central").await;
+    components.join().expect("Error joining component");
+    central.join().expect("Error joining central");
+
+    //Only the delta sent by the producer is left
+    let deltas = get_all_deltas(central_db_path);
+    assert_eq!(deltas.len(), 1);
+    assert_eq!(deltas[0].timestamp, 1);
+    sleep(Duration::from_secs(3));
+}
+
+#[fixture]
+fn operated_components(app_grpc_url: &str,
+                       factory_scenario: SimpleFactoryScenario) -> JoinHandle<()> {
+    let mut options = ComponentOptions::default();
+    options.operators.insert("alice".to_string(), "alicetoken".to_string());
+    create_components_with_options(app_grpc_url.to_string(), &factory_scenario.queries, options)
+}
+
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_manual_assertion_is_audited_and_revoked(start_logging: (),
+                                                      app_grpc_server: &JoinHandle<()>,
+                                                      operated_components: JoinHandle<()>,
+                                                      central: JoinHandle<()>,
+                                                      query_url_map: BTreeMap<String, String>,
+                                                      factory_scenario: SimpleFactoryScenario,
+                                                      central_db_path: PathBuf) {
+    let _ = (app_grpc_server, start_logging); //avoid warning in compile
+    let producer = create_testdata_producer(query_url_map).await;
+    let my_crane = factory_scenario.cranes.first().unwrap();
+    //The crane sensor missed that it picked up the barrel
+    let barrel_at = |trg: &Node| Delta {
+        src: barrels(1).pop().unwrap(),
+        trg: trg.clone(),
+        edge_type: "At".to_string(),
+        timestamp: 4u64,
+        delta_type: DeltaType::Addition,
+    };
+    let assertion = |assertion_id: &str, delta: Option<Delta>, revoke: bool| ManualAssertion {
+        assertion_id: assertion_id.to_string(),
+        operator: "mallory".to_string(),
+        reason: if revoke { "sensor repaired" } else { "crane sensor down" }.to_string(),
+        timestamp: if revoke { 6 } else { 4 },
+        deltas: delta.into_iter().collect(),
+        revoke,
+    };
+    let unauthenticated = producer
+        .send_manual_assertion_now("pickdrop_matched", assertion("myassertion", Some(barrel_at(my_crane)), false), "guess")
+        .await;
+    assert_eq!(unauthenticated.unwrap_err().code(), tonic::Code::Unauthenticated);
+    producer
+        .send_manual_assertion_now("pickdrop_matched", assertion("myassertion", Some(barrel_at(my_crane)), false), "alicetoken")
+        .await
+        .unwrap();
+    //The query never outputs such edges, so there is nothing to override
+    let barrel_near_crane = Delta { edge_type: "Near".to_string(), ..barrel_at(my_crane) };
+    producer
+        .send_manual_assertion_now("pickdrop_matched", assertion("notoutput", Some(barrel_near_crane), false), "alicetoken")
+        .await
+        .unwrap();
+    sleep(Duration::from_secs(3));
+    let deltas = get_all_deltas(central_db_path.clone());
+    assert_eq!(deltas, vec![barrel_at(my_crane)]);
+
+    producer
+        .send_manual_assertion_now("pickdrop_matched", assertion("myassertion", None, true), "alicetoken")
+        .await
+        .unwrap();
+    sleep(Duration::from_secs(3));
+    producer.send_stop_now("pickdrop_matched").await;
+    producer.send_stop_now("central").await;
+    operated_components.join().expect("Error joining component");
+    central.join().expect("Error joining central");
+
+    assert!(get_all_deltas(central_db_path.clone()).is_empty());
+    let records = get_manual_assertions(central_db_path);
+    assert_eq!(records.len(), 1);
+    //The operator is the one authenticated by the token, not the one in the request
+    assert_eq!(records[0].assertion_id, "myassertion");
+    assert_eq!(records[0].operator, "alice");
+    assert_eq!(records[0].reason, "crane sensor down");
+    assert_eq!(records[0].revoked_by.as_deref(), Some("alice"));
+    assert_eq!(records[0].revoked_timestamp, Some(6));
+    sleep(Duration::from_secs(3));
+}
+
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_output_is_explained_by_event_match_and_input_deltas(start_logging: (),
+                                                                  app_grpc_server: &JoinHandle<()>,
+                                                                  config: Configuration,
+                                                                  components: JoinHandle<()>,
+                                                                  central: JoinHandle<()>,
+                                                                  query_url_map: BTreeMap<String, String>,
+                                                                  factory_scenario: SimpleFactoryScenario,
+                                                                  central_db_path: PathBuf) {
+    let _ = (app_grpc_server, start_logging); //avoid warning in compile
+    let producer = create_testdata_producer(query_url_map).await;
+    let my_barrel = barrels(1).pop().unwrap();
+    let my_platform = factory_scenario.platforms.first().unwrap();
+    let my_pickdrop = factory_scenario.crane_pickdrops.first().unwrap();
+    let my_barrel_at_my_platform = Delta {
+        src: my_barrel.clone(),
+        trg: my_platform.clone(),
+        edge_type: "At".to_string(),
+        timestamp: 1u64,
+        delta_type: DeltaType::Addition,
+    };
+    let crane_event = CraneEvent {
+        instance_node_id: my_platform.instance_node_name.as_ref().unwrap().clone(),
+        crane_event_type: CraneEventType::PickUp,
+    };
+    let pickup_barrel_at_platform = Event {
+        event_id: "myevent".to_string(),
+        timestamp: 3u64,
+        node_id: my_pickdrop.instance_node_name.as_ref().unwrap().clone(),
+        payload: bincode::encode_to_vec(crane_event, config).expect("Encodable"),
+    };
+    producer.send_deltas_now("mydelta", "pickdrop_matched", vec![my_barrel_at_my_platform.clone()]).await;
+    producer.send_event_now("pickdrop_matched", pickup_barrel_at_platform).await;
+    sleep(Duration::from_secs(3));
+    producer.send_stop_now("pickdrop_matched").await;
+    producer.send_stop_now("central").await;
+    components.join().expect("Error joining component");
+    central.join().expect("Error joining central");
+
+    let my_barrel_name = my_barrel.instance_node_name.as_ref().unwrap();
+    let my_crane_name = factory_scenario.cranes.first().unwrap().instance_node_name.as_ref().unwrap();
+    assert!(explain_edge(central_db_path.clone(), my_barrel_name, my_crane_name, "At", 2).is_empty());
+    let derivations = explain_edge(central_db_path, my_barrel_name, my_crane_name, "At", 4);
+    assert_eq!(derivations.len(), 1);
+    match &derivations[0].source {
+        DerivationSource::Match { query_name, application, origin_id, origin_timestamp, .. } => {
+            assert_eq!((query_name.as_str(), application.as_str()), ("pickdrop_matched", "pickdrop"));
+            assert_eq!((origin_id.as_str(), *origin_timestamp), ("myevent", 3));
+        }
+        other => panic!("Expected a match, not {:?}", other),
+    }
+    //The barrel was picked up from the platform, where the producer put it
+    let input_derivations: Vec<_> = derivations[0].inputs.iter().flat_map(|i| i.derivations.iter()).collect();
+    assert_eq!(input_derivations.len(), 1);
+    assert_eq!(input_derivations[0].deltas_id, "mydelta");
+    assert_eq!(input_derivations[0].delta, my_barrel_at_my_platform);
+    assert_eq!(input_derivations[0].source, DerivationSource::External);
+    sleep(Duration::from_secs(3));
+}
+
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_subscribers_get_changes_of_an_instance_and_resume_after_cursor(start_logging: (),
+                                                                             app_grpc_server: &JoinHandle<()>,
+                                                                             config: Configuration,
+                                                                             components: JoinHandle<()>,
+                                                                             central: JoinHandle<()>,
+                                                                             query_url_map: BTreeMap<String, String>,
+                                                                             factory_scenario: SimpleFactoryScenario) {
+    let _ = (app_grpc_server, start_logging); //avoid warning in compile
+    let producer = create_testdata_producer(query_url_map.clone()).await;
+    send_pickup_and_correction(&producer, config, &factory_scenario, None).await;
+
+    let my_crane_name = factory_scenario.cranes.first().unwrap().instance_node_name.clone().unwrap();
+    let request = |cursor| SubscribeRequest {
+        edge_types: vec!["At".to_string()],
+        node_types: vec![],
+        instance_node_ids: vec![my_crane_name.clone()],
+        cursor,
+    };
+    let mut client = SubscriptionClient::connect(query_url_map.get("central").unwrap().clone())
+        .await
+        .expect("Could not connect");
+    let mut stream = client.subscribe(request(0)).await.unwrap().into_inner();
+    //The barrel was moved to the crane by the pickup, until the pickup was retracted
+    let added = from_proto_state_change(stream.message().await.unwrap().unwrap()).unwrap();
+    let retracted = from_proto_state_change(stream.message().await.unwrap().unwrap()).unwrap();
+    assert_eq!((added.change_type, retracted.change_type), (ChangeType::Addition, ChangeType::Retraction));
+    assert_eq!(added.delta.trg.instance_node_name.as_ref(), Some(&my_crane_name));
+    assert_eq!(retracted.deltas_id, added.deltas_id);
+    assert_eq!(retracted.delta, added.delta);
+    let mut resumed = client.subscribe(request(added.cursor)).await.unwrap().into_inner();
+    assert_eq!(from_proto_state_change(resumed.message().await.unwrap().unwrap()).unwrap(), retracted);
+
+    producer.send_stop_now("pickdrop_matched").await;
+    producer.send_stop_now("central").await;
+    components.join().expect("Error joining component");
+    central.join().expect("Error joining central");
+    //Stopping central ends the subscriptions
+    assert!(stream.message().await.unwrap().is_none());
+    sleep(Duration::from_secs(3));
+}
+
+#[fixture]
+fn standby_components(app_grpc_url: &str, factory_scenario: SimpleFactoryScenario) -> JoinHandle<()> {
+    let options = ComponentOptions {
+        //Takes over the port of the primary when it has been silent for the failover timeout
+        standby: Some(StandbyOptions { port: 10011, failover_timeout: Duration::from_secs(2) }),
+        ..ComponentOptions::default()
+    };
+    create_components_with_options(app_grpc_url.to_string(), &factory_scenario.queries, options)
+}
+
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_standby_takes_over_without_losing_or_duplicating_output(start_logging: (),
+                                                                      app_grpc_server: &JoinHandle<()>,
+                                                                      app_grpc_url: &str,
+                                                                      config: Configuration,
+                                                                      standby_components: JoinHandle<()>,
+                                                                      central: JoinHandle<()>,
+                                                                      query_url_map: BTreeMap<String, String>,
+                                                                      factory_scenario: SimpleFactoryScenario,
+                                                                      central_db_path: PathBuf) {
+    let _ = (app_grpc_server, start_logging); //avoid warning in compile
+    let mut options = ComponentOptions::default();
+    options.replicas.insert("pickdrop_matched".to_string(), "http://[::1]:10011".to_string());
+    let primary = create_crashable_components(app_grpc_url.to_string(), &factory_scenario.queries, options);
+    let producer = create_testdata_producer(query_url_map.clone()).await;
+
+    let my_barrel = barrels(1).pop().unwrap();
+    let my_platform = factory_scenario.platforms.first().unwrap();
+    let my_crane = factory_scenario.cranes.first().unwrap();
+    let my_pickdrop = factory_scenario.crane_pickdrops.first().unwrap();
+    let my_barrel_at_my_platform = Delta {
+        src: my_barrel.clone(),
+        trg: my_platform.clone(),
+        edge_type: "At".to_string(),
+        timestamp: 1u64,
+        delta_type: DeltaType::Addition,
+    };
+    let crane_event = |event_id: &str, timestamp, crane_event_type| Event {
+        event_id: event_id.to_string(),
+        timestamp,
+        node_id: my_pickdrop.instance_node_name.as_ref().unwrap().clone(),
+        payload: bincode::encode_to_vec(CraneEvent {
+            instance_node_id: my_platform.instance_node_name.as_ref().unwrap().clone(),
+            crane_event_type,
+        }, config).expect("Encodable"),
+    };
+
+    producer.send_deltas_now("mydelta", "pickdrop_matched", vec![my_barrel_at_my_platform.clone()]).await;
+    producer.send_event_now("pickdrop_matched", crane_event("my_first_event", 3, CraneEventType::PickUp)).await;
+    sleep(Duration::from_secs(3));
+    //The primary crashes, and the drop is sent once the standby has taken over its port
+    primary.shutdown_background();
+    sleep(Duration::from_secs(5));
+    //The connections to the crashed primary are broken, so the producer connects again
+    let producer = create_testdata_producer(query_url_map).await;
+    producer.send_event_now("pickdrop_matched", crane_event("my_second_event", 5, CraneEventType::Drop)).await;
+    sleep(Duration::from_secs(3));
+
+    producer.send_stop_now("pickdrop_matched").await;
+    producer.send_stop_now("central").await;
+    standby_components.join().expect("Error joining component");
+    central.join().expect("Error joining central");
+
+    let deltas = get_all_deltas(central_db_path);
+    let expected_deltas =
+        vec![
+            my_barrel_at_my_platform.clone(),
+            Delta { src: my_barrel.clone(), trg: my_platform.clone(), edge_type: "At".to_string(), timestamp: 3, delta_type: DeltaType::Removal },
+            Delta { src: my_barrel.clone(), trg: my_crane.clone(), edge_type: "At".to_string(), timestamp: 4, delta_type: DeltaType::Addition },
+            Delta { src: my_barrel.clone(), trg: my_crane.clone(), edge_type: "At".to_string(), timestamp: 5, delta_type: DeltaType::Removal },
+            Delta { src: my_barrel.clone(), trg: my_platform.clone(), edge_type: "At".to_string(), timestamp: 6, delta_type: DeltaType::Addition },
+        ];
+    assert_eq!(deltas.len(), expected_deltas.len());
+    assert_eq!(BTreeSet::from_iter(deltas), BTreeSet::from_iter(expected_deltas));
+    sleep(Duration::from_secs(3));
+}
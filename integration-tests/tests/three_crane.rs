@@ -1,3 +1,6 @@
+//Tests take their rstest fixtures as arguments
+#![allow(clippy::too_many_arguments)]
+
 use std::collections::BTreeMap;
 use std::path::PathBuf;
 use std::thread::{sleep, JoinHandle};
@@ -7,6 +10,7 @@ use bincode::config::Configuration;
 use rstest::{fixture, rstest};
 use serial_test::serial;
 
+use mbei_component::coordinator::drain_cluster;
 use mbei_core::event::Event;
 use mbei_core::graph::{Delta, DeltaType, Node};
 use mbei_scenario_server::crane::{CraneEvent, CraneEventType};
@@ -15,9 +19,10 @@ use mbei_testdata::factory_scenario_builder::{barrels, SimpleFactoryScenario};
 
 use mbei_testdata::producer::TestdataProducer;
 
-use crate::common::{app_port, central_port, create_app_grpc_url, create_application_grpc_server, create_central, create_components, create_query_url_map, create_testdata_producer, get_all_deltas, three_crane_scenario};
+use crate::common::{app_port, central_port, create_app_grpc_url, create_application_grpc_server, create_central, create_components, create_multiplexed_components, create_multiplexed_query_url_map, create_query_url_map, create_testdata_producer, get_all_deltas, three_crane_scenario};
 
 #[cfg(test)]
+#[allow(dead_code)]
 mod common;
 
 #[fixture]
@@ -74,37 +79,47 @@ fn query_url_map(factory_scenario: SimpleFactoryScenario) -> BTreeMap<String, St
 
 #[fixture]
 async fn producer(query_url_map:BTreeMap<String,String>) -> TestdataProducer {
-    TestdataProducer::new(query_url_map, Some(Duration::from_secs(10)), true).await
+    TestdataProducer::new(query_url_map, Some(Duration::from_secs(10)), true).await.expect("Could not create producer")
 }
 
 #[fixture]
-fn components(app_grpc_url:&String,
+fn components(app_grpc_url: &str,
     factory_scenario: SimpleFactoryScenario,
 ) -> JoinHandle<()> {
-    create_components( app_grpc_url.clone(), &factory_scenario.queries)
+    create_components( app_grpc_url.to_string(), &factory_scenario.queries)
 }
 
 #[rstest]
+#[case::separate_servers(false, false)]
+#[case::one_server(true, false)]
+#[case::one_server_drained(true, true)]
 #[tokio::test]
 #[serial]
 async fn test_one_barrel_through_process(
+    #[case] multiplexed: bool,
+    #[case] drained: bool,
     start_logging: (),
     app_grpc_server: &JoinHandle<()>,
+    app_grpc_url: &str,
     config: Configuration,
-    components: JoinHandle<()>,
     central: JoinHandle<()>,
-    query_url_map: BTreeMap<String, String>,
     factory_scenario: SimpleFactoryScenario,
     central_db_path: PathBuf,
 ) {
     let _ = (app_grpc_server, start_logging); //avoid warning in compile
-    let producer = create_testdata_producer(query_url_map).await;
+    let query_names = factory_scenario.queries.iter().map(|q| q.name.clone()).collect();
+    let (components, query_url_map) = if multiplexed {
+        (create_multiplexed_components(app_grpc_url.to_string(), &factory_scenario.queries), create_multiplexed_query_url_map(&query_names))
+    } else {
+        (create_components(app_grpc_url.to_string(), &factory_scenario.queries), create_query_url_map(&query_names))
+    };
+    let producer = create_testdata_producer(query_url_map.clone()).await;
     let my_barrel = barrels(1).pop().unwrap();
-    let my_ramp = factory_scenario.ramps.get(0).unwrap();
-    let left_pickdrop = factory_scenario.crane_pickdrops.get(0).unwrap();
+    let my_ramp = factory_scenario.ramps.first().unwrap();
+    let left_pickdrop = factory_scenario.crane_pickdrops.first().unwrap();
     let right_pickdrop = factory_scenario.crane_pickdrops.get(1).unwrap();
     let middle_platform = factory_scenario.platforms.get(1).unwrap();
-    let left_crane = factory_scenario.cranes.get(0).unwrap();
+    let left_crane = factory_scenario.cranes.first().unwrap();
     let right_crane = factory_scenario.cranes.get(1).unwrap();
     let middle_stamp = factory_scenario.stamps.get(1).unwrap();
     let middle_stamp_assembly = factory_scenario.stamp_assemblies.get(1).unwrap();
@@ -190,7 +205,7 @@ async fn test_one_barrel_through_process(
         payload: drop_ramp_payload,
     };
 
-    let left_pickdrop_name = factory_scenario.queries.get(0).unwrap().name.clone();
+    let left_pickdrop_name = factory_scenario.queries.first().unwrap().name.clone();
     let right_pickdrop_name = factory_scenario.queries.get(1).unwrap().name.clone();
     let middle_stamp_name = factory_scenario.queries.get(3).unwrap().name.clone();
     producer
@@ -213,12 +228,20 @@ async fn test_one_barrel_through_process(
     producer
         .send_event_now(&left_pickdrop_name, drop_barrel_at_ramp)
         .await;
-    sleep(Duration::from_secs(10));
-
-    for q in &factory_scenario.queries {
-        producer.send_stop_now(q.name.as_str()).await;
+    if drained {
+        //No waiting for the events to be processed, as draining does
+        let all_queries_by_name = factory_scenario.queries.iter().map(|q| (q.name.clone(), q.clone())).collect();
+        let report = drain_cluster(&all_queries_by_name, &query_url_map, true, None, Duration::from_millis(100), Duration::from_secs(60))
+            .await
+            .expect("Cluster should drain");
+        assert_eq!(report.stages.last().unwrap().keys().collect::<Vec<_>>(), vec!["central"]);
+    } else {
+        sleep(Duration::from_secs(10));
+        for q in &factory_scenario.queries {
+            producer.send_stop_now(q.name.as_str()).await;
+        }
+        producer.send_stop_now("central").await;
     }
-    producer.send_stop_now("central").await;
     components.join().expect("Error joining components");
     central.join().expect("Error joining central");
 
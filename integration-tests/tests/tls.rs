@@ -0,0 +1,243 @@
+#![cfg(feature = "tls")]
+//Tests take their rstest fixtures as arguments
+#![allow(clippy::too_many_arguments)]
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::thread::{JoinHandle, sleep};
+use std::time::Duration;
+
+use bincode::config::Configuration;
+use rstest::{fixture, rstest};
+use serial_test::serial;
+use tonic::Code;
+
+use mbei_central::options::CentralOptions;
+use mbei_component::options::ComponentOptions;
+use mbei_core::event::{Event, Update};
+use mbei_core::graph::{Delta, DeltaType};
+use mbei_grpc::process_update::process_update_client::ProcessUpdateClient;
+use mbei_grpc::process_update_mapping::{request_from_update, set_request_target_query};
+use mbei_grpc::tls::{create_endpoint, TlsOptions};
+use mbei_scenario_server::create_tonic_server_with_tls;
+use mbei_scenario_server::crane::{CraneEvent, CraneEventType};
+use mbei_testdata::factory_scenario_builder::{barrels, crane_pickdrops, cranes, matched_pickdrop_query, platforms, ramps, SimpleFactoryScenario};
+use mbei_testdata::producer::TestdataProducer;
+
+use crate::common::{app_port, central_port, create_central_with_options, create_components_with_url_map, create_tls_query_url_map, get_all_deltas};
+
+#[cfg(test)]
+#[allow(dead_code)]
+mod common;
+
+#[fixture]
+#[once]
+pub fn start_logging() {
+    env_logger::init();
+}
+
+#[fixture]
+pub fn testdata_path() -> PathBuf {
+    let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    p.push("tests/tls/");
+    p
+}
+
+//Certificates generated by tests/certs/generate.sh
+fn create_tls_options(name: &str, ca_name: &str) -> TlsOptions {
+    let mut certs_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    certs_path.push("tests/certs/");
+    TlsOptions {
+        ca_certificate_path: certs_path.join(ca_name.to_string() + ".pem"),
+        certificate_path: certs_path.join(name.to_string() + ".pem"),
+        key_path: certs_path.join(name.to_string() + ".key"),
+        allowed_client_hosts: BTreeSet::new(),
+    }
+}
+
+fn tls_app_port() -> u16 {
+    app_port() - 10
+}
+
+#[fixture]
+#[once]
+fn tls_app_grpc_server() -> JoinHandle<()> {
+    create_tonic_server_with_tls(tls_app_port(), Some(create_tls_options("localhost", "ca")))
+}
+
+#[fixture]
+fn central_db_path(testdata_path: PathBuf) -> PathBuf {
+    let mut sqlite_db_path = testdata_path.clone();
+    sqlite_db_path.push("central.db");
+    sqlite_db_path
+}
+
+#[fixture]
+fn central(central_db_path: PathBuf) -> JoinHandle<()> {
+    let options = CentralOptions {
+        tls: Some(create_tls_options("localhost", "ca")),
+        ..CentralOptions::default()
+    };
+    create_central_with_options(central_db_path, central_port(), options)
+}
+
+#[fixture]
+fn config() -> Configuration {
+    bincode::config::standard()
+}
+
+#[fixture]
+pub fn factory_scenario() -> SimpleFactoryScenario {
+    let my_crane = cranes(1).pop().unwrap();
+    let my_pickdrop = crane_pickdrops(1).pop().unwrap();
+    let my_ramp = ramps(1).pop().unwrap();
+    let my_platform = platforms(1).pop().unwrap();
+
+    let matched_query = matched_pickdrop_query(
+        my_crane.clone(),
+        my_pickdrop.clone(),
+        vec![my_ramp.clone(), my_platform.clone(), my_ramp.clone()],
+        "_matched");
+
+    SimpleFactoryScenario {
+        cranes: vec![my_crane],
+        crane_pickdrops: vec![my_pickdrop],
+        platforms: vec![my_platform],
+        ramps: vec![my_ramp],
+        stamps: vec![],
+        stamp_assemblies: vec![],
+        queries: vec![matched_query],
+    }
+}
+
+#[fixture]
+fn query_url_map(factory_scenario: SimpleFactoryScenario) -> BTreeMap<String, String> {
+    let query_names = factory_scenario.queries.iter().map(|q|q.name.clone()).collect();
+    create_tls_query_url_map(&query_names)
+}
+
+#[fixture]
+fn components(tls_app_grpc_server: &JoinHandle<()>,
+              factory_scenario: SimpleFactoryScenario,
+              query_url_map: BTreeMap<String, String>) -> JoinHandle<()> {
+    let _ = tls_app_grpc_server;
+    let options = ComponentOptions {
+        tls: Some(create_tls_options("localhost", "ca")),
+        ..ComponentOptions::default()
+    };
+    let app_grpc_url = "https://localhost:".to_string() + &tls_app_port().to_string();
+    create_components_with_url_map(app_grpc_url, &factory_scenario.queries, query_url_map, options)
+}
+
+async fn create_tls_producer(query_url_map: BTreeMap<String, String>) -> TestdataProducer {
+    TestdataProducer::with_tls(query_url_map, Some(Duration::from_secs(10)), true, Some(create_tls_options("localhost", "ca"))).await.expect("Could not create producer")
+}
+
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_simple_delta_and_event_over_mutual_tls(start_logging: (),
+                                                     config: Configuration,
+                                                     components: JoinHandle<()>,
+                                                     central: JoinHandle<()>,
+                                                     query_url_map: BTreeMap<String, String>,
+                                                     factory_scenario: SimpleFactoryScenario,
+                                                     central_db_path: PathBuf) {
+    let _ = &start_logging;
+    let producer = create_tls_producer(query_url_map).await;
+    let my_barrel = barrels(1).pop().unwrap();
+    let my_platform = factory_scenario.platforms.first().unwrap();
+    let my_crane = factory_scenario.cranes.first().unwrap();
+    let my_pickdrop = factory_scenario.crane_pickdrops.first().unwrap();
+    let my_barrel_at_my_platform = Delta {
+        src: my_barrel.clone(),
+        trg: my_platform.clone(),
+        edge_type: "At".to_string(),
+        timestamp: 1u64,
+        delta_type: DeltaType::Addition,
+    };
+    let crane_event = CraneEvent {
+        instance_node_id: my_platform.instance_node_name.as_ref().unwrap().clone(),
+        crane_event_type: CraneEventType::PickUp,
+    };
+    let payload = bincode::encode_to_vec(crane_event, config)
+        .expect("Encodable");
+    let pickup_barrel_at_platform = Event {
+        event_id: "myevent".to_string(),
+        timestamp: 3u64,
+        node_id: my_pickdrop.instance_node_name.as_ref().unwrap().clone(),
+        payload,
+    };
+
+    producer.send_deltas_now("mydelta", "pickdrop_matched", vec![my_barrel_at_my_platform.clone()]).await;
+    producer.send_event_now( "pickdrop_matched", pickup_barrel_at_platform).await;
+    sleep(Duration::from_secs(5));
+
+    producer.send_stop_now("pickdrop_matched").await;
+    producer.send_stop_now("central").await;
+
+    components.join().expect("Error joining component");
+    central.join().expect("Error joining central");
+
+    let deltas = get_all_deltas(central_db_path);
+    let expected_deltas =
+        vec![
+            my_barrel_at_my_platform.clone(),
+            Delta {
+                src: my_barrel.clone(),
+                trg: my_platform.clone(),
+                edge_type: "At".to_string(),
+                timestamp: 3,
+                delta_type: DeltaType::Removal,
+            },
+            Delta {
+                src: my_barrel.clone(),
+                trg: my_crane.clone(),
+                edge_type: "At".to_string(),
+                timestamp: 4,
+                delta_type: DeltaType::Addition,
+            }];
+    assert_eq!(BTreeSet::from_iter(deltas), BTreeSet::from_iter(expected_deltas));
+    sleep(Duration::from_secs(3));
+}
+
+#[rstest]
+#[tokio::test]
+#[serial]
+async fn test_clients_without_accepted_certificate_are_rejected(start_logging: (),
+                                                                components: JoinHandle<()>,
+                                                                central: JoinHandle<()>,
+                                                                query_url_map: BTreeMap<String, String>) {
+    let _ = &start_logging;
+    let url = query_url_map.get("pickdrop_matched").unwrap().clone();
+    let mut stop = request_from_update(&Update::Stop);
+    set_request_target_query(&mut stop, "pickdrop_matched");
+
+    //The certificate is from the ca, but not for a peer in the url map
+    let intruder = create_endpoint(&url, Some(&create_tls_options("intruder", "ca"))).unwrap();
+    let mut intruder_client = ProcessUpdateClient::connect(intruder).await.expect("Handshake should succeed");
+    let status = intruder_client.send(stop.clone()).await.expect_err("Intruder should be rejected");
+    assert_eq!(status.code(), Code::PermissionDenied);
+
+    //The certificate is for localhost, but not from the ca
+    let untrusted = create_endpoint(&url, Some(&create_tls_options("untrusted", "ca"))).unwrap();
+    let untrusted_result = match ProcessUpdateClient::connect(untrusted).await {
+        Ok(mut client) => client.send(stop.clone()).await.map(|_| ()),
+        Err(_) => Err(tonic::Status::unavailable("Handshake failed")),
+    };
+    assert!(untrusted_result.is_err());
+
+    //Plaintext is not accepted by a server with TLS
+    let plaintext = create_endpoint(&url.replace("https://", "http://"), None).unwrap();
+    let plaintext_result = match ProcessUpdateClient::connect(plaintext).await {
+        Ok(mut client) => client.send(stop.clone()).await.map(|_| ()),
+        Err(_) => Err(tonic::Status::unavailable("Connection failed")),
+    };
+    assert!(plaintext_result.is_err());
+
+    let producer = create_tls_producer(query_url_map).await;
+    producer.send_stop_now("pickdrop_matched").await;
+    producer.send_stop_now("central").await;
+    components.join().expect("Error joining component");
+    central.join().expect("Error joining central");
+}
@@ -2,17 +2,11 @@ use application_component::{ApplicationResponse, QueryNode};
 use delta::{InstanceNode};
 use tonic::{Response, Status};
 
-pub mod event {
-    tonic::include_proto!("event");
-}
-
-pub mod delta {
-    tonic::include_proto!("delta");
-}
+use mbei_core::event::Event;
+use mbei_core::query::{GroupedQueryMatch, Query};
+use mbei_grpc::application_component_mapping::{create_application_request, delta_vec_from_response};
 
-pub mod application_component {
-    tonic::include_proto!("application_component");
-}
+pub use mbei_grpc::{application_component, delta};
 
 pub(crate) fn empty_response() -> Result<Response<ApplicationResponse>, Status> {
     Ok(Response::new(ApplicationResponse { deltas: vec![] }))
@@ -28,4 +22,27 @@ pub(crate) fn as_instance_node(query_node: &QueryNode) -> InstanceNode {
         node_class: query_node.node_class,
         value: None,
     }
-}
\ No newline at end of file
+}
+
+//Native interpreters run the same application functions as the grpc service, on a request built from the component's own data
+pub(crate) fn interpret_with<F>(
+    application_function: F,
+    query: &Query,
+    grouped_match: &GroupedQueryMatch,
+    event: &Event,
+) -> Result<Vec<mbei_core::graph::Delta>, String>
+where
+    F: Fn(&application_component::ApplicationRequest) -> Result<Response<ApplicationResponse>, Status>,
+{
+    let request = create_application_request(
+        query.name.clone(),
+        query.application.clone(),
+        grouped_match,
+        &query.graph,
+        event,
+    );
+    match application_function(&request) {
+        Ok(response) => delta_vec_from_response(response.into_inner()),
+        Err(status) => Err(format!("status {}: {}", status.code(), status.message())),
+    }
+}
@@ -28,6 +28,10 @@ limitations under the License.*/
 
 use crate::common::application_component::{ApplicationRequest, ApplicationResponse, Match, QueryEdge};
 use crate::common::delta::{Delta, DeltaType};
+use crate::common::interpret_with;
+use mbei_component::interpreter::Interpreter;
+use mbei_core::event::Event;
+use mbei_core::query::{GroupedQueryMatch, Query};
 use bincode::{config::Configuration, Decode, Encode};
 use log::{debug, warn};
 use tonic::{Response, Status};
@@ -77,10 +81,10 @@ impl ConveyorApplication {
     }
 
     fn process_conveyor_event(&self, timestamp:u64, _conveyor_event:ConveyorEvent,
-        matches: &Vec<Match>,
-        query_graph: &Vec<QueryEdge>) -> Result<Response<ApplicationResponse>, Status> {
+        matches: &[Match],
+        query_graph: &[QueryEdge]) -> Result<Response<ApplicationResponse>, Status> {
         assert_eq!(matches.len(), 1);
-        let single_match = matches.get(0).unwrap();
+        let single_match = matches.first().unwrap();
         assert_eq!(single_match.tuples.len(), 2);
         let mut barrel_at_conveyor = None;
         let mut barrel_has_type = None;
@@ -132,4 +136,10 @@ impl ConveyorApplication {
 
         Ok(Response::new(ApplicationResponse{ deltas: vec![remove_barrel, add_barrel] }))
     }
+}
+
+impl Interpreter for ConveyorApplication {
+    fn interpret(&self, query: &Query, grouped_match: &GroupedQueryMatch, event: &Event) -> Result<Vec<mbei_core::graph::Delta>, String> {
+        interpret_with(|request| self.conveyor_function(request), query, grouped_match, event)
+    }
 }
\ No newline at end of file
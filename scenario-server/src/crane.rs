@@ -13,11 +13,15 @@ See the License for the specific language governing permissions and
 limitations under the License.*/
 
 use crate::common::application_component::{
-    ApplicationRequest, ApplicationResponse, Match,
+    ApplicationRequest, ApplicationResponse, Match, MatchTuple,
     QueryEdge};
 use crate::common::delta::{Delta, DeltaType};
 use crate::common::{as_instance_node, empty_response};
 
+use crate::common::interpret_with;
+use mbei_component::interpreter::Interpreter;
+use mbei_core::event::Event;
+use mbei_core::query::{GroupedQueryMatch, Query};
 use bincode::{config::Configuration, Decode, Encode};
 use tonic::{Response, Status};
 use log::{debug, info, warn};
@@ -102,7 +106,7 @@ impl CraneApplication {
         let mut crane = None;
         for edge in query_graph {
             if edge.src.as_ref().unwrap().node_type.as_ref().unwrap().s == "Crane" {
-                crane = edge.src.as_ref().clone();
+                crane = edge.src.as_ref();
                 break;
             }
         }
@@ -112,7 +116,7 @@ impl CraneApplication {
         }
         let crane = crane.unwrap();
 
-        let mut found_barrel_at_node_tuple = None;
+        let mut found_barrel_at_node_tuple: Option<&MatchTuple> = None;
 
         for m in matches {
             for match_tuple in &m.tuples {
@@ -126,18 +130,9 @@ impl CraneApplication {
                     .instance_node_id
                     == crane_event.instance_node_id
                 {
-                    if found_barrel_at_node_tuple.is_none() {
-                        found_barrel_at_node_tuple = Some(match_tuple)
-                    } else if found_barrel_at_node_tuple
-                        .unwrap()
-                        .trg
-                        .as_ref()
-                        .unwrap()
-                        .from_timestamp < match_tuple
-                            .trg
-                            .as_ref()
-                            .unwrap()
-                            .from_timestamp
+                    //Keeps the latest of the matched barrels
+                    if !matches!(found_barrel_at_node_tuple, Some(found) if found.trg.as_ref().unwrap().from_timestamp
+                        >= match_tuple.trg.as_ref().unwrap().from_timestamp)
                     {
                         found_barrel_at_node_tuple = Some(match_tuple)
                     }
@@ -159,7 +154,7 @@ impl CraneApplication {
             src: barrel_at_node_tuple.trg.as_ref().unwrap().src.clone(),
             trg: barrel_at_node_tuple.trg.as_ref().unwrap().trg.clone(),
             edge_type: barrel_at_node_tuple.trg.as_ref().unwrap().edge_type.clone(),
-            timestamp: timestamp,
+            timestamp,
             delta_type: DeltaType::Removal as i32,
         };
         let barrel_added_to_crane = Delta {
@@ -206,7 +201,7 @@ impl CraneApplication {
         }
         let object = object.unwrap();
 
-        let mut found_barrel_at_crane_tuple = None;
+        let mut found_barrel_at_crane_tuple: Option<&MatchTuple> = None;
         for m in matches {
             for match_tuple in &m.tuples {
                 if match_tuple
@@ -219,19 +214,9 @@ impl CraneApplication {
                     .node_type
                     == "Crane"
                 {
-                    if found_barrel_at_crane_tuple.is_none() {
-                        found_barrel_at_crane_tuple = Some(match_tuple);
-                    } else if found_barrel_at_crane_tuple
-                        .unwrap()
-                        .trg
-                        .as_ref()
-                        .unwrap()
-                        .from_timestamp
-                        < match_tuple
-                            .trg
-                            .as_ref()
-                            .unwrap()
-                            .from_timestamp
+                    //Keeps the latest of the matched barrels
+                    if !matches!(found_barrel_at_crane_tuple, Some(found) if found.trg.as_ref().unwrap().from_timestamp
+                        >= match_tuple.trg.as_ref().unwrap().from_timestamp)
                     {
                         found_barrel_at_crane_tuple = Some(match_tuple);
                     }
@@ -258,7 +243,7 @@ impl CraneApplication {
                 .unwrap()
                 .edge_type
                 .clone(),
-            timestamp: timestamp,
+            timestamp,
             delta_type: DeltaType::Removal as i32,
         };
         let barrel_added_to_crane = Delta {
@@ -277,4 +262,10 @@ impl CraneApplication {
         debug!("Successfully processed drop event, returning deltas");
         Ok(Response::new(ApplicationResponse { deltas }))
     }
+}
+
+impl Interpreter for CraneApplication {
+    fn interpret(&self, query: &Query, grouped_match: &GroupedQueryMatch, event: &Event) -> Result<Vec<mbei_core::graph::Delta>, String> {
+        interpret_with(|request| self.crane_function(request), query, grouped_match, event)
+    }
 }
\ No newline at end of file
@@ -16,6 +16,10 @@ limitations under the License.*/
 use crate::common::application_component::{ApplicationRequest, ApplicationResponse, Match, QueryEdge};
 use crate::common::delta::{Delta, DeltaType, InstanceNode, NodeClass, OptionalBytes};
 
+use crate::common::interpret_with;
+use mbei_component::interpreter::Interpreter;
+use mbei_core::event::Event;
+use mbei_core::query::{GroupedQueryMatch, Query};
 use bincode::{config::Configuration, Decode, Encode};
 use log::{debug, warn};
 use tonic::{Response, Status};
@@ -69,12 +73,12 @@ impl DetectorApplication {
         }
     }
 
-    fn process_detector_event(&self, timestamp:u64, detector_event:DetectorEvent, matches: &Vec<Match>,
-        _query_graph: &Vec<QueryEdge>) -> Result<Response<ApplicationResponse>, Status>{
+    fn process_detector_event(&self, timestamp:u64, detector_event:DetectorEvent, matches: &[Match],
+        _query_graph: &[QueryEdge]) -> Result<Response<ApplicationResponse>, Status>{
         assert_eq!(matches.len(), 1);
-        let single_match = matches.get(0).unwrap();
+        let single_match = matches.first().unwrap();
         assert_eq!(single_match.tuples.len(), 1);
-        let single_tuple = single_match.tuples.get(0).unwrap();
+        let single_tuple = single_match.tuples.first().unwrap();
         let barrel = single_tuple.trg.as_ref().unwrap().src.as_ref().unwrap();
         let barrel_type = detector_event.barrel_material_type;
 
@@ -91,6 +95,12 @@ impl DetectorApplication {
     }
 }
 
+impl Interpreter for DetectorApplication {
+    fn interpret(&self, query: &Query, grouped_match: &GroupedQueryMatch, event: &Event) -> Result<Vec<mbei_core::graph::Delta>, String> {
+        interpret_with(|request| self.detector_function(request), query, grouped_match, event)
+    }
+}
+
 pub fn create_material_type_node(barrel_node_id: String, barrel_material_type: BarrelMaterialType, config: Configuration) -> InstanceNode {
     let barrel_type_bytes = bincode::encode_to_vec(barrel_material_type, config).expect("Encoding error");
     InstanceNode{
@@ -12,7 +12,11 @@ WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
 See the License for the specific language governing permissions and
 limitations under the License.*/
 
+//The application services return tonic::Status as their error, which is larger than clippy prefers
+#![allow(clippy::result_large_err)]
+
 use std::net::SocketAddr;
+use std::sync::Arc;
 use std::thread;
 use std::thread::{sleep, JoinHandle};
 use std::time::Duration;
@@ -27,6 +31,8 @@ use crate::conveyor::ConveyorApplication;
 use crate::crane::CraneApplication;
 use crate::detector::DetectorApplication;
 use crate::stamp::StampApplication;
+use mbei_component::interpreter::InterpreterRegistry;
+use mbei_grpc::tls::{configure_server, PeerIdentityCheck, TlsOptions};
 
 pub mod conveyor;
 pub mod crane;
@@ -36,6 +42,11 @@ mod common;
 
 
 pub async fn start_serving(port: u16) {
+    start_serving_with_tls(port, None).await
+}
+
+/// Serves with mutual TLS if configured, so that components must present a certificate from the ca
+pub async fn start_serving_with_tls(port: u16, tls: Option<TlsOptions>) {
     info!("Starting server at port {}", &port);
     let address_string = "[::]:".to_owned() + &port.to_string();
     let address: SocketAddr = address_string.parse().expect("Error parsing server address");
@@ -45,8 +56,9 @@ pub async fn start_serving(port: u16) {
         conveyor: ConveyorApplication::new(),
         detector: DetectorApplication::new(),
     };
-    let svc = CallApplicationServer::new(application_component_service);
-    Server::builder()
+    let svc = CallApplicationServer::with_interceptor(application_component_service, PeerIdentityCheck::new(tls.as_ref()));
+    configure_server(Server::builder(), tls.as_ref())
+        .expect("Could not configure server")
         .add_service(svc)
         .serve(address)
         .await
@@ -79,12 +91,25 @@ impl CallApplication for ApplicationComponentService {
     }
 }
 
+/// The scenario applications as native interpreters, so that components can run them without the grpc server.
+pub fn create_interpreter_registry() -> InterpreterRegistry {
+    let mut registry = InterpreterRegistry::new();
+    registry.register("pickdrop", Arc::new(CraneApplication::new()));
+    registry.register("stamp", Arc::new(StampApplication::new()));
+    registry.register("conveyor", Arc::new(ConveyorApplication::new()));
+    registry.register("detector", Arc::new(DetectorApplication::new()));
+    registry
+}
 
 pub fn create_tonic_server(port:u16) -> JoinHandle<()> {
+    create_tonic_server_with_tls(port, None)
+}
+
+pub fn create_tonic_server_with_tls(port:u16, tls: Option<TlsOptions>) -> JoinHandle<()> {
     let rt = Runtime::new().expect("Could not create runtime");
     let handle = thread::spawn(move || {
         rt.block_on(async {
-            let handle = tokio::spawn(start_serving(port));
+            let handle = tokio::spawn(start_serving_with_tls(port, tls));
             handle.await.expect("Panic in grpc server");
         });
     });
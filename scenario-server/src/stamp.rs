@@ -18,6 +18,10 @@ use crate::common::application_component::{
 use crate::common::delta::{Delta, DeltaType, InstanceNode, NodeClass, OptionalBytes};
 
 use crate::common::empty_response;
+use crate::common::interpret_with;
+use mbei_component::interpreter::Interpreter;
+use mbei_core::event::Event;
+use mbei_core::query::{GroupedQueryMatch, Query};
 use bincode::{config::Configuration, Decode, Encode};
 use log::{debug, warn};
 use tonic::{Response, Status};
@@ -126,6 +130,12 @@ impl StampApplication {
     }
 }
 
+impl Interpreter for StampApplication {
+    fn interpret(&self, query: &Query, grouped_match: &GroupedQueryMatch, event: &Event) -> Result<Vec<mbei_core::graph::Delta>, String> {
+        interpret_with(|request| self.stamp_function(request), query, grouped_match, event)
+    }
+}
+
 pub fn create_stamp_node(
     instance_node_id: String,
     timestamp: u64,
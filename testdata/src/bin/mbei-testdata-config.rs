@@ -15,7 +15,7 @@ limitations under the License.*/
 use log::error;
 use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use bincode::config::Configuration;
 use rand_chacha::ChaCha8Rng;
 use rand_chacha::rand_core::{RngCore, SeedableRng};
@@ -24,8 +24,37 @@ use mbei_core::query::Query;
 use mbei_testdata::factory_scenario_builder::{
     complex_factory_scenario_builder, create_simple_factory_scenario,
 };
+use serde::Serialize;
 use structopt::StructOpt;
 
+const COMPONENT_PORT: u16 = 10000;
+
+//The parts of the mbei-component config file that are written here, the rest is left at defaults
+#[derive(Serialize)]
+struct ComponentConfigFile {
+    version: u64,
+    identity: IdentityConfig,
+    queries: QueriesConfig,
+    peers: BTreeMap<String, String>,
+    application: ApplicationConfig,
+}
+
+#[derive(Serialize)]
+struct IdentityConfig {
+    port: u16,
+}
+
+#[derive(Serialize)]
+struct QueriesConfig {
+    path: String,
+    assignments: BTreeMap<u32, Vec<String>>,
+}
+
+#[derive(Serialize)]
+struct ApplicationConfig {
+    url: String,
+}
+
 #[derive(StructOpt)]
 pub struct ComplexFactoryCommandArgs {
     #[structopt(short = "-s", long = "--size")]
@@ -78,12 +107,12 @@ fn main() {
 }
 
 fn write_scenario_to_path(
-    output_path: &PathBuf,
+    output_path: &Path,
     queries: Vec<Query>,
     overwrite_files: bool,
     nodes: u32,
 ) {
-    let ouput_path = output_path.clone();
+    let ouput_path = output_path.to_path_buf();
     if !ouput_path.exists() || !ouput_path.is_dir() {
         error!(
             "Output path {:?} does not exist or is not a directory",
@@ -98,10 +127,9 @@ fn write_scenario_to_path(
     write_all_queries(queries.clone(), ouput_path.clone(), overwrite_files, config);
 
     let assignment = create_assignment(query_names.clone(), nodes);
-    write_assignments(assignment.clone(), ouput_path.clone(), overwrite_files);
-
-    let names_url_map = create_names_url_map(assignment);
-    write_names_url_map(names_url_map, output_path.clone(), overwrite_files);
+    let names_url_map = create_names_url_map(assignment.clone());
+    write_component_config(assignment, names_url_map.clone(), ouput_path.clone(), overwrite_files);
+    write_names_url_map(names_url_map, ouput_path, overwrite_files);
 }
 
 fn check_if_file_exists_and_possibly_overwrite(p: &PathBuf, overwrite: bool) {
@@ -132,13 +160,11 @@ fn create_assignment(mut query_names:Vec<String>, nodes:u32) -> BTreeMap<u32, Ve
 
 fn create_names_url_map(assignment: BTreeMap<u32, Vec<String>>) -> BTreeMap<String, String> {
     let mut names_url_map = BTreeMap::new();
+    //All queries of a host are served on the same port
     for (k, qs) in assignment {
-        let mut i = 0;
+        let url = format!("http://mbei-component-{}.mbei-component.mbei.svc.cluster.local:{}", k, COMPONENT_PORT);
         for q in qs {
-            let port = 10000 + i;
-            let url = format!("http://mbei-component-{}.mbei-component.mbei.svc.cluster.local:{}", k, port);
             names_url_map.insert(q, url.clone());
-            i += 1;
         }
     }
     names_url_map
@@ -157,13 +183,21 @@ fn write_all_queries(queries:Vec<Query>, output_path:PathBuf, overwrite_files:bo
     serde_yaml::to_writer(queries_file, &out).expect("Error writing to disk");
 }
 
-fn write_assignments(assignment: BTreeMap<u32, Vec<String>>, output_path:PathBuf, overwrite_files:bool) {
-    let mut assignments_path = output_path.clone();
-    assignments_path.push("query-assignments.yaml");
-    check_if_file_exists_and_possibly_overwrite(&assignments_path, overwrite_files);
-    let assignment_file =
-        File::create(assignments_path.as_path()).expect("Error opening file for writing");
-    serde_yaml::to_writer(assignment_file, &assignment).expect("Error writing to disk");
+//The config file read by mbei-component, each host picks its queries from the assignment by the number in its hostname
+fn write_component_config(assignment: BTreeMap<u32, Vec<String>>, names_url_map: BTreeMap<String, String>, output_path:PathBuf, overwrite_files:bool) {
+    let mut component_config_path = output_path.clone();
+    component_config_path.push("component-config.yaml");
+    check_if_file_exists_and_possibly_overwrite(&component_config_path, overwrite_files);
+    let component_config = ComponentConfigFile {
+        version: 1,
+        identity: IdentityConfig { port: COMPONENT_PORT },
+        queries: QueriesConfig { path: "all-queries.yaml".to_string(), assignments: assignment },
+        peers: names_url_map,
+        application: ApplicationConfig { url: "http://localhost:9999".to_string() },
+    };
+    let component_config_file =
+        File::create(component_config_path.as_path()).expect("Error opening file for writing");
+    serde_yaml::to_writer(component_config_file, &component_config).expect("Error writing to disk");
 }
 
 fn write_names_url_map(names_url_map: BTreeMap<String, String>, output_path:PathBuf, overwrite_files:bool) {
@@ -14,12 +14,12 @@ limitations under the License.*/
 
 use std::collections::BTreeMap;
 use std::fs::File;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use log::info;
 
 use structopt::StructOpt;
-use mbei_testdata::producer::{TestdataProducer, TopicNameAndUpdate};
+use mbei_testdata::producer::{ProducerTlsArgs, TestdataProducer, TopicNameAndUpdate};
 
 #[derive(StructOpt)]
 pub struct Cli {
@@ -37,6 +37,9 @@ pub struct Cli {
 
     #[structopt(short = "-l", long = "--latency")]
     pub latency: Option<bool>,
+
+    #[structopt(flatten)]
+    pub tls: ProducerTlsArgs,
 }
 
 #[tokio::main]
@@ -47,23 +50,21 @@ async fn main() {
     let messages: Vec<TopicNameAndUpdate> = serde_yaml::from_reader(input_reader).expect("Error parsing messages");
 
     let query_names_url_map = parse_names_url_map(&cli.names_url_path);
-    let use_central = match cli.use_central {
-        None => {false}
-        Some(u) => {u}
-    };
+    let use_central = cli.use_central.unwrap_or_default();
 
     info!("Starting producer");
-    let producer = TestdataProducer::new(query_names_url_map, Some(Duration::from_secs(15)), use_central).await;
+    let tls = cli.tls.to_tls_options();
+    let producer = match TestdataProducer::with_tls(query_names_url_map, Some(Duration::from_secs(15)), use_central, tls).await {
+        Ok(producer) => producer,
+        Err(e) => {
+            eprintln!("Could not create producer: {}", e);
+            std::process::exit(1);
+        }
+    };
     info!("Finished starting producer");
     info!("Started sending messages");
-    let reverse = match cli.reverse {
-        None => {false}
-        Some(t) => {t}
-    };
-    let latency = match cli.latency {
-        None => {false},
-        Some(t) => {t}
-    };
+    let reverse = cli.reverse.unwrap_or_default();
+    let latency = cli.latency.unwrap_or_default();
     if !reverse && !latency {
         info!("Sending messages in normal order");
         producer.send_messages_in_vec_until_max_queue(messages, 20, 100, 10, 1000).await;
@@ -79,8 +80,8 @@ async fn main() {
     info!("Finished sending messages");
 }
 
-fn parse_names_url_map(names_url_path: &PathBuf) -> BTreeMap<String, String> {
-    let names_url_map_reader = File::open(names_url_path.as_path()).expect("File open error");
+fn parse_names_url_map(names_url_path: &Path) -> BTreeMap<String, String> {
+    let names_url_map_reader = File::open(names_url_path).expect("File open error");
     serde_yaml::from_reader(names_url_map_reader).expect("Can parse YAML")
 }
 
@@ -14,12 +14,12 @@ limitations under the License.*/
 
 use std::collections::BTreeMap;
 use std::fs::File;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use log::{debug, info};
 
 use mbei_testdata::factory_scenario_builder::{complex_factory_scenario_builder, create_simple_factory_scenario};
-use mbei_testdata::producer::{TestdataProducer};
+use mbei_testdata::producer::{ProducerTlsArgs, TestdataProducer};
 use structopt::StructOpt;
 use mbei_testdata::complex_factory_scenario::ComplexFactoryScenarioSimulator;
 use mbei_testdata::simple_factory_scenario::{SimpleFactoryScenarioSimulator};
@@ -54,6 +54,17 @@ pub struct Cli {
 
     #[structopt(short = "-c", long = "--central")]
     pub use_central: Option<bool>,
+
+    //How often the producer declares that it sends nothing before its current timestamp
+    #[structopt(long = "--heartbeat-interval-ms", default_value = "1000")]
+    pub heartbeat_interval_ms: u64,
+
+    /// Sent with the watermarks, unique to each producer and the same after restarts
+    #[structopt(long = "--source-id", default_value = "testdata-producer")]
+    pub source_id: String,
+
+    #[structopt(flatten)]
+    pub tls: ProducerTlsArgs,
 }
 
 #[tokio::main]
@@ -63,38 +74,41 @@ async fn main() {
     let mut producer_config_path = PathBuf::new();
     producer_config_path.push("producer-timestamp.yaml");
 
-    let init_timestamp;
-    if producer_config_path.exists() {
+    let init_timestamp = if producer_config_path.exists() {
         let init_timestamp_reader = File::open(producer_config_path.as_path()).expect("File exists");
         let mut init_timestamp_map: BTreeMap<String, u64> = serde_yaml::from_reader(init_timestamp_reader).expect("Can parse YAML");
-        init_timestamp = init_timestamp_map.remove("timestamp").expect("Did not have timestamp in config");
+        init_timestamp_map.remove("timestamp").expect("Did not have timestamp in config")
     } else {
-        init_timestamp = 0;
-    }
+        0
+    };
 
     let query_names_url_map = parse_names_url_map(&cli.names_url_path);
-    let use_central = match cli.use_central {
-        None => {false}
-        Some(u) => {u}
-    };
+    let use_central = cli.use_central.unwrap_or_default();
 
     info!("Starting from timestamp: {}", &init_timestamp);
-    let mut producer = TestdataProducer::new(query_names_url_map, Some(Duration::from_secs(15)), use_central).await;
+    let tls = cli.tls.to_tls_options();
+    let mut producer = match TestdataProducer::with_tls(query_names_url_map, Some(Duration::from_secs(15)), use_central, tls).await {
+        Ok(producer) => producer,
+        Err(e) => {
+            eprintln!("Could not create producer: {}", e);
+            std::process::exit(1);
+        }
+    };
+    producer = producer.with_source_id(&cli.source_id);
     debug!("Created producer");
-    let timestamp;
-    match cli.command {
+    let timestamp = match cli.command {
         Command::SimpleFactoryConfig => {
             let simple_factory_scenario = create_simple_factory_scenario();
 
             let mut sfss = SimpleFactoryScenarioSimulator::new(simple_factory_scenario, init_timestamp, use_central);
-            timestamp = producer.produce_until_interrupted_or_n_reached(&mut sfss, cli.messages_per_second, cli.n).await;
+            producer.produce_until_interrupted_or_n_reached(&mut sfss, cli.messages_per_second, cli.n, Duration::from_millis(cli.heartbeat_interval_ms)).await
         },
         Command::ComplexFactoryConfig(fca) => {
             let complex_factory_scenario = complex_factory_scenario_builder(fca.size);
             let mut cfss = ComplexFactoryScenarioSimulator::new(complex_factory_scenario, init_timestamp, use_central);
-            timestamp = producer.produce_until_interrupted_or_n_reached(&mut cfss, cli.messages_per_second, cli.n).await;
+            producer.produce_until_interrupted_or_n_reached(&mut cfss, cli.messages_per_second, cli.n, Duration::from_millis(cli.heartbeat_interval_ms)).await
         }
-    }
+    };
 
     info!("Writing timestamp: {}", &timestamp);
     let timestamp_map = BTreeMap::from([("timestamp".to_string(), timestamp)]);
@@ -102,8 +116,8 @@ async fn main() {
     serde_yaml::to_writer(timestamp_file, &timestamp_map).expect("Could not write to file");
 }
 
-fn parse_names_url_map(names_url_path: &PathBuf) -> BTreeMap<String, String> {
-    let names_url_map_reader = File::open(names_url_path.as_path()).expect("File open error");
+fn parse_names_url_map(names_url_path: &Path) -> BTreeMap<String, String> {
+    let names_url_map_reader = File::open(names_url_path).expect("File open error");
     serde_yaml::from_reader(names_url_map_reader).expect("Can parse YAML")
 }
 
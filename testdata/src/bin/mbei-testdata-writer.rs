@@ -55,20 +55,16 @@ async fn main() {
     env_logger::init();
     let cli: Cli = Cli::from_args();
 
-    let use_central = match cli.use_central {
-        None => {false}
-        Some(u) => {u}
-    };
+    let use_central = cli.use_central.unwrap_or_default();
 
-    let messages;
     let messages_file = File::create(cli.output_file.as_path()).expect("Error opening file for writing");
-    match cli.command {
+    let messages = match cli.command {
         Command::ComplexFactoryConfig(fca) => {
             let complex_factory_scenario = complex_factory_scenario_builder(fca.size);
             let mut cfss = ComplexFactoryScenarioSimulator::new(complex_factory_scenario, 0, use_central);
-            messages = create_n_messages(&mut cfss, cli.n);
+            create_n_messages(&mut cfss, cli.n)
         }
-    }
+    };
     serde_yaml::to_writer(messages_file, &messages).expect("Could not write to file");
 }
 
@@ -77,12 +73,11 @@ fn create_n_messages(message_creator: &mut dyn MessageCreator, n:u32) -> Vec<Top
     let mut seq = 0;
     let mut stopping = false;
     loop {
-        if !stopping {
-            if n <= seq {
+        if !stopping
+            && n <= seq {
                     stopping = true;
                     info!("Reached max seq, stopping");
             }
-        }
         if stopping && message_creator.is_finished() {
             info!("All barrels are flushed");
             break;
@@ -144,15 +144,13 @@ impl MessageCreator for ComplexFactoryScenarioSimulator {
                         self.use_central,
                     ));
                     let detector = self.detector_of_platform.get(&c).unwrap();
-                    let barrel_material_type;
-                    if self.current_timestamp % 2 == 0 {
-                        barrel_material_type = BarrelMaterialType::Metal;
-                    } else {
-                        barrel_material_type = BarrelMaterialType::Plastic;
-                    }
+                    let barrel_material_type = match self.current_timestamp % 2 {
+                        0 => BarrelMaterialType::Metal,
+                        _ => BarrelMaterialType::Plastic,
+                    };
                     self.barrel_material_type
                         .insert(barrel.clone(), barrel_material_type.clone());
-                    let topic_name = self.topic_names_map.get(detector).unwrap().get(0).unwrap();
+                    let topic_name = self.topic_names_map.get(detector).unwrap().first().unwrap();
                     topic_names_and_updates.push(create_detector_message(
                         barrel,
                         barrel_material_type,
@@ -166,11 +164,11 @@ impl MessageCreator for ComplexFactoryScenarioSimulator {
                 }
             } else if c.node_type.as_ref().unwrap() == "PickDrop" {
                 let crane = self.pickdrop_belongs_to_crane_map.get(&c).unwrap();
-                let surrounding = &self.objects_surrounding_crane_map.get(&crane).unwrap();
+                let surrounding = &self.objects_surrounding_crane_map.get(crane).unwrap();
                 let s = self.generator.next_u64() % (surrounding.len() as u64);
                 let o = surrounding.get(s as usize).unwrap().clone();
                 let topic_names = &self.topic_names_map.get(&c).unwrap();
-                let topic_name = topic_names.get(0).unwrap().clone();
+                let topic_name = topic_names.first().unwrap().clone();
                 if self.state.get(&c).unwrap().is_none() {
                     //Picking up only from other places than the ramp (final destination)
                     if !stopping || (o.node_type.as_ref().unwrap() != "Ramp") {
@@ -191,8 +189,8 @@ impl MessageCreator for ComplexFactoryScenarioSimulator {
                     }
                 } else if let Some(node) = self.state.get(&c).unwrap() {
                     //Dropping off only at conveyor if we are stopping
-                    if !stopping || (o.node_type.as_ref().unwrap() == "Conveyor") {
-                        if self.state.get(&o).unwrap().is_none() {
+                    if (!stopping || (o.node_type.as_ref().unwrap() == "Conveyor"))
+                        && self.state.get(&o).unwrap().is_none() {
                             topic_names_and_updates.push(create_drop_message(
                                 node.clone(),
                                 crane.clone(),
@@ -206,7 +204,6 @@ impl MessageCreator for ComplexFactoryScenarioSimulator {
                             ));
                             sent = true;
                         }
-                    }
                 }
             } else if !stopping && c.node_type.as_ref().unwrap() == "Stamp" {
                 let stamp_assembly = self.stamp_assembly_of_map.get(&c).unwrap();
@@ -224,22 +221,20 @@ impl MessageCreator for ComplexFactoryScenarioSimulator {
                 }
             } else if c.node_type.as_ref().unwrap() == "ConveyorEvent" {
                 let conveyor = self.conveyor_of_conveyor_event.get(&c).unwrap();
-                let plastic_ramp = self.plastic_ramp_of_conveyor.get(&conveyor).unwrap();
-                let metal_ramp = self.metal_ramp_of_conveyor.get(&conveyor).unwrap();
-                if let Some(barrel) = self.state.get(&conveyor).unwrap() {
-                    let barrel_material_type = self.barrel_material_type.get(&barrel).unwrap();
-                    let use_ramp;
-                    if barrel_material_type == &BarrelMaterialType::Metal {
-                        use_ramp = metal_ramp;
+                let plastic_ramp = self.plastic_ramp_of_conveyor.get(conveyor).unwrap();
+                let metal_ramp = self.metal_ramp_of_conveyor.get(conveyor).unwrap();
+                if let Some(barrel) = self.state.get(conveyor).unwrap() {
+                    let barrel_material_type = self.barrel_material_type.get(barrel).unwrap();
+                    let use_ramp = if barrel_material_type == &BarrelMaterialType::Metal {
+                        metal_ramp
                     } else {
-                        use_ramp = plastic_ramp;
-                    }
+                        plastic_ramp
+                    };
                     if self.state.get(use_ramp).unwrap().is_none() {
                         let topic_name = self
                             .topic_names_map
                             .get(&c)
-                            .unwrap()
-                            .get(0)
+                            .unwrap().first()
                             .unwrap()
                             .clone();
                         topic_names_and_updates.push(produce_conveyor_message(
@@ -354,6 +349,7 @@ fn create_conveyor_of_conveyor_event(queries: &Vec<Query>) -> BTreeMap<Node, Nod
     conveyor_of_conveyor_event
 }
 
+#[allow(clippy::too_many_arguments)]
 fn produce_conveyor_message(
     barrel: Node,
     conveyor: Node,
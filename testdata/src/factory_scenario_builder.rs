@@ -91,16 +91,15 @@ pub fn pickdrop_query() -> Query {
         edge_barrel_at_object,
     ]);
 
-    let query = Query {
+    Query {
         name: "pickdrop".to_string(),
         application: "pickdrop".to_string(),
         graph: query_graph,
-        optional_edges: optional_edges,
+        optional_edges,
         group: groupby,
-        output_edges: output_edges,
-        input_nodes: input_nodes,
-    };
-    query
+        output_edges,
+        input_nodes,
+    }
 }
 
 pub fn matched_pickdrop_query(
@@ -112,8 +111,8 @@ pub fn matched_pickdrop_query(
     let query = pickdrop_query();
 
     let my_crane_has_pickdrop = edges_between_node_vectors(
-        &vec![my_crane.clone()],
-        &vec![my_pickdrop.clone()],
+        std::slice::from_ref(&my_crane),
+        std::slice::from_ref(&my_pickdrop),
         "HasEvent",
     )
     .pop()
@@ -173,8 +172,7 @@ pub fn matched_pickdrop_query(
     let grouped_query_match = GroupedQueryMatch {
         grouped_matches: matches,
     };
-    let matched_query = query.create_matched_query(grouped_query_match, suffix.to_string());
-    matched_query
+    query.create_matched_query(grouped_query_match, suffix.to_string())
 }
 
 pub fn stamp_query() -> Query {
@@ -196,16 +194,15 @@ pub fn stamp_query() -> Query {
     let groupby = BTreeSet::new();
     let query_graph = Graph::from_edges(vec![stamp_assembly_has_stamp, barrel_at_stamp_assembly]);
 
-    let query = Query {
+    Query {
         name: "stamp".to_string(),
         application: "stamp".to_string(),
         graph: query_graph,
-        optional_edges: optional_edges,
+        optional_edges,
         group: groupby,
-        output_edges: output_edges,
-        input_nodes: input_nodes,
-    };
-    query
+        output_edges,
+        input_nodes,
+    }
 }
 
 pub fn matched_stamp_query(my_stamp_assembly: Node, my_stamp: Node, suffix: &str) -> Query {
@@ -230,13 +227,12 @@ pub fn matched_stamp_query(my_stamp_assembly: Node, my_stamp: Node, suffix: &str
         }
     }
 
-    let matched_query = query.create_matched_query(
+    query.create_matched_query(
         GroupedQueryMatch {
             grouped_matches: vec![QueryMatch { homomorphism }],
         },
         suffix.to_string(),
-    );
-    matched_query
+    )
 }
 
 pub fn conveyor_query() -> Query {
@@ -269,16 +265,15 @@ pub fn conveyor_query() -> Query {
         conveyor_has_metal_ramp,
     ]);
 
-    let query = Query {
+    Query {
         name: "conveyor".to_string(),
         application: "conveyor".to_string(),
         graph: query_graph,
-        optional_edges: optional_edges,
+        optional_edges,
         group: groupby,
-        output_edges: output_edges,
-        input_nodes: input_nodes,
-    };
-    query
+        output_edges,
+        input_nodes,
+    }
 }
 
 pub fn matched_conveyor_query(
@@ -337,13 +332,12 @@ pub fn matched_conveyor_query(
         }
     }
 
-    let matched_query = query.create_matched_query(
+    query.create_matched_query(
         GroupedQueryMatch {
             grouped_matches: vec![QueryMatch { homomorphism }],
         },
         suffix.to_string(),
-    );
-    matched_query
+    )
 }
 
 pub fn detector_query() -> Query {
@@ -364,16 +358,15 @@ pub fn detector_query() -> Query {
     let groupby = BTreeSet::new();
     let query_graph = Graph::from_edges(vec![platform_has_detector, barrel_at_platform]);
 
-    let query = Query {
+    Query {
         name: "detector".to_string(),
         application: "detector".to_string(),
         graph: query_graph,
-        optional_edges: optional_edges,
+        optional_edges,
         group: groupby,
-        output_edges: output_edges,
-        input_nodes: input_nodes,
-    };
-    query
+        output_edges,
+        input_nodes,
+    }
 }
 
 pub fn matched_detector_query(my_platform: Node, my_detector: Node, suffix: &str) -> Query {
@@ -398,13 +391,12 @@ pub fn matched_detector_query(my_platform: Node, my_detector: Node, suffix: &str
         }
     }
 
-    let matched_query = query.create_matched_query(
+    query.create_matched_query(
         GroupedQueryMatch {
             grouped_matches: vec![QueryMatch { homomorphism }],
         },
         suffix.to_string(),
-    );
-    matched_query
+    )
 }
 
 pub fn cranes(n: u32) -> Vec<Node> {
@@ -518,8 +510,8 @@ pub fn ramps(n: u32) -> Vec<Node> {
 }
 
 pub fn edges_between_node_vectors(
-    sources: &Vec<Node>,
-    targets: &Vec<Node>,
+    sources: &[Node],
+    targets: &[Node],
     edge_type: &str,
 ) -> Vec<Edge> {
     assert!(sources.len() == targets.len());
@@ -528,7 +520,7 @@ pub fn edges_between_node_vectors(
         edges.push(Edge::without_timestamp(
             sources.get(i).unwrap().clone(),
             targets.get(i).unwrap().clone(),
-            &edge_type,
+            edge_type,
         ))
     }
     edges
@@ -561,12 +553,12 @@ pub fn create_simple_factory_scenario() -> SimpleFactoryScenario {
     let my_ramp = ramps(1).pop().unwrap();
 
     let matched_pickdrop_query_1 = matched_pickdrop_query(
-        two_cranes.get(0).unwrap().clone(),
-        two_pickdrops.get(0).unwrap().clone(),
+        two_cranes.first().unwrap().clone(),
+        two_pickdrops.first().unwrap().clone(),
         vec![
-            three_platforms.get(0).unwrap().clone(),
+            three_platforms.first().unwrap().clone(),
             three_platforms.get(1).unwrap().clone(),
-            three_stamp_assemblies.get(0).unwrap().clone(),
+            three_stamp_assemblies.first().unwrap().clone(),
             three_stamp_assemblies.get(1).unwrap().clone(),
             my_ramp.clone(),
         ],
@@ -587,8 +579,8 @@ pub fn create_simple_factory_scenario() -> SimpleFactoryScenario {
     );
 
     let matched_stamp_query_1 = matched_stamp_query(
-        three_stamp_assemblies.get(0).unwrap().clone(),
-        three_stamps.get(0).unwrap().clone(),
+        three_stamp_assemblies.first().unwrap().clone(),
+        three_stamps.first().unwrap().clone(),
         "_matched_1",
     );
 
@@ -633,8 +625,8 @@ pub fn complex_factory_scenario_builder(size: u32) -> ComplexFactoryScenario {
     let some_conveyors = conveyors(size / 2);
     let some_conveyor_events = conveyor_events(size / 2);
     let all_ramps = ramps(size);
-    let some_metal_ramps: Vec<Node> = all_ramps[0..((size/2) as usize)].iter().map(|r|r.clone()).collect();
-    let some_plastic_ramps: Vec<Node> = all_ramps[((size/2) as usize)..all_ramps.len()].iter().map(|r|r.clone()).collect();
+    let some_metal_ramps: Vec<Node> = all_ramps[0..((size/2) as usize)].to_vec();
+    let some_plastic_ramps: Vec<Node> = all_ramps[((size/2) as usize)..all_ramps.len()].to_vec();
 
     let mut matched_crane_queries = vec![];
     for i in 0..size {
@@ -42,14 +42,10 @@ pub(crate) fn create_objects_surrounding_crane_map(
             trg_clone.forget_query_node_name();
             if e.edge_type == "HasObjectAtPosition" {
                 let new_trg = has_object_map.get(&trg_clone).unwrap().clone();
-                if !objects_surrounding_crane_map.contains_key(&src_clone) {
-                    objects_surrounding_crane_map.insert(src_clone, vec![new_trg]);
-                } else {
-                    objects_surrounding_crane_map
-                        .get_mut(&src_clone)
-                        .unwrap()
-                        .push(new_trg);
-                }
+                objects_surrounding_crane_map
+                    .entry(src_clone)
+                    .or_insert_with(Vec::new)
+                    .push(new_trg);
             }
         }
     }
@@ -102,8 +98,7 @@ pub(crate) fn create_topic_names_map(
         .into_iter()
         .map(|(c, s)| (c, s.into_iter().collect()))
         .collect();
-    let topic_names_map = BTreeMap::from_iter(tuples.into_iter());
-    topic_names_map
+    BTreeMap::from_iter(tuples)
 }
 
 pub(crate) fn create_has_object_map(queries: &Vec<Query>) -> BTreeMap<Node, Node> {
@@ -240,7 +235,7 @@ pub(crate) fn create_stamp_message(
     config: Configuration
 ) -> TopicNameAndUpdate {
     let barrel = state.get(&stamp_assembly).unwrap().as_ref().unwrap();
-    let topic_name = topic_names_map.get(&stamp).unwrap().get(0).unwrap().clone();
+    let topic_name = topic_names_map.get(&stamp).unwrap().first().unwrap().clone();
     let (payload, topic_name_and_update) =
         create_stamp_event(stamp, current_timestamp + 1, topic_name, config);
     //This is quite a dirty fix..
@@ -265,6 +260,7 @@ pub(crate) fn create_stamp_message(
     topic_name_and_update
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn create_pickup_message(
     barrel: Node,
     crane: Node,
@@ -305,6 +301,7 @@ pub(crate) fn create_pickup_message(
     )
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn create_drop_message(
     barrel: Node,
     crane: Node,
@@ -9,34 +9,80 @@ use tonic::{Response, Status};
 use tonic::transport::Channel;
 use uuid::Uuid;
 use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
 
-use mbei_core::event::{Deltas, Event, Update};
+use mbei_core::event::{Deltas, Event, EventCorrection, ManualAssertion, Update, Watermark};
 use mbei_core::graph::{Delta, DeltaType, Node};
-use mbei_grpc::process_update_client::{await_deliveries, await_deliveries_max_queue};
+use mbei_grpc::process_update_client::await_deliveries_max_queue;
 use mbei_grpc::process_update::process_update_client::ProcessUpdateClient;
 use mbei_grpc::process_update::{ProcessUpdateRequest, ProcessUpdateResponse};
-use mbei_grpc::process_update_client::create_process_update_client;
-use mbei_grpc::process_update_mapping::request_from_update;
+use mbei_grpc::process_update_client::{create_process_update_client, send_respecting_backpressure};
+use mbei_grpc::operators::with_operator_token;
+use mbei_grpc::process_update_mapping::{request_from_update, set_request_target_query};
+use mbei_grpc::tls::TlsOptions;
 use crate::message_creator::MessageCreator;
 
 type JoinHandleType = JoinHandle<Result<tonic::Response<ProcessUpdateResponse>, Status>>;
 
+//Of a producer not given one. Each producer needs its own, stable across restarts,
+//so that a restarted producer replaces its old watermark instead of holding back finality
+pub const PRODUCER_SOURCE_ID: &str = "testdata-producer";
+
+/// Certificates for producing to components and central that require mutual TLS
+#[derive(StructOpt)]
+pub struct ProducerTlsArgs {
+    #[structopt(long = "--tls-ca", parse(from_os_str), requires_all = &["tls-certificate", "tls-key"])]
+    pub tls_ca: Option<std::path::PathBuf>,
+
+    #[structopt(long = "--tls-certificate", parse(from_os_str), requires = "tls-ca")]
+    pub tls_certificate: Option<std::path::PathBuf>,
+
+    #[structopt(long = "--tls-key", parse(from_os_str), requires = "tls-ca")]
+    pub tls_key: Option<std::path::PathBuf>,
+}
+
+impl ProducerTlsArgs {
+    pub fn to_tls_options(&self) -> Option<TlsOptions> {
+        match (&self.tls_ca, &self.tls_certificate, &self.tls_key) {
+            (Some(ca_certificate_path), Some(certificate_path), Some(key_path)) => Some(TlsOptions {
+                ca_certificate_path: ca_certificate_path.clone(),
+                certificate_path: certificate_path.clone(),
+                key_path: key_path.clone(),
+                allowed_client_hosts: BTreeSet::new(),
+            }),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct TestdataProducer {
     client_map: BTreeMap<String, ProcessUpdateClient<tonic::transport::Channel>>,
-    use_central: bool
+    use_central: bool,
+    source_id: String,
 }
 
 impl TestdataProducer {
-    pub async fn new(query_url_map: BTreeMap<String, String>, max_elapsed_time: Option<Duration>, use_central:bool) -> TestdataProducer {
+    pub async fn new(query_url_map: BTreeMap<String, String>, max_elapsed_time: Option<Duration>, use_central:bool) -> Result<TestdataProducer, String> {
+        TestdataProducer::with_tls(query_url_map, max_elapsed_time, use_central, None).await
+    }
+
+    pub async fn with_tls(query_url_map: BTreeMap<String, String>, max_elapsed_time: Option<Duration>, use_central:bool, tls: Option<TlsOptions>) -> Result<TestdataProducer, String> {
         let mut client_map = BTreeMap::new();
         for (q, url) in query_url_map {
-            client_map.insert(q, create_process_update_client(url, max_elapsed_time).await.expect("Problem creating client"));
+            client_map.insert(q, create_process_update_client(url, max_elapsed_time, tls.clone()).await?);
         }
-        TestdataProducer {
+        Ok(TestdataProducer {
             client_map,
-            use_central
-        }
+            use_central,
+            source_id: PRODUCER_SOURCE_ID.to_string(),
+        })
+    }
+
+    /// The producer with the source id its watermarks are sent with, which components can be configured to wait for
+    pub fn with_source_id(mut self, source_id: &str) -> TestdataProducer {
+        self.source_id = source_id.to_string();
+        self
     }
 
     pub async fn send_messages_in_vec_until_max_queue(&self, messages:Vec<TopicNameAndUpdate>, max_queue_size:u32, init_messages_per_second:u32, step:u32, every:u32) {
@@ -45,9 +91,8 @@ impl TestdataProducer {
         let receiver_handle = tokio::spawn(await_deliveries_max_queue(receiver, stop_sender, max_queue_size));
 
         let mut messages_per_second = init_messages_per_second;
-        let mut seq = 0;
         let mut at_last_1000 = Instant::now();
-        for m in messages {
+        for (seq, m) in (0u32..).zip(messages) {
             if let Ok(()) = stop_receiver.try_recv() {
                 info!("Stopping due to queue size backpressure at {} messages per second", &messages_per_second);
                 break;
@@ -61,7 +106,6 @@ impl TestdataProducer {
             let handle = self.send_update(&m.update, m.topic_name).await;
             sender.send(handle).expect("Error sending");
             delay(messages_per_second);
-            seq += 1;
         }
         drop(sender);
         receiver_handle.await.expect("Error in receiver");
@@ -119,6 +163,22 @@ impl TestdataProducer {
         self.send_update(&update, topic_name.to_string()).await
     }
 
+    /// Retracts an event already sent to the query, and sends the replacement instead if there is one.
+    pub async fn send_event_correction_now(&self, topic_name: &str, correction: EventCorrection) {
+        let update = Update::EventCorrection(correction);
+        let handle = self.send_update(&update, topic_name.to_string()).await;
+        handle.await.expect("Sending failed").expect("Sending failed");
+    }
+
+    /// Asserts or revokes deltas as the operator with the token. The component rejects unknown tokens, so the error is returned.
+    pub async fn send_manual_assertion_now(&self, topic_name: &str, assertion: ManualAssertion, token: &str) -> Result<(), Status> {
+        let mut client = self.client_map.get(topic_name).unwrap().clone();
+        let mut request = request_from_update(&Update::ManualAssertion(assertion));
+        set_request_target_query(&mut request, topic_name);
+        let request = with_operator_token(request, token).map_err(Status::invalid_argument)?;
+        client.send(request).await.map(|_| ())
+    }
+
     pub async fn send_deltas_now(&self, deltas_id: &str, topic_name: &str, deltas: Vec<Delta>) {
         let handles = self.send_deltas(
             deltas_id, topic_name, deltas
@@ -134,7 +194,7 @@ impl TestdataProducer {
             deltas_id: deltas_id.to_string(),
             origin_id: deltas_id.to_string(),
             origin_timestamp: min_ts,
-            deltas: BTreeSet::from_iter(deltas.into_iter()),
+            deltas: BTreeSet::from_iter(deltas),
         }) ;
         let mut handles = vec![];
         if self.use_central {
@@ -146,18 +206,36 @@ impl TestdataProducer {
         handles
     }
 
+    /// Tells every component, and central if used, that the producer sends nothing before the timestamp.
+    /// Only to be sent when the updates sent before have been acknowledged, as they could otherwise arrive after it.
+    pub async fn send_watermark_now(&self, timestamp: u64) {
+        let update = Update::Watermark(Watermark {
+            source_id: self.source_id.clone(),
+            timestamp,
+        });
+        let mut handles = vec![];
+        for topic_name in self.client_map.keys() {
+            if topic_name != "central" || self.use_central {
+                handles.push(self.send_update(&update, topic_name.clone()).await);
+            }
+        }
+        for h in handles {
+            h.await.expect("Sending failed").expect("Sending failed");
+        }
+    }
+
     pub async fn send_update(&self, update: &Update, topic_name: String) -> JoinHandleType {
         debug!("Sending update to {}",&topic_name);
         let client = self.client_map.get(&topic_name).unwrap().clone();
-        let request = request_from_update(update);
+        let mut request = request_from_update(update);
+        set_request_target_query(&mut request, &topic_name);
 
-        let handle = tokio::spawn(TestdataProducer::owning_send(client, request
-        ));
-        handle
+        tokio::spawn(TestdataProducer::owning_send(client, request
+        ))
     }
 
-    async fn owning_send(mut client:ProcessUpdateClient<Channel>, request:ProcessUpdateRequest) -> Result<Response<ProcessUpdateResponse>, Status> {
-        client.send(request).await
+    async fn owning_send(client:ProcessUpdateClient<Channel>, request:ProcessUpdateRequest) -> Result<Response<ProcessUpdateResponse>, Status> {
+        send_respecting_backpressure(client, request).await
     }
 
     pub async fn produce_until_interrupted_or_n_reached(
@@ -165,9 +243,12 @@ impl TestdataProducer {
         message_creator: &mut dyn MessageCreator,
         messages_per_second: u32,
         n: Option<u32>,
+        heartbeat_interval: Duration,
     ) -> u64 {
-        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
-        let receiver_handle = tokio::spawn(await_deliveries(receiver));
+        //Messages are created in timestamp order, so the current timestamp is a watermark once the messages before it are delivered
+        self.send_watermark_now(message_creator.get_current_timestamp()).await;
+        let mut last_heartbeat = Instant::now();
+        let mut handles = vec![];
 
         let (ctrl_c_sender, mut ctrl_c_receiver) = tokio::sync::oneshot::channel();
         tokio::spawn(loop_to_send_stop_if_ctrl_c_received(ctrl_c_sender));
@@ -193,16 +274,18 @@ impl TestdataProducer {
             }
             let (_, topic_name_and_update) = message_creator.create_one_new_message(stopping);
             for tu in topic_name_and_update {
-                let h = self.send_update(&tu.update, tu.topic_name).await;
-                sender
-                    .send(h)
-                    .expect("Error sending handle to receiver task");
+                handles.push(self.send_update(&tu.update, tu.topic_name).await);
+            }
+            if last_heartbeat.elapsed() >= heartbeat_interval {
+                await_all(std::mem::take(&mut handles)).await;
+                self.send_watermark_now(message_creator.get_current_timestamp()).await;
+                last_heartbeat = Instant::now();
             }
             seq += 1;
             delay(messages_per_second);
         }
-        drop(sender);
-        receiver_handle.await.expect("Problem producing");
+        await_all(handles).await;
+        self.send_watermark_now(message_creator.get_current_timestamp()).await;
         message_creator.get_current_timestamp()
     }
 }
@@ -220,15 +303,21 @@ pub fn create_single_delta_update(
             origin_id: id,
             origin_timestamp: timestamp,
             deltas: BTreeSet::from([Delta {
-                src: src,
-                trg: trg,
+                src,
+                trg,
                 edge_type: edge_type.to_string(),
-                timestamp: timestamp,
-                delta_type: delta_type,
+                timestamp,
+                delta_type,
             }]),
         })
     }
 
+async fn await_all(handles: Vec<JoinHandleType>) {
+    for h in handles {
+        h.await.expect("Sending failed").expect("Sending failed");
+    }
+}
+
 pub(crate) async fn loop_to_send_stop_if_ctrl_c_received(sender: Sender<()>) {
     tokio::signal::ctrl_c()
         .await
@@ -117,11 +117,11 @@ impl MessageCreator for SimpleFactoryScenarioSimulator {
                 }
             } else if c.node_type.as_ref().unwrap() == "PickDrop" {
                 let crane = self.pickdrop_belongs_to_crane_map.get(&c).unwrap();
-                let surrounding = &self.objects_surrounding_crane_map.get(&crane).unwrap();
+                let surrounding = &self.objects_surrounding_crane_map.get(crane).unwrap();
                 let s = self.generator.next_u64() % (surrounding.len() as u64);
                 let o = surrounding.get(s as usize).unwrap().clone();
                 let topic_names = &self.topic_names_map.get(&c).unwrap();
-                let topic_name = topic_names.get(0).unwrap().clone();
+                let topic_name = topic_names.first().unwrap().clone();
                 if self.state.get(&c).unwrap().is_none() {
                     //Picking up only from other places than the ramp (final destination)
                     if !stopping || (o.node_type.as_ref().unwrap() != "Ramp") {
@@ -144,8 +144,8 @@ impl MessageCreator for SimpleFactoryScenarioSimulator {
                     }
                 } else if let Some(node) = self.state.get(&c).unwrap() {
                     //Dropping off only at ramp if we are stopping
-                    if !stopping || (o.node_type.as_ref().unwrap() == "Ramp") {
-                        if self.state.get(&o).unwrap().is_none() {
+                    if (!stopping || (o.node_type.as_ref().unwrap() == "Ramp"))
+                        && self.state.get(&o).unwrap().is_none() {
                             topics_and_updates.push(
                                 create_drop_message(
                                     node.clone(),
@@ -161,7 +161,6 @@ impl MessageCreator for SimpleFactoryScenarioSimulator {
                             );
                             sent = true;
                         }
-                    }
                 }
             } else if !stopping && c.node_type.as_ref().unwrap() == "Stamp" {
                 let stamp_assembly = self.stamp_assembly_of_map.get(&c).unwrap();